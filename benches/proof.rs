@@ -0,0 +1,83 @@
+#![allow(missing_docs)]
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::prelude::*;
+use std::hint::black_box;
+use zktrie_ng::{
+    db::{kv::HashMapDb, NodeDb},
+    hash::{key_hasher::PrehashedKeyHasher, poseidon::Poseidon},
+    trie::ZkTrie,
+};
+
+const KEYS: usize = 10_000;
+const PROVED: usize = 100;
+const THREADS: usize = 8;
+
+fn build(db: &mut NodeDb<HashMapDb>, keys: &[[u8; 32]]) -> ZkTrie<Poseidon, PrehashedKeyHasher> {
+    let mut trie = ZkTrie::new(PrehashedKeyHasher);
+    for key in keys {
+        trie.raw_update(db, key, vec![*key], 1).unwrap();
+    }
+    trie.commit(db).unwrap();
+    trie
+}
+
+fn prove_all(
+    trie: &ZkTrie<Poseidon, PrehashedKeyHasher>,
+    db: &NodeDb<HashMapDb>,
+    keys: &[[u8; 32]],
+) {
+    for key in keys {
+        black_box(trie.prove(db, key).unwrap());
+    }
+}
+
+fn prove_all_into(
+    trie: &ZkTrie<Poseidon, PrehashedKeyHasher>,
+    db: &NodeDb<HashMapDb>,
+    keys: &[[u8; 32]],
+) {
+    for key in keys {
+        black_box(trie.prove_into(db, key, &mut std::io::sink()).unwrap());
+    }
+}
+
+fn bench_prove(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(42);
+    // prehashed keys, so the proofs don't hash
+    let keys: Vec<[u8; 32]> = (0..KEYS)
+        .map(|_| {
+            let mut key: [u8; 32] = rng.gen();
+            key[0] = 0;
+            key
+        })
+        .collect();
+    let proved = &keys[..PROVED];
+
+    let mut group = c.benchmark_group("Prove");
+    for (name, cached) in [("no cache", false), ("canonical cache", true)] {
+        let mut db = NodeDb::new(HashMapDb::default());
+        if cached {
+            db = db.with_canonical_cache(KEYS);
+        }
+        let trie = build(&mut db, &keys);
+        prove_all(&trie, &db, proved);
+
+        group.bench_function(name, |b| b.iter(|| prove_all(&trie, &db, proved)));
+        group.bench_function(format!("{name}, into writer"), |b| {
+            b.iter(|| prove_all_into(&trie, &db, proved))
+        });
+        group.bench_function(format!("{name}, into writer, {THREADS} threads"), |b| {
+            b.iter(|| {
+                std::thread::scope(|s| {
+                    for _ in 0..THREADS {
+                        s.spawn(|| prove_all_into(&trie, &db, proved));
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_prove);
+criterion_main!(benches);
@@ -113,6 +113,7 @@ fn bench_trie_operation(c: &mut Criterion) {
                     let root = *trie.root().unwrap_ref();
                     let db = HashMapDb::from_map(false, trie_db.inner().inner().clone());
                     let trie_db = NodeDb::new(db);
+                    let root = trie_db.committed_root(root).unwrap();
                     let trie =
                         ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
                     (trie_db, trie, keys)
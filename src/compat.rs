@@ -0,0 +1,124 @@
+//! Differential checks against the legacy [zkTrie](https://github.com/scroll-tech/zktrie).
+//!
+//! Forks changing the hashing or the node encoding can re-run the cross-implementation checks
+//! against their own operation sequences, e.g. in their CI.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use zktrie_ng::compat::{assert_same_root, Op};
+//!
+//! assert_same_root([
+//!     Op::Update {
+//!         key: [1u8; 32],
+//!         values: vec![[1u8; 32]],
+//!         compression_flags: 1,
+//!     },
+//!     Op::Delete { key: [1u8; 32] },
+//! ]);
+//! ```
+use crate::{
+    db::NodeDb,
+    hash::{poseidon::Poseidon, HashScheme, ZkHash},
+    trie::ZkTrie,
+};
+use poseidon_bn254::{hash_with_domain, Fr, PrimeField};
+use std::sync::Once;
+use zktrie::HashField;
+use zktrie_rust::{db::SimpleDb, hash::AsHash, types::TrieHashScheme};
+
+/// Node of the legacy implementation.
+pub type LegacyNode = zktrie_rust::types::Node<AsHash<HashField>>;
+
+/// Trie of the legacy implementation.
+pub type LegacyTrie =
+    zktrie_rust::raw::ZkTrieImpl<AsHash<HashField>, SimpleDb, { Poseidon::TRIE_MAX_LEVELS }>;
+
+/// An operation applied by [`assert_same_root`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Insert or update a key
+    Update {
+        /// The key, before hashing
+        key: [u8; 32],
+        /// The value preimages
+        values: Vec<[u8; 32]>,
+        /// The compression flags of the values
+        compression_flags: u32,
+    },
+    /// Delete a key, does nothing if the key is absent
+    Delete {
+        /// The key, before hashing
+        key: [u8; 32],
+    },
+}
+
+/// Register the Poseidon hash scheme with the legacy implementation.
+///
+/// Called by [`new_legacy_trie`], calling it again is a no-op.
+pub fn init_legacy_hash_scheme() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| zktrie::init_hash_scheme_simple(poseidon_hash_scheme));
+}
+
+/// Create an empty legacy trie.
+pub fn new_legacy_trie() -> LegacyTrie {
+    init_legacy_hash_scheme();
+    LegacyTrie::new_zktrie_impl(SimpleDb::new()).unwrap()
+}
+
+/// Apply `ops` to both implementations and assert they have the same root after every operation.
+///
+/// Returns the final root.
+///
+/// # Panics
+///
+/// Panics at the first operation after which the roots differ, or if any operation fails.
+pub fn assert_same_root(ops: impl IntoIterator<Item = Op>) -> ZkHash {
+    let mut legacy_trie = new_legacy_trie();
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    for (i, op) in ops.into_iter().enumerate() {
+        match &op {
+            Op::Update {
+                key,
+                values,
+                compression_flags,
+            } => {
+                let legacy_key = LegacyNode::hash_bytes(key).unwrap();
+                legacy_trie
+                    .try_update(&legacy_key, *compression_flags, values.clone())
+                    .unwrap();
+                trie.raw_update(&trie_db, key, values.clone(), *compression_flags)
+                    .unwrap();
+            }
+            Op::Delete { key } => {
+                if trie.delete(&trie_db, key).unwrap() {
+                    let legacy_key = LegacyNode::hash_bytes(key).unwrap();
+                    legacy_trie.try_delete(&legacy_key).unwrap();
+                }
+            }
+        }
+        legacy_trie.prepare_root().unwrap();
+        legacy_trie.commit().unwrap();
+        trie.commit(&mut trie_db).unwrap();
+
+        let root = *trie.root().unwrap_ref();
+        if legacy_trie.root().as_ref() != root.as_slice() {
+            error!(op = ?op, "roots differ");
+            panic!(
+                "roots differ after op #{i}: legacy {}, zktrie-ng {root}",
+                hex::encode(legacy_trie.root().as_ref())
+            );
+        }
+    }
+    *trie.root().unwrap_ref()
+}
+
+fn poseidon_hash_scheme(a: &[u8; 32], b: &[u8; 32], domain: &[u8; 32]) -> Option<[u8; 32]> {
+    let a = Fr::from_repr_vartime(*a)?;
+    let b = Fr::from_repr_vartime(*b)?;
+    let domain = Fr::from_repr_vartime(*domain)?;
+    Some(hash_with_domain(&[a, b], domain).to_repr())
+}
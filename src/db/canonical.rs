@@ -0,0 +1,118 @@
+//! Cache of the canonical encoding of nodes, see [`NodeDb::with_canonical_cache`].
+//!
+//! Nodes are stored archived, so every proof re-encodes the nodes on its path
+//! into their [canonical bytes](crate::trie::Node::canonical_value).
+//! Proofs of nearby keys share most of their nodes, the upper levels being in every proof,
+//! so a proof-serving database can keep the encoded bytes instead.
+//! Nodes are addressed by their hash and never change, so cached bytes are never stale.
+//! Hits only take a read lock and share the cached [`Bytes`],
+//! e.g. written by [`ZkTrie::prove_into`](crate::trie::ZkTrie::prove_into) without copying.
+//!
+//! Encoding an archived node is cheap next to reading it, which the cache doesn't avoid:
+//! measure with `benches/proof.rs` before enabling it, with an in-memory database
+//! the cache is slower than encoding every time.
+use super::{kv::KVDatabase, NodeDb};
+use crate::hash::{HashScheme, ZkHash};
+use crate::trie::INode;
+use crate::HashMap;
+use alloy_primitives::bytes::Bytes;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, RwLock};
+
+type CacheKey = (ZkHash, bool);
+
+/// Canonical bytes by node hash and key preimage inclusion, evicted first in first out.
+#[derive(Clone)]
+pub(crate) struct CanonicalCache {
+    capacity: usize,
+    inner: Arc<RwLock<CacheInner>>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<CacheKey, Bytes>,
+    order: VecDeque<CacheKey>,
+}
+
+impl Debug for CanonicalCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CanonicalCache")
+            .field("capacity", &self.capacity)
+            .field("len", &self.inner.read().unwrap().entries.len())
+            .finish()
+    }
+}
+
+impl CanonicalCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Default::default(),
+        }
+    }
+
+    fn get_or_encode(&self, key: CacheKey, encode: impl FnOnce() -> Vec<u8>) -> Bytes {
+        if let Some(bytes) = self.inner.read().unwrap().entries.get(&key) {
+            return bytes.clone();
+        }
+        let bytes = Bytes::from(encode());
+        let mut inner = self.inner.write().unwrap();
+        if inner.entries.insert(key, bytes.clone()).is_none() {
+            inner.order.push_back(key);
+            while inner.order.len() > self.capacity {
+                let evicted = inner.order.pop_front().unwrap();
+                inner.entries.remove(&evicted);
+            }
+        }
+        bytes
+    }
+}
+
+impl<KvDb: KVDatabase> NodeDb<KvDb> {
+    /// Cache the canonical bytes of up to `capacity` nodes read by proofs,
+    /// see the [module docs](self).
+    ///
+    /// Handles created afterwards share the cache.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn with_canonical_cache(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "canonical cache capacity must be positive");
+        self.canonical_cache = Some(CanonicalCache::new(capacity));
+        self
+    }
+
+    /// Get the number of cached canonical encodings, if caching.
+    pub fn canonical_cache_len(&self) -> Option<usize> {
+        self.canonical_cache
+            .as_ref()
+            .map(|cache| cache.inner.read().unwrap().entries.len())
+    }
+
+    /// Drop the cached canonical encodings, caching goes on.
+    pub fn clear_canonical_cache(&self) {
+        if let Some(cache) = self.canonical_cache.as_ref() {
+            *cache.inner.write().unwrap() = CacheInner::default();
+        }
+    }
+
+    /// Encode a node into its canonical bytes, through the cache if enabled.
+    ///
+    /// Only archived nodes are cached, owned nodes may not be hashed yet.
+    /// Cached bytes are shared, not copied.
+    pub(crate) fn canonical_value<H: HashScheme>(
+        &self,
+        node: &INode<H>,
+        include_key_preimage: bool,
+    ) -> Bytes {
+        match (self.canonical_cache.as_ref(), node) {
+            (Some(cache), INode::Archived(viewer)) => cache
+                .get_or_encode((viewer.node_hash, include_key_preimage), || {
+                    node.canonical_value(include_key_preimage)
+                }),
+            _ => node.canonical_value(include_key_preimage).into(),
+        }
+    }
+}
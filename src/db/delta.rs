@@ -0,0 +1,253 @@
+//! Incremental backups of trie nodes, see [`NodeDb::export_delta`].
+//!
+//! A delta holds the nodes added between two committed roots:
+//!
+//! ```text
+//! header: magic (8 bytes) | version (u32 le) | reserved (4 bytes) | since root | now root
+//! record: length (u32 le) | canonical node bytes
+//! ```
+//!
+//! Nodes are written children first, and are parsed and hashed again on import,
+//! so a delta doesn't need to be trusted.
+use crate::db::kv::KVDatabase;
+use crate::db::{DbError, NodeDb, NodeDbError};
+use crate::hash::{HashScheme, ZkHash, HASH_SIZE};
+use crate::trie::{CommittedRoot, Node, NodeViewer};
+use crate::HashSet;
+use std::io::{self, Read, Write};
+
+/// The magic bytes at the start of a delta.
+pub const DELTA_MAGIC: &[u8; 8] = b"ZKDELTA\0";
+
+/// The current delta format version.
+pub const DELTA_VERSION: u32 = 1;
+
+/// Errors that can occur when exporting or importing a delta.
+#[derive(Debug, thiserror::Error)]
+pub enum DeltaError<DbErr> {
+    /// IO error
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Error when accessing the database
+    #[error("Database error: {0}")]
+    Db(#[from] DbError<DbErr>),
+    /// The delta does not start with [`DELTA_MAGIC`]
+    #[error("Invalid delta magic bytes")]
+    InvalidMagic,
+    /// The delta version is not supported
+    #[error("Unsupported delta version: {0}")]
+    UnsupportedVersion(u32),
+    /// A node record can't be parsed or hashed
+    #[error("Invalid node record {0}")]
+    InvalidNode(usize),
+    /// A node is missing, e.g. the since root of a delta being imported
+    #[error("Node {0} not found")]
+    NodeNotFound(ZkHash),
+    /// A stored node can't be decoded
+    #[error("Node {0} is corrupted")]
+    CorruptedNode(ZkHash),
+}
+
+impl<KvDb: KVDatabase> NodeDb<KvDb> {
+    /// Write the nodes reachable from `now_root` but not from `since_root` into `writer`,
+    /// to be applied on a copy holding `since_root` with [`NodeDb::import_delta`].
+    ///
+    /// Both tries are walked side by side and subtrees they share are skipped,
+    /// so only the changed paths are read.
+    /// Returns the number of nodes written.
+    pub fn export_delta<H: HashScheme, W: Write>(
+        &self,
+        since_root: CommittedRoot,
+        now_root: CommittedRoot,
+        mut writer: W,
+    ) -> Result<usize, DeltaError<KvDb::Error>> {
+        writer.write_all(DELTA_MAGIC)?;
+        writer.write_all(&DELTA_VERSION.to_le_bytes())?;
+        writer.write_all(&[0; 4])?;
+        writer.write_all(since_root.hash().as_slice())?;
+        writer.write_all(now_root.hash().as_slice())?;
+
+        let mut exporter = DeltaExporter {
+            db: self,
+            writer,
+            old_leafs: HashSet::new(),
+            nodes: 0,
+        };
+        exporter.walk::<H>(now_root.hash(), since_root.hash())?;
+        let nodes = exporter.nodes;
+        exporter.writer.flush()?;
+        trace!("exported {nodes} nodes in delta");
+        Ok(nodes)
+    }
+
+    /// Insert the nodes of a delta written by [`NodeDb::export_delta`].
+    ///
+    /// The since root of the delta must be committed in this database,
+    /// and its now root must be reachable once the nodes are inserted,
+    /// otherwise nothing is written.
+    /// Nodes already in the database are skipped.
+    ///
+    /// Returns the now root and the number of nodes inserted.
+    pub fn import_delta<H: HashScheme, R: Read>(
+        &mut self,
+        mut reader: R,
+    ) -> Result<(CommittedRoot, usize), DeltaError<KvDb::Error>> {
+        let mut header = [0u8; 16 + 2 * HASH_SIZE];
+        reader.read_exact(&mut header)?;
+        if &header[..DELTA_MAGIC.len()] != DELTA_MAGIC {
+            return Err(DeltaError::InvalidMagic);
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != DELTA_VERSION {
+            return Err(DeltaError::UnsupportedVersion(version));
+        }
+        let since_root = ZkHash::from_slice(&header[16..16 + HASH_SIZE]);
+        let now_root = ZkHash::from_slice(&header[16 + HASH_SIZE..]);
+        self.committed_root(since_root)
+            .map_err(|_| DeltaError::NodeNotFound(since_root))?;
+
+        let mut nodes = Vec::new();
+        let mut imported = HashSet::new();
+        let mut len = [0u8; 4];
+        loop {
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+            reader.read_exact(&mut bytes)?;
+            let index = nodes.len();
+            let node = Node::<H>::try_from(bytes.as_slice())
+                .map_err(|_| DeltaError::InvalidNode(index))?;
+            let node_hash = *node
+                .get_or_calculate_node_hash()
+                .map_err(|_| DeltaError::InvalidNode(index))?;
+            imported.insert(node_hash);
+            nodes.push((node_hash, node));
+        }
+
+        if !now_root.is_zero()
+            && !imported.contains(&now_root)
+            && !self
+                .inner()
+                .contains_key(now_root.as_slice())
+                .map_err(DbError::classify::<KvDb>)?
+        {
+            return Err(DeltaError::NodeNotFound(now_root));
+        }
+
+        let mut inserted = 0;
+        for (node_hash, node) in nodes {
+            if self
+                .inner()
+                .contains_key(node_hash.as_slice())
+                .map_err(DbError::classify::<KvDb>)?
+            {
+                continue;
+            }
+            self.put_node(node).map_err(DbError::classify::<KvDb>)?;
+            inserted += 1;
+        }
+        trace!("imported {inserted} nodes from delta");
+        Ok((CommittedRoot::new_unchecked(now_root), inserted))
+    }
+}
+
+struct DeltaExporter<'a, KvDb, W> {
+    db: &'a NodeDb<KvDb>,
+    writer: W,
+    /// Leafs of the since trie met on changed paths, which may move deeper in the now trie
+    old_leafs: HashSet<ZkHash>,
+    nodes: usize,
+}
+
+impl<KvDb: KVDatabase, W: Write> DeltaExporter<'_, KvDb, W> {
+    /// Write the nodes of the now subtree `now` missing from the since subtree `since`,
+    /// both on the same path.
+    fn walk<H: HashScheme>(
+        &mut self,
+        now: ZkHash,
+        since: ZkHash,
+    ) -> Result<(), DeltaError<KvDb::Error>> {
+        if now == since || now.is_zero() || self.old_leafs.contains(&now) {
+            return Ok(());
+        }
+        let now_node = self.get::<H>(&now)?;
+        let since_node = (!since.is_zero())
+            .then(|| self.get::<H>(&since))
+            .transpose()?;
+        let since_children = match since_node.as_ref().map(|n| n.view()) {
+            Some(view) if view.is_branch() => {
+                let branch = view.as_branch().unwrap();
+                Some((
+                    *branch.child_left().unwrap_ref(),
+                    *branch.child_right().unwrap_ref(),
+                ))
+            }
+            Some(view) => {
+                if view.as_leaf().is_some() {
+                    self.old_leafs.insert(since);
+                }
+                None
+            }
+            None => None,
+        };
+
+        let view = now_node.view();
+        if let Some(branch) = view.as_branch() {
+            let (since_left, since_right) = since_children.unwrap_or_default();
+            self.walk::<H>(*branch.child_left().unwrap_ref(), since_left)?;
+            self.walk::<H>(*branch.child_right().unwrap_ref(), since_right)?;
+        } else if view.as_leaf().is_some() && since_children.is_some() {
+            // the leaf may be deeper in the since trie, after its siblings were deleted
+            if self.contains_leaf::<H>(since, &now)? {
+                return Ok(());
+            }
+        }
+
+        let bytes = view.canonical_value(true);
+        self.writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&bytes)?;
+        self.nodes += 1;
+        Ok(())
+    }
+
+    /// Check if `leaf` is below the since subtree `root`.
+    ///
+    /// The whole subtree is searched, as the path order of the trie is unknown,
+    /// but it only holds `leaf` and leafs deleted since.
+    fn contains_leaf<H: HashScheme>(
+        &self,
+        root: ZkHash,
+        leaf: &ZkHash,
+    ) -> Result<bool, DeltaError<KvDb::Error>> {
+        let mut stack = vec![root];
+        while let Some(node_hash) = stack.pop() {
+            if node_hash == *leaf {
+                return Ok(true);
+            }
+            if node_hash.is_zero() {
+                continue;
+            }
+            let node = self.get::<H>(&node_hash)?;
+            if let Some(branch) = node.view().as_branch() {
+                stack.push(*branch.child_left().unwrap_ref());
+                stack.push(*branch.child_right().unwrap_ref());
+            }
+        }
+        Ok(false)
+    }
+
+    fn get<H: HashScheme>(
+        &self,
+        node_hash: &ZkHash,
+    ) -> Result<NodeViewer, DeltaError<KvDb::Error>> {
+        match self.db.get_node::<H>(node_hash) {
+            Ok(node) => node.ok_or(DeltaError::NodeNotFound(*node_hash)),
+            Err(NodeDbError::CorruptedNode(node_hash)) => Err(DeltaError::CorruptedNode(node_hash)),
+            Err(NodeDbError::Db(e)) => Err(DeltaError::Db(e)),
+            Err(e) => unreachable!("unexpected error reading a node: {e}"),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::db::kv::KVDatabase;
+use std::io;
+use strum::Display;
+
+/// Classification of backend database errors, see [`KVDatabase::classify_error`].
+#[derive(Copy, Clone, Debug, Display, PartialEq, Eq, Hash)]
+pub enum DbErrorKind {
+    /// A required part of the database, e.g. a tree, doesn't exist
+    NotFound,
+    /// The stored data is corrupted, retrying won't help
+    Corruption,
+    /// I/O failure of the storage
+    Io,
+    /// The database is temporarily unavailable
+    Busy,
+    /// Errors that are not classified
+    Other,
+}
+
+impl DbErrorKind {
+    /// Classify an I/O error.
+    pub fn from_io(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut => {
+                DbErrorKind::Busy
+            }
+            io::ErrorKind::NotFound => DbErrorKind::NotFound,
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => DbErrorKind::Corruption,
+            _ => DbErrorKind::Io,
+        }
+    }
+
+    /// Check if the operation may succeed when retried.
+    #[inline]
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, DbErrorKind::Io | DbErrorKind::Busy)
+    }
+}
+
+/// A backend database error with its classification.
+#[derive(Debug, thiserror::Error)]
+#[error("{source} ({kind})")]
+pub struct DbError<E> {
+    kind: DbErrorKind,
+    source: E,
+}
+
+impl<E> DbError<E> {
+    /// Create a new error with the given classification.
+    #[inline]
+    pub fn new(kind: DbErrorKind, source: E) -> Self {
+        Self { kind, source }
+    }
+
+    /// Classify a backend error of `Db`.
+    #[inline]
+    pub fn classify<Db: KVDatabase<Error = E>>(source: E) -> Self {
+        Self::new(Db::classify_error(&source), source)
+    }
+
+    /// Get the classification.
+    #[inline]
+    pub fn kind(&self) -> DbErrorKind {
+        self.kind
+    }
+
+    /// Check if the operation may succeed when retried, see [`DbErrorKind::is_retryable`].
+    #[inline]
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+
+    /// Get the backend error.
+    #[inline]
+    pub fn inner(&self) -> &E {
+        &self.source
+    }
+
+    /// Into the backend error.
+    #[inline]
+    pub fn into_inner(self) -> E {
+        self.source
+    }
+}
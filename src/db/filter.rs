@@ -0,0 +1,118 @@
+//! A probabilistic filter over leaf node keys.
+//!
+//! The filter is a plain bloom filter, it never yields false negatives,
+//! so a negative answer can be used to skip the trie walk entirely.
+use crate::hash::{ZkHash, HASH_SIZE};
+
+#[cfg(test)]
+mod tests;
+
+/// A bloom filter over the `node_key` of stored leaf nodes.
+///
+/// Since node keys are already outputs of the hash scheme,
+/// the bit indices are derived directly from the key bytes by double hashing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeafKeyFilter {
+    num_hashes: u32,
+    num_bits: u64,
+    bits: Vec<u64>,
+}
+
+impl LeafKeyFilter {
+    /// Create a filter sized for `expected_items` keys with the given false positive rate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `false_positive_rate` is not in `(0, 1)`.
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "false positive rate must be in (0, 1)"
+        );
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 32.0) as u32;
+        Self {
+            num_hashes,
+            num_bits,
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+        }
+    }
+
+    /// Number of bits in the filter.
+    #[inline]
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    /// Number of probes per key.
+    #[inline]
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    /// Record a leaf node key.
+    pub fn insert(&mut self, node_key: &ZkHash) {
+        let (h1, h2) = Self::split(node_key);
+        for i in 0..self.num_hashes as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits;
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Check if a leaf node key may be stored.
+    ///
+    /// `false` means the key is definitely absent.
+    pub fn may_contain(&self, node_key: &ZkHash) -> bool {
+        let (h1, h2) = Self::split(node_key);
+        (0..self.num_hashes as u64).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits;
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        })
+    }
+
+    /// Clear all recorded keys.
+    pub fn clear(&mut self) {
+        self.bits.iter_mut().for_each(|word| *word = 0);
+    }
+
+    /// Encode the filter into bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + 8 + self.bits.len() * 8);
+        bytes.extend_from_slice(&self.num_hashes.to_le_bytes());
+        bytes.extend_from_slice(&self.num_bits.to_le_bytes());
+        for word in self.bits.iter() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decode the filter from bytes produced by [`LeafKeyFilter::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let num_hashes = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?);
+        let num_bits = u64::from_le_bytes(bytes.get(4..12)?.try_into().ok()?);
+        let words = bytes.get(12..)?;
+        if num_hashes == 0 || num_bits == 0 || words.len() as u64 != num_bits.div_ceil(64) * 8 {
+            return None;
+        }
+        let bits = words
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        Some(Self {
+            num_hashes,
+            num_bits,
+            bits,
+        })
+    }
+
+    #[inline]
+    fn split(node_key: &ZkHash) -> (u64, u64) {
+        let h1 = u64::from_le_bytes(node_key[HASH_SIZE - 8..].try_into().unwrap());
+        let h2 = u64::from_le_bytes(node_key[HASH_SIZE - 16..HASH_SIZE - 8].try_into().unwrap());
+        (h1, h2 | 1)
+    }
+}
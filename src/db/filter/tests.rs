@@ -0,0 +1,42 @@
+use super::*;
+use crate::db::{kv::HashMapDb, NodeDb};
+use crate::hash::{
+    key_hasher::{KeyHasher, NoCacheHasher},
+    poseidon::Poseidon,
+};
+use crate::trie::ZkTrie;
+use rand::random;
+
+#[test]
+fn test_leaf_filter() {
+    let mut trie_db = NodeDb::default().with_leaf_filter(LeafKeyFilter::new(100, 0.01));
+    let mut trie = ZkTrie::default();
+
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    for k in keys.iter() {
+        let node_key = <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, k).unwrap();
+        assert!(trie_db.may_contain_leaf(&node_key));
+        let value: Option<[[u8; 32]; 1]> = trie.get(&trie_db, k).unwrap();
+        assert_eq!(value, Some([[1u8; 32]]));
+    }
+    for _ in 0..20 {
+        let k: [u8; 32] = random();
+        let value: Option<[[u8; 32]; 1]> = trie.get(&trie_db, k).unwrap();
+        assert!(value.is_none());
+    }
+
+    trie_db.persist_leaf_filter().unwrap();
+    let expected = trie_db.leaf_filter().cloned();
+    trie_db.set_leaf_filter(None);
+    assert!(trie_db.load_leaf_filter().unwrap());
+    assert_eq!(trie_db.leaf_filter().cloned(), expected);
+
+    // metadata survives full gc
+    trie.full_gc(&mut trie_db, HashMapDb::default()).unwrap();
+    assert!(trie_db.load_leaf_filter().unwrap());
+}
@@ -0,0 +1,211 @@
+//! Read-through healing of missing nodes, see [`NodeDb::with_resolver`].
+//!
+//! When a node is not found in the backend database, the attached [`MissingNodeResolver`]
+//! is asked for it, e.g. fetching it from a network peer during snap sync.
+//! The returned bytes are untrusted: the node is parsed and its hash checked before use.
+//!
+//! [`NodeDb::get_node`] only borrows the database, so healed nodes are kept in a buffer
+//! shared by all handles, and written into the backend by [`NodeDb::persist_healed`],
+//! or by the next write of a node, e.g. on commit.
+//! The buffer holds at most [`DEFAULT_HEALED_CAPACITY`] nodes, see [`NodeDb::with_healed_capacity`]:
+//! once full, the oldest healed nodes are dropped and resolved again if needed.
+//! [`NodeDb::committed_root`] only checks the backend, so a missing root must be
+//! fetched with [`NodeDb::get_node`] and persisted before opening a trie at it.
+use super::{kv::KVDatabase, NodeDb};
+use crate::hash::{HashScheme, ZkHash};
+use crate::trie::Node;
+use alloy_primitives::bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// The default maximum number of healed nodes waiting to be persisted.
+pub const DEFAULT_HEALED_CAPACITY: usize = 4096;
+
+/// Error returned by a [`MissingNodeResolver`].
+pub type ResolveError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Fetches nodes missing from a [`NodeDb`], see [`NodeDb::with_resolver`].
+pub trait MissingNodeResolver: Send + Sync {
+    /// Fetch the [canonical bytes](Node::canonical_value) of the node with `node_hash`.
+    ///
+    /// Returns `None` if the node can't be found either.
+    fn resolve(&self, node_hash: &ZkHash) -> Result<Option<Vec<u8>>, ResolveError>;
+}
+
+impl<F> MissingNodeResolver for F
+where
+    F: Fn(&ZkHash) -> Result<Option<Vec<u8>>, ResolveError> + Send + Sync,
+{
+    fn resolve(&self, node_hash: &ZkHash) -> Result<Option<Vec<u8>>, ResolveError> {
+        self(node_hash)
+    }
+}
+
+/// Healed nodes not yet persisted, in healing order.
+#[derive(Default)]
+struct Healed {
+    nodes: HashMap<ZkHash, Bytes>,
+    /// May contain nodes persisted since, skipped when evicting.
+    order: VecDeque<ZkHash>,
+}
+
+impl Healed {
+    fn insert(&mut self, node_hash: ZkHash, bytes: Bytes, capacity: usize) {
+        while self.nodes.len() >= capacity.max(1) {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if self.nodes.remove(&oldest).is_some() {
+                trace!(node_hash = %oldest, "healed node evicted before being persisted");
+            }
+        }
+        if self.nodes.insert(node_hash, bytes).is_none() {
+            self.order.push_back(node_hash);
+        }
+    }
+
+    fn remove(&mut self, node_hash: &ZkHash) {
+        self.nodes.remove(node_hash);
+        if self.nodes.is_empty() {
+            self.order.clear();
+        }
+    }
+}
+
+/// The resolver of a [`NodeDb`] and the nodes it healed.
+#[derive(Clone)]
+pub(crate) struct Healer {
+    resolver: Arc<dyn MissingNodeResolver>,
+    healed: Arc<Mutex<Healed>>,
+    capacity: usize,
+}
+
+impl Debug for Healer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Healer")
+            .field("healed", &self.healed.lock().unwrap().nodes.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl Healer {
+    pub(crate) fn new(resolver: Arc<dyn MissingNodeResolver>) -> Self {
+        Self {
+            resolver,
+            healed: Default::default(),
+            capacity: DEFAULT_HEALED_CAPACITY,
+        }
+    }
+
+    /// Get the archived bytes of a missing node, resolving and verifying it if not healed yet.
+    pub(crate) fn heal<H: HashScheme>(&self, node_hash: &ZkHash) -> Option<Bytes> {
+        if let Some(bytes) = self.healed.lock().unwrap().nodes.get(node_hash) {
+            return Some(bytes.clone());
+        }
+        let bytes = match self.resolver.resolve(node_hash) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return None,
+            Err(e) => {
+                warn!("failed to resolve missing node {node_hash}: {e}");
+                return None;
+            }
+        };
+        let node = match Node::<H>::try_from(bytes.as_slice()) {
+            Ok(node) => node,
+            Err(e) => {
+                warn!("resolved node {node_hash} is invalid: {e}");
+                return None;
+            }
+        };
+        match node.get_or_calculate_node_hash() {
+            Ok(actual) if actual == node_hash => {}
+            Ok(actual) => {
+                warn!("resolved node {node_hash} has hash {actual}, discarded");
+                return None;
+            }
+            Err(e) => {
+                warn!("failed to hash resolved node {node_hash}: {e}");
+                return None;
+            }
+        }
+        let bytes = Bytes::from(node.archived().to_vec());
+        trace!(node_hash = %node_hash, "healed missing node");
+        self.healed
+            .lock()
+            .unwrap()
+            .insert(*node_hash, bytes.clone(), self.capacity);
+        Some(bytes)
+    }
+}
+
+impl<KvDb: KVDatabase> NodeDb<KvDb> {
+    /// Attach a resolver invoked by [`NodeDb::get_node`] when a node is not found,
+    /// see the [module docs](self).
+    ///
+    /// Handles created afterwards share the resolver and the healed nodes.
+    pub fn with_resolver<R: MissingNodeResolver + 'static>(mut self, resolver: R) -> Self {
+        self.healer = Some(Healer::new(Arc::new(resolver)));
+        self
+    }
+
+    /// Set the maximum number of healed nodes waiting to be persisted,
+    /// [`DEFAULT_HEALED_CAPACITY`] by default.
+    ///
+    /// Has no effect without a resolver, handles created afterwards share the capacity.
+    pub fn with_healed_capacity(mut self, capacity: usize) -> Self {
+        if let Some(healer) = self.healer.as_mut() {
+            healer.capacity = capacity;
+        }
+        self
+    }
+
+    /// Remove the resolver, healed nodes not yet persisted are dropped.
+    pub fn clear_resolver(&mut self) {
+        self.healer = None;
+    }
+
+    /// Check if a resolver is attached.
+    #[inline]
+    pub fn has_resolver(&self) -> bool {
+        self.healer.is_some()
+    }
+
+    /// Get the number of healed nodes not yet persisted.
+    pub fn healed_nodes(&self) -> usize {
+        self.healer
+            .as_ref()
+            .map(|healer| healer.healed.lock().unwrap().nodes.len())
+            .unwrap_or_default()
+    }
+
+    /// Write the healed nodes into the backend database.
+    ///
+    /// Called by every write of a node, e.g. on commit.
+    /// Returns the number of written nodes.
+    pub fn persist_healed(&mut self) -> Result<usize, KvDb::Error> {
+        let Some(healer) = self.healer.as_ref() else {
+            return Ok(0);
+        };
+        let healed = healer.healed.clone();
+        let nodes: Vec<_> = healed
+            .lock()
+            .unwrap()
+            .nodes
+            .iter()
+            .map(|(node_hash, bytes)| (*node_hash, bytes.clone()))
+            .collect();
+        let count = nodes.len();
+        if count == 0 {
+            return Ok(0);
+        }
+        for (node_hash, bytes) in nodes {
+            // SAFETY: healed nodes are archived after their hash is checked
+            unsafe { self.put_archived_node_unchecked(node_hash, &bytes)? };
+            healed.lock().unwrap().remove(&node_hash);
+        }
+        trace!("persisted {count} healed nodes");
+        Ok(count)
+    }
+}
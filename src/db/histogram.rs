@@ -0,0 +1,117 @@
+//! Per-depth node occupancy of the tries committed into a [`NodeDb`](super::NodeDb).
+//!
+//! The histogram is updated by every commit of a trie into a database tracking it,
+//! see [`NodeDb::with_depth_histogram`](super::NodeDb::with_depth_histogram),
+//! so the shape of a trie can be watched without traversing it.
+
+/// Number of branch and leaf nodes at each depth, the root being at depth 0.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DepthHistogram {
+    branches: Vec<u64>,
+    leafs: Vec<u64>,
+}
+
+impl DepthHistogram {
+    /// Number of branch nodes at `depth`.
+    #[inline]
+    pub fn branches_at(&self, depth: usize) -> u64 {
+        self.branches.get(depth).copied().unwrap_or_default()
+    }
+
+    /// Number of leaf nodes at `depth`.
+    #[inline]
+    pub fn leafs_at(&self, depth: usize) -> u64 {
+        self.leafs.get(depth).copied().unwrap_or_default()
+    }
+
+    /// One past the deepest depth holding a node.
+    pub fn depth(&self) -> usize {
+        let last = |counts: &[u64]| counts.iter().rposition(|c| *c > 0).map_or(0, |d| d + 1);
+        last(&self.branches).max(last(&self.leafs))
+    }
+
+    /// Total number of branch nodes.
+    pub fn total_branches(&self) -> u64 {
+        self.branches.iter().sum()
+    }
+
+    /// Total number of leaf nodes.
+    pub fn total_leafs(&self) -> u64 {
+        self.leafs.iter().sum()
+    }
+
+    /// Check if no node is recorded.
+    pub fn is_empty(&self) -> bool {
+        self.depth() == 0
+    }
+
+    /// Record a node added at `depth`.
+    pub(crate) fn add(&mut self, is_branch: bool, depth: usize) {
+        let counts = self.counts_mut(is_branch, depth);
+        *counts += 1;
+    }
+
+    /// Record a node removed from `depth`.
+    pub(crate) fn remove(&mut self, is_branch: bool, depth: usize) {
+        let counts = self.counts_mut(is_branch, depth);
+        if *counts == 0 {
+            warn!(depth, is_branch, "depth histogram doesn't cover the trie");
+        }
+        *counts = counts.saturating_sub(1);
+        self.trim();
+    }
+
+    /// Drop trailing empty depths, so equal histograms compare equal.
+    fn trim(&mut self) {
+        for counts in [&mut self.branches, &mut self.leafs] {
+            while counts.last() == Some(&0) {
+                counts.pop();
+            }
+        }
+    }
+
+    fn counts_mut(&mut self, is_branch: bool, depth: usize) -> &mut u64 {
+        let counts = if is_branch {
+            &mut self.branches
+        } else {
+            &mut self.leafs
+        };
+        if counts.len() <= depth {
+            counts.resize(depth + 1, 0);
+        }
+        &mut counts[depth]
+    }
+
+    /// Encode the histogram into bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let depth = self.depth();
+        let mut bytes = Vec::with_capacity(4 + depth * 16);
+        bytes.extend_from_slice(&(depth as u32).to_le_bytes());
+        for d in 0..depth {
+            bytes.extend_from_slice(&self.branches_at(d).to_le_bytes());
+            bytes.extend_from_slice(&self.leafs_at(d).to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decode the histogram from bytes produced by [`DepthHistogram::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let depth = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+        let counts = bytes.get(4..)?;
+        if counts.len() as u64 != depth as u64 * 16 {
+            return None;
+        }
+        let (branches, leafs) = counts
+            .chunks_exact(16)
+            .map(|chunk| {
+                (
+                    u64::from_le_bytes(chunk[..8].try_into().unwrap()),
+                    u64::from_le_bytes(chunk[8..].try_into().unwrap()),
+                )
+            })
+            .unzip();
+        let mut histogram = Self { branches, leafs };
+        histogram.trim();
+        Some(histogram)
+    }
+}
@@ -1,5 +1,7 @@
 //! KVDatabase in-memory implementation using a [`BTreeMap`].
 use super::KVDatabase;
+use crate::db::snapshot::{load_snapshot, SnapshotError};
+use crate::hash::{ZkHash, HASH_SIZE};
 use alloy_primitives::bytes::Bytes;
 use std::collections::BTreeMap;
 use std::convert::Infallible;
@@ -18,6 +20,8 @@ use std::fmt::Debug;
 pub struct BTreeMapDb {
     gc_enabled: bool,
     db: BTreeMap<Box<[u8]>, Bytes>,
+    /// Nodes loaded from a snapshot, keyed by value to save an allocation per node
+    snapshot: BTreeMap<ZkHash, Bytes>,
 }
 
 impl BTreeMapDb {
@@ -26,28 +30,100 @@ impl BTreeMapDb {
         Self {
             gc_enabled,
             db: BTreeMap::new(),
+            snapshot: BTreeMap::new(),
         }
     }
 
     /// Create a new `BTreeMapDb` from a `BTreeMap`.
     pub fn from_map(gc_enabled: bool, db: BTreeMap<Box<[u8]>, Bytes>) -> Self {
-        Self { gc_enabled, db }
+        Self {
+            gc_enabled,
+            db,
+            snapshot: BTreeMap::new(),
+        }
     }
 
     /// Get the inner `BTreeMap`.
+    ///
+    /// Nodes loaded by [`BTreeMapDb::from_snapshot`] are kept apart, see [`BTreeMapDb::snapshot_nodes`].
     pub fn inner(&self) -> &BTreeMap<Box<[u8]>, Bytes> {
         &self.db
     }
 
+    /// Get the nodes loaded by [`BTreeMapDb::from_snapshot`] and not overwritten or removed since.
+    pub fn snapshot_nodes(&self) -> &BTreeMap<ZkHash, Bytes> {
+        &self.snapshot
+    }
+
     /// Into the inner `BTreeMap`.
+    ///
+    /// Nodes loaded from a snapshot are moved in, allocating their keys.
     pub fn into_inner(self) -> BTreeMap<Box<[u8]>, Bytes> {
-        self.db
+        let mut db = self.db;
+        db.extend(
+            self.snapshot
+                .into_iter()
+                .map(|(node_hash, node)| (node_hash.as_slice().into(), node)),
+        );
+        db
+    }
+
+    /// Load the nodes of a [snapshot](crate::db::snapshot) held in `data`.
+    ///
+    /// Values are slices of `data`, so nodes are not copied and `data` is kept alive
+    /// as long as any of them, see [`ZkTrie::export_snapshot`](crate::trie::ZkTrie::export_snapshot).
+    /// Nodes are keyed by value, so no allocation is made per node.
+    ///
+    /// Every archived node is validated, as nodes are read without checks.
+    /// `data` is copied once into an aligned buffer if it's not aligned to
+    /// [`SNAPSHOT_ALIGNMENT`](crate::db::snapshot::SNAPSHOT_ALIGNMENT).
+    pub fn from_snapshot(gc_enabled: bool, data: Bytes) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            gc_enabled,
+            db: BTreeMap::new(),
+            snapshot: load_snapshot(data)?,
+        })
+    }
+
+    /// Remove a node loaded from a snapshot.
+    #[inline]
+    fn remove_snapshot_node(&mut self, k: &[u8]) -> Option<Bytes> {
+        if self.snapshot.is_empty() || k.len() != HASH_SIZE {
+            return None;
+        }
+        self.snapshot.remove(&ZkHash::from_slice(k))
+    }
+
+    /// Get a node loaded from a snapshot.
+    #[inline]
+    fn get_snapshot_node(&self, k: &[u8]) -> Option<&Bytes> {
+        if self.snapshot.is_empty() || k.len() != HASH_SIZE {
+            return None;
+        }
+        self.snapshot.get(&ZkHash::from_slice(k))
+    }
+
+    /// Load the nodes of a memory mapped snapshot file, see [`BTreeMapDb::from_snapshot`].
+    ///
+    /// # Note
+    ///
+    /// The file must not be modified while any node of it is alive.
+    #[cfg(feature = "mmap")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "mmap")))]
+    pub fn open_snapshot<P: AsRef<std::path::Path>>(
+        gc_enabled: bool,
+        path: P,
+    ) -> Result<Self, SnapshotError> {
+        Self::from_snapshot(gc_enabled, super::mmap::map_file(path)?)
     }
 }
 
 impl Debug for BTreeMapDb {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_tuple("BTreeMapDb").field(&self.db.len()).finish()
+        f.debug_struct("BTreeMapDb")
+            .field("len", &self.db.len())
+            .field("snapshot_nodes", &self.snapshot.len())
+            .finish()
     }
 }
 
@@ -58,16 +134,20 @@ impl KVDatabase for BTreeMapDb {
 
     #[inline]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
-        Ok(self.db.contains_key(k))
+        Ok(self.db.contains_key(k) || self.get_snapshot_node(k).is_some())
     }
 
     #[inline]
     fn put(&mut self, k: &[u8], v: &[u8]) -> Result<Option<Self::Item>, Self::Error> {
-        Ok(self.db.insert(k.into(), Bytes::copy_from_slice(v)))
+        let old = self.remove_snapshot_node(k);
+        Ok(self.db.insert(k.into(), Bytes::copy_from_slice(v)).or(old))
     }
 
     #[inline]
     fn or_put(&mut self, k: &[u8], v: &[u8]) -> Result<(), Self::Error> {
+        if self.get_snapshot_node(k).is_some() {
+            return Ok(());
+        }
         self.db
             .entry(k.into())
             .or_insert_with(|| Bytes::copy_from_slice(v));
@@ -80,6 +160,9 @@ impl KVDatabase for BTreeMapDb {
         k: &[u8],
         default: F,
     ) -> Result<(), Self::Error> {
+        if self.get_snapshot_node(k).is_some() {
+            return Ok(());
+        }
         self.db.entry(k.into()).or_insert_with(|| default().into());
         Ok(())
     }
@@ -90,12 +173,18 @@ impl KVDatabase for BTreeMapDb {
         k: K,
         v: impl Into<Self::Item>,
     ) -> Result<Option<Self::Item>, Self::Error> {
-        Ok(self.db.insert(k.into(), v.into()))
+        let old = self.remove_snapshot_node(k.as_ref());
+        Ok(self.db.insert(k.into(), v.into()).or(old))
     }
 
     #[inline]
     fn get<K: AsRef<[u8]> + Clone>(&self, k: K) -> Result<Option<Self::Item>, Self::Error> {
-        Ok(self.db.get(k.as_ref()).cloned())
+        let k = k.as_ref();
+        Ok(self
+            .db
+            .get(k)
+            .or_else(|| self.get_snapshot_node(k))
+            .cloned())
     }
 
     #[inline]
@@ -103,6 +192,12 @@ impl KVDatabase for BTreeMapDb {
         true
     }
 
+    #[inline]
+    fn is_extend_atomic(&self) -> bool {
+        // writes never fail
+        true
+    }
+
     #[inline]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         self.gc_enabled = gc_enabled;
@@ -117,6 +212,7 @@ impl KVDatabase for BTreeMapDb {
     fn remove(&mut self, k: &[u8]) -> Result<(), Self::Error> {
         if self.gc_enabled {
             self.db.remove(k);
+            self.remove_snapshot_node(k);
         } else {
             warn!("garbage collection is disabled, remove is ignored");
         }
@@ -136,16 +232,40 @@ impl KVDatabase for BTreeMapDb {
             }
             keep
         });
+        self.snapshot.retain(|k, v| {
+            let keep = f(k.as_slice(), v);
+            if !keep {
+                removed += 1;
+            }
+            keep
+        });
         trace!("{} key-value pairs removed", removed);
         Ok(())
     }
 
+    #[inline]
+    fn for_each_key<F>(&self, mut f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.db.keys().for_each(|k| f(k));
+        self.snapshot.keys().for_each(|k| f(k.as_slice()));
+        Ok(true)
+    }
+
     #[inline]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
         other: T,
     ) -> Result<(), Self::Error> {
-        self.db.extend(other);
+        if self.snapshot.is_empty() {
+            self.db.extend(other);
+        } else {
+            for (k, v) in other {
+                self.remove_snapshot_node(&k);
+                self.db.insert(k, v);
+            }
+        }
         Ok(())
     }
 }
@@ -1,4 +1,5 @@
 use super::KVDatabase;
+use crate::db::DbErrorKind;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex, RwLock};
@@ -7,6 +8,11 @@ impl<Db: KVDatabase> KVDatabase for RwLock<Db> {
     type Item = Db::Item;
     type Error = Db::Error;
 
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     #[inline(always)]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         self.read().unwrap().contains_key(k)
@@ -50,6 +56,11 @@ impl<Db: KVDatabase> KVDatabase for RwLock<Db> {
         self.read().unwrap().is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        self.read().unwrap().is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, _gc_enabled: bool) {
         self.get_mut().unwrap().set_gc_enabled(_gc_enabled)
@@ -73,6 +84,14 @@ impl<Db: KVDatabase> KVDatabase for RwLock<Db> {
         self.get_mut().unwrap().retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.read().unwrap().for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -80,12 +99,22 @@ impl<Db: KVDatabase> KVDatabase for RwLock<Db> {
     ) -> Result<(), Self::Error> {
         self.get_mut().unwrap().extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.get_mut().unwrap().flush()
+    }
 }
 
 impl<Db: KVDatabase> KVDatabase for Mutex<Db> {
     type Item = Db::Item;
     type Error = Db::Error;
 
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     #[inline(always)]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         self.lock().unwrap().contains_key(k)
@@ -129,6 +158,11 @@ impl<Db: KVDatabase> KVDatabase for Mutex<Db> {
         self.lock().unwrap().is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        self.lock().unwrap().is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         self.get_mut().unwrap().set_gc_enabled(gc_enabled)
@@ -152,6 +186,14 @@ impl<Db: KVDatabase> KVDatabase for Mutex<Db> {
         self.get_mut().unwrap().retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.lock().unwrap().for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -159,12 +201,22 @@ impl<Db: KVDatabase> KVDatabase for Mutex<Db> {
     ) -> Result<(), Self::Error> {
         self.get_mut().unwrap().extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.get_mut().unwrap().flush()
+    }
 }
 
 impl<Db: KVDatabase> KVDatabase for Arc<RwLock<Db>> {
     type Item = Db::Item;
     type Error = Db::Error;
 
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     #[inline(always)]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         self.read().unwrap().contains_key(k)
@@ -208,6 +260,11 @@ impl<Db: KVDatabase> KVDatabase for Arc<RwLock<Db>> {
         self.read().unwrap().is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        self.read().unwrap().is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, _gc_enabled: bool) {
         self.write().unwrap().set_gc_enabled(_gc_enabled)
@@ -231,6 +288,14 @@ impl<Db: KVDatabase> KVDatabase for Arc<RwLock<Db>> {
         self.write().unwrap().retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.read().unwrap().for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -238,12 +303,22 @@ impl<Db: KVDatabase> KVDatabase for Arc<RwLock<Db>> {
     ) -> Result<(), Self::Error> {
         self.write().unwrap().extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.write().unwrap().flush()
+    }
 }
 
 impl<Db: KVDatabase> KVDatabase for Arc<Mutex<Db>> {
     type Item = Db::Item;
     type Error = Db::Error;
 
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     #[inline(always)]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         self.lock().unwrap().contains_key(k)
@@ -287,6 +362,11 @@ impl<Db: KVDatabase> KVDatabase for Arc<Mutex<Db>> {
         self.lock().unwrap().is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        self.lock().unwrap().is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         self.lock().unwrap().set_gc_enabled(gc_enabled)
@@ -310,6 +390,14 @@ impl<Db: KVDatabase> KVDatabase for Arc<Mutex<Db>> {
         self.lock().unwrap().retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.lock().unwrap().for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -317,12 +405,22 @@ impl<Db: KVDatabase> KVDatabase for Arc<Mutex<Db>> {
     ) -> Result<(), Self::Error> {
         self.lock().unwrap().extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.lock().unwrap().flush()
+    }
 }
 
 impl<Db: KVDatabase> KVDatabase for RefCell<Db> {
     type Item = Db::Item;
     type Error = Db::Error;
 
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     #[inline(always)]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         self.borrow().contains_key(k)
@@ -366,6 +464,11 @@ impl<Db: KVDatabase> KVDatabase for RefCell<Db> {
         self.borrow().is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        self.borrow().is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         self.borrow_mut().set_gc_enabled(gc_enabled)
@@ -389,6 +492,14 @@ impl<Db: KVDatabase> KVDatabase for RefCell<Db> {
         self.borrow_mut().retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.borrow().for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -396,12 +507,22 @@ impl<Db: KVDatabase> KVDatabase for RefCell<Db> {
     ) -> Result<(), Self::Error> {
         self.borrow_mut().extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.borrow_mut().flush()
+    }
 }
 
 impl<Db: KVDatabase> KVDatabase for Rc<RefCell<Db>> {
     type Item = Db::Item;
     type Error = Db::Error;
 
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     #[inline(always)]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         self.borrow().contains_key(k)
@@ -445,6 +566,11 @@ impl<Db: KVDatabase> KVDatabase for Rc<RefCell<Db>> {
         self.borrow().is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        self.borrow().is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         self.borrow_mut().set_gc_enabled(gc_enabled)
@@ -468,6 +594,14 @@ impl<Db: KVDatabase> KVDatabase for Rc<RefCell<Db>> {
         self.borrow_mut().retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.borrow().for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -475,12 +609,22 @@ impl<Db: KVDatabase> KVDatabase for Rc<RefCell<Db>> {
     ) -> Result<(), Self::Error> {
         self.borrow_mut().extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.borrow_mut().flush()
+    }
 }
 
 impl<Db: KVDatabase> KVDatabase for Arc<RefCell<Db>> {
     type Item = Db::Item;
     type Error = Db::Error;
 
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     #[inline(always)]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         self.borrow().contains_key(k)
@@ -524,6 +668,11 @@ impl<Db: KVDatabase> KVDatabase for Arc<RefCell<Db>> {
         self.borrow().is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        self.borrow().is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         self.borrow_mut().set_gc_enabled(gc_enabled)
@@ -547,6 +696,14 @@ impl<Db: KVDatabase> KVDatabase for Arc<RefCell<Db>> {
         self.borrow_mut().retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.borrow().for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -554,12 +711,22 @@ impl<Db: KVDatabase> KVDatabase for Arc<RefCell<Db>> {
     ) -> Result<(), Self::Error> {
         self.borrow_mut().extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.borrow_mut().flush()
+    }
 }
 
 impl<Db: KVDatabase> KVDatabase for Box<Db> {
     type Item = Db::Item;
     type Error = Db::Error;
 
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     #[inline(always)]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         (**self).contains_key(k)
@@ -603,6 +770,11 @@ impl<Db: KVDatabase> KVDatabase for Box<Db> {
         (**self).is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        (**self).is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         (**self).set_gc_enabled(gc_enabled)
@@ -626,6 +798,14 @@ impl<Db: KVDatabase> KVDatabase for Box<Db> {
         (**self).retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        (**self).for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -633,6 +813,11 @@ impl<Db: KVDatabase> KVDatabase for Box<Db> {
     ) -> Result<(), Self::Error> {
         (**self).extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        (**self).flush()
+    }
 }
 
 impl<Db: KVDatabase> KVDatabase for &mut Db {
@@ -640,6 +825,11 @@ impl<Db: KVDatabase> KVDatabase for &mut Db {
 
     type Error = Db::Error;
 
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     #[inline(always)]
     fn put(&mut self, k: &[u8], v: &[u8]) -> Result<Option<Self::Item>, Self::Error> {
         (*self).put(k, v)
@@ -664,6 +854,11 @@ impl<Db: KVDatabase> KVDatabase for &mut Db {
         (**self).is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        (**self).is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         (*self).set_gc_enabled(gc_enabled)
@@ -687,6 +882,14 @@ impl<Db: KVDatabase> KVDatabase for &mut Db {
         (*self).retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        (**self).for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -694,4 +897,9 @@ impl<Db: KVDatabase> KVDatabase for &mut Db {
     ) -> Result<(), Self::Error> {
         (*self).extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        (*self).flush()
+    }
 }
@@ -1,11 +1,15 @@
 //! Middleware for kv database.
 use crate::db::kv::{KVDatabase, KVDatabaseItem};
+use crate::db::DbErrorKind;
 use crate::HashMap;
 use alloy_primitives::bytes::Bytes;
 use std::mem;
 use std::ops::DerefMut;
 use std::sync::{Arc, Mutex};
 
+#[cfg(test)]
+mod tests;
+
 /// A middleware that records all read items.
 #[derive(Debug)]
 pub struct RecorderMiddleware<Db> {
@@ -47,6 +51,10 @@ impl<Db: KVDatabase> KVDatabase for RecorderMiddleware<Db> {
     type Item = Db::Item;
     type Error = Db::Error;
 
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         self.inner.contains_key(k)
     }
@@ -91,6 +99,11 @@ impl<Db: KVDatabase> KVDatabase for RecorderMiddleware<Db> {
         self.inner.is_gc_supported()
     }
 
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        self.inner.is_extend_atomic()
+    }
+
     #[inline(always)]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         self.inner.set_gc_enabled(gc_enabled)
@@ -114,6 +127,14 @@ impl<Db: KVDatabase> KVDatabase for RecorderMiddleware<Db> {
         self.inner.retain(f)
     }
 
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.inner.for_each_key(f)
+    }
+
     #[inline(always)]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -121,4 +142,197 @@ impl<Db: KVDatabase> KVDatabase for RecorderMiddleware<Db> {
     ) -> Result<(), Self::Error> {
         self.inner.extend(other)
     }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
+    }
+}
+
+/// A middleware that tags every written entry with a logical version,
+/// so entries can be pruned by age with [`purge_older_than`](VersionedMiddleware::purge_older_than).
+///
+/// The versions are kept in memory, entries written before wrapping are never purged.
+///
+/// # Note
+///
+/// An entry's version is only refreshed when it's written or [touched](VersionedMiddleware::touch),
+/// regardless of whether it's still reachable: a node shared with a newer trie
+/// keeps the version of the trie that first wrote it.
+#[derive(Debug)]
+pub struct VersionedMiddleware<Db> {
+    inner: Db,
+    version: u64,
+    versions: HashMap<Box<[u8]>, u64>,
+}
+
+impl<Db: KVDatabase> VersionedMiddleware<Db> {
+    /// Create a new `VersionedMiddleware` wrapping the given database, starting at version 0.
+    pub fn new(inner: Db) -> Self {
+        Self {
+            inner,
+            version: 0,
+            versions: HashMap::new(),
+        }
+    }
+
+    /// Get the version assigned to written entries.
+    #[inline]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Set the version assigned to written entries, e.g. the block number.
+    #[inline]
+    pub fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+
+    /// Get the version of an entry, `None` if it's not written through this middleware.
+    #[inline]
+    pub fn version_of(&self, k: &[u8]) -> Option<u64> {
+        self.versions.get(k).copied()
+    }
+
+    /// Tag an existing entry with the current version, e.g. to keep a live node.
+    pub fn touch(&mut self, k: &[u8]) -> Result<bool, Db::Error> {
+        if !self.inner.contains_key(k)? {
+            return Ok(false);
+        }
+        self.versions.insert(k.into(), self.version);
+        Ok(true)
+    }
+
+    /// Remove every entry whose version is older than `version`.
+    ///
+    /// Removal goes through [`KVDatabase::remove`], so the inner database must have
+    /// garbage collection enabled. Returns the number of entries removed.
+    pub fn purge_older_than(&mut self, version: u64) -> Result<usize, Db::Error> {
+        if !self.inner.gc_enabled() {
+            warn!("garbage collection disabled, skipping purge");
+            return Ok(0);
+        }
+        let expired: Vec<_> = self
+            .versions
+            .iter()
+            .filter(|(_, v)| **v < version)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for k in expired.iter() {
+            self.inner.remove(k)?;
+            self.versions.remove(k);
+        }
+        trace!("purged {} entries older than {version}", expired.len());
+        Ok(expired.len())
+    }
+
+    /// Into the inner database.
+    pub fn into_inner(self) -> Db {
+        self.inner
+    }
+}
+
+impl<Db: KVDatabase> KVDatabase for VersionedMiddleware<Db> {
+    type Item = Db::Item;
+    type Error = Db::Error;
+
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
+    fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
+        self.inner.contains_key(k)
+    }
+
+    fn put(&mut self, k: &[u8], v: &[u8]) -> Result<Option<Self::Item>, Self::Error> {
+        let result = self.inner.put(k, v)?;
+        self.versions.insert(k.into(), self.version);
+        Ok(result)
+    }
+
+    fn or_put(&mut self, k: &[u8], v: &[u8]) -> Result<(), Self::Error> {
+        self.inner.or_put(k, v)?;
+        self.versions.insert(k.into(), self.version);
+        Ok(())
+    }
+
+    fn or_put_with<O: Into<Self::Item>, F: FnOnce() -> O>(
+        &mut self,
+        k: &[u8],
+        default: F,
+    ) -> Result<(), Self::Error> {
+        self.inner.or_put_with(k, default)?;
+        self.versions.insert(k.into(), self.version);
+        Ok(())
+    }
+
+    fn put_owned<K: AsRef<[u8]> + Into<Box<[u8]>>>(
+        &mut self,
+        k: K,
+        v: impl Into<Self::Item>,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        let key: Box<[u8]> = k.into();
+        self.versions.insert(key.clone(), self.version);
+        self.inner.put_owned(key, v)
+    }
+
+    fn get<K: AsRef<[u8]> + Clone>(&self, k: K) -> Result<Option<Self::Item>, Self::Error> {
+        self.inner.get(k)
+    }
+
+    #[inline(always)]
+    fn is_gc_supported(&self) -> bool {
+        self.inner.is_gc_supported()
+    }
+
+    #[inline(always)]
+    fn set_gc_enabled(&mut self, gc_enabled: bool) {
+        self.inner.set_gc_enabled(gc_enabled)
+    }
+
+    #[inline(always)]
+    fn gc_enabled(&self) -> bool {
+        self.inner.gc_enabled()
+    }
+
+    fn remove(&mut self, k: &[u8]) -> Result<(), Self::Error> {
+        self.inner.remove(k)?;
+        if self.inner.gc_enabled() {
+            self.versions.remove(k);
+        }
+        Ok(())
+    }
+
+    fn retain<F>(&mut self, mut f: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        let mut removed = Vec::new();
+        self.inner.retain(|k, v| {
+            let keep = f(k, v);
+            if !keep {
+                removed.push(Box::<[u8]>::from(k));
+            }
+            keep
+        })?;
+        if self.inner.gc_enabled() {
+            for k in removed {
+                self.versions.remove(&k);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.inner.for_each_key(f)
+    }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
+    }
 }
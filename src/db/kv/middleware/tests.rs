@@ -0,0 +1,47 @@
+use super::*;
+use crate::db::{kv::HashMapDb, NodeDb};
+use crate::hash::{key_hasher::NoCacheHasher, poseidon::Poseidon};
+use crate::trie::ZkTrie;
+
+#[test]
+fn test_versioned_middleware() {
+    let mut trie_db = NodeDb::new(VersionedMiddleware::new(HashMapDb::new(true)));
+    let mut old = ZkTrie::default();
+    for i in 0..20u8 {
+        old.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1).unwrap();
+    }
+    let old_root = old.commit(&mut trie_db).unwrap();
+
+    trie_db.inner_mut().set_version(1);
+    let mut new = ZkTrie::default();
+    for i in 20..40u8 {
+        new.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1).unwrap();
+    }
+    let new_root = new.commit(&mut trie_db).unwrap();
+    assert_eq!(
+        trie_db.inner().version_of(old_root.as_ref().as_slice()),
+        Some(0)
+    );
+    assert_eq!(
+        trie_db.inner().version_of(new_root.as_ref().as_slice()),
+        Some(1)
+    );
+
+    // keep the old root node only
+    trie_db.inner_mut().set_version(2);
+    assert!(trie_db
+        .inner_mut()
+        .touch(old_root.as_ref().as_slice())
+        .unwrap());
+    let purged = trie_db.inner_mut().purge_older_than(1).unwrap();
+    assert!(purged > 0);
+    assert!(trie_db.committed_root(old_root.hash()).is_ok());
+    assert!(old.get::<_, [[u8; 32]; 1], _>(&trie_db, [1u8; 32]).is_err());
+
+    let new = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, new_root).unwrap();
+    for i in 20..40u8 {
+        let value: Option<[[u8; 32]; 1]> = new.get(&trie_db, [i; 32]).unwrap();
+        assert_eq!(value, Some([[i; 32]]));
+    }
+    assert_eq!(trie_db.inner_mut().purge_older_than(1).unwrap(), 0);
+}
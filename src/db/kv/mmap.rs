@@ -0,0 +1,200 @@
+//! Read-only [`KVDatabase`] over a memory mapped [snapshot](crate::db::snapshot).
+//!
+//! Values returned by [`MmapDb`] are [`Bytes`] slices into the mapping,
+//! so [`NodeViewer`](crate::trie::NodeViewer)s built on top of it never copy node data.
+//! Nodes are looked up in the index of the snapshot, opening reads only its header and trailer.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use zktrie_ng::{
+//!     db::NodeDb,
+//!     hash::{key_hasher::NoCacheHasher, poseidon::Poseidon, ZkHash},
+//!     trie::ZkTrie,
+//! };
+//!
+//! # let root = ZkHash::ZERO;
+//! let db = NodeDb::open_snapshot("nodes.snapshot").unwrap();
+//! let root = db.committed_root(root).unwrap();
+//! let trie = ZkTrie::<Poseidon, NoCacheHasher>::new_with_root(&db, NoCacheHasher, root).unwrap();
+//! ```
+use super::KVDatabase;
+use crate::db::snapshot::{check_alignment, check_record, SnapshotError, SnapshotIndex};
+use crate::db::DbErrorKind;
+use crate::hash::ZkHash;
+use alloy_primitives::bytes::Bytes;
+use std::fmt::Debug;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A read-only key-value store backed by a snapshot buffer, usually a memory mapped file.
+#[derive(Clone)]
+pub struct MmapDb {
+    data: Bytes,
+    index: SnapshotIndex,
+    /// One bit per index entry, set once its node is validated, `None` if unchecked
+    validated: Option<Arc<[AtomicU64]>>,
+}
+
+impl MmapDb {
+    /// Memory map the snapshot file at `path`.
+    ///
+    /// # Note
+    ///
+    /// The file must not be modified while it's mapped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        Self::from_bytes(map_file(path)?)
+    }
+
+    /// Open a snapshot held in `data`, only its header and index framing are read.
+    ///
+    /// `data` must start at an address aligned to
+    /// [`SNAPSHOT_ALIGNMENT`](crate::db::snapshot::SNAPSHOT_ALIGNMENT),
+    /// otherwise [`SnapshotError::Misaligned`] is returned.
+    /// Snapshots without an index, i.e. of version 1, are rejected.
+    ///
+    /// Each archived node is validated on its first read, as nodes are read without checks,
+    /// see [`MmapDb::from_bytes_unchecked`] to skip it.
+    pub fn from_bytes(data: Bytes) -> Result<Self, SnapshotError> {
+        Self::index(data, true)
+    }
+
+    /// Same as [`MmapDb::from_bytes`], but without validating the archived nodes,
+    /// e.g. for snapshots just written by this process.
+    ///
+    /// # Safety
+    ///
+    /// Every record must hold a valid archived node, as written by
+    /// [`SnapshotWriter`](crate::db::snapshot::SnapshotWriter),
+    /// otherwise reading the node is undefined behavior.
+    pub unsafe fn from_bytes_unchecked(data: Bytes) -> Result<Self, SnapshotError> {
+        Self::index(data, false)
+    }
+
+    fn index(data: Bytes, validate: bool) -> Result<Self, SnapshotError> {
+        check_alignment(&data)?;
+        let index = SnapshotIndex::locate(&data)?;
+        let validated = validate.then(|| {
+            std::iter::repeat_with(|| AtomicU64::new(0))
+                .take(index.len().div_ceil(64))
+                .collect()
+        });
+        trace!("opened snapshot of {} nodes", index.len());
+        Ok(Self {
+            data,
+            index,
+            validated,
+        })
+    }
+
+    /// Number of nodes in the snapshot.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Check if the snapshot contains no nodes.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.index.len() == 0
+    }
+
+    /// Validate the node of the `i`-th index entry on its first read.
+    fn check_once(&self, i: usize, node_hash: &[u8], archived: &[u8]) -> Result<(), SnapshotError> {
+        let Some(validated) = self.validated.as_ref() else {
+            return Ok(());
+        };
+        let (word, bit) = (&validated[i / 64], 1 << (i % 64));
+        // the mapping is immutable, a node validated once stays valid
+        if word.load(Ordering::Relaxed) & bit == 0 {
+            check_record(&ZkHash::from_slice(node_hash), archived)?;
+            word.fetch_or(bit, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+impl Debug for MmapDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MmapDb")
+            .field("bytes", &self.data.len())
+            .field("nodes", &self.index.len())
+            .field("validated", &self.validated.is_some())
+            .finish()
+    }
+}
+
+/// Memory map a snapshot file, the file must not be modified while it's mapped.
+pub(crate) fn map_file<P: AsRef<Path>>(path: P) -> std::io::Result<Bytes> {
+    let file = File::open(path)?;
+    // SAFETY: snapshots are immutable once written, see the note of the callers.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    Ok(Bytes::from_owner(map))
+}
+
+impl KVDatabase for MmapDb {
+    type Item = Bytes;
+
+    type Error = SnapshotError;
+
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        match err {
+            SnapshotError::Io(e) => DbErrorKind::from_io(e),
+            SnapshotError::InvalidMagic
+            | SnapshotError::UnsupportedVersion(_)
+            | SnapshotError::Truncated(_)
+            | SnapshotError::NodeCountMismatch { .. }
+            | SnapshotError::SizeMismatch { .. }
+            | SnapshotError::ChecksumMismatch { .. }
+            | SnapshotError::MissingRoot(_)
+            | SnapshotError::InvalidNode(_)
+            | SnapshotError::InvalidIndex
+            | SnapshotError::RecordTooLarge { .. } => DbErrorKind::Corruption,
+            SnapshotError::ReadOnly | SnapshotError::Misaligned => DbErrorKind::Other,
+        }
+    }
+
+    #[inline]
+    fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
+        Ok(k.len() == ZkHash::len_bytes() && self.index.find(&self.data, k).is_some())
+    }
+
+    #[inline]
+    fn put(&mut self, _k: &[u8], _v: &[u8]) -> Result<Option<Self::Item>, Self::Error> {
+        Err(SnapshotError::ReadOnly)
+    }
+
+    #[inline]
+    fn put_owned<K: AsRef<[u8]> + Into<Box<[u8]>>>(
+        &mut self,
+        _k: K,
+        _v: impl Into<Self::Item>,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        Err(SnapshotError::ReadOnly)
+    }
+
+    #[inline]
+    fn get<K: AsRef<[u8]> + Clone>(&self, k: K) -> Result<Option<Self::Item>, Self::Error> {
+        let k = k.as_ref();
+        if k.len() != ZkHash::len_bytes() {
+            return Ok(None);
+        }
+        let Some(i) = self.index.find(&self.data, k) else {
+            return Ok(None);
+        };
+        let range = self.index.record(&self.data, i)?;
+        self.check_once(i, k, &self.data[range.clone()])?;
+        Ok(Some(self.data.slice(range)))
+    }
+
+    #[inline]
+    fn for_each_key<F>(&self, f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.index.keys(&self.data).for_each(f);
+        Ok(true)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::db::DbErrorKind;
 use alloy_primitives::bytes::Bytes;
 
 mod extend;
@@ -8,8 +9,26 @@ pub use btree_map::BTreeMapDb;
 pub mod hash_map;
 pub use hash_map::HashMapDb;
 
+#[cfg(feature = "middleware")]
+#[cfg_attr(docsrs, doc(cfg(feature = "middleware")))]
 pub mod middleware;
 
+pub mod namespaced;
+pub use namespaced::{Namespace, NamespacedDb};
+
+pub mod sharded;
+pub use sharded::ShardedDb;
+
+#[cfg(feature = "mmap")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mmap")))]
+pub mod mmap;
+#[cfg(feature = "mmap")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mmap")))]
+pub use mmap::MmapDb;
+
+pub mod tx;
+pub use tx::TxDb;
+
 #[cfg(feature = "sled")]
 #[cfg_attr(docsrs, doc(cfg(feature = "sled")))]
 pub mod sled;
@@ -42,6 +61,11 @@ pub trait KVDatabase {
     /// Associated error type.
     type Error: std::error::Error + Send + Sync + 'static;
 
+    /// Classify an error of this database, e.g. to decide whether to retry.
+    fn classify_error(_err: &Self::Error) -> DbErrorKind {
+        DbErrorKind::Other
+    }
+
     /// Check if the database contains a key.
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         Ok(self.get(k)?.is_some())
@@ -89,6 +113,12 @@ pub trait KVDatabase {
         false
     }
 
+    /// Check if [`KVDatabase::extend`] writes either all pairs or none,
+    /// e.g. in one batch or transaction.
+    fn is_extend_atomic(&self) -> bool {
+        false
+    }
+
     /// Enable or disable the garbage collection support.
     fn set_gc_enabled(&mut self, _gc_enabled: bool) {}
 
@@ -128,6 +158,17 @@ pub trait KVDatabase {
         Ok(())
     }
 
+    /// Visit every key of the database, in no particular order.
+    ///
+    /// Returns `Ok(false)` without visiting anything if the database can't enumerate its keys,
+    /// unlike [`KVDatabase::retain`] callers can tell the two apart.
+    fn for_each_key<F>(&self, _f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        Ok(false)
+    }
+
     /// Extend the database with the key-value pairs from the iterator.
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -138,6 +179,11 @@ pub trait KVDatabase {
         }
         Ok(())
     }
+
+    /// Persist buffered writes, for databases that buffer them, e.g. [`SledDb`](super::SledDb).
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 impl KVDatabaseItem for Bytes {
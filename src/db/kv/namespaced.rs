@@ -0,0 +1,276 @@
+//! A [`KVDatabase`] whose keys are salted with a namespace id.
+//!
+//! See [`NamespacedNodeDb`](crate::db::NamespacedNodeDb) for sharing one database between tries.
+use crate::db::kv::KVDatabase;
+use crate::db::DbErrorKind;
+use std::fmt::{Debug, Formatter};
+
+#[cfg(test)]
+mod tests;
+
+/// The max length of a [`Namespace`] id.
+pub const MAX_NAMESPACE_LEN: usize = 4;
+
+/// A 1 to [`MAX_NAMESPACE_LEN`] bytes namespace id, prepended to every stored key.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Namespace {
+    id: [u8; MAX_NAMESPACE_LEN],
+    len: u8,
+}
+
+impl Namespace {
+    /// Create a namespace from its id.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the id is empty or longer than [`MAX_NAMESPACE_LEN`] bytes.
+    pub fn new(id: &[u8]) -> Self {
+        assert!(
+            (1..=MAX_NAMESPACE_LEN).contains(&id.len()),
+            "namespace id must be 1 to {MAX_NAMESPACE_LEN} bytes"
+        );
+        let mut bytes = [0u8; MAX_NAMESPACE_LEN];
+        bytes[..id.len()].copy_from_slice(id);
+        Self {
+            id: bytes,
+            len: id.len() as u8,
+        }
+    }
+
+    /// Get the namespace id.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.id[..self.len as usize]
+    }
+
+    #[inline]
+    fn key(&self, k: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(self.len as usize + k.len());
+        key.extend_from_slice(self.as_bytes());
+        key.extend_from_slice(k);
+        key
+    }
+}
+
+impl From<u8> for Namespace {
+    fn from(id: u8) -> Self {
+        Self::new(&[id])
+    }
+}
+
+impl From<u32> for Namespace {
+    fn from(id: u32) -> Self {
+        Self::new(&id.to_be_bytes())
+    }
+}
+
+impl Debug for Namespace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Namespace(0x{})", hex::encode(self.as_bytes()))
+    }
+}
+
+/// A key-value store prepending a [`Namespace`] to every key,
+/// so stores sharing the same inner database (e.g. handles of an `Arc<RwLock<Db>>`)
+/// are logically isolated.
+///
+/// The namespace is only part of the stored keys, node hashes are unchanged.
+/// All namespaces of a database should have the same length,
+/// otherwise a namespace may be a prefix of another one.
+pub struct NamespacedDb<Db> {
+    inner: Db,
+    namespace: Namespace,
+}
+
+impl<Db: KVDatabase> NamespacedDb<Db> {
+    /// Create a new `NamespacedDb` over the given database.
+    pub fn new(inner: Db, namespace: impl Into<Namespace>) -> Self {
+        Self {
+            inner,
+            namespace: namespace.into(),
+        }
+    }
+
+    /// Get the namespace.
+    #[inline]
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+
+    /// Get the inner database.
+    #[inline]
+    pub fn inner(&self) -> &Db {
+        &self.inner
+    }
+
+    /// Into the inner database.
+    pub fn into_inner(self) -> Db {
+        self.inner
+    }
+
+    /// Enumerate the keys of the namespace, without the namespace id.
+    ///
+    /// Keys are visited with [`KVDatabase::retain`], so backends that don't support it
+    /// return nothing.
+    pub fn keys(&mut self) -> Result<Vec<Box<[u8]>>, Db::Error> {
+        let namespace = self.namespace;
+        let mut keys = Vec::new();
+        self.inner.retain(|k, _| {
+            if let Some(k) = k.strip_prefix(namespace.as_bytes()) {
+                keys.push(k.into());
+            }
+            true
+        })?;
+        Ok(keys)
+    }
+
+    /// Remove every key of the namespace, returns the number of removed entries.
+    ///
+    /// Entries are removed with [`KVDatabase::retain`], so backends that don't support it
+    /// remove nothing.
+    pub fn drop_namespace(&mut self) -> Result<usize, Db::Error> {
+        let namespace = self.namespace;
+        let mut removed = 0;
+        self.inner.retain(|k, _| {
+            let keep = !k.starts_with(namespace.as_bytes());
+            removed += !keep as usize;
+            keep
+        })?;
+        trace!(namespace = ?namespace, "dropped {removed} entries");
+        Ok(removed)
+    }
+}
+
+impl<Db: Clone> Clone for NamespacedDb<Db> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            namespace: self.namespace,
+        }
+    }
+}
+
+impl<Db: Debug> Debug for NamespacedDb<Db> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NamespacedDb")
+            .field("inner", &self.inner)
+            .field("namespace", &self.namespace)
+            .finish()
+    }
+}
+
+impl<Db: KVDatabase> KVDatabase for NamespacedDb<Db> {
+    type Item = Db::Item;
+    type Error = Db::Error;
+
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
+    #[inline(always)]
+    fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
+        self.inner.contains_key(&self.namespace.key(k))
+    }
+
+    #[inline(always)]
+    fn put(&mut self, k: &[u8], v: &[u8]) -> Result<Option<Self::Item>, Self::Error> {
+        self.inner.put(&self.namespace.key(k), v)
+    }
+
+    #[inline(always)]
+    fn or_put(&mut self, k: &[u8], v: &[u8]) -> Result<(), Self::Error> {
+        self.inner.or_put(&self.namespace.key(k), v)
+    }
+
+    #[inline(always)]
+    fn or_put_with<O: Into<Self::Item>, F: FnOnce() -> O>(
+        &mut self,
+        k: &[u8],
+        default: F,
+    ) -> Result<(), Self::Error> {
+        self.inner.or_put_with(&self.namespace.key(k), default)
+    }
+
+    #[inline(always)]
+    fn put_owned<K: AsRef<[u8]> + Into<Box<[u8]>>>(
+        &mut self,
+        k: K,
+        v: impl Into<Self::Item>,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        self.inner.put_owned(self.namespace.key(k.as_ref()), v)
+    }
+
+    #[inline(always)]
+    fn get<K: AsRef<[u8]> + Clone>(&self, k: K) -> Result<Option<Self::Item>, Self::Error> {
+        self.inner.get(self.namespace.key(k.as_ref()))
+    }
+
+    #[inline(always)]
+    fn is_gc_supported(&self) -> bool {
+        self.inner.is_gc_supported()
+    }
+
+    #[inline(always)]
+    fn is_extend_atomic(&self) -> bool {
+        self.inner.is_extend_atomic()
+    }
+
+    #[inline(always)]
+    fn set_gc_enabled(&mut self, gc_enabled: bool) {
+        self.inner.set_gc_enabled(gc_enabled)
+    }
+
+    #[inline(always)]
+    fn gc_enabled(&self) -> bool {
+        self.inner.gc_enabled()
+    }
+
+    #[inline(always)]
+    fn remove(&mut self, k: &[u8]) -> Result<(), Self::Error> {
+        self.inner.remove(&self.namespace.key(k))
+    }
+
+    /// Retain within the namespace, keys of other namespaces are kept.
+    fn retain<F>(&mut self, mut f: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        let namespace = self.namespace;
+        self.inner
+            .retain(|k, v| match k.strip_prefix(namespace.as_bytes()) {
+                Some(k) => f(k, v),
+                None => true,
+            })
+    }
+
+    /// Visit the keys within the namespace.
+    fn for_each_key<F>(&self, mut f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        let namespace = self.namespace;
+        self.inner.for_each_key(|k| {
+            if let Some(k) = k.strip_prefix(namespace.as_bytes()) {
+                f(k)
+            }
+        })
+    }
+
+    fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
+        &mut self,
+        other: T,
+    ) -> Result<(), Self::Error> {
+        let namespace = self.namespace;
+        self.inner.extend(
+            other
+                .into_iter()
+                .map(|(k, v)| (namespace.key(&k).into_boxed_slice(), v)),
+        )
+    }
+
+    #[inline(always)]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
+    }
+}
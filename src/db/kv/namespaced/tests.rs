@@ -0,0 +1,39 @@
+use super::*;
+use crate::db::{kv::HashMapDb, NamespacedNodeDb, NodeDb};
+use crate::hash::{key_hasher::NoCacheHasher, poseidon::Poseidon};
+use crate::trie::ZkTrie;
+use std::sync::{Arc, RwLock};
+
+#[test]
+fn test_namespaced_node_db() {
+    let shared = Arc::new(RwLock::new(HashMapDb::new(true)));
+    let mut dbs: Vec<NamespacedNodeDb<_>> = [1u8, 2u8]
+        .into_iter()
+        .map(|ns| NodeDb::new(NamespacedDb::new(Arc::clone(&shared), ns)))
+        .collect();
+    assert_eq!(dbs[1].namespace(), Namespace::new(&[2]));
+
+    let mut roots = Vec::new();
+    for (i, db) in dbs.iter_mut().enumerate() {
+        let mut trie = ZkTrie::default();
+        for j in 0..10u8 {
+            trie.raw_update(db, [j; 32], vec![[j; 32]], 1).unwrap();
+        }
+        if i == 1 {
+            trie.raw_update(db, [10u8; 32], vec![[1u8; 32]], 1).unwrap();
+        }
+        roots.push(trie.commit(db).unwrap());
+    }
+    assert_ne!(roots[0], roots[1]);
+    let keys = dbs[0].inner_mut().keys().unwrap();
+    let other_keys = dbs[1].inner_mut().keys().unwrap();
+    // the common leaves are stored in both namespaces, under the same hashes
+    assert!(keys.iter().filter(|k| other_keys.contains(k)).count() >= 10);
+    let total = shared.read().unwrap().inner().len();
+    assert_eq!(total, keys.len() + other_keys.len());
+
+    assert_eq!(dbs[0].drop_namespace().unwrap(), keys.len());
+    assert!(dbs[0].committed_root(roots[0].hash()).is_err());
+    let trie = ZkTrie::<Poseidon>::new_with_root(&dbs[1], NoCacheHasher, roots[1]).unwrap();
+    assert_eq!(trie.leafs(&dbs[1]).count(), 11);
+}
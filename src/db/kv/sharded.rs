@@ -0,0 +1,176 @@
+//! A [`KVDatabase`] split into shards, each behind its own lock.
+//!
+//! See [`SyncNodeDb`](crate::db::SyncNodeDb) for sharing one database between threads.
+use crate::db::kv::KVDatabase;
+use crate::db::DbErrorKind;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, RwLock};
+
+/// A key-value store whose keys are routed to shards by their last byte,
+/// so concurrent readers and writers only contend on the same shard.
+///
+/// The last byte is used because node hashes are big endian field elements,
+/// whose leading bytes are not uniformly distributed.
+///
+/// Shards can be either independent stores, or handles to the same store
+/// (e.g. clones of a [`SledDb`](crate::db::kv::SledDb)), where the locks only limit contention.
+/// Clones of a `ShardedDb` share the same shards.
+pub struct ShardedDb<Db> {
+    shards: Arc<[RwLock<Db>]>,
+}
+
+impl<Db: KVDatabase> ShardedDb<Db> {
+    /// Create a new `ShardedDb` over the given shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no shard is given.
+    pub fn new(shards: impl IntoIterator<Item = Db>) -> Self {
+        let shards: Arc<[RwLock<Db>]> = shards.into_iter().map(RwLock::new).collect();
+        assert!(!shards.is_empty(), "at least one shard is required");
+        Self { shards }
+    }
+
+    /// Create `n` shards by cloning `db`, for stores whose clones share storage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn cloned(db: Db, n: usize) -> Self
+    where
+        Db: Clone,
+    {
+        Self::new(std::iter::repeat(db).take(n))
+    }
+
+    /// Number of shards.
+    #[inline]
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    #[inline(always)]
+    fn shard(&self, k: &[u8]) -> &RwLock<Db> {
+        let byte = k.last().copied().unwrap_or_default() as usize;
+        &self.shards[byte % self.shards.len()]
+    }
+}
+
+impl<Db> Clone for ShardedDb<Db> {
+    fn clone(&self) -> Self {
+        Self {
+            shards: Arc::clone(&self.shards),
+        }
+    }
+}
+
+impl<Db> Debug for ShardedDb<Db> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShardedDb")
+            .field("shards", &self.shards.len())
+            .finish()
+    }
+}
+
+impl<Db: KVDatabase> KVDatabase for ShardedDb<Db> {
+    type Item = Db::Item;
+    type Error = Db::Error;
+
+    #[inline(always)]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        Db::classify_error(err)
+    }
+
+    #[inline(always)]
+    fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
+        self.shard(k).read().unwrap().contains_key(k)
+    }
+
+    #[inline(always)]
+    fn put(&mut self, k: &[u8], v: &[u8]) -> Result<Option<Self::Item>, Self::Error> {
+        self.shard(k).write().unwrap().put(k, v)
+    }
+
+    #[inline(always)]
+    fn or_put(&mut self, k: &[u8], v: &[u8]) -> Result<(), Self::Error> {
+        self.shard(k).write().unwrap().or_put(k, v)
+    }
+
+    #[inline(always)]
+    fn or_put_with<O: Into<Self::Item>, F: FnOnce() -> O>(
+        &mut self,
+        k: &[u8],
+        default: F,
+    ) -> Result<(), Self::Error> {
+        self.shard(k).write().unwrap().or_put_with(k, default)
+    }
+
+    #[inline(always)]
+    fn put_owned<K: AsRef<[u8]> + Into<Box<[u8]>>>(
+        &mut self,
+        k: K,
+        v: impl Into<Self::Item>,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        self.shard(k.as_ref()).write().unwrap().put_owned(k, v)
+    }
+
+    #[inline(always)]
+    fn get<K: AsRef<[u8]> + Clone>(&self, k: K) -> Result<Option<Self::Item>, Self::Error> {
+        self.shard(k.as_ref()).read().unwrap().get(k)
+    }
+
+    #[inline(always)]
+    fn is_gc_supported(&self) -> bool {
+        self.shards[0].read().unwrap().is_gc_supported()
+    }
+
+    fn set_gc_enabled(&mut self, gc_enabled: bool) {
+        for shard in self.shards.iter() {
+            shard.write().unwrap().set_gc_enabled(gc_enabled)
+        }
+    }
+
+    #[inline(always)]
+    fn gc_enabled(&self) -> bool {
+        self.shards[0].read().unwrap().gc_enabled()
+    }
+
+    #[inline(always)]
+    fn remove(&mut self, k: &[u8]) -> Result<(), Self::Error> {
+        self.shard(k).write().unwrap().remove(k)
+    }
+
+    /// Retain on every shard, holding all locks.
+    ///
+    /// Shards sharing storage each visit every key.
+    fn retain<F>(&mut self, mut f: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        let mut shards: Vec<_> = self.shards.iter().map(|s| s.write().unwrap()).collect();
+        for shard in shards.iter_mut() {
+            shard.retain(&mut f)?;
+        }
+        Ok(())
+    }
+
+    /// Shards sharing storage each visit every key.
+    fn for_each_key<F>(&self, mut f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        for shard in self.shards.iter() {
+            if !shard.read().unwrap().for_each_key(&mut f)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        for shard in self.shards.iter() {
+            shard.write().unwrap().flush()?;
+        }
+        Ok(())
+    }
+}
@@ -27,7 +27,7 @@
 //! ```
 
 use super::KVDatabase;
-use crate::db::KVDatabaseItem;
+use crate::db::{DbErrorKind, KVDatabaseItem};
 use alloy_primitives::bytes::Bytes;
 use sled::{Batch, IVec};
 
@@ -77,6 +77,15 @@ impl KVDatabase for SledDb {
 
     type Error = sled::Error;
 
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        match err {
+            sled::Error::CollectionNotFound(_) => DbErrorKind::NotFound,
+            sled::Error::Io(e) => DbErrorKind::from_io(e),
+            sled::Error::Corruption { .. } => DbErrorKind::Corruption,
+            sled::Error::Unsupported(_) | sled::Error::ReportableBug(_) => DbErrorKind::Other,
+        }
+    }
+
     #[inline]
     fn contains_key(&self, k: &[u8]) -> Result<bool, Self::Error> {
         self.db.contains_key(k)
@@ -106,6 +115,11 @@ impl KVDatabase for SledDb {
         true
     }
 
+    #[inline]
+    fn is_extend_atomic(&self) -> bool {
+        true
+    }
+
     #[inline]
     fn set_gc_enabled(&mut self, gc_enabled: bool) {
         self.gc_enabled = gc_enabled;
@@ -144,6 +158,17 @@ impl KVDatabase for SledDb {
         self.db.apply_batch(batch)
     }
 
+    #[inline]
+    fn for_each_key<F>(&self, mut f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        for k in self.db.iter().keys() {
+            f(k?.as_ref());
+        }
+        Ok(true)
+    }
+
     #[inline]
     fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
         &mut self,
@@ -155,4 +180,9 @@ impl KVDatabase for SledDb {
         }
         self.db.apply_batch(batch)
     }
+
+    #[inline]
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.db.flush().map(drop)
+    }
 }
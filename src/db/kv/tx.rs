@@ -0,0 +1,224 @@
+//! [`KVDatabase`] adapter over transactional stores, e.g. reth's mdbx `Database`.
+//!
+//! The store only needs to open read and write transactions,
+//! implementing [`TxDatabase`], [`ReadTx`] and [`WriteTx`] is a few lines
+//! over a dedicated table of the node's database:
+//!
+//! ```rust,ignore
+//! tables! {
+//!     table ZkTrieNodes<Key = Vec<u8>, Value = Vec<u8>>;
+//! }
+//!
+//! impl<K: TransactionKind> ReadTx for Tx<K> {
+//!     type Error = DatabaseError;
+//!
+//!     fn get(&self, k: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+//!         DbTx::get::<ZkTrieNodes>(self, k.to_vec())
+//!     }
+//! }
+//!
+//! impl WriteTx for Tx<RW> {
+//!     fn put(&mut self, k: &[u8], v: &[u8]) -> Result<(), Self::Error> {
+//!         DbTxMut::put::<ZkTrieNodes>(self, k.to_vec(), v.to_vec())
+//!     }
+//!
+//!     fn delete(&mut self, k: &[u8]) -> Result<(), Self::Error> {
+//!         DbTxMut::delete::<ZkTrieNodes>(self, k.to_vec(), None).map(drop)
+//!     }
+//!
+//!     fn commit(self) -> Result<(), Self::Error> {
+//!         DbTx::commit(self).map(drop)
+//!     }
+//! }
+//!
+//! struct RethDb(DatabaseEnv);
+//!
+//! impl TxDatabase for RethDb {
+//!     type Error = DatabaseError;
+//!     type Tx = <DatabaseEnv as Database>::TX;
+//!     type TxMut = <DatabaseEnv as Database>::TXMut;
+//!
+//!     fn tx(&self) -> Result<Self::Tx, Self::Error> {
+//!         self.0.tx()
+//!     }
+//!
+//!     fn tx_mut(&self) -> Result<Self::TxMut, Self::Error> {
+//!         self.0.tx_mut()
+//!     }
+//! }
+//! ```
+//!
+//! Every read opens a read transaction, every write a write transaction committed right away,
+//! except [`KVDatabase::extend`] which writes all pairs in one transaction.
+use super::KVDatabase;
+use crate::db::DbErrorKind;
+use alloy_primitives::bytes::Bytes;
+use std::fmt::{Debug, Formatter};
+
+#[cfg(test)]
+mod tests;
+
+/// A store opening read and write transactions.
+pub trait TxDatabase {
+    /// Error of the store and its transactions.
+    type Error: std::error::Error + Send + Sync + 'static;
+    /// Read transaction
+    type Tx: ReadTx<Error = Self::Error>;
+    /// Write transaction
+    type TxMut: WriteTx<Error = Self::Error>;
+
+    /// Classify an error of the store, see [`KVDatabase::classify_error`].
+    fn classify_error(_err: &Self::Error) -> DbErrorKind {
+        DbErrorKind::Other
+    }
+
+    /// Open a read transaction.
+    fn tx(&self) -> Result<Self::Tx, Self::Error>;
+
+    /// Open a write transaction.
+    fn tx_mut(&self) -> Result<Self::TxMut, Self::Error>;
+}
+
+/// A read transaction of a [`TxDatabase`].
+pub trait ReadTx {
+    /// Error of the transaction.
+    type Error;
+
+    /// Retrieve the value associated with a key.
+    fn get(&self, k: &[u8]) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// Visit every key, e.g. with a cursor over the table.
+    ///
+    /// Returns `Ok(false)` if the store can't enumerate its keys, see [`KVDatabase::for_each_key`].
+    fn for_each_key(&self, _f: &mut dyn FnMut(&[u8])) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+}
+
+/// A write transaction of a [`TxDatabase`], discarded if dropped without [`WriteTx::commit`].
+pub trait WriteTx: ReadTx {
+    /// Insert a key-value pair.
+    fn put(&mut self, k: &[u8], v: &[u8]) -> Result<(), Self::Error>;
+
+    /// Remove a key-value pair, if present.
+    fn delete(&mut self, k: &[u8]) -> Result<(), Self::Error>;
+
+    /// Commit the transaction.
+    fn commit(self) -> Result<(), Self::Error>;
+}
+
+/// A key-value store backed by a [`TxDatabase`].
+#[derive(Clone)]
+pub struct TxDb<D> {
+    gc_enabled: bool,
+    db: D,
+}
+
+impl<D: TxDatabase> TxDb<D> {
+    /// Create a new `TxDb` wrapping the given store.
+    pub fn new(gc_enabled: bool, db: D) -> Self {
+        Self { gc_enabled, db }
+    }
+
+    /// Get the inner store
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    /// Into the inner store
+    pub fn into_inner(self) -> D {
+        self.db
+    }
+}
+
+impl<D> Debug for TxDb<D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TxDb")
+            .field("gc_enabled", &self.gc_enabled)
+            .finish()
+    }
+}
+
+impl<D: TxDatabase> KVDatabase for TxDb<D> {
+    type Item = Bytes;
+
+    type Error = D::Error;
+
+    #[inline]
+    fn classify_error(err: &Self::Error) -> DbErrorKind {
+        D::classify_error(err)
+    }
+
+    fn put(&mut self, k: &[u8], v: &[u8]) -> Result<Option<Self::Item>, Self::Error> {
+        let mut tx = self.db.tx_mut()?;
+        let old = tx.get(k)?;
+        tx.put(k, v)?;
+        tx.commit()?;
+        Ok(old.map(Bytes::from))
+    }
+
+    fn put_owned<K: AsRef<[u8]> + Into<Box<[u8]>>>(
+        &mut self,
+        k: K,
+        v: impl Into<Self::Item>,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        self.put(k.as_ref(), v.into().as_ref())
+    }
+
+    fn get<K: AsRef<[u8]> + Clone>(&self, k: K) -> Result<Option<Self::Item>, Self::Error> {
+        Ok(self.db.tx()?.get(k.as_ref())?.map(Bytes::from))
+    }
+
+    fn for_each_key<F>(&self, mut f: F) -> Result<bool, Self::Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.db.tx()?.for_each_key(&mut f)
+    }
+
+    #[inline]
+    fn is_gc_supported(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn is_extend_atomic(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn set_gc_enabled(&mut self, gc_enabled: bool) {
+        self.gc_enabled = gc_enabled;
+    }
+
+    #[inline]
+    fn gc_enabled(&self) -> bool {
+        self.gc_enabled
+    }
+
+    fn remove(&mut self, k: &[u8]) -> Result<(), Self::Error> {
+        if self.gc_enabled {
+            let mut tx = self.db.tx_mut()?;
+            tx.delete(k)?;
+            tx.commit()?;
+        } else {
+            warn!("garbage collection is disabled, remove is ignored");
+        }
+        Ok(())
+    }
+
+    fn extend<T: IntoIterator<Item = (Box<[u8]>, Self::Item)>>(
+        &mut self,
+        other: T,
+    ) -> Result<(), Self::Error> {
+        let mut tx = self.db.tx_mut()?;
+        let mut written = 0;
+        for (k, v) in other {
+            tx.put(&k, &v)?;
+            written += 1;
+        }
+        tx.commit()?;
+        trace!("{} key-value pairs written in one transaction", written);
+        Ok(())
+    }
+}
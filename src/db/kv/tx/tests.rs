@@ -0,0 +1,121 @@
+use super::*;
+use crate::db::{NodeDb, NodeDbError, NodeEncoding};
+use crate::hash::{key_hasher::NoCacheHasher, poseidon::Poseidon};
+use crate::trie::ZkTrie;
+use rand::random;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn test_tx_db() {
+    type Store = Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>;
+
+    /// Writes are staged until commit
+    #[derive(Clone, Default)]
+    struct MemStore {
+        data: Store,
+        commits: Arc<Mutex<usize>>,
+    }
+
+    struct MemTx {
+        data: Store,
+        staged: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        commits: Arc<Mutex<usize>>,
+    }
+
+    impl ReadTx for MemTx {
+        type Error = Infallible;
+
+        fn get(&self, k: &[u8]) -> Result<Option<Vec<u8>>, Infallible> {
+            Ok(match self.staged.get(k) {
+                Some(v) => v.clone(),
+                None => self.data.lock().unwrap().get(k).cloned(),
+            })
+        }
+    }
+
+    impl WriteTx for MemTx {
+        fn put(&mut self, k: &[u8], v: &[u8]) -> Result<(), Infallible> {
+            self.staged.insert(k.to_vec(), Some(v.to_vec()));
+            Ok(())
+        }
+
+        fn delete(&mut self, k: &[u8]) -> Result<(), Infallible> {
+            self.staged.insert(k.to_vec(), None);
+            Ok(())
+        }
+
+        fn commit(self) -> Result<(), Infallible> {
+            let mut data = self.data.lock().unwrap();
+            for (k, v) in self.staged {
+                match v {
+                    Some(v) => data.insert(k, v),
+                    None => data.remove(&k),
+                };
+            }
+            *self.commits.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    impl TxDatabase for MemStore {
+        type Error = Infallible;
+        type Tx = MemTx;
+        type TxMut = MemTx;
+
+        fn tx(&self) -> Result<MemTx, Infallible> {
+            self.tx_mut()
+        }
+
+        fn tx_mut(&self) -> Result<MemTx, Infallible> {
+            Ok(MemTx {
+                data: self.data.clone(),
+                staged: BTreeMap::new(),
+                commits: self.commits.clone(),
+            })
+        }
+    }
+
+    let store = MemStore::default();
+    let mut trie_db = NodeDb::new(TxDb::new(true, store.clone()));
+    let mut trie = ZkTrie::default();
+    let mut expected = ZkTrie::default();
+    let mut expected_db = NodeDb::default();
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+        expected.raw_update(&expected_db, k, vec![*k], 1).unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap();
+    assert_eq!(root, expected.commit(&mut expected_db).unwrap());
+    assert!(*store.commits.lock().unwrap() > 0);
+
+    let reopened = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+    for k in keys.iter() {
+        assert_eq!(
+            reopened.get::<_, [[u8; 32]; 1], _>(&trie_db, k).unwrap(),
+            Some([*k])
+        );
+    }
+
+    // a store that can't enumerate its keys is not converted, nor marked as converted
+    assert!(matches!(
+        trie_db.compact_to_canonical::<Poseidon>(),
+        Err(NodeDbError::KeysNotEnumerable)
+    ));
+    assert_eq!(trie_db.node_encoding(), NodeEncoding::Archived);
+    assert!(!trie_db
+        .inner()
+        .contains_key(b"zktrie-ng:node-encoding")
+        .unwrap());
+
+    let mut kv = TxDb::new(true, store.clone());
+    let commits = *store.commits.lock().unwrap();
+    kv.extend((0..10u8).map(|i| (vec![i].into_boxed_slice(), vec![i].into())))
+        .unwrap();
+    assert_eq!(*store.commits.lock().unwrap(), commits + 1);
+    assert_eq!(kv.put(&[1], &[2]).unwrap().as_deref(), Some(&[1u8][..]));
+    kv.remove(&[1]).unwrap();
+    assert!(!kv.contains_key(&[1]).unwrap());
+}
@@ -4,16 +4,207 @@
 //! helper types and functions for working with databases.
 
 use crate::db::kv::{HashMapDb, KVDatabase, KVDatabaseItem};
-use crate::hash::{HashScheme, ZkHash};
-use crate::trie::{Node, NodeKind, NodeViewer};
+use crate::hash::{HashScheme, ZkHash, HASH_SIZE};
+use crate::trie::{ArchivedNode, CommittedRoot, Node, NodeArena, NodeKind, NodeViewer};
+use alloy_primitives::bytes::Bytes;
+use rkyv::util::AlignedVec;
 use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
 
 /// key-value databases
 pub mod kv;
 
+pub mod filter;
+use filter::LeafKeyFilter;
+
+pub mod histogram;
+use histogram::DepthHistogram;
+
+mod error;
+pub use error::{DbError, DbErrorKind};
+
+pub mod snapshot;
+
+pub mod delta;
+
+pub mod canonical;
+use canonical::CanonicalCache;
+
+pub mod pin;
+use pin::PinnedRoots;
+
+mod versions;
+
+#[cfg(feature = "heal")]
+#[cfg_attr(docsrs, doc(cfg(feature = "heal")))]
+pub mod heal;
+
+/// The key under which the leaf key filter is persisted.
+///
+/// Its length differs from [`HASH_SIZE`], so it never collides with a node hash.
+const LEAF_FILTER_KEY: &[u8] = b"zktrie-ng:leaf-key-filter";
+
+/// The key under which the depth histogram is persisted, see [`NodeDb::with_depth_histogram`].
+const DEPTH_HISTOGRAM_KEY: &[u8] = b"zktrie-ng:depth-histogram";
+
+/// The key under which the [`HashScheme::ID`] is persisted, see [`NodeDb::open`].
+const HASH_SCHEME_KEY: &[u8] = b"zktrie-ng:hash-scheme";
+
+/// The key under which the [`NodeEncoding`] is persisted, absent for [`NodeEncoding::Archived`].
+const NODE_ENCODING_KEY: &[u8] = b"zktrie-ng:node-encoding";
+const CANONICAL_ENCODING: &[u8] = b"canonical";
+const ARCHIVED_ENCODING: &[u8] = b"archived";
+/// Persisted while [`NodeDb::convert_encoding`] writes the nodes
+const CONVERTING_ENCODING: &[u8] = b"converting";
+
+/// Number of nodes rewritten at once by [`NodeDb::convert_encoding`].
+pub const CONVERT_CHUNK_SIZE: usize = 4096;
+
+/// How nodes are stored in the backend database, see [`NodeDb::convert_encoding`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NodeEncoding {
+    /// rkyv archived nodes, which are read without copying
+    #[default]
+    Archived,
+    /// The [canonical bytes](Node::canonical_value) of nodes, smaller but parsed on every read
+    Canonical,
+}
+
+/// The space used by nodes before and after [`NodeDb::convert_encoding`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EncodingReport {
+    /// Number of converted nodes
+    pub nodes: usize,
+    /// Total bytes of the nodes before conversion
+    pub bytes_before: usize,
+    /// Total bytes of the nodes after conversion
+    pub bytes_after: usize,
+}
+
+impl EncodingReport {
+    /// Bytes saved by the conversion, negative if the nodes grew.
+    #[inline]
+    pub fn saved(&self) -> i64 {
+        self.bytes_before as i64 - self.bytes_after as i64
+    }
+}
+
+/// What garbage collection does when it can't run, see [`NodeDb::with_gc_policy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GcPolicy {
+    /// Skip silently
+    Ignore,
+    /// Log a warning and skip
+    #[default]
+    Warn,
+    /// Fail with the [reason](GcSkipped)
+    Error,
+}
+
+/// Why garbage collection couldn't run, see [`GcPolicy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum GcSkipped {
+    /// The backend database doesn't support removing nodes
+    #[error("backend database does not support garbage collection")]
+    Unsupported,
+    /// Garbage collection is disabled, see [`NodeDb::set_gc_enabled`]
+    #[error("garbage collection is disabled")]
+    Disabled,
+    /// The trie has uncommitted changes
+    #[error("dirty nodes found, commit before garbage collection")]
+    Dirty,
+}
+
+/// Errors when putting untrusted archived node bytes, see [`NodeDb::put_archived_node`].
+#[derive(Debug, thiserror::Error)]
+pub enum ArchivedNodeError<HashErr, DbErr> {
+    /// Error when accessing the database
+    #[error("Database error: {0}")]
+    Db(DbErr),
+    /// The bytes are not a valid archived node
+    #[error("Invalid archived node: {0}")]
+    Invalid(rkyv::rancor::Error),
+    /// A branch node has an invalid node type
+    #[error("Invalid branch node type {0}")]
+    InvalidNodeType(u8),
+    /// Error when hashing
+    #[error(transparent)]
+    Hash(HashErr),
+    /// The node, or a hash cached in the archived bytes, doesn't match its content
+    #[error("Node hash mismatch, expected {expected}, computed {actual}")]
+    HashMismatch {
+        /// The claimed hash
+        expected: ZkHash,
+        /// The hash computed from the node content
+        actual: ZkHash,
+    },
+}
+
+/// Errors that can occur when opening a [`NodeDb`].
+#[derive(Debug, thiserror::Error)]
+pub enum NodeDbError<DbErr> {
+    /// Error when accessing the database
+    #[error("Database error: {0}")]
+    Db(#[from] DbError<DbErr>),
+    /// The database was written with another hash scheme
+    #[error("Database written with hash scheme {found}, expected {expected}")]
+    HashSchemeMismatch {
+        /// The hash scheme of the caller
+        expected: &'static str,
+        /// The hash scheme persisted in the database
+        found: String,
+    },
+    /// A root or a node reachable from it is missing,
+    /// see [`NodeDb::committed_root`] and [`NodeDb::full_gc`]
+    #[error("Node {0} not found")]
+    NodeNotFound(ZkHash),
+    /// Error when accessing the temporary purge store, see [`NodeDb::full_gc`]
+    #[error("Purge store error: {0}")]
+    PurgeStore(Box<dyn std::error::Error + Send + Sync>),
+    /// Garbage collection couldn't run, see [`GcPolicy::Error`]
+    #[error("Garbage collection skipped: {0}")]
+    GcSkipped(#[from] GcSkipped),
+    /// A stored node can't be decoded
+    #[error("Node {0} is corrupted")]
+    CorruptedNode(ZkHash),
+    /// The persisted node encoding is unknown
+    #[error("Unknown node encoding {0}")]
+    UnknownEncoding(String),
+    /// A [`NodeDb::convert_encoding`] was interrupted, the nodes are in mixed encodings
+    #[error("Node encoding conversion interrupted")]
+    ConversionInterrupted,
+    /// The backend database can't enumerate its keys, see [`KVDatabase::for_each_key`]
+    #[error("Database keys can't be enumerated")]
+    KeysNotEnumerable,
+}
+
+/// A [`NodeDb`] whose handles share the same backend database, see [`NodeDb::handle`].
+pub type SharedNodeDb<KvDb> = NodeDb<Arc<RwLock<KvDb>>>;
+
+/// A [`NodeDb`] that can be shared by tries on several threads, see [`ShardedDb`](kv::ShardedDb).
+///
+/// Each thread works on its own [handle](NodeDb::handle),
+/// reads and writes only lock the shard of the node.
+pub type SyncNodeDb<KvDb> = NodeDb<kv::ShardedDb<KvDb>>;
+
+/// A [`NodeDb`] isolated in a namespace of a backend database shared with other tries,
+/// see [`NamespacedDb`](kv::NamespacedDb).
+///
+/// Nodes shared by tries of different namespaces are stored once per namespace.
+pub type NamespacedNodeDb<KvDb> = NodeDb<kv::NamespacedDb<KvDb>>;
+
 /// A wrapper to store a trie node in the database.
 pub struct NodeDb<KvDb> {
     db: KvDb,
+    leaf_filter: Option<LeafKeyFilter>,
+    depth_histogram: Option<DepthHistogram>,
+    hash_scheme: Option<&'static str>,
+    encoding: NodeEncoding,
+    gc_policy: GcPolicy,
+    canonical_cache: Option<CanonicalCache>,
+    pins: PinnedRoots,
+    #[cfg(feature = "heal")]
+    healer: Option<heal::Healer>,
 }
 
 impl Default for NodeDb<HashMapDb> {
@@ -22,11 +213,315 @@ impl Default for NodeDb<HashMapDb> {
     }
 }
 
+#[cfg(feature = "mmap")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mmap")))]
+impl NodeDb<kv::MmapDb> {
+    /// Open a read-only `NodeDb` over a memory mapped snapshot file.
+    ///
+    /// See also [`ZkTrie::export_snapshot`](crate::trie::ZkTrie::export_snapshot).
+    pub fn open_snapshot<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> Result<Self, snapshot::SnapshotError> {
+        Ok(Self::new(kv::MmapDb::open(path)?))
+    }
+}
+
 impl<KvDb: KVDatabase> NodeDb<KvDb> {
     /// Create a new `NodeDb` with the given database.
-    #[inline]
+    ///
+    /// Nodes are read in [`NodeEncoding::Archived`], use [`NodeDb::try_new`]
+    /// for a database that may have been converted by [`NodeDb::convert_encoding`].
     pub fn new(db: KvDb) -> Self {
-        Self { db }
+        Self {
+            db,
+            leaf_filter: None,
+            depth_histogram: None,
+            hash_scheme: None,
+            encoding: NodeEncoding::Archived,
+            gc_policy: GcPolicy::default(),
+            canonical_cache: None,
+            pins: PinnedRoots::default(),
+            #[cfg(feature = "heal")]
+            healer: None,
+        }
+    }
+
+    /// Same as [`NodeDb::new`], but reads the [`NodeEncoding`] persisted by
+    /// [`NodeDb::convert_encoding`].
+    ///
+    /// Returns [`NodeDbError::ConversionInterrupted`] if a [`NodeDb::convert_encoding`]
+    /// was interrupted.
+    pub fn try_new(db: KvDb) -> Result<Self, NodeDbError<KvDb::Error>> {
+        let marker = db
+            .get(NODE_ENCODING_KEY)
+            .map_err(DbError::classify::<KvDb>)?;
+        let encoding = match marker.as_ref().map(|marker| marker.as_ref()) {
+            None | Some(ARCHIVED_ENCODING) => NodeEncoding::Archived,
+            Some(CANONICAL_ENCODING) => NodeEncoding::Canonical,
+            Some(CONVERTING_ENCODING) => return Err(NodeDbError::ConversionInterrupted),
+            Some(unknown) => {
+                return Err(NodeDbError::UnknownEncoding(
+                    String::from_utf8_lossy(unknown).into_owned(),
+                ))
+            }
+        };
+        let mut this = Self::new(db);
+        this.encoding = encoding;
+        Ok(this)
+    }
+
+    /// Open a `NodeDb` for tries using the hash scheme `H`.
+    ///
+    /// The [`HashScheme::ID`] is persisted on the first open,
+    /// later opens with another hash scheme fail instead of producing garbage roots.
+    /// Tries check the hash scheme of the `NodeDb` they're used with, see [`NodeDb::hash_scheme`].
+    pub fn open<H: HashScheme>(mut db: KvDb) -> Result<Self, NodeDbError<KvDb::Error>> {
+        match db.get(HASH_SCHEME_KEY).map_err(DbError::classify::<KvDb>)? {
+            Some(found) if found.as_ref() != H::ID.as_bytes() => {
+                return Err(NodeDbError::HashSchemeMismatch {
+                    expected: H::ID,
+                    found: String::from_utf8_lossy(found.as_ref()).into_owned(),
+                });
+            }
+            Some(_) => {}
+            None => {
+                db.put(HASH_SCHEME_KEY, H::ID.as_bytes())
+                    .map_err(DbError::classify::<KvDb>)?;
+            }
+        }
+        let mut this = Self::try_new(db)?;
+        this.hash_scheme = Some(H::ID);
+        Ok(this)
+    }
+
+    /// Get the encoding of the stored nodes.
+    #[inline]
+    pub fn node_encoding(&self) -> NodeEncoding {
+        self.encoding
+    }
+
+    /// Rewrite all stored nodes into the [canonical encoding](NodeEncoding::Canonical),
+    /// see [`NodeDb::convert_encoding`].
+    #[inline]
+    pub fn compact_to_canonical<H: HashScheme>(
+        &mut self,
+    ) -> Result<EncodingReport, NodeDbError<KvDb::Error>> {
+        self.convert_encoding::<H>(NodeEncoding::Canonical)
+    }
+
+    /// Rewrite all stored nodes into `encoding` in place, and persist the new encoding.
+    ///
+    /// # Note
+    ///
+    /// Nodes are enumerated with [`KVDatabase::for_each_key`], backends that can't enumerate
+    /// their keys fail with [`NodeDbError::KeysNotEnumerable`] before anything is written.
+    /// Canonical nodes are checked before anything is written too.
+    ///
+    /// Nodes are rewritten in chunks of [`CONVERT_CHUNK_SIZE`], between a marker persisted first
+    /// and the new encoding persisted last, so [`NodeDb::try_new`] refuses a database
+    /// left in mixed encodings by an interrupted conversion.
+    pub fn convert_encoding<H: HashScheme>(
+        &mut self,
+        encoding: NodeEncoding,
+    ) -> Result<EncodingReport, NodeDbError<KvDb::Error>> {
+        let mut report = EncodingReport::default();
+        if encoding == self.encoding {
+            return Ok(report);
+        }
+        let mut node_hashes = Vec::new();
+        let enumerated = self
+            .db
+            .for_each_key(|k| {
+                if k.len() == HASH_SIZE {
+                    node_hashes.push(ZkHash::from_slice(k));
+                }
+            })
+            .map_err(DbError::classify::<KvDb>)?;
+        if !enumerated {
+            return Err(NodeDbError::KeysNotEnumerable);
+        }
+        // shards sharing storage visit every key once per shard
+        node_hashes.sort_unstable();
+        node_hashes.dedup();
+
+        if self.encoding == NodeEncoding::Canonical {
+            for node_hash in node_hashes.iter() {
+                if let Some(bytes) = self.db.get(node_hash).map_err(DbError::classify::<KvDb>)? {
+                    if parse_canonical::<H>(node_hash, bytes.as_ref()).is_none() {
+                        return Err(NodeDbError::CorruptedNode(*node_hash));
+                    }
+                }
+            }
+        }
+
+        self.db
+            .put(NODE_ENCODING_KEY, CONVERTING_ENCODING)
+            .map_err(DbError::classify::<KvDb>)?;
+        for chunk in node_hashes.chunks(CONVERT_CHUNK_SIZE) {
+            let mut converted = Vec::with_capacity(chunk.len());
+            for node_hash in chunk {
+                let Some(v) = self.db.get(node_hash).map_err(DbError::classify::<KvDb>)? else {
+                    continue;
+                };
+                let v = v.as_ref();
+                let bytes = match self.encoding {
+                    NodeEncoding::Archived => {
+                        let aligned = aligned_copy(v);
+                        // SAFETY: nodes are archived in this encoding
+                        unsafe { rkyv::access_unchecked::<ArchivedNode>(&aligned) }
+                            .canonical_value(true)
+                    }
+                    NodeEncoding::Canonical => parse_canonical::<H>(node_hash, v)
+                        .ok_or(NodeDbError::CorruptedNode(*node_hash))?
+                        .archived()
+                        .to_vec(),
+                };
+                report.nodes += 1;
+                report.bytes_before += v.len();
+                report.bytes_after += bytes.len();
+                converted.push((Box::<[u8]>::from(node_hash.as_slice()), bytes.into()));
+            }
+            self.db
+                .extend(converted)
+                .map_err(DbError::classify::<KvDb>)?;
+        }
+        let marker = match encoding {
+            NodeEncoding::Archived => ARCHIVED_ENCODING,
+            NodeEncoding::Canonical => CANONICAL_ENCODING,
+        };
+        self.db
+            .put(NODE_ENCODING_KEY, marker)
+            .map_err(DbError::classify::<KvDb>)?;
+        self.encoding = encoding;
+        trace!(report = ?report, "converted node encoding");
+        Ok(report)
+    }
+
+    /// Get the hash scheme checked by [`NodeDb::open`], if any.
+    #[inline]
+    pub fn hash_scheme(&self) -> Option<&'static str> {
+        self.hash_scheme
+    }
+
+    /// Attach a [`LeafKeyFilter`] to the database.
+    ///
+    /// Every leaf stored afterwards is recorded into the filter,
+    /// and tries consult it to short-circuit lookups of absent keys.
+    ///
+    /// # Note
+    ///
+    /// The filter must cover all leaves already in the database,
+    /// e.g. start from an empty database, or a filter loaded via [`NodeDb::load_leaf_filter`].
+    pub fn with_leaf_filter(mut self, filter: LeafKeyFilter) -> Self {
+        self.leaf_filter = Some(filter);
+        self
+    }
+
+    /// Set or remove the leaf key filter.
+    ///
+    /// See also [`NodeDb::with_leaf_filter`].
+    pub fn set_leaf_filter(&mut self, filter: Option<LeafKeyFilter>) {
+        self.leaf_filter = filter;
+    }
+
+    /// Get the leaf key filter, if any.
+    #[inline]
+    pub fn leaf_filter(&self) -> Option<&LeafKeyFilter> {
+        self.leaf_filter.as_ref()
+    }
+
+    /// Check if a leaf with `node_key` may be stored in the database.
+    ///
+    /// Always `true` if no filter is attached.
+    #[inline]
+    pub fn may_contain_leaf(&self, node_key: &ZkHash) -> bool {
+        self.leaf_filter
+            .as_ref()
+            .map(|filter| filter.may_contain(node_key))
+            .unwrap_or(true)
+    }
+
+    /// Persist the leaf key filter into the backend database.
+    pub fn persist_leaf_filter(&mut self) -> Result<(), KvDb::Error> {
+        if let Some(filter) = self.leaf_filter.as_ref() {
+            self.db.put_owned(LEAF_FILTER_KEY, filter.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Load the leaf key filter persisted by [`NodeDb::persist_leaf_filter`].
+    ///
+    /// Returns `true` if a filter was found and attached.
+    pub fn load_leaf_filter(&mut self) -> Result<bool, KvDb::Error> {
+        let filter = self
+            .db
+            .get(LEAF_FILTER_KEY)?
+            .and_then(|bytes| LeafKeyFilter::from_bytes(bytes.as_ref()));
+        let found = filter.is_some();
+        if found {
+            self.leaf_filter = filter;
+        } else {
+            warn!("no valid leaf key filter found in database");
+        }
+        Ok(found)
+    }
+
+    /// Track the per-depth node occupancy of the tries committed into the database.
+    ///
+    /// Every commit updates the histogram by walking only the changed nodes, and persists it.
+    /// Tries sharing the database all count into the same histogram.
+    ///
+    /// # Note
+    ///
+    /// The histogram must cover the tries already in the database, e.g. start from an empty one,
+    /// a histogram computed by [`ZkTrie::compute_depth_histogram`](crate::trie::ZkTrie::compute_depth_histogram),
+    /// or one loaded via [`NodeDb::load_depth_histogram`].
+    pub fn with_depth_histogram(mut self, histogram: DepthHistogram) -> Self {
+        self.depth_histogram = Some(histogram);
+        self
+    }
+
+    /// Set or remove the depth histogram.
+    ///
+    /// See also [`NodeDb::with_depth_histogram`].
+    pub fn set_depth_histogram(&mut self, histogram: Option<DepthHistogram>) {
+        self.depth_histogram = histogram;
+    }
+
+    /// Get the per-depth node occupancy, if tracked.
+    #[inline]
+    pub fn depth_histogram(&self) -> Option<&DepthHistogram> {
+        self.depth_histogram.as_ref()
+    }
+
+    /// Update the depth histogram on commit, and persist it.
+    pub(crate) fn update_depth_histogram(
+        &mut self,
+        update: impl FnOnce(&mut DepthHistogram),
+    ) -> Result<(), KvDb::Error> {
+        if let Some(histogram) = self.depth_histogram.as_mut() {
+            update(histogram);
+            self.db
+                .put_owned(DEPTH_HISTOGRAM_KEY, histogram.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Load the depth histogram persisted by the last commit.
+    ///
+    /// Returns `true` if a histogram was found and attached.
+    pub fn load_depth_histogram(&mut self) -> Result<bool, KvDb::Error> {
+        let histogram = self
+            .db
+            .get(DEPTH_HISTOGRAM_KEY)?
+            .and_then(|bytes| DepthHistogram::from_bytes(bytes.as_ref()));
+        let found = histogram.is_some();
+        if found {
+            self.depth_histogram = histogram;
+        } else {
+            warn!("no valid depth histogram found in database");
+        }
+        Ok(found)
     }
 
     /// Get inner db
@@ -34,6 +529,13 @@ impl<KvDb: KVDatabase> NodeDb<KvDb> {
         &self.db
     }
 
+    /// Get inner db mutably, e.g. to configure a middleware.
+    ///
+    /// Nodes written directly are not added to the leaf key filter.
+    pub fn inner_mut(&mut self) -> &mut KvDb {
+        &mut self.db
+    }
+
     /// Into inner db
     pub fn into_inner(self) -> KvDb {
         self.db
@@ -57,41 +559,331 @@ impl<KvDb: KVDatabase> NodeDb<KvDb> {
         self.db.gc_enabled()
     }
 
+    /// Set what garbage collection, of the database or of a trie, does when it can't run,
+    /// e.g. [`GcPolicy::Error`] so orchestration can react to a misconfigured backend.
+    pub fn with_gc_policy(mut self, policy: GcPolicy) -> Self {
+        self.gc_policy = policy;
+        self
+    }
+
+    /// Get the garbage collection policy.
+    #[inline]
+    pub fn gc_policy(&self) -> GcPolicy {
+        self.gc_policy
+    }
+
+    /// Apply the garbage collection policy to a skipped collection.
+    pub(crate) fn skip_gc(&self, reason: GcSkipped) -> Result<(), GcSkipped> {
+        match self.gc_policy {
+            GcPolicy::Ignore => {
+                trace!("garbage collection skipped: {reason}");
+                Ok(())
+            }
+            GcPolicy::Warn => {
+                warn!("garbage collection skipped: {reason}");
+                Ok(())
+            }
+            GcPolicy::Error => Err(reason),
+        }
+    }
+
+    /// Why garbage collection can't remove nodes, if it can't.
+    pub(crate) fn gc_blocker(&self) -> Option<GcSkipped> {
+        if !self.is_gc_supported() {
+            Some(GcSkipped::Unsupported)
+        } else if !self.gc_enabled() {
+            Some(GcSkipped::Disabled)
+        } else {
+            None
+        }
+    }
+
     /// Put a node into the database.
     pub fn put_node<H: HashScheme>(&mut self, node: Node<H>) -> Result<(), KvDb::Error> {
+        #[cfg(feature = "heal")]
+        self.persist_healed()?;
         let node_hash = *node.node_hash.get().expect("Node hash not calculated");
         if let NodeKind::Branch(branch) = node.data.as_ref() {
             if !branch.child_right().is_resolved() || !branch.child_left().is_resolved() {
                 panic!("Cannot archive branch node with unresolved child hash");
             }
         }
-        let bytes = node.archived();
-        self.db.put(node_hash.as_ref(), bytes.as_ref())?;
+        if let (Some(filter), Some(leaf)) = (self.leaf_filter.as_mut(), node.as_leaf()) {
+            filter.insert(&leaf.node_key());
+        }
+        match self.encoding {
+            NodeEncoding::Archived => {
+                let bytes = node.archived();
+                self.db.put(node_hash.as_ref(), bytes.as_ref())?;
+            }
+            NodeEncoding::Canonical => {
+                self.db.put_owned(node_hash.0, node.canonical_value(true))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Put the nodes of an arena into the database.
+    ///
+    /// Archived nodes are stored as slices of the arena, backends keeping the values as
+    /// [`Bytes`], e.g. [`HashMapDb`], free the arena once all its nodes are removed.
+    pub fn put_arena(&mut self, arena: &NodeArena) -> Result<(), KvDb::Error> {
+        #[cfg(feature = "heal")]
+        self.persist_healed()?;
+        for viewer in arena.viewers() {
+            let node = viewer.view();
+            if let (Some(filter), Some(leaf)) = (self.leaf_filter.as_mut(), node.as_leaf()) {
+                filter.insert(&leaf.node_key());
+            }
+            match self.encoding {
+                NodeEncoding::Archived => {
+                    let bytes = KvDb::Item::from_bytes(viewer.data.clone());
+                    self.db.put_owned(viewer.node_hash.0, bytes)?;
+                }
+                NodeEncoding::Canonical => {
+                    self.db
+                        .put_owned(viewer.node_hash.0, node.canonical_value(true))?;
+                }
+            }
+        }
         Ok(())
     }
 
-    /// Put a archived node bytes into the database.
+    /// Same as [`NodeDb::put_arena`], but in one [`KVDatabase::extend`],
+    /// which writes either all nodes or none if the backend [supports it](KVDatabase::is_extend_atomic).
+    pub fn put_arena_atomic(&mut self, arena: &NodeArena) -> Result<(), KvDb::Error> {
+        #[cfg(feature = "heal")]
+        self.persist_healed()?;
+        let mut pairs = Vec::with_capacity(arena.len());
+        for viewer in arena.viewers() {
+            let node = viewer.view();
+            if let (Some(filter), Some(leaf)) = (self.leaf_filter.as_mut(), node.as_leaf()) {
+                filter.insert(&leaf.node_key());
+            }
+            let bytes = match self.encoding {
+                NodeEncoding::Archived => KvDb::Item::from_bytes(viewer.data.clone()),
+                NodeEncoding::Canonical => node.canonical_value(true).into(),
+            };
+            pairs.push((Box::<[u8]>::from(viewer.node_hash.as_slice()), bytes));
+        }
+        self.db.extend(pairs)
+    }
+
+    /// Put untrusted archived node bytes into the database,
+    /// e.g. nodes received from a peer.
+    ///
+    /// The rkyv layout is validated and the node hash recomputed from the node content,
+    /// as well as the hashes cached in the archived bytes, before anything is written.
+    /// The bytes don't need to be aligned, they're copied into an aligned buffer first.
+    pub fn put_archived_node<H: HashScheme>(
+        &mut self,
+        node_hash: ZkHash,
+        bytes: impl AsRef<[u8]>,
+    ) -> Result<(), ArchivedNodeError<H::Error, KvDb::Error>> {
+        let aligned = aligned_copy(bytes.as_ref());
+        let node = rkyv::access::<ArchivedNode, rkyv::rancor::Error>(&aligned)
+            .map_err(ArchivedNodeError::Invalid)?;
+        if let Some(branch) = node.as_branch() {
+            branch
+                .try_node_type()
+                .map_err(ArchivedNodeError::InvalidNodeType)?;
+        }
+        let check = |expected: ZkHash, actual: ZkHash| {
+            if expected == actual {
+                Ok(())
+            } else {
+                Err(ArchivedNodeError::HashMismatch { expected, actual })
+            }
+        };
+        if let Some(leaf) = node.as_leaf() {
+            if let Some(value_hash) = leaf.value_hash() {
+                let actual = leaf
+                    .calc_value_hash::<H>()
+                    .map_err(ArchivedNodeError::Hash)?;
+                check(value_hash, actual)?;
+            }
+        }
+        let actual = node
+            .calculate_node_hash::<H>()
+            .map_err(ArchivedNodeError::Hash)?;
+        check(node_hash, actual)?;
+        if let Some(cached) = node.cached_node_hash() {
+            check(cached, actual)?;
+        }
+        self.put_aligned_node(node_hash, aligned)
+            .map_err(ArchivedNodeError::Db)
+    }
+
+    /// Put archived node bytes into the database without any check,
+    /// for trusted bulk imports only, see [`NodeDb::put_archived_node`].
+    ///
+    /// The bytes don't need to be aligned, they're copied into an aligned buffer first.
     ///
     /// # Safety
     ///
-    /// The bytes must be valid rkyv archived `Node` bytes and the hash must be the hash of the node.
+    /// The bytes must be valid rkyv archived `Node` bytes, as produced by [`Node::archived`],
+    /// otherwise reading the node is undefined behavior.
+    /// The hash must be the hash of the node, and the hashes cached in the archived bytes
+    /// must match the node content, otherwise the tries reading the node are silently corrupted.
     pub unsafe fn put_archived_node_unchecked(
         &mut self,
         node_hash: ZkHash,
-        bytes: Vec<u8>,
+        bytes: impl AsRef<[u8]>,
+    ) -> Result<(), KvDb::Error> {
+        self.put_aligned_node(node_hash, aligned_copy(bytes.as_ref()))
+    }
+
+    /// Put archived node bytes already validated, or trusted, by the caller.
+    fn put_aligned_node(
+        &mut self,
+        node_hash: ZkHash,
+        aligned: AlignedVec<16>,
     ) -> Result<(), KvDb::Error> {
-        self.db.put_owned(node_hash.0, bytes)?;
+        // SAFETY: the buffer is aligned, and the callers validated its content
+        // or have the caller guarantee it
+        let node = unsafe { rkyv::access_unchecked::<ArchivedNode>(&aligned) };
+        if let Some(filter) = self.leaf_filter.as_mut() {
+            if let Some(leaf) = node.as_leaf() {
+                filter.insert(&leaf.node_key());
+            }
+        }
+        match self.encoding {
+            NodeEncoding::Archived => self.db.put_owned(
+                node_hash.0,
+                KvDb::Item::from_bytes(Bytes::from_owner(aligned)),
+            )?,
+            NodeEncoding::Canonical => {
+                self.db.put_owned(node_hash.0, node.canonical_value(true))?
+            }
+        };
         Ok(())
     }
 
     /// Get a node from the database.
-    pub fn get_node<H>(&self, hash: &ZkHash) -> Result<Option<NodeViewer>, KvDb::Error> {
-        Ok(self.db.get(hash)?.map(|b| NodeViewer {
-            data: b.into_bytes(),
+    ///
+    /// With the `heal` feature, a missing node is fetched by the attached
+    /// [resolver](NodeDb::with_resolver).
+    ///
+    /// Returns [`NodeDbError::CorruptedNode`] if a [canonically encoded](NodeEncoding::Canonical)
+    /// node can't be parsed.
+    pub fn get_node<H: HashScheme>(
+        &self,
+        hash: &ZkHash,
+    ) -> Result<Option<NodeViewer>, NodeDbError<KvDb::Error>> {
+        let data = match self.db.get(hash).map_err(DbError::classify::<KvDb>)? {
+            Some(b) => match self.encoding {
+                NodeEncoding::Archived => b.into_bytes(),
+                NodeEncoding::Canonical => {
+                    let node = parse_canonical::<H>(hash, b.as_ref())
+                        .ok_or(NodeDbError::CorruptedNode(*hash))?;
+                    Bytes::from(node.archived().to_vec())
+                }
+            },
+            #[cfg(feature = "heal")]
+            None => match self
+                .healer
+                .as_ref()
+                .and_then(|healer| healer.heal::<H>(hash))
+            {
+                Some(data) => data,
+                None => return Ok(None),
+            },
+            #[cfg(not(feature = "heal"))]
+            None => return Ok(None),
+        };
+        Ok(Some(NodeViewer {
+            data,
             node_hash: *hash,
         }))
     }
 
+    /// Check that the root node of a trie is persisted, so the trie can be opened with
+    /// [`ZkTrie::new_with_root`](crate::trie::ZkTrie::new_with_root).
+    ///
+    /// The empty root is always committed.
+    pub fn committed_root(&self, root: ZkHash) -> Result<CommittedRoot, NodeDbError<KvDb::Error>> {
+        if root.is_zero()
+            || self
+                .db
+                .contains_key(root.as_slice())
+                .map_err(DbError::classify::<KvDb>)?
+        {
+            Ok(CommittedRoot::new_unchecked(root))
+        } else {
+            Err(NodeDbError::NodeNotFound(root))
+        }
+    }
+
+    /// Remove every node not reachable from any of `roots`.
+    ///
+    /// All live roots must be given, e.g. the account trie root, every storage root it refers to,
+    /// and the roots of retained history. The hashes of the reachable nodes are marked
+    /// in `tmp_purge_store` before sweeping, shared subtrees are only visited once.
+    ///
+    /// Nothing is removed if a reachable node is missing.
+    /// The [pinned roots](NodeDb::pin) are live too.
+    /// Returns the number of removed nodes.
+    ///
+    /// # Notes
+    ///
+    /// This method will enable the gc support regardless of the current state.
+    pub fn full_gc<H: HashScheme, T: KVDatabase>(
+        &mut self,
+        roots: &[ZkHash],
+        mut tmp_purge_store: T,
+    ) -> Result<usize, NodeDbError<KvDb::Error>> {
+        if !self.is_gc_supported() {
+            self.skip_gc(GcSkipped::Unsupported)?;
+            return Ok(0);
+        }
+
+        let pins = self.pins();
+        let pins = pins.lock();
+
+        // mark
+        let mut stack = roots.to_vec();
+        stack.extend(pins.roots());
+        while let Some(node_hash) = stack.pop() {
+            if node_hash.is_zero()
+                || tmp_purge_store
+                    .contains_key(node_hash.as_slice())
+                    .map_err(|e| NodeDbError::PurgeStore(Box::new(e)))?
+            {
+                continue;
+            }
+            let node = self
+                .get_node::<H>(&node_hash)?
+                .ok_or(NodeDbError::NodeNotFound(node_hash))?;
+            if let Some(branch) = node.view().as_branch() {
+                stack.push(*branch.child_left().unwrap_ref());
+                stack.push(*branch.child_right().unwrap_ref());
+            }
+            tmp_purge_store
+                .put(node_hash.as_slice(), &[])
+                .map_err(|e| NodeDbError::PurgeStore(Box::new(e)))?;
+        }
+
+        // sweep
+        let gc_enabled = self.gc_enabled();
+        self.set_gc_enabled(true);
+        let mut removed = 0;
+        let result = self.retain(|node_hash| match tmp_purge_store.get(node_hash) {
+            Ok(Some(_)) => true,
+            Ok(None) => {
+                removed += 1;
+                false
+            }
+            Err(e) => {
+                error!("Failed to check node in purge store: {}", e);
+                true
+            }
+        });
+        self.set_gc_enabled(gc_enabled);
+        result.map_err(DbError::classify::<KvDb>)?;
+        trace!("full garbage collection done, removed {removed} nodes");
+        Ok(removed)
+    }
+
     /// Removes a node from the database.
     ///
     /// # Note
@@ -103,6 +895,8 @@ impl<KvDb: KVDatabase> NodeDb<KvDb> {
 
     /// Retain only the nodes that satisfy the predicate.
     ///
+    /// Entries that are not nodes (e.g. the persisted leaf key filter) are always kept.
+    ///
     /// # Note
     ///
     /// See also [`KVDatabase::retain`].
@@ -110,13 +904,134 @@ impl<KvDb: KVDatabase> NodeDb<KvDb> {
     where
         F: FnMut(&ZkHash) -> bool,
     {
-        self.db.retain(|k, _| f(&ZkHash::from_slice(k)))
+        self.db
+            .retain(|k, _| k.len() != HASH_SIZE || f(&ZkHash::from_slice(k)))
+    }
+}
+
+impl<KvDb: KVDatabase> NodeDb<KvDb> {
+    /// Move the backend database behind a lock, so that it can be shared by several handles.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a leaf key filter or a depth histogram is attached,
+    /// since they can't be kept in sync across handles.
+    pub fn into_shared(self) -> SharedNodeDb<KvDb> {
+        assert!(
+            self.leaf_filter.is_none(),
+            "cannot share a NodeDb with a leaf key filter"
+        );
+        assert!(
+            self.depth_histogram.is_none(),
+            "cannot share a NodeDb with a depth histogram"
+        );
+        NodeDb {
+            db: Arc::new(RwLock::new(self.db)),
+            leaf_filter: None,
+            depth_histogram: None,
+            hash_scheme: self.hash_scheme,
+            encoding: self.encoding,
+            gc_policy: self.gc_policy,
+            canonical_cache: self.canonical_cache.clone(),
+            pins: self.pins.clone(),
+            #[cfg(feature = "heal")]
+            healer: self.healer.clone(),
+        }
+    }
+}
+
+impl<KvDb: KVDatabase> SharedNodeDb<KvDb> {
+    /// Create another handle to the same backend database.
+    ///
+    /// Each handle can be written through independently,
+    /// e.g. a storage trie can be committed into its own handle
+    /// while the account trie still borrows another one.
+    /// The lock is only held for the duration of a single read or write.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a leaf key filter is attached, since it can't be kept in sync across handles.
+    pub fn handle(&self) -> Self {
+        assert!(
+            self.leaf_filter.is_none(),
+            "cannot share a NodeDb with a leaf key filter"
+        );
+        NodeDb {
+            db: Arc::clone(&self.db),
+            leaf_filter: None,
+            depth_histogram: None,
+            hash_scheme: self.hash_scheme,
+            encoding: self.encoding,
+            gc_policy: self.gc_policy,
+            canonical_cache: self.canonical_cache.clone(),
+            pins: self.pins.clone(),
+            #[cfg(feature = "heal")]
+            healer: self.healer.clone(),
+        }
+    }
+}
+
+impl<KvDb: KVDatabase> SyncNodeDb<KvDb> {
+    /// Create another handle to the same shards, see [`SharedNodeDb::handle`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if a leaf key filter is attached, since it can't be kept in sync across handles.
+    pub fn handle(&self) -> Self {
+        assert!(
+            self.leaf_filter.is_none(),
+            "cannot share a NodeDb with a leaf key filter"
+        );
+        NodeDb {
+            db: self.db.clone(),
+            leaf_filter: None,
+            depth_histogram: None,
+            hash_scheme: self.hash_scheme,
+            encoding: self.encoding,
+            gc_policy: self.gc_policy,
+            canonical_cache: self.canonical_cache.clone(),
+            pins: self.pins.clone(),
+            #[cfg(feature = "heal")]
+            healer: self.healer.clone(),
+        }
+    }
+}
+
+impl<KvDb: KVDatabase> NamespacedNodeDb<KvDb> {
+    /// Get the namespace of the stored keys.
+    #[inline]
+    pub fn namespace(&self) -> kv::Namespace {
+        self.db.namespace()
+    }
+
+    /// Remove every node and metadata of the namespace,
+    /// see [`NamespacedDb::drop_namespace`](kv::NamespacedDb::drop_namespace).
+    ///
+    /// Returns the number of removed entries.
+    pub fn drop_namespace(&mut self) -> Result<usize, KvDb::Error> {
+        let removed = self.db.drop_namespace()?;
+        if let Some(filter) = self.leaf_filter.as_mut() {
+            filter.clear();
+        }
+        Ok(removed)
     }
 }
 
 impl<KvDb: Debug> Debug for NodeDb<KvDb> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("NodeDb").field("db", &self.db).finish()
+        let mut debug = f.debug_struct("NodeDb");
+        debug
+            .field("db", &self.db)
+            .field("leaf_filter", &self.leaf_filter.is_some())
+            .field("depth_histogram", &self.depth_histogram.is_some())
+            .field("hash_scheme", &self.hash_scheme)
+            .field("encoding", &self.encoding)
+            .field("gc_policy", &self.gc_policy)
+            .field("canonical_cache", &self.canonical_cache)
+            .field("pins", &self.pins);
+        #[cfg(feature = "heal")]
+        debug.field("healer", &self.healer);
+        debug.finish()
     }
 }
 
@@ -124,6 +1039,32 @@ impl<KvDb: Clone> Clone for NodeDb<KvDb> {
     fn clone(&self) -> Self {
         Self {
             db: self.db.clone(),
+            leaf_filter: self.leaf_filter.clone(),
+            depth_histogram: self.depth_histogram.clone(),
+            hash_scheme: self.hash_scheme,
+            encoding: self.encoding,
+            gc_policy: self.gc_policy,
+            canonical_cache: self.canonical_cache.clone(),
+            pins: self.pins.clone(),
+            #[cfg(feature = "heal")]
+            healer: self.healer.clone(),
         }
     }
 }
+
+/// Copy bytes into a buffer aligned for an [`ArchivedNode`].
+fn aligned_copy(bytes: &[u8]) -> AlignedVec<16> {
+    let mut aligned = AlignedVec::<16>::with_capacity(bytes.len());
+    aligned.extend_from_slice(bytes);
+    aligned
+}
+
+/// Parse a canonically encoded node, whose node hash is known.
+///
+/// Returns `None` if the bytes are corrupted.
+fn parse_canonical<H: HashScheme>(node_hash: &ZkHash, bytes: &[u8]) -> Option<Node<H>> {
+    let node = Node::<H>::try_from(bytes).ok()?;
+    // SAFETY: nodes are stored under their node hash
+    unsafe { node.set_node_hash(*node_hash) };
+    Some(node)
+}
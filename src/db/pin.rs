@@ -0,0 +1,146 @@
+//! Roots protected from garbage collection, see [`NodeDb::pin`].
+//!
+//! Proof generation and witness export read a committed root node by node,
+//! while another thread may garbage collect the nodes a later commit replaced.
+//! Pinning the root first keeps its nodes in the database until it's unpinned:
+//!
+//! - [`ZkTrie::gc`](crate::trie::ZkTrie::gc) and
+//!   [`ZkTrie::gc_incremental`](crate::trie::ZkTrie::gc_incremental) keep the retired nodes
+//!   reachable from a pinned root, they're removed by the first collection after the unpin.
+//! - [`NodeDb::full_gc`] marks the pinned roots as live.
+//!
+//! A collection holds the pins for its whole run, so pinning waits for it to finish.
+//! The nodes reachable from a pinned root are walked by the first collection after the pin
+//! and cached until it's unpinned, so later collections only look up their candidates.
+use super::{kv::KVDatabase, DbError, NodeDb, NodeDbError};
+use crate::hash::{HashScheme, ZkHash};
+use crate::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Pins shared by the handles of a database.
+#[derive(Clone, Default)]
+pub(crate) struct PinnedRoots(Arc<Mutex<Pins>>);
+
+/// Pin counts by root, and the nodes reachable from each pinned root once walked.
+#[derive(Default)]
+pub(crate) struct Pins {
+    counts: HashMap<ZkHash, usize>,
+    reachable: HashMap<ZkHash, HashSet<ZkHash>>,
+}
+
+impl Debug for PinnedRoots {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.lock().roots()).finish()
+    }
+}
+
+impl PinnedRoots {
+    /// Lock the pins, no root can be pinned until the guard is dropped.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Pins> {
+        self.0.lock().unwrap()
+    }
+}
+
+impl Pins {
+    /// Get the pinned roots.
+    pub(crate) fn roots(&self) -> impl Iterator<Item = &ZkHash> {
+        self.counts.keys()
+    }
+
+    /// Get the number of pinned roots.
+    pub(crate) fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Check if `node_hash` is reachable from a pinned root,
+    /// after [`NodeDb::walk_pinned`] cached the reachable nodes.
+    pub(crate) fn contains(&self, node_hash: &ZkHash) -> bool {
+        self.reachable
+            .values()
+            .any(|nodes| nodes.contains(node_hash))
+    }
+}
+
+impl<KvDb: KVDatabase> NodeDb<KvDb> {
+    /// Protect the nodes reachable from `root` from garbage collection until
+    /// [unpinned](NodeDb::unpin), see the [module docs](self).
+    ///
+    /// Pins are counted, a root pinned twice must be unpinned twice.
+    /// Handles of a shared database share the pins.
+    ///
+    /// Returns [`NodeDbError::NodeNotFound`] if the root was already collected.
+    pub fn pin(&self, root: ZkHash) -> Result<(), NodeDbError<KvDb::Error>> {
+        let mut pins = self.pins.lock();
+        let pins = &mut pins.counts;
+        // checked under the lock, so no collection runs in between
+        if !root.is_zero()
+            && !self
+                .db
+                .contains_key(root.as_slice())
+                .map_err(DbError::classify::<KvDb>)?
+        {
+            return Err(NodeDbError::NodeNotFound(root));
+        }
+        *pins.entry(root).or_default() += 1;
+        trace!("pinned root {root}");
+        Ok(())
+    }
+
+    /// Release a pin of `root`, returns `false` if it wasn't pinned.
+    pub fn unpin(&self, root: ZkHash) -> bool {
+        let mut pins = self.pins.lock();
+        let Some(count) = pins.counts.get_mut(&root) else {
+            return false;
+        };
+        *count -= 1;
+        if *count == 0 {
+            pins.counts.remove(&root);
+            pins.reachable.remove(&root);
+        }
+        trace!("unpinned root {root}");
+        true
+    }
+
+    /// Get the pinned roots.
+    pub fn pinned_roots(&self) -> Vec<ZkHash> {
+        self.pins.lock().roots().copied().collect()
+    }
+
+    /// Walk the pinned roots not walked since they were pinned, while holding the `pins`,
+    /// see [`Pins::contains`].
+    pub(crate) fn walk_pinned<H: HashScheme>(
+        &self,
+        pins: &mut Pins,
+    ) -> Result<(), NodeDbError<KvDb::Error>> {
+        let Pins { counts, reachable } = pins;
+        for root in counts.keys() {
+            if reachable.contains_key(root) {
+                continue;
+            }
+            let mut nodes = HashSet::new();
+            let mut stack = vec![*root];
+            while let Some(node_hash) = stack.pop() {
+                if node_hash.is_zero() || !nodes.insert(node_hash) {
+                    continue;
+                }
+                let node = self
+                    .get_node::<H>(&node_hash)?
+                    .ok_or(NodeDbError::NodeNotFound(node_hash))?;
+                if let Some(branch) = node.view().as_branch() {
+                    stack.push(*branch.child_left().unwrap_ref());
+                    stack.push(*branch.child_right().unwrap_ref());
+                }
+            }
+            trace!(nodes = nodes.len(), "walked pinned root {root}");
+            reachable.insert(*root, nodes);
+        }
+        Ok(())
+    }
+
+    /// Get the pins, to hold them while collecting.
+    #[inline]
+    pub(crate) fn pins(&self) -> PinnedRoots {
+        self.pins.clone()
+    }
+}
@@ -0,0 +1,616 @@
+//! A flat file format for exported trie nodes.
+//!
+//! A snapshot is a 16-byte header followed by node records and an index record:
+//!
+//! ```text
+//! header: magic (8 bytes) | version (u32 le) | reserved (4 bytes)
+//! record: node hash (32 bytes) | length (u32 le) | reserved (12 bytes) | archived node | padding
+//! index:  zero hash (32 bytes) | length (u32 le) | reserved (12 bytes) | entries | trailer
+//! entry:  node hash (32 bytes) | record offset (u64 le) | reserved (8 bytes)
+//! trailer: node count (u64 le) | index magic (8 bytes)
+//! ```
+//!
+//! Archived node bytes always start at a 16-byte aligned offset,
+//! so a snapshot mapped at an aligned address can be viewed without copying.
+//! The index entries are sorted by node hash and the index ends the snapshot,
+//! so a mapped snapshot is searched in place, see [`MmapDb`](crate::db::kv::MmapDb).
+//! Version 1 snapshots have no index.
+//!
+//! A [`SnapshotManifest`] records the root, size and keccak checksum of a snapshot,
+//! so a backup can be checked with [`verify_backup`] before it is restored.
+use crate::hash::{ZkHash, HASH_SIZE};
+use crate::trie::ArchivedNode;
+use alloy_primitives::{bytes::Bytes, Keccak256, B256};
+use rkyv::util::AlignedVec;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+#[cfg(test)]
+mod tests;
+
+/// The magic bytes at the start of a snapshot.
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"ZKTRIENG";
+
+/// The current snapshot format version.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// The first snapshot format version, without an index.
+const SNAPSHOT_VERSION_UNINDEXED: u32 = 1;
+
+/// Alignment of archived node bytes inside a snapshot.
+pub const SNAPSHOT_ALIGNMENT: usize = 16;
+
+/// The maximum size of the archived node of a record, far above the largest node.
+pub const MAX_RECORD_SIZE: usize = 1 << 16;
+
+const HEADER_SIZE: usize = 16;
+const RECORD_HEADER_SIZE: usize = HASH_SIZE + 16;
+const INDEX_ENTRY_SIZE: usize = HASH_SIZE + 16;
+const INDEX_TRAILER_SIZE: usize = 16;
+const INDEX_MAGIC: &[u8; 8] = b"ZKTRIEIX";
+
+/// Errors that can occur when reading or writing a snapshot.
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    /// IO error
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The snapshot does not start with [`SNAPSHOT_MAGIC`]
+    #[error("Invalid snapshot magic bytes")]
+    InvalidMagic,
+    /// The snapshot version is not supported
+    #[error("Unsupported snapshot version: {0}")]
+    UnsupportedVersion(u32),
+    /// The snapshot ends in the middle of a record
+    #[error("Snapshot truncated at offset {0}")]
+    Truncated(usize),
+    /// The snapshot is read-only
+    #[error("Snapshot is read-only")]
+    ReadOnly,
+    /// The number of nodes does not match the manifest
+    #[error("Node count mismatch: expected {expected}, got {actual}")]
+    NodeCountMismatch {
+        /// expected count
+        expected: usize,
+        /// actual count
+        actual: usize,
+    },
+    /// The snapshot size does not match the manifest
+    #[error("Size mismatch: expected {expected} bytes, got {actual}")]
+    SizeMismatch {
+        /// expected size
+        expected: usize,
+        /// actual size
+        actual: usize,
+    },
+    /// The snapshot checksum does not match the manifest
+    #[error("Checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        /// expected checksum
+        expected: B256,
+        /// actual checksum
+        actual: B256,
+    },
+    /// The root node of the manifest is not in the snapshot
+    #[error("Root node {0} not found in snapshot")]
+    MissingRoot(ZkHash),
+    /// The snapshot buffer doesn't start at an address aligned to [`SNAPSHOT_ALIGNMENT`]
+    #[error("Snapshot buffer not aligned to {SNAPSHOT_ALIGNMENT} bytes")]
+    Misaligned,
+    /// The archived node of a record is invalid, or its cached hash isn't the record hash
+    #[error("Invalid archived node {0}")]
+    InvalidNode(ZkHash),
+    /// The snapshot has no valid index at its end
+    #[error("Invalid snapshot index")]
+    InvalidIndex,
+    /// A record at the offset is larger than [`MAX_RECORD_SIZE`]
+    #[error("Record at offset {offset} too large: {len} bytes")]
+    RecordTooLarge {
+        /// offset of the record
+        offset: usize,
+        /// length of the archived node
+        len: usize,
+    },
+}
+
+/// The size of an encoded [`SnapshotManifest`].
+pub const MANIFEST_SIZE: usize = HASH_SIZE + 8 + 8 + 32;
+
+/// Integrity manifest of a snapshot, written alongside it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotManifest {
+    /// The root hash of the exported trie
+    pub root: ZkHash,
+    /// Number of node records
+    pub nodes: usize,
+    /// Total size of the snapshot, including the header
+    pub bytes: usize,
+    /// Keccak256 of the whole snapshot
+    pub checksum: B256,
+}
+
+impl SnapshotManifest {
+    /// Encode the manifest: root | nodes (u64 le) | bytes (u64 le) | checksum.
+    pub fn to_bytes(&self) -> [u8; MANIFEST_SIZE] {
+        let mut buf = [0u8; MANIFEST_SIZE];
+        buf[..HASH_SIZE].copy_from_slice(self.root.as_slice());
+        buf[HASH_SIZE..HASH_SIZE + 8].copy_from_slice(&(self.nodes as u64).to_le_bytes());
+        buf[HASH_SIZE + 8..HASH_SIZE + 16].copy_from_slice(&(self.bytes as u64).to_le_bytes());
+        buf[HASH_SIZE + 16..].copy_from_slice(self.checksum.as_slice());
+        buf
+    }
+
+    /// Decode a manifest encoded by [`SnapshotManifest::to_bytes`].
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() != MANIFEST_SIZE {
+            return None;
+        }
+        let nodes = u64::from_le_bytes(buf[HASH_SIZE..HASH_SIZE + 8].try_into().unwrap());
+        let bytes = u64::from_le_bytes(buf[HASH_SIZE + 8..HASH_SIZE + 16].try_into().unwrap());
+        Some(Self {
+            root: ZkHash::from_slice(&buf[..HASH_SIZE]),
+            nodes: usize::try_from(nodes).ok()?,
+            bytes: usize::try_from(bytes).ok()?,
+            checksum: B256::from_slice(&buf[HASH_SIZE + 16..]),
+        })
+    }
+}
+
+/// Writes nodes into a snapshot.
+#[derive(Debug)]
+pub struct SnapshotWriter<W> {
+    inner: ChecksumWriter<W>,
+    written: usize,
+    nodes: usize,
+    /// The record offset of every node, written as the index on finish
+    index: Vec<(ZkHash, u64)>,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    /// Create a new writer, writing the snapshot header.
+    pub fn new(inner: W) -> io::Result<Self> {
+        let mut inner = ChecksumWriter {
+            inner,
+            hasher: Keccak256::new(),
+        };
+        inner.write_all(SNAPSHOT_MAGIC)?;
+        inner.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        inner.write_all(&[0; 4])?;
+        Ok(Self {
+            inner,
+            written: HEADER_SIZE,
+            nodes: 0,
+            index: Vec::new(),
+        })
+    }
+
+    /// Write an archived node.
+    pub fn write_node(&mut self, node_hash: &ZkHash, archived: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(archived.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "node too large"))?;
+        self.inner.write_all(node_hash.as_slice())?;
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(&[0; 12])?;
+        self.inner.write_all(archived)?;
+        self.index.push((*node_hash, self.written as u64));
+        let padding = padding(archived.len());
+        self.inner.write_all(&[0; SNAPSHOT_ALIGNMENT][..padding])?;
+        self.written += RECORD_HEADER_SIZE + archived.len() + padding;
+        self.nodes += 1;
+        Ok(())
+    }
+
+    /// Number of nodes written.
+    #[inline]
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// Number of bytes written, including the header but not the index written on finish.
+    #[inline]
+    pub fn bytes_written(&self) -> usize {
+        self.written
+    }
+
+    /// Flush and return the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.finish_with_manifest(ZkHash::ZERO)
+            .map(|(inner, _)| inner)
+    }
+
+    /// Flush and return the inner writer, with the manifest of the snapshot rooted at `root`.
+    pub fn finish_with_manifest(mut self, root: ZkHash) -> io::Result<(W, SnapshotManifest)> {
+        self.write_index()?;
+        self.inner.flush()?;
+        let manifest = SnapshotManifest {
+            root,
+            nodes: self.nodes,
+            bytes: self.written,
+            checksum: self.inner.hasher.finalize(),
+        };
+        Ok((self.inner.inner, manifest))
+    }
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    /// Write the index record, sorted by node hash.
+    fn write_index(&mut self) -> io::Result<()> {
+        self.index.sort_unstable_by_key(|(node_hash, _)| *node_hash);
+        let len = self.index.len() * INDEX_ENTRY_SIZE + INDEX_TRAILER_SIZE;
+        let len = u32::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "index too large"))?;
+        self.inner.write_all(ZkHash::ZERO.as_slice())?;
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(&[0; 12])?;
+        for (node_hash, offset) in self.index.iter() {
+            self.inner.write_all(node_hash.as_slice())?;
+            self.inner.write_all(&offset.to_le_bytes())?;
+            self.inner.write_all(&[0; 8])?;
+        }
+        self.inner.write_all(&(self.nodes as u64).to_le_bytes())?;
+        self.inner.write_all(INDEX_MAGIC)?;
+        self.written += RECORD_HEADER_SIZE + len as usize;
+        Ok(())
+    }
+}
+
+/// Hashes everything written through it.
+#[derive(Debug)]
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: Keccak256,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Validate a snapshot stream against its manifest, without loading it into a database.
+///
+/// Checks the header and the framing of every record, the node count, the size,
+/// the checksum, and that the root node is present unless the root is empty.
+/// Node contents and the index are covered by the checksum only.
+pub fn verify_backup<R: Read>(manifest: &SnapshotManifest, reader: R) -> Result<(), SnapshotError> {
+    let mut reader = ChecksumReader {
+        inner: reader,
+        hasher: Keccak256::new(),
+        read: 0,
+    };
+
+    let mut header = [0u8; HEADER_SIZE];
+    if !reader.read_exact_or_eof(&mut header)? {
+        return Err(SnapshotError::Truncated(0));
+    }
+    if &header[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+        return Err(SnapshotError::InvalidMagic);
+    }
+    check_version(&header)?;
+
+    let mut nodes = 0;
+    let mut root_found = manifest.root.is_zero();
+    let mut record_header = [0u8; RECORD_HEADER_SIZE];
+    let mut data = Vec::new();
+    loop {
+        let offset = reader.read;
+        if !reader.read_exact_or_eof(&mut record_header)? {
+            if reader.read != offset {
+                return Err(SnapshotError::Truncated(offset));
+            }
+            break;
+        }
+        let node_hash = ZkHash::from_slice(&record_header[..HASH_SIZE]);
+        let len = u32::from_le_bytes(record_header[HASH_SIZE..HASH_SIZE + 4].try_into().unwrap())
+            as usize;
+        let is_index = node_hash.is_zero();
+        // the length is untrusted, check it before allocating
+        if len > MAX_RECORD_SIZE && !is_index {
+            return Err(SnapshotError::RecordTooLarge { offset, len });
+        }
+        if reader.read + len + padding(len) > manifest.bytes {
+            return Err(SnapshotError::SizeMismatch {
+                expected: manifest.bytes,
+                actual: reader.read + len + padding(len),
+            });
+        }
+        data.resize(len + padding(len), 0);
+        if !reader.read_exact_or_eof(&mut data)? {
+            return Err(SnapshotError::Truncated(offset));
+        }
+        if is_index {
+            continue;
+        }
+        root_found |= node_hash == manifest.root;
+        nodes += 1;
+    }
+
+    if nodes != manifest.nodes {
+        return Err(SnapshotError::NodeCountMismatch {
+            expected: manifest.nodes,
+            actual: nodes,
+        });
+    }
+    if reader.read != manifest.bytes {
+        return Err(SnapshotError::SizeMismatch {
+            expected: manifest.bytes,
+            actual: reader.read,
+        });
+    }
+    let checksum = reader.hasher.finalize();
+    if checksum != manifest.checksum {
+        return Err(SnapshotError::ChecksumMismatch {
+            expected: manifest.checksum,
+            actual: checksum,
+        });
+    }
+    if !root_found {
+        return Err(SnapshotError::MissingRoot(manifest.root));
+    }
+    trace!(nodes, bytes = manifest.bytes, "verified backup");
+    Ok(())
+}
+
+/// Hashes and counts everything read through it.
+struct ChecksumReader<R> {
+    inner: R,
+    hasher: Keccak256,
+    read: usize,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    /// Fill `buf`, returns `false` if the stream ended before.
+    fn read_exact_or_eof(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.inner.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.hasher.update(&buf[filled..filled + n]);
+                    self.read += n;
+                    filled += n;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled == buf.len())
+    }
+}
+
+/// Check the header of a snapshot, returns its format version.
+fn check_version(header: &[u8]) -> Result<u32, SnapshotError> {
+    if &header[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+        return Err(SnapshotError::InvalidMagic);
+    }
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if version != SNAPSHOT_VERSION && version != SNAPSHOT_VERSION_UNINDEXED {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    Ok(version)
+}
+
+/// Iterate over the node records of a snapshot,
+/// yielding the node hash and the range of archived node bytes.
+pub fn snapshot_records(
+    data: &[u8],
+) -> Result<impl Iterator<Item = Result<(ZkHash, Range<usize>), SnapshotError>> + '_, SnapshotError>
+{
+    if data.len() < HEADER_SIZE {
+        return Err(SnapshotError::Truncated(data.len()));
+    }
+    check_version(data)?;
+
+    let mut offset = HEADER_SIZE;
+    Ok(std::iter::from_fn(move || loop {
+        if offset == data.len() {
+            return None;
+        }
+        if data.len() - offset < RECORD_HEADER_SIZE {
+            let truncated = offset;
+            offset = data.len();
+            return Some(Err(SnapshotError::Truncated(truncated)));
+        }
+        let node_hash = ZkHash::from_slice(&data[offset..offset + HASH_SIZE]);
+        let len = u32::from_le_bytes(
+            data[offset + HASH_SIZE..offset + HASH_SIZE + 4]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let start = offset + RECORD_HEADER_SIZE;
+        let end = start + len;
+        if end > data.len() {
+            let truncated = offset;
+            offset = data.len();
+            return Some(Err(SnapshotError::Truncated(truncated)));
+        }
+        offset = (end + padding(len)).min(data.len());
+        // the index record
+        if node_hash.is_zero() {
+            continue;
+        }
+        return Some(Ok((node_hash, start..end)));
+    }))
+}
+
+/// The index of a snapshot held in memory, see the [module docs](self).
+#[cfg(feature = "mmap")]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SnapshotIndex {
+    /// Offset of the first entry
+    entries: usize,
+    /// Number of entries
+    nodes: usize,
+}
+
+#[cfg(feature = "mmap")]
+impl SnapshotIndex {
+    /// Locate the index at the end of a snapshot held in `data`,
+    /// only the header and the index framing are read.
+    pub(crate) fn locate(data: &[u8]) -> Result<Self, SnapshotError> {
+        if data.len() < HEADER_SIZE {
+            return Err(SnapshotError::Truncated(data.len()));
+        }
+        let version = check_version(data)?;
+        if version == SNAPSHOT_VERSION_UNINDEXED {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        if data.len() < HEADER_SIZE + RECORD_HEADER_SIZE + INDEX_TRAILER_SIZE {
+            return Err(SnapshotError::InvalidIndex);
+        }
+        let trailer = &data[data.len() - INDEX_TRAILER_SIZE..];
+        if &trailer[8..] != INDEX_MAGIC {
+            return Err(SnapshotError::InvalidIndex);
+        }
+        let nodes = usize::try_from(u64::from_le_bytes(trailer[..8].try_into().unwrap()))
+            .map_err(|_| SnapshotError::InvalidIndex)?;
+        let len = nodes
+            .checked_mul(INDEX_ENTRY_SIZE)
+            .and_then(|len| len.checked_add(INDEX_TRAILER_SIZE))
+            .filter(|len| *len <= data.len() - HEADER_SIZE - RECORD_HEADER_SIZE)
+            .ok_or(SnapshotError::InvalidIndex)?;
+        let entries = data.len() - len;
+        let header = &data[entries - RECORD_HEADER_SIZE..entries];
+        if header[..HASH_SIZE].iter().any(|b| *b != 0)
+            || u32::from_le_bytes(header[HASH_SIZE..HASH_SIZE + 4].try_into().unwrap()) as usize
+                != len
+        {
+            return Err(SnapshotError::InvalidIndex);
+        }
+        Ok(Self { entries, nodes })
+    }
+
+    /// Number of indexed nodes.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.nodes
+    }
+
+    /// Get the node hash of the `i`-th entry.
+    #[inline]
+    fn key<'a>(&self, data: &'a [u8], i: usize) -> &'a [u8] {
+        let offset = self.entries + i * INDEX_ENTRY_SIZE;
+        &data[offset..offset + HASH_SIZE]
+    }
+
+    /// Iterate over the indexed node hashes, in order.
+    pub(crate) fn keys<'a>(&self, data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let this = *self;
+        (0..self.nodes).map(move |i| this.key(data, i))
+    }
+
+    /// Binary search the entry of `node_hash`.
+    pub(crate) fn find(&self, data: &[u8], node_hash: &[u8]) -> Option<usize> {
+        let (mut lo, mut hi) = (0, self.nodes);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.key(data, mid).cmp(node_hash) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Get the range of archived node bytes of the `i`-th entry,
+    /// checking the untrusted record offset and length.
+    pub(crate) fn record(&self, data: &[u8], i: usize) -> Result<Range<usize>, SnapshotError> {
+        let entry = self.entries + i * INDEX_ENTRY_SIZE;
+        let node_hash = &data[entry..entry + HASH_SIZE];
+        let offset = u64::from_le_bytes(
+            data[entry + HASH_SIZE..entry + HASH_SIZE + 8]
+                .try_into()
+                .unwrap(),
+        );
+        let invalid = || SnapshotError::InvalidNode(ZkHash::from_slice(node_hash));
+        let records_end = self.entries - RECORD_HEADER_SIZE;
+        let offset = usize::try_from(offset)
+            .ok()
+            .filter(|offset| {
+                *offset >= HEADER_SIZE
+                    && *offset % SNAPSHOT_ALIGNMENT == 0
+                    && offset + RECORD_HEADER_SIZE <= records_end
+            })
+            .ok_or_else(invalid)?;
+        if &data[offset..offset + HASH_SIZE] != node_hash {
+            return Err(invalid());
+        }
+        let len = u32::from_le_bytes(
+            data[offset + HASH_SIZE..offset + HASH_SIZE + 4]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let start = offset + RECORD_HEADER_SIZE;
+        if len > MAX_RECORD_SIZE || start + len > records_end {
+            return Err(SnapshotError::Truncated(offset));
+        }
+        Ok(start..start + len)
+    }
+}
+
+/// Collect the records of a snapshot held in `data`,
+/// with the archived nodes as slices of `data` validated by [`check_record`].
+///
+/// `data` is copied once into an aligned buffer if it's not aligned to [`SNAPSHOT_ALIGNMENT`].
+pub(crate) fn load_snapshot<M: FromIterator<(ZkHash, Bytes)>>(
+    data: Bytes,
+) -> Result<M, SnapshotError> {
+    let data = if check_alignment(&data).is_ok() {
+        data
+    } else {
+        let mut aligned = AlignedVec::<SNAPSHOT_ALIGNMENT>::with_capacity(data.len());
+        aligned.extend_from_slice(&data);
+        Bytes::from_owner(aligned)
+    };
+    let nodes = snapshot_records(&data)?
+        .map(|record| {
+            let (node_hash, range) = record?;
+            check_record(&node_hash, &data[range.clone()])?;
+            Ok((node_hash, data.slice(range)))
+        })
+        .collect();
+    nodes
+}
+
+/// Check that a snapshot held in `data` can be viewed in place,
+/// i.e. starts at an address aligned to [`SNAPSHOT_ALIGNMENT`].
+pub(crate) fn check_alignment(data: &[u8]) -> Result<(), SnapshotError> {
+    if data.as_ptr() as usize % SNAPSHOT_ALIGNMENT != 0 {
+        return Err(SnapshotError::Misaligned);
+    }
+    Ok(())
+}
+
+/// Check that the archived node of a record is valid, so it can be read without checks.
+///
+/// The rkyv layout and the branch node type are validated,
+/// and the node hash cached in the archived bytes must be the record hash.
+/// The node hash is not recomputed.
+pub(crate) fn check_record(node_hash: &ZkHash, archived: &[u8]) -> Result<(), SnapshotError> {
+    let invalid = || SnapshotError::InvalidNode(*node_hash);
+    let node =
+        rkyv::access::<ArchivedNode, rkyv::rancor::Error>(archived).map_err(|_| invalid())?;
+    if let Some(branch) = node.as_branch() {
+        branch.try_node_type().map_err(|_| invalid())?;
+    }
+    if node
+        .cached_node_hash()
+        .is_some_and(|cached| cached != *node_hash)
+    {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+#[inline]
+fn padding(len: usize) -> usize {
+    (SNAPSHOT_ALIGNMENT - len % SNAPSHOT_ALIGNMENT) % SNAPSHOT_ALIGNMENT
+}
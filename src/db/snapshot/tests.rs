@@ -0,0 +1,65 @@
+use super::*;
+use crate::db::NodeDb;
+use crate::trie::ZkTrie;
+use rand::random;
+
+#[test]
+fn test_verify_backup() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    for _ in 0..20 {
+        let k: [u8; 32] = random();
+        trie.raw_update(&trie_db, k, vec![k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    let mut buffer = Vec::new();
+    let manifest = trie
+        .export_snapshot_with_manifest(&trie_db, &mut buffer)
+        .unwrap();
+    assert_eq!(manifest.root, *trie.root().unwrap_ref());
+    assert_eq!(manifest.bytes, buffer.len());
+    assert_eq!(
+        SnapshotManifest::from_bytes(&manifest.to_bytes()),
+        Some(manifest)
+    );
+    verify_backup(&manifest, buffer.as_slice()).unwrap();
+
+    assert!(matches!(
+        verify_backup(&manifest, &buffer[..buffer.len() - 1]),
+        Err(SnapshotError::Truncated(_))
+    ));
+    let mut corrupted = buffer.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        verify_backup(&manifest, corrupted.as_slice()),
+        Err(SnapshotError::ChecksumMismatch { .. })
+    ));
+    let mut other = manifest;
+    other.nodes += 1;
+    assert!(matches!(
+        verify_backup(&other, buffer.as_slice()),
+        Err(SnapshotError::NodeCountMismatch { .. })
+    ));
+    let mut other = manifest;
+    other.root = ZkHash::repeat_byte(1);
+    assert!(matches!(
+        verify_backup(&other, buffer.as_slice()),
+        Err(SnapshotError::MissingRoot(_))
+    ));
+
+    // record lengths are checked before allocating
+    let len_offset = 16 + HASH_SIZE;
+    let mut corrupted = buffer.clone();
+    corrupted[len_offset..len_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(
+        verify_backup(&manifest, corrupted.as_slice()),
+        Err(SnapshotError::RecordTooLarge { offset: 16, len }) if len == u32::MAX as usize
+    ));
+    let mut corrupted = buffer.clone();
+    corrupted[len_offset..len_offset + 4].copy_from_slice(&(buffer.len() as u32).to_le_bytes());
+    assert!(matches!(
+        verify_backup(&manifest, corrupted.as_slice()),
+        Err(SnapshotError::SizeMismatch { expected, .. }) if expected == buffer.len()
+    ));
+}
@@ -0,0 +1,101 @@
+//! Root and leaf versions persisted by tries recording
+//! [leaf versions](crate::trie::ZkTrie::with_leaf_versions).
+//!
+//! Versions are stored under reserved key prefixes, so their keys never collide with a node hash:
+//!
+//! - the root version of every committed root, by root hash
+//! - the root version each leaf last changed in, by node key
+use super::{
+    kv::{KVDatabase, KVDatabaseItem},
+    DbError, NodeDb, NodeDbError,
+};
+use crate::hash::{ZkHash, HASH_SIZE};
+
+/// Key prefix of the root versions, followed by the root hash.
+const ROOT_VERSION_PREFIX: &[u8] = b"zktrie-ng:root-version:";
+
+/// Key prefix of the leaf versions, followed by the node key.
+const LEAF_VERSION_PREFIX: &[u8] = b"zktrie-ng:leaf-version:";
+
+#[inline]
+fn version_key(prefix: &[u8], hash: &ZkHash) -> Box<[u8]> {
+    [prefix, hash.as_slice()].concat().into_boxed_slice()
+}
+
+#[inline]
+fn decode_version(bytes: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+impl<KvDb: KVDatabase> NodeDb<KvDb> {
+    /// Get the version persisted for a committed root, see
+    /// [`ZkTrie::root_version`](crate::trie::ZkTrie::root_version).
+    ///
+    /// Only roots committed by tries recording leaf versions have one.
+    pub fn root_version(&self, root: &ZkHash) -> Result<Option<u64>, NodeDbError<KvDb::Error>> {
+        let key = version_key(ROOT_VERSION_PREFIX, root);
+        let bytes = self.db.get(key).map_err(DbError::classify::<KvDb>)?;
+        Ok(bytes.and_then(|bytes| decode_version(bytes.as_ref())))
+    }
+
+    /// Get the root version in which the leaf of `node_key` last changed, see
+    /// [`ZkTrie::last_modified`](crate::trie::ZkTrie::last_modified).
+    pub fn leaf_version(&self, node_key: &ZkHash) -> Result<Option<u64>, NodeDbError<KvDb::Error>> {
+        let key = version_key(LEAF_VERSION_PREFIX, node_key);
+        let bytes = self.db.get(key).map_err(DbError::classify::<KvDb>)?;
+        Ok(bytes.and_then(|bytes| decode_version(bytes.as_ref())))
+    }
+
+    /// Get the node keys of the leafs changed after root version `version`,
+    /// with the version they last changed in, in node key order.
+    ///
+    /// Returns [`NodeDbError::KeysNotEnumerable`] if the backend can't enumerate its keys.
+    pub fn leaf_versions_since(
+        &self,
+        version: u64,
+    ) -> Result<Vec<(ZkHash, u64)>, NodeDbError<KvDb::Error>> {
+        let mut node_keys = Vec::new();
+        let enumerable = self
+            .db
+            .for_each_key(|key| {
+                if let Some(node_key) = key.strip_prefix(LEAF_VERSION_PREFIX) {
+                    if node_key.len() == HASH_SIZE {
+                        node_keys.push(ZkHash::from_slice(node_key));
+                    }
+                }
+            })
+            .map_err(DbError::classify::<KvDb>)?;
+        if !enumerable {
+            return Err(NodeDbError::KeysNotEnumerable);
+        }
+        node_keys.sort_unstable();
+        let mut changed = Vec::new();
+        for node_key in node_keys {
+            match self.leaf_version(&node_key)? {
+                Some(v) if v > version => changed.push((node_key, v)),
+                _ => {}
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Persist the version of a newly committed root, and of the leafs changed in it.
+    pub(crate) fn put_versions(
+        &mut self,
+        root: &ZkHash,
+        root_version: u64,
+        changed_leafs: &[ZkHash],
+    ) -> Result<(), NodeDbError<KvDb::Error>> {
+        let version = root_version.to_be_bytes();
+        let pairs = std::iter::once(version_key(ROOT_VERSION_PREFIX, root))
+            .chain(
+                changed_leafs
+                    .iter()
+                    .map(|node_key| version_key(LEAF_VERSION_PREFIX, node_key)),
+            )
+            .map(|key| (key, KvDb::Item::from_slice(&version)))
+            .collect::<Vec<_>>();
+        self.db.extend(pairs).map_err(DbError::classify::<KvDb>)?;
+        Ok(())
+    }
+}
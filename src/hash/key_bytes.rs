@@ -0,0 +1,115 @@
+//! Canonical byte encodings of trie keys.
+//!
+//! [`HashScheme::hash_bytes`](super::HashScheme::hash_bytes) pads short keys with zeros
+//! on the right, so the same logical key encoded with another width or byte order
+//! ends up at a different node key. Scroll hashes account keys as the 20 address bytes,
+//! and storage keys as the 32-byte big endian slot.
+use super::HASH_SIZE;
+use alloy_primitives::{Address, B256, U256};
+use std::fmt::{Debug, Formatter};
+
+#[cfg(test)]
+mod tests;
+
+/// The canonical bytes of a trie key, at most [`HASH_SIZE`] long.
+///
+/// Passed to the trie APIs as any other key, e.g. `trie.get(db, KeyBytes::from_address(addr))`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyBytes {
+    bytes: [u8; HASH_SIZE],
+    len: u8,
+}
+
+impl KeyBytes {
+    /// Wrap raw key bytes, `None` if longer than [`HASH_SIZE`].
+    ///
+    /// The bytes are taken as is, prefer the typed constructors for addresses and slots.
+    pub fn new(key: &[u8]) -> Option<Self> {
+        if key.len() > HASH_SIZE {
+            return None;
+        }
+        let mut bytes = [0u8; HASH_SIZE];
+        bytes[..key.len()].copy_from_slice(key);
+        Some(Self {
+            bytes,
+            len: key.len() as u8,
+        })
+    }
+
+    /// The key of an account, the 20 address bytes.
+    pub fn from_address(address: Address) -> Self {
+        Self::new(address.as_slice()).unwrap()
+    }
+
+    /// The key of an account given as a 32-byte word, e.g. an EVM stack word.
+    ///
+    /// The address is right-aligned as in the EVM,
+    /// `None` if any of the leading 12 bytes is not zero.
+    pub fn from_address_word(word: &[u8; HASH_SIZE]) -> Option<Self> {
+        let (padding, address) = word.split_at(HASH_SIZE - Address::len_bytes());
+        padding
+            .iter()
+            .all(|b| *b == 0)
+            .then(|| Self::from_address(Address::from_slice(address)))
+    }
+
+    /// The key of a storage slot, the 32-byte big endian slot.
+    pub fn from_storage_slot(slot: U256) -> Self {
+        Self::from_word(B256::from(slot.to_be_bytes::<HASH_SIZE>()))
+    }
+
+    /// The key of a storage slot given as big endian bytes of any width up to 32,
+    /// e.g. with leading zeros stripped, `None` if longer.
+    pub fn from_storage_slot_be(slot: &[u8]) -> Option<Self> {
+        U256::try_from_be_slice(slot).map(Self::from_storage_slot)
+    }
+
+    /// The key of a full 32-byte word, e.g. a hashed key.
+    pub fn from_word(word: B256) -> Self {
+        Self {
+            bytes: word.0,
+            len: HASH_SIZE as u8,
+        }
+    }
+
+    /// Get the key bytes.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl AsRef<[u8]> for KeyBytes {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl From<Address> for KeyBytes {
+    fn from(address: Address) -> Self {
+        Self::from_address(address)
+    }
+}
+
+impl From<U256> for KeyBytes {
+    fn from(slot: U256) -> Self {
+        Self::from_storage_slot(slot)
+    }
+}
+
+impl From<B256> for KeyBytes {
+    fn from(word: B256) -> Self {
+        Self::from_word(word)
+    }
+}
+
+impl Debug for KeyBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "KeyBytes(0x{})",
+            alloy_primitives::hex::encode(self.as_bytes())
+        )
+    }
+}
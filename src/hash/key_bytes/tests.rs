@@ -0,0 +1,61 @@
+use super::*;
+use crate::db::{kv::HashMapDb, NodeDb};
+use crate::hash::{key_hasher::NoCacheHasher, poseidon::Poseidon};
+use crate::trie::ZkTrie;
+
+#[test]
+fn test_key_bytes() {
+    let address = Address::repeat_byte(0xab);
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(address.as_slice());
+    assert_eq!(
+        KeyBytes::from_address_word(&word),
+        Some(KeyBytes::from_address(address))
+    );
+    word[0] = 1;
+    assert_eq!(KeyBytes::from_address_word(&word), None);
+    assert_eq!(
+        KeyBytes::from_address(address).as_bytes(),
+        address.as_slice()
+    );
+
+    let slot = U256::from(0x1234);
+    assert_eq!(
+        KeyBytes::from_storage_slot_be(&[0x12, 0x34]),
+        Some(KeyBytes::from_storage_slot(slot))
+    );
+    assert_eq!(
+        KeyBytes::from_storage_slot_be(&slot.to_be_bytes::<32>()),
+        Some(slot.into())
+    );
+    assert_eq!(KeyBytes::from_storage_slot_be(&[1u8; 33]), None);
+    assert_eq!(KeyBytes::new(&[1u8; 33]), None);
+    assert_eq!(
+        KeyBytes::from(B256::from(slot.to_be_bytes::<32>())),
+        KeyBytes::from(slot)
+    );
+
+    // differently padded encodings of the same logical key reach the same leaf
+    let mut trie_db = NodeDb::new(HashMapDb::default());
+    let mut trie = ZkTrie::<Poseidon>::new(NoCacheHasher);
+    trie.raw_update(&trie_db, KeyBytes::from(address), vec![[1u8; 32]], 1)
+        .unwrap();
+    trie.raw_update(&trie_db, KeyBytes::from(slot), vec![[2u8; 32]], 1)
+        .unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    word[0] = 0;
+    let value: [[u8; 32]; 1] = trie
+        .get(&trie_db, KeyBytes::from_address_word(&word).unwrap())
+        .unwrap()
+        .unwrap();
+    assert_eq!(value, [[1u8; 32]]);
+    let value: [[u8; 32]; 1] = trie
+        .get(
+            &trie_db,
+            KeyBytes::from_storage_slot_be(&[0x12, 0x34]).unwrap(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(value, [[2u8; 32]]);
+    assert!(!trie.contains_key(&trie_db, [0x12, 0x34]).unwrap());
+}
@@ -1,14 +1,33 @@
 //! KeyHasher is a trait that helps to utilize cache while hashing keys.
-use crate::hash::{HashScheme, ZkHash};
+//!
+//! [`KeyHasher`] is dyn compatible, so the hasher can be chosen at runtime with [`DynKeyHasher`].
+use crate::hash::{poseidon::Poseidon, HashScheme, ZkHash};
 use std::error::Error;
+use std::rc::Rc;
+use std::sync::Arc;
+
+mod namespaced;
+pub use namespaced::*;
 
 mod no_cache;
 pub use no_cache::*;
 
+mod parallel;
+pub use parallel::*;
+
+mod prehashed;
+pub use prehashed::*;
+
+#[cfg(feature = "key-cache")]
 mod ref_cache;
+#[cfg(feature = "key-cache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "key-cache")))]
 pub use ref_cache::*;
 
+#[cfg(feature = "key-cache")]
 mod sync_cache;
+#[cfg(feature = "key-cache")]
+#[cfg_attr(docsrs, doc(cfg(feature = "key-cache")))]
 pub use sync_cache::*;
 
 /// Error type for KeyCacheDb
@@ -28,4 +47,93 @@ pub trait KeyHasher<H: HashScheme> {
     fn hash(&self, key: &[u8]) -> Result<ZkHash, KeyHasherError<H::Error>> {
         H::hash_bytes(key).map_err(KeyHasherError::Hash)
     }
+
+    /// Hash a batch of keys, returning the hashes in the order of `keys`.
+    ///
+    /// Hashes one key after another by default,
+    /// hashers override it to hash on several threads or to look up their cache at once,
+    /// e.g. [`ParallelKeyHasher`].
+    fn hash_many(&self, keys: &[&[u8]]) -> Result<Vec<ZkHash>, KeyHasherError<H::Error>> {
+        keys.iter().map(|key| self.hash(key)).collect()
+    }
+
+    /// Check if the keys are preimages of the node keys, `true` by default.
+    ///
+    /// Hashers taking node keys as keys, e.g. [`PrehashedKeyHasher`], return `false`,
+    /// so the keys are not reported as preimages, e.g. to a
+    /// [`SecondaryCommitment`](crate::trie::SecondaryCommitment).
+    fn knows_preimages(&self) -> bool {
+        true
+    }
+}
+
+/// A key hasher chosen at runtime, e.g. `ZkTrie<Poseidon, DynKeyHasher>`.
+pub type DynKeyHasher<H = Poseidon> = Box<dyn KeyHasher<H> + Send + Sync>;
+
+impl<H: HashScheme, K: KeyHasher<H> + ?Sized> KeyHasher<H> for &K {
+    #[inline]
+    fn hash(&self, key: &[u8]) -> Result<ZkHash, KeyHasherError<H::Error>> {
+        (**self).hash(key)
+    }
+
+    #[inline]
+    fn hash_many(&self, keys: &[&[u8]]) -> Result<Vec<ZkHash>, KeyHasherError<H::Error>> {
+        (**self).hash_many(keys)
+    }
+
+    #[inline]
+    fn knows_preimages(&self) -> bool {
+        (**self).knows_preimages()
+    }
+}
+
+impl<H: HashScheme, K: KeyHasher<H> + ?Sized> KeyHasher<H> for Box<K> {
+    #[inline]
+    fn hash(&self, key: &[u8]) -> Result<ZkHash, KeyHasherError<H::Error>> {
+        (**self).hash(key)
+    }
+
+    #[inline]
+    fn hash_many(&self, keys: &[&[u8]]) -> Result<Vec<ZkHash>, KeyHasherError<H::Error>> {
+        (**self).hash_many(keys)
+    }
+
+    #[inline]
+    fn knows_preimages(&self) -> bool {
+        (**self).knows_preimages()
+    }
+}
+
+impl<H: HashScheme, K: KeyHasher<H> + ?Sized> KeyHasher<H> for Rc<K> {
+    #[inline]
+    fn hash(&self, key: &[u8]) -> Result<ZkHash, KeyHasherError<H::Error>> {
+        (**self).hash(key)
+    }
+
+    #[inline]
+    fn hash_many(&self, keys: &[&[u8]]) -> Result<Vec<ZkHash>, KeyHasherError<H::Error>> {
+        (**self).hash_many(keys)
+    }
+
+    #[inline]
+    fn knows_preimages(&self) -> bool {
+        (**self).knows_preimages()
+    }
+}
+
+impl<H: HashScheme, K: KeyHasher<H> + ?Sized> KeyHasher<H> for Arc<K> {
+    #[inline]
+    fn hash(&self, key: &[u8]) -> Result<ZkHash, KeyHasherError<H::Error>> {
+        (**self).hash(key)
+    }
+
+    #[inline]
+    fn hash_many(&self, keys: &[&[u8]]) -> Result<Vec<ZkHash>, KeyHasherError<H::Error>> {
+        (**self).hash_many(keys)
+    }
+
+    #[inline]
+    fn knows_preimages(&self) -> bool {
+        (**self).knows_preimages()
+    }
 }
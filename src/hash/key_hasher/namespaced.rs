@@ -0,0 +1,74 @@
+use crate::hash::{
+    key_hasher::{KeyHasher, KeyHasherError, NoCacheHasher},
+    HashScheme, ZkHash,
+};
+
+/// Domain of the namespaced key hash, distinct from the node and value domains.
+const NAMESPACE_DOMAIN: u64 = 32;
+
+/// A hasher placing keys in a namespace, so several logical maps can share one trie.
+///
+/// The node key is `H::hash(NAMESPACE_DOMAIN, [namespace, inner.hash(key)])`,
+/// where the namespace is the hash of a tag, e.g. a contract address.
+/// The same key under two tags never gets the same node key,
+/// without callers concatenating tags and keys themselves.
+///
+/// Caching inner hashers only cache the hash of the key, not the namespaced one.
+#[derive(Clone, Debug, Default)]
+pub struct NamespacedKeyHasher<K = NoCacheHasher> {
+    inner: K,
+    namespace: ZkHash,
+}
+
+impl<K> NamespacedKeyHasher<K> {
+    /// Create a hasher for the namespace of `tag`, hashed by [`HashScheme::hash_bytes`].
+    ///
+    /// Fails if `tag` can't be hashed, e.g. longer than 32 bytes for Poseidon.
+    pub fn new<H: HashScheme>(inner: K, tag: &[u8]) -> Result<Self, H::Error> {
+        Ok(Self::from_namespace(inner, H::hash_bytes(tag)?))
+    }
+
+    /// Create a hasher for an already hashed namespace.
+    pub fn from_namespace(inner: K, namespace: ZkHash) -> Self {
+        Self { inner, namespace }
+    }
+
+    /// Get the hashed namespace
+    #[inline]
+    pub fn namespace(&self) -> ZkHash {
+        self.namespace
+    }
+
+    /// Get the inner hasher
+    #[inline]
+    pub fn inner(&self) -> &K {
+        &self.inner
+    }
+
+    /// Into the inner hasher
+    pub fn into_inner(self) -> K {
+        self.inner
+    }
+}
+
+impl<H: HashScheme, K: KeyHasher<H>> KeyHasher<H> for NamespacedKeyHasher<K> {
+    #[inline]
+    fn hash(&self, key: &[u8]) -> Result<ZkHash, KeyHasherError<H::Error>> {
+        let key_hash = self.inner.hash(key)?;
+        H::hash(NAMESPACE_DOMAIN, [self.namespace, key_hash]).map_err(KeyHasherError::Hash)
+    }
+
+    fn hash_many(&self, keys: &[&[u8]]) -> Result<Vec<ZkHash>, KeyHasherError<H::Error>> {
+        self.inner
+            .hash_many(keys)?
+            .into_iter()
+            .map(|key_hash| H::hash(NAMESPACE_DOMAIN, [self.namespace, key_hash]))
+            .collect::<Result<_, _>>()
+            .map_err(KeyHasherError::Hash)
+    }
+
+    #[inline]
+    fn knows_preimages(&self) -> bool {
+        self.inner.knows_preimages()
+    }
+}
@@ -0,0 +1,64 @@
+use crate::hash::{
+    key_hasher::{KeyHasher, KeyHasherError},
+    HashScheme, ZkHash,
+};
+use std::thread;
+
+/// Batches with fewer keys are hashed on the calling thread.
+const MIN_PARALLEL_KEYS: usize = 64;
+
+/// A hasher hashing batches of keys on several threads, see [`KeyHasher::hash_many`].
+///
+/// Keys are hashed like [`NoCacheHasher`](super::NoCacheHasher), so both give the same node keys,
+/// e.g. Poseidon hashing the keys of a large access list is spread over all cores.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ParallelKeyHasher {
+    threads: usize,
+}
+
+impl ParallelKeyHasher {
+    /// Create a hasher using up to `threads` threads, `0` for the available parallelism.
+    pub fn new(threads: usize) -> Self {
+        Self { threads }
+    }
+
+    /// Get the number of threads, `0` for the available parallelism.
+    #[inline]
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+}
+
+impl<H: HashScheme> KeyHasher<H> for ParallelKeyHasher
+where
+    H::Error: Send,
+{
+    fn hash_many(&self, keys: &[&[u8]]) -> Result<Vec<ZkHash>, KeyHasherError<H::Error>> {
+        let threads = match self.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let chunk_size = keys.len().div_ceil(threads).max(MIN_PARALLEL_KEYS);
+        let hash_chunk = |chunk: &[&[u8]]| {
+            chunk
+                .iter()
+                .map(|key| H::hash_bytes(key))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        if keys.len() <= chunk_size {
+            return hash_chunk(keys).map_err(KeyHasherError::Hash);
+        }
+        thread::scope(|s| {
+            let handles: Vec<_> = keys
+                .chunks(chunk_size)
+                .map(|chunk| s.spawn(move || hash_chunk(chunk)))
+                .collect();
+            let mut hashes = Vec::with_capacity(keys.len());
+            for handle in handles {
+                hashes.extend(handle.join().unwrap().map_err(KeyHasherError::Hash)?);
+            }
+            trace!(keys = keys.len(), threads, "hashed keys in parallel");
+            Ok(hashes)
+        })
+    }
+}
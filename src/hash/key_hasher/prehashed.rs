@@ -0,0 +1,34 @@
+use crate::hash::{
+    key_hasher::{KeyHasher, KeyHasherError},
+    HashScheme, ZkHash, HASH_SIZE,
+};
+
+/// A hasher taking keys as already computed node keys,
+/// e.g. Poseidon key hashes computed on a GPU or received in witness data.
+///
+/// Keys must be exactly [`HASH_SIZE`] big endian bytes, valid in the hash scheme
+/// as checked by [`HashScheme::new_hash_try_from_bytes`], e.g. a field element for Poseidon.
+/// Nothing is hashed, so key preimages are never known.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PrehashedKeyHasher;
+
+/// A prehashed key is not [`HASH_SIZE`] bytes,
+/// returned as [`KeyHasherError::Other`] by [`PrehashedKeyHasher`].
+#[derive(Copy, Clone, Debug, thiserror::Error)]
+#[error("prehashed keys must be {HASH_SIZE} bytes, got {0} bytes")]
+pub struct InvalidKeyLength(pub usize);
+
+impl<H: HashScheme> KeyHasher<H> for PrehashedKeyHasher {
+    #[inline]
+    fn hash(&self, key: &[u8]) -> Result<ZkHash, KeyHasherError<H::Error>> {
+        if key.len() != HASH_SIZE {
+            return Err(KeyHasherError::Other(Box::new(InvalidKeyLength(key.len()))));
+        }
+        H::new_hash_try_from_bytes(key).map_err(KeyHasherError::Hash)
+    }
+
+    #[inline]
+    fn knows_preimages(&self) -> bool {
+        false
+    }
+}
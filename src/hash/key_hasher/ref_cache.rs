@@ -1,8 +1,9 @@
-use crate::db::kv::{HashMapDb, KVDatabase};
+use crate::db::kv::{HashMapDb, KVDatabase, KVDatabaseItem};
 use crate::hash::{
     key_hasher::{KeyHasher, KeyHasherError},
     HashScheme, ZkHash,
 };
+use crate::HashMap;
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -49,22 +50,31 @@ impl<H: HashScheme, Db: KVDatabase> RefCachedKeyHasher<H, Db> {
     }
 }
 
+/// Read the cached hash of `key`.
+fn cached_hash<H: HashScheme, Db: KVDatabase>(
+    db: &Db,
+    key: &[u8],
+) -> Result<Option<ZkHash>, KeyHasherError<H::Error>> {
+    let Some(hash) = db
+        .get(key)
+        .map_err(RefCachedKeyHasherErr::Db)
+        .map_err(|e| KeyHasherError::Other(Box::new(e)))?
+    else {
+        return Ok(None);
+    };
+    let hash = hash.as_ref();
+    let hash: &[u8; 32] = hash
+        .try_into()
+        .map_err(|_| RefCachedKeyHasherErr::<Db::Error>::InvalidHash)
+        .map_err(|e| KeyHasherError::Other(Box::new(e)))?;
+    Ok(Some(ZkHash::from(*hash)))
+}
+
 impl<H: HashScheme, Db: KVDatabase> KeyHasher<H> for RefCachedKeyHasher<H, Db> {
     fn hash(&self, key: &[u8]) -> Result<ZkHash, KeyHasherError<H::Error>> {
-        if let Some(hash) = self
-            .inner
-            .borrow_mut()
-            .get(key)
-            .map_err(RefCachedKeyHasherErr::Db)
-            .map_err(|e| KeyHasherError::Other(Box::new(e)))?
-        {
-            let hash = hash.as_ref();
-            let hash: &[u8; 32] = hash
-                .try_into()
-                .map_err(|_| RefCachedKeyHasherErr::<Db::Error>::InvalidHash)
-                .map_err(|e| KeyHasherError::Other(Box::new(e)))?;
-            return Ok(ZkHash::from(*hash));
-        };
+        if let Some(hash) = cached_hash::<H, Db>(&self.inner.borrow(), key)? {
+            return Ok(hash);
+        }
         let hash = H::hash_bytes(key).map_err(KeyHasherError::Hash)?;
         self.inner
             .borrow_mut()
@@ -73,4 +83,33 @@ impl<H: HashScheme, Db: KVDatabase> KeyHasher<H> for RefCachedKeyHasher<H, Db> {
             .map_err(|e| KeyHasherError::Other(Box::new(e)))?;
         Ok(hash)
     }
+
+    /// Look up all keys under a single borrow and cache the missing ones in a single write.
+    fn hash_many(&self, keys: &[&[u8]]) -> Result<Vec<ZkHash>, KeyHasherError<H::Error>> {
+        let mut db = self.inner.borrow_mut();
+        let mut missing = HashMap::new();
+        let mut hashes = Vec::with_capacity(keys.len());
+        for key in keys {
+            let hash = match missing.get(*key) {
+                Some(hash) => *hash,
+                None => match cached_hash::<H, Db>(&db, key)? {
+                    Some(hash) => hash,
+                    None => {
+                        let hash = H::hash_bytes(key).map_err(KeyHasherError::Hash)?;
+                        missing.insert(Box::<[u8]>::from(*key), hash);
+                        hash
+                    }
+                },
+            };
+            hashes.push(hash);
+        }
+        db.extend(
+            missing
+                .into_iter()
+                .map(|(key, hash)| (key, Db::Item::from_slice(hash.as_slice()))),
+        )
+        .map_err(RefCachedKeyHasherErr::Db)
+        .map_err(|e| KeyHasherError::Other(Box::new(e)))?;
+        Ok(hashes)
+    }
 }
@@ -1,8 +1,9 @@
-use crate::db::kv::{HashMapDb, KVDatabase};
+use crate::db::kv::{HashMapDb, KVDatabase, KVDatabaseItem};
 use crate::hash::{
     key_hasher::{KeyHasher, KeyHasherError},
     HashScheme, ZkHash,
 };
+use crate::HashMap;
 use std::sync::{Arc, Mutex};
 
 /// Error type for [`SyncCachedKeyHasher`]
@@ -17,12 +18,46 @@ pub enum SyncCachedKeyHasherErr<DbErr> {
 }
 
 /// A Send & Sync hasher that cache the hash result into a db.
-#[derive(Clone, Debug)]
+///
+/// Clones share the same cache, so every trie built with a clone reuses the hashes computed
+/// by the others. Backed by a persistent database, e.g. [`SledDb`](crate::db::kv::SledDb),
+/// the cache survives restarts, call [`flush`](SyncCachedKeyHasher::flush) before shutting down.
+///
+/// ```rust
+/// use zktrie_ng::{
+///     db::{kv::HashMapDb, NodeDb},
+///     hash::{key_hasher::SyncCachedKeyHasher, poseidon::Poseidon},
+///     trie::ZkTrie,
+/// };
+///
+/// let hasher = SyncCachedKeyHasher::<Poseidon>::new(HashMapDb::default());
+/// // e.g. the addresses of the accounts touched by a block
+/// let addresses = [[1u8; 20], [2u8; 20]];
+/// assert_eq!(hasher.warm_up(addresses).unwrap(), 2);
+///
+/// let db = NodeDb::new(HashMapDb::default());
+/// let mut account_trie = ZkTrie::<Poseidon, _>::new(hasher.clone());
+/// let mut other_trie = ZkTrie::<Poseidon, _>::new(hasher.clone());
+/// account_trie.raw_update(&db, [1u8; 20], vec![[1u8; 32]], 1).unwrap();
+/// other_trie.raw_update(&db, [2u8; 20], vec![[2u8; 32]], 1).unwrap();
+///
+/// hasher.flush().unwrap();
+/// ```
+#[derive(Debug)]
 pub struct SyncCachedKeyHasher<H, Db = HashMapDb> {
     inner: Arc<Mutex<Db>>,
     _hash_scheme: std::marker::PhantomData<H>,
 }
 
+impl<H, Db> Clone for SyncCachedKeyHasher<H, Db> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _hash_scheme: std::marker::PhantomData,
+        }
+    }
+}
+
 impl<H: HashScheme, Db: KVDatabase> SyncCachedKeyHasher<H, Db> {
     /// Create a new KeyCacheDb wrapping the given database.
     pub fn new(inner: Db) -> Self {
@@ -37,6 +72,56 @@ impl<H: HashScheme, Db: KVDatabase> SyncCachedKeyHasher<H, Db> {
         Arc::into_inner(self.inner).and_then(|db| db.into_inner().ok())
     }
 
+    /// Hash the keys not cached yet and cache them in a single write,
+    /// returns the number of newly cached keys.
+    pub fn warm_up<KEY: AsRef<[u8]>>(
+        &self,
+        keys: impl IntoIterator<Item = KEY>,
+    ) -> Result<usize, KeyHasherError<H::Error>> {
+        let mut db = self.inner.lock().unwrap();
+        let mut missing = HashMap::new();
+        for key in keys {
+            let key = key.as_ref();
+            if missing.contains_key(key)
+                || db
+                    .contains_key(key)
+                    .map_err(SyncCachedKeyHasherErr::Db)
+                    .map_err(|e| KeyHasherError::Other(Box::new(e)))?
+            {
+                continue;
+            }
+            let hash = H::hash_bytes(key).map_err(KeyHasherError::Hash)?;
+            missing.insert(
+                Box::<[u8]>::from(key),
+                Db::Item::from_slice(hash.as_slice()),
+            );
+        }
+        let cached = missing.len();
+        db.extend(missing)
+            .map_err(SyncCachedKeyHasherErr::Db)
+            .map_err(|e| KeyHasherError::Other(Box::new(e)))?;
+        trace!("warmed up {cached} key hashes");
+        Ok(cached)
+    }
+
+    /// Persist the cached hashes, see [`KVDatabase::flush`].
+    pub fn flush(&self) -> Result<(), Db::Error> {
+        self.inner.lock().unwrap().flush()
+    }
+
+    /// Remove all cached hashes, returns the number of removed entries.
+    ///
+    /// Entries are removed with [`KVDatabase::retain`], so backends that don't support it
+    /// remove nothing.
+    pub fn clear(&self) -> Result<usize, Db::Error> {
+        let mut removed = 0;
+        self.inner.lock().unwrap().retain(|_, _| {
+            removed += 1;
+            false
+        })?;
+        Ok(removed)
+    }
+
     /// Put a key-hash pair into the cache.
     ///
     /// # Safety
@@ -48,25 +133,66 @@ impl<H: HashScheme, Db: KVDatabase> SyncCachedKeyHasher<H, Db> {
     }
 }
 
+/// Read the cached hash of `key`.
+fn cached_hash<H: HashScheme, Db: KVDatabase>(
+    db: &Db,
+    key: &[u8],
+) -> Result<Option<ZkHash>, KeyHasherError<H::Error>> {
+    let Some(hash) = db
+        .get(key)
+        .map_err(SyncCachedKeyHasherErr::Db)
+        .map_err(|e| KeyHasherError::Other(Box::new(e)))?
+    else {
+        return Ok(None);
+    };
+    let hash = hash.as_ref();
+    let hash: &[u8; 32] = hash
+        .try_into()
+        .map_err(|_| SyncCachedKeyHasherErr::<Db::Error>::InvalidHash)
+        .map_err(|e| KeyHasherError::Other(Box::new(e)))?;
+    Ok(Some(ZkHash::from(*hash)))
+}
+
 impl<H: HashScheme, Db: KVDatabase> KeyHasher<H> for SyncCachedKeyHasher<H, Db> {
     fn hash(&self, key: &[u8]) -> Result<ZkHash, KeyHasherError<H::Error>> {
         let mut db = self.inner.lock().unwrap();
-        if let Some(hash) = db
-            .get(key)
-            .map_err(SyncCachedKeyHasherErr::Db)
-            .map_err(|e| KeyHasherError::Other(Box::new(e)))?
-        {
-            let hash = hash.as_ref();
-            let hash: &[u8; 32] = hash
-                .try_into()
-                .map_err(|_| SyncCachedKeyHasherErr::<Db::Error>::InvalidHash)
-                .map_err(|e| KeyHasherError::Other(Box::new(e)))?;
-            return Ok(ZkHash::from(*hash));
-        };
+        if let Some(hash) = cached_hash::<H, Db>(&db, key)? {
+            return Ok(hash);
+        }
         let hash = H::hash_bytes(key).map_err(KeyHasherError::Hash)?;
         db.put(key, hash.as_slice())
             .map_err(SyncCachedKeyHasherErr::Db)
             .map_err(|e| KeyHasherError::Other(Box::new(e)))?;
         Ok(hash)
     }
+
+    /// Look up all keys under a single lock and cache the missing ones in a single write,
+    /// as [`warm_up`](SyncCachedKeyHasher::warm_up) does.
+    fn hash_many(&self, keys: &[&[u8]]) -> Result<Vec<ZkHash>, KeyHasherError<H::Error>> {
+        let mut db = self.inner.lock().unwrap();
+        let mut missing = HashMap::new();
+        let mut hashes = Vec::with_capacity(keys.len());
+        for key in keys {
+            let hash = match missing.get(*key) {
+                Some(hash) => *hash,
+                None => match cached_hash::<H, Db>(&db, key)? {
+                    Some(hash) => hash,
+                    None => {
+                        let hash = H::hash_bytes(key).map_err(KeyHasherError::Hash)?;
+                        missing.insert(Box::<[u8]>::from(*key), hash);
+                        hash
+                    }
+                },
+            };
+            hashes.push(hash);
+        }
+        db.extend(
+            missing
+                .into_iter()
+                .map(|(key, hash)| (key, Db::Item::from_slice(hash.as_slice()))),
+        )
+        .map_err(SyncCachedKeyHasherErr::Db)
+        .map_err(|e| KeyHasherError::Other(Box::new(e)))?;
+        Ok(hashes)
+    }
 }
@@ -7,6 +7,9 @@ pub mod poseidon;
 
 pub mod key_hasher;
 
+mod key_bytes;
+pub use key_bytes::KeyBytes;
+
 /// The size of an element in the hash scheme.
 pub const HASH_SIZE: usize = 32;
 
@@ -30,6 +33,17 @@ pub trait HashScheme: Debug + Copy + Clone + Sized {
     /// Max level of the trie when using this hash scheme.
     const TRIE_MAX_LEVELS: usize;
 
+    /// Identifier of the hash scheme, persisted by [`NodeDb::open`](crate::db::NodeDb::open).
+    ///
+    /// Must be changed whenever the scheme hashes the same input differently.
+    const ID: &'static str;
+
+    /// The root of an empty trie, i.e. the hash of the empty node.
+    ///
+    /// Branches refer to empty children by the zero hash,
+    /// so this is zero for every scheme and not meant to be overridden.
+    const EMPTY_ROOT: ZkHash = ZkHash::ZERO;
+
     /// The error type for hashing.
     type Error: std::error::Error;
 
@@ -58,6 +72,28 @@ pub trait HashScheme: Debug + Copy + Clone + Sized {
         Self::raw_hash(kind, le_bytes).map(|h| h.as_canonical_repr())
     }
 
+    /// Hash `branches` in order, returning the hash of each.
+    ///
+    /// A [`BranchInput::Output`] refers to an earlier branch in `branches`,
+    /// so a dirty subtree can be hashed bottom-up in one call.
+    fn hash_branches(branches: &[(u64, [BranchInput; 2])]) -> Result<Vec<ZkHash>, Self::Error> {
+        let mut hashes: Vec<ZkHash> = Vec::with_capacity(branches.len());
+        for (kind, inputs) in branches {
+            let inputs = inputs.map(|input| match input {
+                BranchInput::Hash(hash) => hash,
+                BranchInput::Output(index) => hashes[index],
+            });
+            hashes.push(Self::hash(*kind, inputs)?);
+        }
+        Ok(hashes)
+    }
+
+    /// Get the root of an empty trie, see [`HashScheme::EMPTY_ROOT`].
+    #[inline(always)]
+    fn empty_root() -> ZkHash {
+        Self::EMPTY_ROOT
+    }
+
     /// Hash a variable length byte array with maximum length of `ELEMENT_SIZE`.
     fn hash_bytes(v: &[u8]) -> Result<ZkHash, Self::Error>;
 
@@ -65,14 +101,16 @@ pub trait HashScheme: Debug + Copy + Clone + Sized {
     ///
     /// The first 24 values can be compressed (consider as hash).
     ///
-    /// # Panics
-    ///
-    /// Panics if `value_bytes` is empty.
+    /// An empty array hashes to [`ZkHash::ZERO`]. Leaves never have empty values,
+    /// see [`Node::check_leaf_values`](crate::trie::Node::check_leaf_values),
+    /// so this only keeps hashing malformed nodes from panicking.
     fn hash_bytes_array(
         value_bytes: &[[u8; 32]],
         compression_flag: u32,
     ) -> Result<ZkHash, Self::Error> {
-        assert!(!value_bytes.is_empty());
+        if value_bytes.is_empty() {
+            return Ok(ZkHash::ZERO);
+        }
         let mut hashes = Vec::with_capacity(value_bytes.len());
         for (i, bytes) in value_bytes.iter().enumerate() {
             if i <= 24 && compression_flag & (1 << i) != 0 {
@@ -83,17 +121,49 @@ pub trait HashScheme: Debug + Copy + Clone + Sized {
         }
 
         let domain = value_bytes.len() as u64 * HASH_DOMAIN_ELEMS_BASE;
-        while hashes.len() > 1 {
-            let length = hashes.len();
-            for i in 0..length / 2 {
-                hashes[i] = Self::hash(domain, [hashes[2 * i], hashes[2 * i + 1]])?;
-            }
-            if length % 2 != 0 {
-                hashes[length / 2] = hashes.pop().unwrap();
-            }
-            hashes.truncate(length / 2 + length % 2);
-        }
+        fold_hashes(hashes, |a, b| Self::hash(domain, [a, b]))
+    }
+}
+
+/// An input of [`HashScheme::hash_branches`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BranchInput {
+    /// A known hash
+    Hash(ZkHash),
+    /// The output of the branch at this index
+    Output(usize),
+}
+
+/// A [`HashScheme`] whose hashes are native field elements,
+/// so chained hashes can skip the conversion to and from [`ZkHash`] in between.
+pub trait NativeHashScheme: HashScheme {
+    /// The native element, e.g. a field element
+    type Element: HashOutput + Debug;
 
-        Ok(hashes[0])
+    /// Convert a hash into an element, failing if it's not a valid element.
+    fn element(hash: &ZkHash) -> Result<Self::Element, Self::Error>;
+
+    /// Same as [`HashScheme::hash`], on elements.
+    fn hash_elements(kind: u64, inputs: [Self::Element; 2]) -> Result<Self::Element, Self::Error>;
+
+    /// Same as [`HashScheme::hash_bytes`], returning an element.
+    fn hash_bytes_element(v: &[u8]) -> Result<Self::Element, Self::Error>;
+}
+
+/// Hash pairs of neighbours level by level until one is left, an odd one is carried up.
+fn fold_hashes<T: Copy, E>(
+    mut hashes: Vec<T>,
+    mut hash: impl FnMut(T, T) -> Result<T, E>,
+) -> Result<T, E> {
+    while hashes.len() > 1 {
+        let length = hashes.len();
+        for i in 0..length / 2 {
+            hashes[i] = hash(hashes[2 * i], hashes[2 * i + 1])?;
+        }
+        if length % 2 != 0 {
+            hashes[length / 2] = hashes.pop().unwrap();
+        }
+        hashes.truncate(length / 2 + length % 2);
     }
+    Ok(hashes[0])
 }
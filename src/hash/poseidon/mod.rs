@@ -1,8 +1,11 @@
 //! Poseidon bn254 hash scheme.
-use super::{HashOutput, HashScheme, ZkHash, HASH_DOMAIN_ELEMS_BASE, HASH_SIZE};
+use super::{
+    fold_hashes, BranchInput, HashOutput, HashScheme, NativeHashScheme, ZkHash,
+    HASH_DOMAIN_ELEMS_BASE, HASH_SIZE,
+};
 use poseidon_bn254::{hash_with_domain, Fr, PrimeField};
 
-#[cfg(test)]
+#[cfg(all(test, feature = "compat-tests"))]
 pub(crate) mod tests;
 
 /// The length of a Poseidon hash.
@@ -59,6 +62,8 @@ impl HashOutput for Fr {
 impl HashScheme for Poseidon {
     const TRIE_MAX_LEVELS: usize = TRIE_MAX_LEVELS;
 
+    const ID: &'static str = "poseidon-bn254";
+
     type Error = PoseidonError;
 
     fn new_hash_try_from_bytes(bytes: &[u8]) -> Result<ZkHash, Self::Error> {
@@ -85,20 +90,81 @@ impl HashScheme for Poseidon {
     }
 
     fn hash_bytes(v: &[u8]) -> Result<ZkHash, Self::Error> {
+        Self::hash_bytes_element(v).map(|h| h.as_canonical_repr())
+    }
+
+    /// Same as the default implementation,
+    /// but intermediate hashes stay field elements.
+    fn hash_bytes_array(
+        value_bytes: &[[u8; 32]],
+        compression_flag: u32,
+    ) -> Result<ZkHash, Self::Error> {
+        if value_bytes.is_empty() {
+            return Ok(ZkHash::ZERO);
+        }
+        let mut elements = Vec::with_capacity(value_bytes.len());
+        for (i, bytes) in value_bytes.iter().enumerate() {
+            if i <= 24 && compression_flag & (1 << i) != 0 {
+                elements.push(Self::hash_bytes_element(bytes.as_slice())?);
+            } else {
+                elements.push(Self::element(&ZkHash::from(*bytes))?);
+            }
+        }
+
+        let domain = value_bytes.len() as u64 * HASH_DOMAIN_ELEMS_BASE;
+        fold_hashes(elements, |a, b| Self::hash_elements(domain, [a, b]))
+            .map(|h| h.as_canonical_repr())
+    }
+
+    /// Same as the default implementation,
+    /// but outputs are passed on as field elements.
+    fn hash_branches(branches: &[(u64, [BranchInput; 2])]) -> Result<Vec<ZkHash>, Self::Error> {
+        let mut elements: Vec<Fr> = Vec::with_capacity(branches.len());
+        for (kind, [left, right]) in branches {
+            let input = |input: &BranchInput| match input {
+                BranchInput::Hash(hash) => Self::element(hash),
+                BranchInput::Output(index) => Ok(elements[*index]),
+            };
+            let inputs = [input(left)?, input(right)?];
+            elements.push(Self::hash_elements(*kind, inputs)?);
+        }
+        Ok(elements.iter().map(|e| e.as_canonical_repr()).collect())
+    }
+}
+
+impl NativeHashScheme for Poseidon {
+    type Element = Fr;
+
+    #[inline]
+    fn element(hash: &ZkHash) -> Result<Fr, Self::Error> {
+        Fr::from_canonical_repr(*hash).ok_or(PoseidonError::InvalidFieldElement)
+    }
+
+    #[inline]
+    fn hash_elements(kind: u64, inputs: [Fr; 2]) -> Result<Fr, Self::Error> {
+        Ok(hash_with_domain(&inputs, Fr::from(kind)))
+    }
+
+    fn hash_bytes_element(v: &[u8]) -> Result<Fr, Self::Error> {
         if v.len() > HASH_SIZE {
             return Err(PoseidonError::InvalidByteLength(v.len()));
         }
         const HALF_LEN: usize = HASH_SIZE / 2;
 
+        // little endian halves, the big endian high half of each element is zero
         let mut v_lo = [0u8; HASH_SIZE];
         let mut v_hi = [0u8; HASH_SIZE];
         if v.len() > HALF_LEN {
-            v_lo[HALF_LEN..].copy_from_slice(&v[..HALF_LEN]);
-            v_hi[HALF_LEN..v.len()].copy_from_slice(&v[HALF_LEN..]);
+            v_lo[..HALF_LEN].copy_from_slice(&v[..HALF_LEN]);
+            v_hi[..v.len() - HALF_LEN].copy_from_slice(&v[HALF_LEN..]);
         } else {
-            v_lo[HALF_LEN..HALF_LEN + v.len()].copy_from_slice(v);
+            v_lo[..v.len()].copy_from_slice(v);
         }
+        v_lo[..HALF_LEN].reverse();
+        v_hi[..HALF_LEN].reverse();
 
-        Self::hash(HASH_DOMAIN_BYTE32, [v_lo.into(), v_hi.into()])
+        let lo = Fr::from_repr_vartime(v_lo).ok_or(PoseidonError::InvalidFieldElement)?;
+        let hi = Fr::from_repr_vartime(v_hi).ok_or(PoseidonError::InvalidFieldElement)?;
+        Self::hash_elements(HASH_DOMAIN_BYTE32, [lo, hi])
     }
 }
@@ -1,5 +1,5 @@
 use super::{HashOutput, HashScheme, Poseidon};
-use poseidon_bn254::{hash_with_domain, Field, Fr, PrimeField};
+use poseidon_bn254::{Field, Fr};
 use rand::{random, thread_rng, Rng};
 use zktrie::HashField;
 use zktrie_rust::hash::{AsHash, Hash as _};
@@ -7,7 +7,7 @@ use zktrie_rust::types::{Node, TrieHashScheme};
 
 #[ctor::ctor]
 fn set_hash_scheme() {
-    zktrie::init_hash_scheme_simple(poseidon_hash_scheme)
+    crate::compat::init_legacy_hash_scheme()
 }
 
 pub(crate) fn gen_random_bytes() -> (Vec<[u8; 32]>, u32) {
@@ -28,13 +28,6 @@ pub(crate) fn gen_random_bytes() -> (Vec<[u8; 32]>, u32) {
     (values, compression_flag)
 }
 
-fn poseidon_hash_scheme(a: &[u8; 32], b: &[u8; 32], domain: &[u8; 32]) -> Option<[u8; 32]> {
-    let a = Fr::from_repr_vartime(*a)?;
-    let b = Fr::from_repr_vartime(*b)?;
-    let domain = Fr::from_repr_vartime(*domain)?;
-    Some(hash_with_domain(&[a, b], domain).to_repr())
-}
-
 #[test]
 fn test_hash() {
     for _ in 0..1000 {
@@ -47,11 +47,23 @@
 extern crate tracing;
 extern crate core;
 
+#[cfg(feature = "compat-tests")]
+#[cfg_attr(docsrs, doc(cfg(feature = "compat-tests")))]
+pub mod compat;
 pub mod db;
 pub mod hash;
+#[cfg(feature = "maintenance")]
+#[cfg_attr(docsrs, doc(cfg(feature = "maintenance")))]
+pub mod maintenance;
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub mod metrics;
 #[cfg(feature = "scroll")]
 #[cfg_attr(docsrs, doc(cfg(feature = "scroll")))]
 pub mod scroll_types;
+#[cfg(feature = "testvectors")]
+#[cfg_attr(docsrs, doc(cfg(feature = "testvectors")))]
+pub mod testvectors;
 pub mod trie;
 
 #[cfg(feature = "hashbrown")]
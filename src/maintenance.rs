@@ -0,0 +1,497 @@
+//! Background maintenance for long-running services, see [`spawn_maintenance`].
+//!
+//! Tries, databases and caches to maintain implement [`Maintain`] and are shared with the
+//! maintenance thread, e.g. behind an `Arc<Mutex<_>>`. Every task runs on its own interval
+//! of the [`MaintenanceSchedule`], failures are logged and counted but never stop the thread.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use std::sync::{Arc, Mutex};
+//! use std::time::Duration;
+//! use zktrie_ng::{
+//!     db::{kv::HashMapDb, NodeDb},
+//!     maintenance::{spawn_maintenance, MaintenanceSchedule, TrieMaintenance},
+//!     trie::ZkTrie,
+//! };
+//!
+//! let mut db = NodeDb::new(HashMapDb::default()).into_shared();
+//! let trie = Arc::new(Mutex::new(ZkTrie::default()));
+//!
+//! let schedule = MaintenanceSchedule::default().with_gc_interval(Some(Duration::from_secs(10)));
+//! let target = TrieMaintenance::new(trie.clone(), db.handle()).boxed();
+//! let handle = spawn_maintenance(schedule, [target]).unwrap();
+//!
+//! let mut guard = trie.lock().unwrap();
+//! guard.raw_update(&db, [1u8; 32], vec![[1u8; 32]], 1).unwrap();
+//! guard.commit(&mut db).unwrap();
+//! drop(guard);
+//!
+//! let stats = handle.stop();
+//! ```
+use crate::{
+    db::{kv::KVDatabase, NodeDb},
+    hash::{key_hasher::KeyHasher, HashScheme},
+    trie::{PathOrder, ZkTrie},
+};
+use std::fmt::{Debug, Formatter};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Error of a maintenance task.
+pub type MaintenanceError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A trie, database or cache maintained by [`spawn_maintenance`].
+///
+/// All tasks do nothing by default.
+pub trait Maintain: Send {
+    /// Run incremental garbage collection, returns the number of removed entries.
+    fn gc(&mut self) -> Result<usize, MaintenanceError> {
+        Ok(0)
+    }
+
+    /// Evict cached entries, returns the number of evicted entries.
+    fn evict(&mut self) -> Result<usize, MaintenanceError> {
+        Ok(0)
+    }
+
+    /// Flush metrics, e.g. gauges that are not updated by the operations.
+    fn flush_metrics(&mut self) {}
+}
+
+impl<T: Maintain + ?Sized> Maintain for Box<T> {
+    #[inline]
+    fn gc(&mut self) -> Result<usize, MaintenanceError> {
+        (**self).gc()
+    }
+
+    #[inline]
+    fn evict(&mut self) -> Result<usize, MaintenanceError> {
+        (**self).evict()
+    }
+
+    #[inline]
+    fn flush_metrics(&mut self) {
+        (**self).flush_metrics()
+    }
+}
+
+impl<T: Maintain + ?Sized> Maintain for Arc<Mutex<T>> {
+    #[inline]
+    fn gc(&mut self) -> Result<usize, MaintenanceError> {
+        self.lock().unwrap().gc()
+    }
+
+    #[inline]
+    fn evict(&mut self) -> Result<usize, MaintenanceError> {
+        self.lock().unwrap().evict()
+    }
+
+    #[inline]
+    fn flush_metrics(&mut self) {
+        self.lock().unwrap().flush_metrics()
+    }
+}
+
+/// Maintain a shared trie and a handle to its database.
+///
+/// Garbage collection removes at most [`gc_budget`](TrieMaintenance::with_gc_budget)
+/// retired nodes per run, oldest first, see [`ZkTrie::gc_incremental`].
+/// The trie is locked while it runs.
+pub struct TrieMaintenance<H: HashScheme, K: KeyHasher<H>, P: PathOrder, Db> {
+    trie: Arc<Mutex<ZkTrie<H, K, P>>>,
+    db: NodeDb<Db>,
+    gc_budget: usize,
+}
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder, Db: KVDatabase> TrieMaintenance<H, K, P, Db> {
+    /// Default number of retired nodes removed per run.
+    pub const DEFAULT_GC_BUDGET: usize = 10_000;
+
+    /// Maintain `trie`, whose nodes are in `db`.
+    pub fn new(trie: Arc<Mutex<ZkTrie<H, K, P>>>, db: NodeDb<Db>) -> Self {
+        Self {
+            trie,
+            db,
+            gc_budget: Self::DEFAULT_GC_BUDGET,
+        }
+    }
+
+    /// Set the number of retired nodes removed per run.
+    pub fn with_gc_budget(mut self, gc_budget: usize) -> Self {
+        self.gc_budget = gc_budget;
+        self
+    }
+
+    /// Get the number of retired nodes removed per run.
+    #[inline]
+    pub fn gc_budget(&self) -> usize {
+        self.gc_budget
+    }
+
+    /// Box the target, to pass it to [`spawn_maintenance`] with other targets.
+    pub fn boxed(self) -> Box<dyn Maintain>
+    where
+        Self: Maintain + 'static,
+    {
+        Box::new(self)
+    }
+}
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder, Db> Debug for TrieMaintenance<H, K, P, Db> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TrieMaintenance")
+            .field("gc_budget", &self.gc_budget)
+            .finish()
+    }
+}
+
+impl<H, K, P, Db> Maintain for TrieMaintenance<H, K, P, Db>
+where
+    H: HashScheme,
+    K: KeyHasher<H> + Send,
+    P: PathOrder,
+    Db: KVDatabase + Send,
+    ZkTrie<H, K, P>: Send,
+{
+    fn gc(&mut self) -> Result<usize, MaintenanceError> {
+        self.trie
+            .lock()
+            .unwrap()
+            .gc_incremental(&mut self.db, self.gc_budget)
+            .map_err(|e| e.to_string().into())
+    }
+
+    fn flush_metrics(&mut self) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_retired_nodes(self.trie.lock().unwrap().retired_nodes().count());
+    }
+}
+
+#[cfg(feature = "key-cache")]
+impl<H, Db> Maintain for crate::hash::key_hasher::SyncCachedKeyHasher<H, Db>
+where
+    H: HashScheme + Send + Sync,
+    Db: KVDatabase + Send,
+{
+    /// Clear the cache, see [`SyncCachedKeyHasher::clear`](crate::hash::key_hasher::SyncCachedKeyHasher::clear).
+    fn evict(&mut self) -> Result<usize, MaintenanceError> {
+        Ok(self.clear()?)
+    }
+}
+
+/// How often the tasks of [`spawn_maintenance`] run, disabled tasks never run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaintenanceSchedule {
+    gc_interval: Option<Duration>,
+    evict_interval: Option<Duration>,
+    metrics_interval: Option<Duration>,
+}
+
+impl Default for MaintenanceSchedule {
+    /// gc every minute, eviction every 10 minutes, metrics every 15 seconds
+    fn default() -> Self {
+        Self {
+            gc_interval: Some(Duration::from_secs(60)),
+            evict_interval: Some(Duration::from_secs(600)),
+            metrics_interval: Some(Duration::from_secs(15)),
+        }
+    }
+}
+
+impl MaintenanceSchedule {
+    /// A schedule with all tasks disabled.
+    pub fn disabled() -> Self {
+        Self {
+            gc_interval: None,
+            evict_interval: None,
+            metrics_interval: None,
+        }
+    }
+
+    /// Set the garbage collection interval, `None` to disable it.
+    pub fn with_gc_interval(mut self, interval: Option<Duration>) -> Self {
+        self.gc_interval = interval;
+        self
+    }
+
+    /// Set the cache eviction interval, `None` to disable it.
+    pub fn with_evict_interval(mut self, interval: Option<Duration>) -> Self {
+        self.evict_interval = interval;
+        self
+    }
+
+    /// Set the metrics flush interval, `None` to disable it.
+    pub fn with_metrics_interval(mut self, interval: Option<Duration>) -> Self {
+        self.metrics_interval = interval;
+        self
+    }
+
+    /// Get the garbage collection interval
+    #[inline]
+    pub fn gc_interval(&self) -> Option<Duration> {
+        self.gc_interval
+    }
+
+    /// Get the cache eviction interval
+    #[inline]
+    pub fn evict_interval(&self) -> Option<Duration> {
+        self.evict_interval
+    }
+
+    /// Get the metrics flush interval
+    #[inline]
+    pub fn metrics_interval(&self) -> Option<Duration> {
+        self.metrics_interval
+    }
+}
+
+/// The tasks of a [`MaintenanceSchedule`].
+#[derive(Clone, Copy, Debug)]
+enum Task {
+    Gc,
+    Evict,
+    Metrics,
+}
+
+/// What the maintenance thread has done so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MaintenanceStats {
+    /// Number of garbage collection runs over all targets
+    pub gc_runs: usize,
+    /// Number of entries removed by garbage collection
+    pub gc_removed: usize,
+    /// Number of cache eviction runs
+    pub evict_runs: usize,
+    /// Number of evicted cache entries
+    pub evicted: usize,
+    /// Number of metrics flushes
+    pub metrics_flushes: usize,
+    /// Number of failed tasks, one per target
+    pub errors: usize,
+}
+
+impl MaintenanceStats {
+    fn add(&mut self, other: &Self) {
+        self.gc_runs += other.gc_runs;
+        self.gc_removed += other.gc_removed;
+        self.evict_runs += other.evict_runs;
+        self.evicted += other.evicted;
+        self.metrics_flushes += other.metrics_flushes;
+        self.errors += other.errors;
+    }
+}
+
+/// Handle to the thread started by [`spawn_maintenance`].
+///
+/// The thread is stopped when the handle is dropped, after the running task finishes.
+pub struct MaintenanceHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    stats: Arc<Mutex<MaintenanceStats>>,
+}
+
+impl MaintenanceHandle {
+    /// Get the stats so far
+    pub fn stats(&self) -> MaintenanceStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Check if the thread is still running
+    pub fn is_running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    /// Stop the thread, wait for the running task to finish and return the final stats.
+    pub fn stop(mut self) -> MaintenanceStats {
+        self.shutdown();
+        self.stats()
+    }
+
+    fn shutdown(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("maintenance thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for MaintenanceHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Debug for MaintenanceHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MaintenanceHandle")
+            .field("running", &self.is_running())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// Start a thread running the maintenance tasks of `targets` on `schedule`.
+///
+/// Every task first runs one interval after the start.
+pub fn spawn_maintenance(
+    schedule: MaintenanceSchedule,
+    targets: impl IntoIterator<Item = Box<dyn Maintain>>,
+) -> std::io::Result<MaintenanceHandle> {
+    let mut targets: Vec<_> = targets.into_iter().collect();
+    let (stop, stopped) = mpsc::channel::<()>();
+    let stats = Arc::new(Mutex::new(MaintenanceStats::default()));
+    let thread_stats = stats.clone();
+    let thread = std::thread::Builder::new()
+        .name("zktrie-maintenance".to_string())
+        .spawn(move || {
+            let start = Instant::now();
+            let tasks = [
+                (Task::Gc, schedule.gc_interval),
+                (Task::Evict, schedule.evict_interval),
+                (Task::Metrics, schedule.metrics_interval),
+            ];
+            let mut due = tasks.map(|(_, interval)| interval.map(|i| start + i));
+            loop {
+                let timeout = match due.iter().flatten().min() {
+                    Some(next) => next.saturating_duration_since(Instant::now()),
+                    None => Duration::MAX,
+                };
+                match stopped.recv_timeout(timeout) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // stopped or the handle is dropped
+                    _ => break,
+                }
+                let now = Instant::now();
+                for (due, (task, interval)) in due.iter_mut().zip(tasks) {
+                    let (Some(at), Some(interval)) = (*due, interval) else {
+                        continue;
+                    };
+                    if at > now {
+                        continue;
+                    }
+                    let stats = run_task(task, &mut targets);
+                    thread_stats.lock().unwrap().add(&stats);
+                    *due = Some(now + interval);
+                }
+            }
+            trace!("maintenance thread stopped");
+        })?;
+    Ok(MaintenanceHandle {
+        stop: Some(stop),
+        thread: Some(thread),
+        stats,
+    })
+}
+
+/// Run a task for all targets.
+fn run_task(task: Task, targets: &mut [Box<dyn Maintain>]) -> MaintenanceStats {
+    let mut stats = MaintenanceStats::default();
+    match task {
+        Task::Gc => stats.gc_runs += 1,
+        Task::Evict => stats.evict_runs += 1,
+        Task::Metrics => stats.metrics_flushes += 1,
+    }
+    for (index, target) in targets.iter_mut().enumerate() {
+        match task {
+            Task::Gc => match target.gc() {
+                Ok(removed) => stats.gc_removed += removed,
+                Err(e) => {
+                    warn!(target = index, error = %e, "maintenance gc failed");
+                    stats.errors += 1;
+                }
+            },
+            Task::Evict => match target.evict() {
+                Ok(evicted) => stats.evicted += evicted,
+                Err(e) => {
+                    warn!(target = index, error = %e, "maintenance eviction failed");
+                    stats.errors += 1;
+                }
+            },
+            Task::Metrics => target.flush_metrics(),
+        }
+    }
+    trace!(task = ?task, targets = targets.len(), "maintenance task done");
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::kv::HashMapDb;
+
+    #[test]
+    fn test_spawn_maintenance() {
+        let mut db = NodeDb::new(HashMapDb::new(true)).into_shared();
+        let trie = Arc::new(Mutex::new(ZkTrie::default()));
+        {
+            let mut trie = trie.lock().unwrap();
+            for i in 0..10u8 {
+                trie.raw_update(&db, [i; 32], vec![[i; 32]], 1).unwrap();
+                trie.commit(&mut db).unwrap();
+            }
+            assert!(trie.retired_nodes().count() > 0);
+        }
+
+        let schedule = MaintenanceSchedule::disabled()
+            .with_gc_interval(Some(Duration::from_millis(5)))
+            .with_metrics_interval(Some(Duration::from_millis(5)));
+        let target = TrieMaintenance::new(trie.clone(), db.handle()).with_gc_budget(4);
+        let handle = spawn_maintenance(schedule, [target.boxed()]).unwrap();
+        assert!(handle.is_running());
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while trie.lock().unwrap().retired_nodes().count() > 0 {
+            assert!(Instant::now() < deadline, "retired nodes not collected");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let stats = handle.stop();
+        assert!(stats.gc_runs > 1);
+        assert!(stats.gc_removed > 4);
+        assert!(stats.metrics_flushes > 0);
+        assert_eq!(stats.evict_runs, 0);
+        assert_eq!(stats.errors, 0);
+
+        let trie = trie.lock().unwrap();
+        for i in 0..10u8 {
+            assert!(trie
+                .get::<_, [[u8; 32]; 1], _>(&db, [i; 32])
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    #[cfg(feature = "key-cache")]
+    #[test]
+    fn test_evict_key_cache() {
+        use crate::hash::{key_hasher::SyncCachedKeyHasher, poseidon::Poseidon};
+
+        let hasher = SyncCachedKeyHasher::<Poseidon>::new(HashMapDb::default());
+        for i in 0..8u8 {
+            KeyHasher::<Poseidon>::hash(&hasher, &[i; 32]).unwrap();
+        }
+        let hasher = Arc::new(Mutex::new(hasher));
+        let schedule =
+            MaintenanceSchedule::disabled().with_evict_interval(Some(Duration::from_millis(5)));
+        let handle =
+            spawn_maintenance(schedule, [Box::new(hasher.clone()) as Box<dyn Maintain>]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while handle.stats().evict_runs == 0 {
+            assert!(Instant::now() < deadline, "cache not evicted");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let stats = handle.stop();
+        assert_eq!(stats.evicted, 8);
+        assert_eq!(hasher.lock().unwrap().clear().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_stop_disabled() {
+        let handle = spawn_maintenance(MaintenanceSchedule::disabled(), []).unwrap();
+        assert_eq!(handle.stop(), MaintenanceStats::default());
+    }
+}
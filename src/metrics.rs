@@ -0,0 +1,68 @@
+//! Trie metrics exported through the [`metrics`](::metrics) facade.
+//!
+//! Install any recorder, e.g. `metrics-exporter-prometheus`, to collect them.
+//! Gauges are shared by all tries in the process.
+use crate::trie::NodeReadStats;
+use ::metrics::{
+    counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit,
+};
+use std::time::Duration;
+
+/// Number of dirty nodes of the last updated trie.
+pub const DIRTY_NODES: &str = "zktrie_dirty_nodes";
+/// Duration of commits.
+pub const COMMIT_DURATION: &str = "zktrie_commit_duration_seconds";
+/// Number of nodes removed by garbage collection.
+pub const GC_REMOVED_NODES: &str = "zktrie_gc_removed_nodes_total";
+/// Number of backend database reads per operation, labeled by `op`.
+pub const DB_READS: &str = "zktrie_db_reads";
+/// Root version of the last committed trie.
+pub const ROOT_VERSION: &str = "zktrie_root_version";
+/// Number of retired nodes waiting for garbage collection, flushed by
+/// [`maintenance`](crate::maintenance) tasks.
+pub const RETIRED_NODES: &str = "zktrie_retired_nodes";
+
+/// Describe all trie metrics to the installed recorder.
+pub fn describe() {
+    describe_gauge!(DIRTY_NODES, Unit::Count, "Number of dirty nodes");
+    describe_histogram!(COMMIT_DURATION, Unit::Seconds, "Duration of commits");
+    describe_counter!(
+        GC_REMOVED_NODES,
+        Unit::Count,
+        "Number of nodes removed by garbage collection"
+    );
+    describe_histogram!(
+        DB_READS,
+        Unit::Count,
+        "Number of backend database reads per operation"
+    );
+    describe_gauge!(ROOT_VERSION, "Root version of the last committed trie");
+    describe_gauge!(
+        RETIRED_NODES,
+        Unit::Count,
+        "Number of retired nodes waiting for garbage collection"
+    );
+}
+
+pub(crate) fn record_dirty_nodes(dirty_nodes: usize) {
+    gauge!(DIRTY_NODES).set(dirty_nodes as f64);
+}
+
+pub(crate) fn record_db_reads(op: &'static str, stats: &NodeReadStats) {
+    histogram!(DB_READS, "op" => op).record(stats.db as f64);
+}
+
+pub(crate) fn record_commit(duration: Duration, root_version: u64) {
+    histogram!(COMMIT_DURATION).record(duration);
+    gauge!(ROOT_VERSION).set(root_version as f64);
+    gauge!(DIRTY_NODES).set(0.0);
+}
+
+#[cfg_attr(not(feature = "maintenance"), allow(dead_code))]
+pub(crate) fn record_retired_nodes(retired_nodes: usize) {
+    gauge!(RETIRED_NODES).set(retired_nodes as f64);
+}
+
+pub(crate) fn record_gc(removed: usize) {
+    counter!(GC_REMOVED_NODES).increment(removed as u64);
+}
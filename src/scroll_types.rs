@@ -24,10 +24,48 @@
 //!
 //! assert_eq!(trie_account, account);
 //! ```
-use crate::hash::ZkHash;
-use crate::trie::{DecodeValueBytes, EncodeValueBytes};
-use alloy_primitives::{B256, U256};
-use revm_primitives::AccountInfo;
+use crate::db::{kv::KVDatabase, NodeDb};
+use crate::hash::{
+    key_hasher::{KeyHasher, NoCacheHasher},
+    poseidon::{Poseidon, PoseidonError},
+    HashScheme, ZkHash,
+};
+use crate::trie::{
+    CommittedRoot, DecodeValueBytes, EncodeValueBytes, Node, RecomputeRootError, SharedProofNodes,
+    ZkTrie, ZkTrieError, MAX_COMPRESSED_VALUES,
+};
+use crate::HashMap;
+use alloy_primitives::{Address, B256, U256};
+use revm_primitives::{keccak256, poseidon, AccountInfo, KECCAK_EMPTY};
+use std::thread;
+
+mod genesis;
+pub use genesis::{GenesisAccount, GenesisBuilder};
+mod view;
+pub use view::AccountTrieView;
+
+type Result<T, Db> = std::result::Result<T, ZkTrieError<PoseidonError, <Db as KVDatabase>::Error>>;
+
+/// The account trie, keyed by address with [`Account`] values.
+pub type AccountTrie<K = NoCacheHasher> = ZkTrie<Poseidon, K>;
+
+/// The storage trie of an account, keyed by slot with [`U256`] values.
+pub type StorageTrie<K = NoCacheHasher> = ZkTrie<Poseidon, K>;
+
+/// The number of value preimages of a storage slot leaf.
+pub const STORAGE_VALUE_FIELDS: usize = 1;
+
+/// The compression flags of a storage slot leaf, the value is compressed.
+pub const STORAGE_COMPRESSION_FLAGS: u32 = 1;
+
+// The circuits expect exactly these layouts, changing them changes every state root.
+const _: () = {
+    assert!(Account::VALUE_FIELDS == 5 && Account::COMPRESSION_FLAGS == 8);
+    assert!(STORAGE_VALUE_FIELDS == 1 && STORAGE_COMPRESSION_FLAGS == 1);
+    assert!(Account::VALUE_FIELDS <= MAX_COMPRESSED_VALUES);
+    assert!(Account::COMPRESSION_FLAGS >> Account::VALUE_FIELDS == 0);
+    assert!(STORAGE_COMPRESSION_FLAGS >> STORAGE_VALUE_FIELDS == 0);
+};
 
 /// Account data stored in zkTrie.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -48,16 +86,14 @@ pub struct Account {
 
 impl EncodeValueBytes for &Account {
     fn encode_values_bytes(&self) -> (Vec<[u8; 32]>, u32) {
-        (
-            vec![
-                U256::from_limbs([self.nonce, self.code_size, 0, 0]).to_be_bytes(),
-                self.balance.to_be_bytes(),
-                self.storage_root.0,
-                self.code_hash.0,
-                self.poseidon_code_hash.0,
-            ],
-            8,
-        )
+        let values: [[u8; 32]; Account::VALUE_FIELDS] = [
+            U256::from_limbs([self.nonce, self.code_size, 0, 0]).to_be_bytes(),
+            self.balance.to_be_bytes(),
+            self.storage_root.0,
+            self.code_hash.0,
+            self.poseidon_code_hash.0,
+        ];
+        (values.to_vec(), Account::COMPRESSION_FLAGS)
     }
 }
 
@@ -69,7 +105,7 @@ impl EncodeValueBytes for Account {
 
 impl DecodeValueBytes for Account {
     fn decode_values_bytes(values: &[[u8; 32]]) -> Option<Self> {
-        let values: &[[u8; 32]; 5] = values.try_into().ok()?;
+        let values: &[[u8; 32]; Account::VALUE_FIELDS] = values.try_into().ok()?;
         Some(Account {
             nonce: u64::from_be_bytes(values[0][24..].try_into().unwrap()),
             code_size: u64::from_be_bytes(values[0][16..24].try_into().unwrap()),
@@ -82,6 +118,12 @@ impl DecodeValueBytes for Account {
 }
 
 impl Account {
+    /// The number of value preimages of an account leaf.
+    pub const VALUE_FIELDS: usize = 5;
+
+    /// The compression flags of an account leaf, only the keccak code hash is compressed.
+    pub const COMPRESSION_FLAGS: u32 = 8;
+
     /// Create an account from revm account and storage root.
     pub fn from_revm_account_with_storage_root(acc: AccountInfo, storage_root: B256) -> Self {
         Account {
@@ -93,6 +135,119 @@ impl Account {
             poseidon_code_hash: acc.poseidon_code_hash,
         }
     }
+
+    /// Set the code size and both code hashes from the contract bytecode, see [`code_hashes`].
+    pub fn set_code(&mut self, code: &[u8]) {
+        (self.code_hash, self.poseidon_code_hash) = code_hashes(code);
+        self.code_size = code.len() as u64;
+    }
+
+    /// Set the code from the contract bytecode, see [`Account::set_code`].
+    pub fn with_code(mut self, code: &[u8]) -> Self {
+        self.set_code(code);
+        self
+    }
+
+    /// Check if the code size and both code hashes are the ones of `code`.
+    pub fn commits_to_code(&self, code: &[u8]) -> bool {
+        self.code_size == code.len() as u64
+            && (self.code_hash, self.poseidon_code_hash) == code_hashes(code)
+    }
+
+    /// Check if the account is empty as defined by EIP-161:
+    /// zero nonce, zero balance and no code.
+    ///
+    /// The storage root is not considered.
+    pub fn is_empty(&self) -> bool {
+        self.nonce == 0
+            && self.balance.is_zero()
+            && self.code_size == 0
+            && (self.code_hash == KECCAK_EMPTY || self.code_hash.is_zero())
+    }
+}
+
+/// Get the keccak and the poseidon code hash of a contract bytecode.
+///
+/// The poseidon code hash follows Scroll's spec, the code is split into 31 byte field elements,
+/// hashed by the same function the executor uses.
+pub fn code_hashes(code: &[u8]) -> (B256, B256) {
+    (keccak256(code), poseidon(code))
+}
+
+/// Compute the state root of `accounts` with their storage slots in one shot,
+/// e.g. for a genesis or a test fixture, without any database.
+///
+/// The storage root of every account is replaced by the root of its slots,
+/// zero slots are skipped. All tries are built bottom-up, see [`ZkTrie::recompute_root`],
+/// the storage tries spread over up to `threads` threads (`0` for the available parallelism).
+/// An address or a slot given twice fails with [`RecomputeRootError::DuplicateNodeKey`].
+pub fn compute_state_root<A, S>(
+    accounts: A,
+    threads: usize,
+) -> std::result::Result<ZkHash, RecomputeRootError<PoseidonError>>
+where
+    A: IntoIterator<Item = (Address, Account, S)>,
+    S: IntoIterator<Item = (U256, U256)>,
+{
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let accounts: Vec<_> = accounts
+        .into_iter()
+        .map(|(address, account, storage)| {
+            let slots: Vec<_> = storage
+                .into_iter()
+                .filter(|(_, value)| !value.is_zero())
+                .collect();
+            (address, account, slots)
+        })
+        .collect();
+    let chunk_size = accounts.len().div_ceil(threads).max(1);
+    let leaves = thread::scope(|s| {
+        let handles: Vec<_> = accounts
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(address, account, slots)| {
+                            let storage_leaves = slots
+                                .iter()
+                                .map(|(slot, value)| leaf(&slot.to_be_bytes::<32>(), value))
+                                .collect::<std::result::Result<Vec<_>, _>>()
+                                .map_err(RecomputeRootError::Hash)?;
+                            let account = Account {
+                                storage_root: StorageTrie::<NoCacheHasher>::recompute_root(
+                                    &storage_leaves,
+                                    1,
+                                )?,
+                                ..*account
+                            };
+                            leaf(address.as_slice(), account).map_err(RecomputeRootError::Hash)
+                        })
+                        .collect::<std::result::Result<Vec<_>, _>>()
+                })
+            })
+            .collect();
+        let mut leaves = Vec::with_capacity(accounts.len());
+        for handle in handles {
+            leaves.extend(handle.join().unwrap()?);
+        }
+        Ok::<_, RecomputeRootError<PoseidonError>>(leaves)
+    })?;
+    let root = AccountTrie::<NoCacheHasher>::recompute_root(&leaves, threads)?;
+    trace!(accounts = leaves.len(), "computed state root {root}");
+    Ok(root)
+}
+
+/// The leaf of `key` holding `value`, keyed as by [`NoCacheHasher`].
+fn leaf<T: EncodeValueBytes>(
+    key: &[u8],
+    value: T,
+) -> std::result::Result<Node<Poseidon>, PoseidonError> {
+    let (values, flags) = value.encode_values_bytes();
+    Node::new_leaf(Poseidon::hash_bytes(key)?, values, flags, None)
 }
 
 impl From<Account> for AccountInfo {
@@ -108,9 +263,404 @@ impl From<Account> for AccountInfo {
     }
 }
 
+/// The account trie together with lazily opened storage tries.
+///
+/// Storage tries are opened on first touch and cached by address.
+/// Once more than `capacity` tries are cached, the least recently used clean tries are evicted;
+/// dirty tries are kept until [`ScrollTrie::commit_all`].
+#[derive(Debug)]
+pub struct ScrollTrie {
+    account_trie: AccountTrie,
+    storage_tries: HashMap<Address, CachedStorageTrie>,
+    capacity: usize,
+    clock: u64,
+    delete_zero_slots: bool,
+    prune_empty_accounts: bool,
+    strict_storage_roots: bool,
+}
+
+/// Proofs of the accounts and storage slots of an access list,
+/// see [`ScrollTrie::prove_access_list`].
+#[derive(Clone, Debug, Default)]
+pub struct AccessListProof {
+    /// The unique nodes of all proofs
+    pub nodes: SharedProofNodes,
+    /// The proofs of every account, in access list order
+    pub accounts: Vec<AccountAccessProof>,
+}
+
+/// Proofs of an account and its accessed storage slots, as indices into [`AccessListProof::nodes`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountAccessProof {
+    /// The account address
+    pub address: Address,
+    /// The account proof
+    pub account_proof: Vec<usize>,
+    /// The storage proof of every accessed slot
+    pub storage_proofs: Vec<(U256, Vec<usize>)>,
+}
+
+#[derive(Debug)]
+struct CachedStorageTrie {
+    trie: StorageTrie,
+    /// The storage root of the account when the trie was opened or last committed
+    storage_root: ZkHash,
+    last_used: u64,
+}
+
+impl CachedStorageTrie {
+    /// Deleting the last slot leaves no dirty nodes, so compare roots as well
+    fn is_dirty(&self) -> bool {
+        self.trie.is_dirty() || *self.trie.root().unwrap_ref() != self.storage_root
+    }
+}
+
+impl ScrollTrie {
+    /// Create a new `ScrollTrie` caching at most `capacity` clean storage tries.
+    pub fn new(account_trie: AccountTrie, capacity: usize) -> Self {
+        Self {
+            account_trie,
+            storage_tries: HashMap::new(),
+            capacity,
+            clock: 0,
+            delete_zero_slots: true,
+            prune_empty_accounts: false,
+            strict_storage_roots: false,
+        }
+    }
+
+    /// Set whether writing a zero value deletes the storage slot, enabled by default.
+    ///
+    /// Scroll never stores zero-valued slots, so disabling this changes the storage roots
+    /// and should only be done for other protocols or testing.
+    pub fn with_zero_slot_deletion(mut self, enabled: bool) -> Self {
+        self.delete_zero_slots = enabled;
+        self
+    }
+
+    /// Check if writing a zero value deletes the storage slot
+    #[inline]
+    pub fn zero_slot_deletion(&self) -> bool {
+        self.delete_zero_slots
+    }
+
+    /// Get the account trie.
+    #[inline]
+    pub fn account_trie(&self) -> &AccountTrie {
+        &self.account_trie
+    }
+
+    /// Number of storage tries in the cache.
+    #[inline]
+    pub fn cached_storage_tries(&self) -> usize {
+        self.storage_tries.len()
+    }
+
+    /// Get an account.
+    pub fn get_account<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        address: Address,
+    ) -> Result<Option<Account>, Db> {
+        self.account_trie.get(db, address)
+    }
+
+    /// Set whether accounts becoming [empty](Account::is_empty) are deleted, disabled by default.
+    ///
+    /// See [`update_account`](ScrollTrie::update_account).
+    pub fn with_empty_account_pruning(mut self, enabled: bool) -> Self {
+        self.prune_empty_accounts = enabled;
+        self
+    }
+
+    /// Check if accounts becoming empty are deleted
+    #[inline]
+    pub fn empty_account_pruning(&self) -> bool {
+        self.prune_empty_accounts
+    }
+
+    /// Set whether [`update_account`](ScrollTrie::update_account) rejects storage roots
+    /// other than the committed storage root of the account, disabled by default.
+    ///
+    /// The committed storage root is the root of the cached storage trie as of its last commit,
+    /// or the storage root in the account trie, or the empty root for a new account.
+    /// Storage roots should only be changed through the storage tries and
+    /// [`ScrollTrie::commit_all`], so any other root is a desync.
+    pub fn with_strict_storage_roots(mut self, enabled: bool) -> Self {
+        self.strict_storage_roots = enabled;
+        self
+    }
+
+    /// Check if unknown storage roots are rejected
+    #[inline]
+    pub fn strict_storage_roots(&self) -> bool {
+        self.strict_storage_roots
+    }
+
+    /// Update an account.
+    ///
+    /// The storage root is overwritten by [`ScrollTrie::commit_all`] if the storage trie is dirty.
+    /// In [strict mode](ScrollTrie::with_strict_storage_roots), a storage root that isn't
+    /// the committed storage root of the account fails with [`ZkTrieError::RootMismatch`].
+    ///
+    /// If [empty account pruning](ScrollTrie::with_empty_account_pruning) is enabled,
+    /// an [empty](Account::is_empty) account is deleted instead, together with its cached
+    /// storage trie and uncommitted storage changes.
+    pub fn update_account<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        address: Address,
+        account: Account,
+    ) -> Result<(), Db> {
+        if self.prune_empty_accounts && account.is_empty() {
+            trace!(address = ?address, "pruning empty account");
+            self.storage_tries.remove(&address);
+            self.account_trie.delete(db, address)?;
+            return Ok(());
+        }
+        if self.strict_storage_roots {
+            let expected = self.committed_storage_root(db, address)?;
+            if account.storage_root != expected {
+                warn!(address = ?address, "storage root desync, expected {expected}, got {}", account.storage_root);
+                return Err(ZkTrieError::RootMismatch {
+                    expected,
+                    actual: account.storage_root,
+                });
+            }
+        }
+        self.account_trie.update(db, address, account)
+    }
+
+    /// The storage root of an account as of the last commit of its storage trie.
+    fn committed_storage_root<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        address: Address,
+    ) -> Result<ZkHash, Db> {
+        if let Some(cached) = self.storage_tries.get(&address) {
+            return Ok(cached.storage_root);
+        }
+        Ok(self
+            .get_account(db, address)?
+            .map(|account| account.storage_root)
+            .unwrap_or(<ZkTrie>::EMPTY_ROOT))
+    }
+
+    /// Set the code of an account, e.g. after a contract deployment, see [`Account::set_code`].
+    ///
+    /// An account that doesn't exist is created with an empty [`AccountInfo`].
+    pub fn update_code<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        address: Address,
+        code: &[u8],
+    ) -> Result<(), Db> {
+        let account = match self.get_account(db, address)? {
+            Some(account) => account,
+            None => Account::from_revm_account_with_storage_root(
+                AccountInfo::default(),
+                <ZkTrie>::EMPTY_ROOT,
+            ),
+        };
+        self.update_account(db, address, account.with_code(code))
+    }
+
+    /// Get the storage trie of an account, opening it at the account's storage root if not cached.
+    pub fn storage_trie<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        address: Address,
+    ) -> Result<&mut StorageTrie, Db> {
+        self.clock += 1;
+        if !self.storage_tries.contains_key(&address) {
+            let storage_root = self.committed_storage_root(db, address)?;
+            let trie =
+                StorageTrie::new_with_root(db, NoCacheHasher, db.committed_root(storage_root)?)?;
+            self.evict(self.capacity.saturating_sub(1));
+            trace!(address = ?address, "opened storage trie");
+            self.storage_tries.insert(
+                address,
+                CachedStorageTrie {
+                    trie,
+                    storage_root,
+                    last_used: 0,
+                },
+            );
+        }
+        let cached = self.storage_tries.get_mut(&address).unwrap();
+        cached.last_used = self.clock;
+        Ok(&mut cached.trie)
+    }
+
+    /// Get a storage slot of an account.
+    pub fn get_storage<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        address: Address,
+        slot: U256,
+    ) -> Result<Option<U256>, Db> {
+        self.storage_trie(db, address)?
+            .get(db, slot.to_be_bytes::<32>())
+    }
+
+    /// Update a storage slot of an account.
+    ///
+    /// A zero value deletes the slot, unless disabled by
+    /// [`with_zero_slot_deletion`](ScrollTrie::with_zero_slot_deletion).
+    pub fn update_storage<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        address: Address,
+        slot: U256,
+        value: U256,
+    ) -> Result<(), Db> {
+        let delete = value.is_zero() && self.delete_zero_slots;
+        let trie = self.storage_trie(db, address)?;
+        if delete {
+            trie.delete(db, slot.to_be_bytes::<32>())?;
+        } else {
+            trie.update(db, slot.to_be_bytes::<32>(), value)?;
+        }
+        Ok(())
+    }
+
+    /// Commit all dirty storage tries, patch the storage roots of their accounts,
+    /// then commit the account trie.
+    ///
+    /// Accounts that don't exist are created with an empty [`AccountInfo`].
+    /// Returns the new state root.
+    pub fn commit_all<Db: KVDatabase>(&mut self, db: &mut NodeDb<Db>) -> Result<CommittedRoot, Db> {
+        let mut dirty: Vec<Address> = self
+            .storage_tries
+            .iter()
+            .filter(|(_, cached)| cached.is_dirty())
+            .map(|(address, _)| *address)
+            .collect();
+        dirty.sort_unstable();
+        for address in dirty.iter() {
+            let cached = self.storage_tries.get_mut(address).unwrap();
+            let storage_root = cached.trie.commit(db)?.hash();
+            cached.storage_root = storage_root;
+            let account = match self.account_trie.get::<_, Account, _>(db, address)? {
+                Some(account) => Account {
+                    storage_root,
+                    ..account
+                },
+                None => Account::from_revm_account_with_storage_root(
+                    AccountInfo::default(),
+                    storage_root,
+                ),
+            };
+            self.account_trie.update(db, address, account)?;
+        }
+        let root = self.account_trie.commit(db)?;
+        trace!("committed {} storage tries", dirty.len());
+        self.evict(self.capacity);
+        Ok(root)
+    }
+
+    /// The account trie root and every storage root it refers to,
+    /// i.e. the live roots to pass to [`NodeDb::full_gc`].
+    ///
+    /// All tries must be committed.
+    pub fn live_roots<Db: KVDatabase>(&self, db: &NodeDb<Db>) -> Result<Vec<ZkHash>, Db> {
+        if self.account_trie.is_dirty() || self.storage_tries.values().any(|c| c.is_dirty()) {
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let mut roots = vec![*self.account_trie.root().unwrap_ref()];
+        for leaf in self.account_trie.leafs(db) {
+            let leaf = leaf?;
+            let account = Account::decode_values_bytes(leaf.as_leaf().unwrap().value_preimages())
+                .ok_or(ZkTrieError::UnexpectValue)?;
+            if account.storage_root != <ZkTrie>::EMPTY_ROOT {
+                roots.push(account.storage_root);
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Prove every account of an access list and its storage slots in one pass,
+    /// e.g. the witness for the stateless execution of a transaction.
+    ///
+    /// Proof nodes are deduplicated across all accounts and slots.
+    /// The addresses, and the slots of each account, are hashed in a batch,
+    /// see [`KeyHasher::hash_many`].
+    /// All tries must be committed.
+    pub fn prove_access_list<Db, A, S>(
+        &mut self,
+        db: &NodeDb<Db>,
+        access_list: A,
+    ) -> Result<AccessListProof, Db>
+    where
+        Db: KVDatabase,
+        A: IntoIterator<Item = (Address, S)>,
+        S: IntoIterator<Item = U256>,
+    {
+        if self.account_trie.is_dirty() || self.storage_tries.values().any(|c| c.is_dirty()) {
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let access_list: Vec<(Address, S)> = access_list.into_iter().collect();
+        let addresses: Vec<&[u8]> = access_list
+            .iter()
+            .map(|(address, _)| address.as_slice())
+            .collect();
+        let account_keys =
+            KeyHasher::<Poseidon>::hash_many(self.account_trie.key_hasher(), &addresses)?;
+        let mut proof = AccessListProof::default();
+        for ((address, slots), account_key) in access_list.into_iter().zip(account_keys) {
+            let account_proof =
+                self.account_trie
+                    .prove_shared_node_key(db, account_key, &mut proof.nodes)?;
+            let storage_trie = self.storage_trie(db, address)?;
+            let slots: Vec<U256> = slots.into_iter().collect();
+            let slot_bytes: Vec<[u8; 32]> = slots.iter().map(|slot| slot.to_be_bytes()).collect();
+            let slot_bytes: Vec<&[u8]> = slot_bytes.iter().map(|bytes| bytes.as_slice()).collect();
+            let slot_keys =
+                KeyHasher::<Poseidon>::hash_many(storage_trie.key_hasher(), &slot_bytes)?;
+            let storage_proofs = slots
+                .into_iter()
+                .zip(slot_keys)
+                .map(|(slot, slot_key)| {
+                    storage_trie
+                        .prove_shared_node_key(db, slot_key, &mut proof.nodes)
+                        .map(|indices| (slot, indices))
+                })
+                .collect::<Result<_, Db>>()?;
+            proof.accounts.push(AccountAccessProof {
+                address,
+                account_proof,
+                storage_proofs,
+            });
+        }
+        trace!(
+            accounts = proof.accounts.len(),
+            nodes = proof.nodes.len(),
+            "proved access list"
+        );
+        Ok(proof)
+    }
+
+    /// Evict least recently used clean storage tries until at most `len` are cached.
+    fn evict(&mut self, len: usize) {
+        while self.storage_tries.len() > len {
+            let Some(address) = self
+                .storage_tries
+                .iter()
+                .filter(|(_, cached)| !cached.is_dirty())
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(address, _)| *address)
+            else {
+                break;
+            };
+            self.storage_tries.remove(&address);
+        }
+    }
+}
+
 impl EncodeValueBytes for &U256 {
     fn encode_values_bytes(&self) -> (Vec<[u8; 32]>, u32) {
-        (vec![self.to_be_bytes()], 1)
+        let values: [[u8; 32]; STORAGE_VALUE_FIELDS] = [self.to_be_bytes()];
+        (values.to_vec(), STORAGE_COMPRESSION_FLAGS)
     }
 }
 
@@ -129,10 +679,10 @@ impl DecodeValueBytes for U256 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::NodeDb;
-    use crate::hash::HashOutput;
-    use crate::trie::ZkTrie;
-    use alloy_primitives::address;
+    use crate::db::{kv::HashMapDb, NodeDb, NodeDbError};
+    use crate::hash::{poseidon::Poseidon, HashOutput};
+    use crate::trie::{Node, ZkTrie};
+    use alloy_primitives::{address, b256};
     use poseidon_bn254::{Field, Fr};
     use rand::thread_rng;
     use revm_primitives::AccountInfo;
@@ -157,4 +707,630 @@ mod tests {
 
         assert_eq!(trie_account, account);
     }
+
+    #[test]
+    fn test_value_layouts() {
+        let trie_db = NodeDb::default();
+        let account = Account::from_revm_account_with_storage_root(
+            AccountInfo::default(),
+            <AccountTrie>::EMPTY_ROOT,
+        );
+        let (values, flags) = account.encode_values_bytes();
+        assert_eq!(
+            (values.len(), flags),
+            (Account::VALUE_FIELDS, Account::COMPRESSION_FLAGS)
+        );
+        let (values, flags) = U256::from(1).encode_values_bytes();
+        assert_eq!(
+            (values.len(), flags),
+            (STORAGE_VALUE_FIELDS, STORAGE_COMPRESSION_FLAGS)
+        );
+
+        let mut storage_trie = StorageTrie::default();
+        storage_trie
+            .update(&trie_db, [1u8; 32], U256::from(1))
+            .unwrap();
+        let mut account_trie = AccountTrie::default();
+        account_trie
+            .update(&trie_db, Address::repeat_byte(1), account)
+            .unwrap();
+        assert_eq!(
+            account_trie
+                .get::<_, Account, _>(&trie_db, Address::repeat_byte(1))
+                .unwrap(),
+            Some(account)
+        );
+    }
+
+    #[test]
+    fn test_scroll_trie() {
+        let mut trie_db = NodeDb::default();
+        let mut state = ScrollTrie::new(ZkTrie::default(), 2);
+
+        let addresses: Vec<Address> = (0..5u8).map(|i| Address::repeat_byte(i + 1)).collect();
+        for (i, address) in addresses.iter().enumerate() {
+            state
+                .update_account(
+                    &trie_db,
+                    *address,
+                    Account::from_revm_account_with_storage_root(
+                        AccountInfo::default(),
+                        ZkHash::ZERO,
+                    ),
+                )
+                .unwrap();
+            for slot in 0..=i {
+                let slot = U256::from(slot);
+                state
+                    .update_storage(&trie_db, *address, slot, slot + U256::from(1))
+                    .unwrap();
+            }
+        }
+        // dirty tries are never evicted
+        assert_eq!(state.cached_storage_tries(), addresses.len());
+        state.commit_all(&mut trie_db).unwrap();
+        assert_eq!(state.cached_storage_tries(), 2);
+
+        for (i, address) in addresses.iter().enumerate() {
+            let mut expected = ZkTrie::default();
+            for slot in 0..=i {
+                let slot = U256::from(slot);
+                expected
+                    .update(&trie_db, slot.to_be_bytes::<32>(), slot + U256::from(1))
+                    .unwrap();
+            }
+            expected.commit(&mut trie_db).unwrap();
+            let account = state.get_account(&trie_db, *address).unwrap().unwrap();
+            assert_eq!(account.storage_root, *expected.root().unwrap_ref());
+
+            // evicted tries are reopened at the committed root
+            let value = state
+                .get_storage(&trie_db, *address, U256::from(i))
+                .unwrap();
+            assert_eq!(value, Some(U256::from(i + 1)));
+            assert!(state.cached_storage_tries() <= 2);
+        }
+
+        state
+            .update_storage(&trie_db, addresses[0], U256::ZERO, U256::ZERO)
+            .unwrap();
+        state.commit_all(&mut trie_db).unwrap();
+        let account = state.get_account(&trie_db, addresses[0]).unwrap().unwrap();
+        assert_eq!(account.storage_root, ZkHash::ZERO);
+    }
+
+    #[test]
+    fn test_full_gc_live_roots() {
+        let mut trie_db = NodeDb::new(HashMapDb::new(true));
+        let mut state = ScrollTrie::new(ZkTrie::default(), 4);
+        let addresses: Vec<Address> = (0..8u8).map(|i| Address::repeat_byte(i + 1)).collect();
+        for round in 0..3u64 {
+            for address in addresses.iter() {
+                for slot in 0..4u64 {
+                    state
+                        .update_storage(
+                            &trie_db,
+                            *address,
+                            U256::from(slot),
+                            U256::from(round * 10 + slot + 1),
+                        )
+                        .unwrap();
+                }
+            }
+            state.commit_all(&mut trie_db).unwrap();
+        }
+
+        let live = state.live_roots(&trie_db).unwrap();
+        assert_eq!(live.len(), 1 + addresses.len());
+
+        // a missing live root aborts before sweeping
+        let nodes = trie_db.inner().inner().len();
+        let err = trie_db
+            .full_gc::<Poseidon, _>(&[ZkHash::repeat_byte(1)], HashMapDb::default())
+            .unwrap_err();
+        assert!(matches!(err, NodeDbError::NodeNotFound(_)));
+        assert_eq!(trie_db.inner().inner().len(), nodes);
+
+        let removed = trie_db
+            .full_gc::<Poseidon, _>(&live, HashMapDb::default())
+            .unwrap();
+        assert!(removed > 0);
+        assert_eq!(
+            trie_db
+                .full_gc::<Poseidon, _>(&live, HashMapDb::default())
+                .unwrap(),
+            0
+        );
+
+        let account_trie = ZkTrie::new_with_root(
+            &trie_db,
+            NoCacheHasher,
+            trie_db.committed_root(live[0]).unwrap(),
+        )
+        .unwrap();
+        let mut state = ScrollTrie::new(account_trie, 4);
+        for address in addresses.iter() {
+            for slot in 0..4u64 {
+                let value = state
+                    .get_storage(&trie_db, *address, U256::from(slot))
+                    .unwrap();
+                assert_eq!(value, Some(U256::from(20 + slot + 1)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_compute_state_root() {
+        let mut trie_db = NodeDb::default();
+        let mut state = ScrollTrie::new(ZkTrie::default(), 4);
+        let mut dump = Vec::new();
+        for i in 0..10u8 {
+            let address = Address::repeat_byte(i + 1);
+            let account = Account::from_revm_account_with_storage_root(
+                AccountInfo {
+                    nonce: i as u64,
+                    balance: U256::from(i) * U256::from(1000),
+                    ..Default::default()
+                },
+                // replaced by the computed storage root
+                ZkHash::repeat_byte(0xff),
+            )
+            .with_code(&[i; 7]);
+            let slots: Vec<_> = (0..i as u64)
+                .map(|slot| (U256::from(slot), U256::from(slot * 3 + 1)))
+                .chain([(U256::from(100), U256::ZERO)])
+                .collect();
+            state
+                .update_account(
+                    &trie_db,
+                    address,
+                    Account {
+                        storage_root: ZkHash::ZERO,
+                        ..account
+                    },
+                )
+                .unwrap();
+            for (slot, value) in slots.iter() {
+                state
+                    .update_storage(&trie_db, address, *slot, *value)
+                    .unwrap();
+            }
+            dump.push((address, account, slots));
+        }
+        let expected = state.commit_all(&mut trie_db).unwrap().hash();
+
+        for threads in [0, 1, 3] {
+            assert_eq!(compute_state_root(dump.clone(), threads).unwrap(), expected);
+        }
+        assert_eq!(
+            compute_state_root(Vec::<(Address, Account, Vec<_>)>::new(), 1).unwrap(),
+            <ZkTrie>::EMPTY_ROOT
+        );
+
+        dump.push(dump[0].clone());
+        assert!(matches!(
+            compute_state_root(dump, 2),
+            Err(RecomputeRootError::DuplicateNodeKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_genesis_builder() {
+        let alloc = [
+            (
+                Address::repeat_byte(1),
+                GenesisAccount::with_balance(U256::from(10).pow(U256::from(18))),
+            ),
+            (
+                Address::repeat_byte(2),
+                GenesisAccount::default()
+                    .with_code(vec![0x60, 0x00, 0x60, 0x00, 0xf3])
+                    .with_slot(U256::from(0), U256::from(42))
+                    .with_slot(U256::from(1), U256::ZERO)
+                    .with_slot(U256::MAX, U256::from(7)),
+            ),
+            (
+                Address::repeat_byte(3),
+                GenesisAccount {
+                    nonce: 1,
+                    ..GenesisAccount::with_balance(U256::from(5))
+                },
+            ),
+        ];
+
+        let mut roots = Vec::new();
+        let mut dbs = Vec::new();
+        for order in [[0, 1, 2], [2, 0, 1]] {
+            let mut trie_db = NodeDb::default();
+            let root = order
+                .iter()
+                .map(|i| alloc[*i].clone())
+                .collect::<GenesisBuilder>()
+                .build(&mut trie_db)
+                .unwrap();
+            trie_db.committed_root(root.hash()).unwrap();
+            roots.push(root.hash());
+
+            let mut state = ScrollTrie::new(
+                ZkTrie::new_with_root(&trie_db, NoCacheHasher, root).unwrap(),
+                1,
+            );
+            let account = state
+                .get_account(&trie_db, Address::repeat_byte(2))
+                .unwrap()
+                .unwrap();
+            assert!(account.commits_to_code(&alloc[1].1.code));
+            let value = state
+                .get_storage(&trie_db, Address::repeat_byte(2), U256::MAX)
+                .unwrap();
+            assert_eq!(value, Some(U256::from(7)));
+            dbs.push(trie_db);
+        }
+        // the same nodes are written whatever the input order
+        assert_eq!(roots[0], roots[1]);
+        assert_eq!(dbs[0].inner().inner(), dbs[1].inner().inner());
+
+        assert_eq!(
+            GenesisBuilder::new()
+                .build(&mut NodeDb::default())
+                .unwrap()
+                .hash(),
+            <ZkTrie>::EMPTY_ROOT
+        );
+    }
+
+    #[test]
+    fn test_genesis_state_root() {
+        let eoa = [
+            (
+                Address::repeat_byte(1),
+                GenesisAccount::with_balance(U256::from(10).pow(U256::from(18))),
+            ),
+            (
+                Address::repeat_byte(3),
+                GenesisAccount {
+                    nonce: 1,
+                    ..GenesisAccount::with_balance(U256::from(5))
+                },
+            ),
+        ];
+        let root = GenesisBuilder::from_iter(eoa.clone())
+            .build(&mut NodeDb::default())
+            .unwrap();
+        assert_eq!(
+            root.hash(),
+            b256!("107e76b0e9f40c3db0ea1a476188b4cb06b459acce99e048ea0473cd6f8a9eae")
+        );
+
+        let root = GenesisBuilder::from_iter(eoa)
+            .with_account(
+                Address::repeat_byte(4),
+                GenesisAccount::default()
+                    .with_slot(U256::from(0), U256::from(42))
+                    .with_slot(U256::MAX, U256::from(7)),
+            )
+            .build(&mut NodeDb::default())
+            .unwrap();
+        assert_eq!(
+            root.hash(),
+            b256!("163f22852fe0c56b6ebc8b18bf425d54371694676aac99041c5f3ccb9c2f037b")
+        );
+    }
+
+    #[test]
+    fn test_insert_storage() {
+        let mut trie_db = NodeDb::default();
+        let mut trie = ZkTrie::default();
+
+        let slots: Vec<U256> = (0..20).map(|i| U256::from(i)).collect();
+        for slot in slots.iter() {
+            let old = trie
+                .insert(&trie_db, slot.to_be_bytes::<32>(), *slot)
+                .unwrap();
+            assert_eq!(old, None);
+        }
+        trie.commit(&mut trie_db).unwrap();
+
+        for slot in slots.iter() {
+            let old = trie
+                .insert(&trie_db, slot.to_be_bytes::<32>(), *slot + U256::from(1))
+                .unwrap();
+            assert_eq!(old, Some(*slot));
+            let value = trie
+                .get::<_, U256, _>(&trie_db, slot.to_be_bytes::<32>())
+                .unwrap();
+            assert_eq!(value, Some(*slot + U256::from(1)));
+        }
+    }
+
+    #[test]
+    fn test_zero_slot_deletion() {
+        let mut trie_db = NodeDb::default();
+        let address = Address::repeat_byte(1);
+
+        let mut roots = Vec::new();
+        for delete_zero_slots in [true, false] {
+            let mut state =
+                ScrollTrie::new(ZkTrie::default(), 1).with_zero_slot_deletion(delete_zero_slots);
+            assert_eq!(state.zero_slot_deletion(), delete_zero_slots);
+            state
+                .update_storage(&trie_db, address, U256::from(1), U256::from(1))
+                .unwrap();
+            state
+                .update_storage(&trie_db, address, U256::from(2), U256::ZERO)
+                .unwrap();
+            state.commit_all(&mut trie_db).unwrap();
+            let value = state.get_storage(&trie_db, address, U256::from(2)).unwrap();
+            assert_eq!(value, (!delete_zero_slots).then_some(U256::ZERO));
+            roots.push(state.account_trie().root().clone());
+        }
+        // a zero-valued leaf changes the root
+        assert_ne!(roots[0], roots[1]);
+    }
+
+    #[test]
+    fn test_update_code() {
+        let mut trie_db = NodeDb::default();
+        let address = Address::repeat_byte(1);
+        let code = [0x60, 0x00, 0x60, 0x00, 0xf3];
+
+        let account = Account::from_revm_account_with_storage_root(
+            AccountInfo::default(),
+            <ZkTrie>::EMPTY_ROOT,
+        );
+        // the empty code hashes used by Scroll
+        let empty_code_hashes = (
+            b256!("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"),
+            b256!("2098f5fb9e239eab3ceac3f27b81e481dc3124d55ffed523a839ee8446b64864"),
+        );
+        assert_eq!(
+            (account.code_hash, account.poseidon_code_hash),
+            empty_code_hashes
+        );
+        assert!(account.commits_to_code(&[]));
+
+        let account = account.with_code(&code);
+        assert_eq!(account.code_size, code.len() as u64);
+        assert_eq!(account.code_hash, keccak256(code));
+        assert_eq!(account.poseidon_code_hash, poseidon(&code));
+        assert!(account.commits_to_code(&code));
+        assert!(!account.commits_to_code(&code[1..]));
+        assert_ne!(account.poseidon_code_hash, empty_code_hashes.1);
+
+        let emptied = account.with_code(&[]);
+        assert_eq!(emptied.code_size, 0);
+        assert_eq!(
+            (emptied.code_hash, emptied.poseidon_code_hash),
+            empty_code_hashes
+        );
+
+        let mut state = ScrollTrie::new(ZkTrie::default(), 1);
+        state
+            .update_storage(&trie_db, address, U256::from(1), U256::from(1))
+            .unwrap();
+        state.commit_all(&mut trie_db).unwrap();
+        state.update_code(&trie_db, address, &code).unwrap();
+        state.commit_all(&mut trie_db).unwrap();
+        let updated = state.get_account(&trie_db, address).unwrap().unwrap();
+        assert!(updated.commits_to_code(&code));
+        // the storage is kept
+        assert_ne!(updated.storage_root, <ZkTrie>::EMPTY_ROOT);
+        assert_eq!(
+            state.get_storage(&trie_db, address, U256::from(1)).unwrap(),
+            Some(U256::from(1))
+        );
+
+        // a missing account is created
+        let other = Address::repeat_byte(2);
+        state.update_code(&trie_db, other, &code).unwrap();
+        state.commit_all(&mut trie_db).unwrap();
+        let created = state.get_account(&trie_db, other).unwrap().unwrap();
+        assert!(created.commits_to_code(&code));
+        assert_eq!(created.nonce, 0);
+    }
+
+    #[test]
+    fn test_empty_account_pruning() {
+        let mut trie_db = NodeDb::default();
+        let mut state = ScrollTrie::new(ZkTrie::default(), 1).with_empty_account_pruning(true);
+        assert!(state.empty_account_pruning());
+        let address = Address::repeat_byte(1);
+
+        let funded = Account::from_revm_account_with_storage_root(
+            AccountInfo {
+                balance: U256::from(1),
+                ..Default::default()
+            },
+            ZkHash::ZERO,
+        );
+        assert!(!funded.is_empty());
+        state.update_account(&trie_db, address, funded).unwrap();
+        state
+            .update_storage(&trie_db, address, U256::from(1), U256::from(1))
+            .unwrap();
+        state.commit_all(&mut trie_db).unwrap();
+        assert!(state.get_account(&trie_db, address).unwrap().is_some());
+
+        // draining the balance prunes the account and drops pending storage changes
+        state
+            .update_storage(&trie_db, address, U256::from(2), U256::from(2))
+            .unwrap();
+        let drained = Account {
+            balance: U256::ZERO,
+            ..funded
+        };
+        assert!(drained.is_empty());
+        state.update_account(&trie_db, address, drained).unwrap();
+        assert_eq!(state.cached_storage_tries(), 0);
+        assert_eq!(
+            state.commit_all(&mut trie_db).unwrap(),
+            CommittedRoot::EMPTY
+        );
+
+        // without pruning the empty account is kept
+        let mut state = ScrollTrie::new(ZkTrie::default(), 1);
+        state.update_account(&trie_db, address, drained).unwrap();
+        assert_eq!(state.get_account(&trie_db, address).unwrap(), Some(drained));
+    }
+
+    #[test]
+    fn test_strict_storage_roots() {
+        let mut trie_db = NodeDb::default();
+        let mut state = ScrollTrie::new(ZkTrie::default(), 1).with_strict_storage_roots(true);
+        assert!(state.strict_storage_roots());
+        let address = Address::repeat_byte(1);
+        let account = Account::from_revm_account_with_storage_root(
+            AccountInfo::default(),
+            <ZkTrie>::EMPTY_ROOT,
+        );
+
+        // a new account must start with an empty storage
+        let desynced = Account {
+            storage_root: ZkHash::repeat_byte(1),
+            ..account
+        };
+        assert!(matches!(
+            state.update_account(&trie_db, address, desynced),
+            Err(ZkTrieError::RootMismatch { .. })
+        ));
+        state.update_account(&trie_db, address, account).unwrap();
+        state
+            .update_storage(&trie_db, address, U256::from(1), U256::from(1))
+            .unwrap();
+        state.commit_all(&mut trie_db).unwrap();
+        let committed = state.get_account(&trie_db, address).unwrap().unwrap();
+        assert_ne!(committed.storage_root, <ZkTrie>::EMPTY_ROOT);
+
+        // the committed root is known, a stale root is not
+        let updated = Account {
+            nonce: 1,
+            ..committed
+        };
+        state.update_account(&trie_db, address, updated).unwrap();
+        assert!(matches!(
+            state.update_account(&trie_db, address, account),
+            Err(ZkTrieError::RootMismatch { .. })
+        ));
+
+        // also after the storage trie is evicted
+        let mut state = ScrollTrie::new(state.account_trie, 1).with_strict_storage_roots(true);
+        state.update_account(&trie_db, address, updated).unwrap();
+        assert!(state.update_account(&trie_db, address, account).is_err());
+
+        // without strict mode any root is accepted
+        let mut state = ScrollTrie::new(ZkTrie::default(), 1);
+        state.update_account(&trie_db, address, desynced).unwrap();
+    }
+
+    #[test]
+    fn test_prove_access_list() {
+        let mut trie_db = NodeDb::default();
+        let mut state = ScrollTrie::new(ZkTrie::default(), 1);
+        let addresses: Vec<Address> = (0..4u8).map(|i| Address::repeat_byte(i + 1)).collect();
+        for address in addresses.iter() {
+            for slot in 0..8u64 {
+                state
+                    .update_storage(&trie_db, *address, U256::from(slot), U256::from(1))
+                    .unwrap();
+            }
+        }
+        let state_root = state.commit_all(&mut trie_db).unwrap().hash();
+
+        // the last account doesn't exist
+        let access_list: Vec<(Address, Vec<U256>)> = addresses
+            .iter()
+            .copied()
+            .chain([Address::repeat_byte(0xff)])
+            .map(|address| (address, (0..10u64).map(U256::from).collect()))
+            .collect();
+        let proof = state
+            .prove_access_list(&trie_db, access_list.clone())
+            .unwrap();
+        assert_eq!(proof.accounts.len(), access_list.len());
+
+        let mut total = 0;
+        for ((address, slots), account) in access_list.iter().zip(proof.accounts.iter()) {
+            assert_eq!(account.address, *address);
+            let account_proof = proof.nodes.proof(&account.account_proof);
+            assert_eq!(
+                account_proof,
+                state.account_trie().prove(&trie_db, address).unwrap()
+            );
+            total += account_proof.len();
+
+            let storage_trie = state.storage_trie(&trie_db, *address).unwrap();
+            for ((slot, indices), expected) in account.storage_proofs.iter().zip(slots) {
+                assert_eq!(slot, expected);
+                let storage_proof = proof.nodes.proof(indices);
+                assert_eq!(
+                    storage_proof,
+                    storage_trie
+                        .prove(&trie_db, slot.to_be_bytes::<32>())
+                        .unwrap()
+                );
+                total += storage_proof.len();
+            }
+        }
+        // shared nodes like the state root are only included once
+        assert!(proof.nodes.len() < total);
+        let root_node = &proof.nodes.nodes()[0];
+        assert_eq!(
+            *Node::<Poseidon>::try_from(root_node.as_slice())
+                .unwrap()
+                .get_or_calculate_node_hash()
+                .unwrap(),
+            state_root
+        );
+    }
+
+    #[test]
+    fn test_account_trie_view() {
+        let mut trie_db = NodeDb::default();
+        let mut view = AccountTrieView::default();
+        let mut trie = AccountTrie::default();
+        let address = address!("deadbeefdeadbeefdeadbeefdeadbeefdeadbeef");
+        let account = Account::from_revm_account_with_storage_root(
+            AccountInfo {
+                balance: U256::from(1),
+                ..Default::default()
+            },
+            <ZkTrie>::EMPTY_ROOT,
+        );
+        for i in 1..4u8 {
+            view.update(&trie_db, Address::repeat_byte(i), account)
+                .unwrap();
+            trie.update(&trie_db, Address::repeat_byte(i), account)
+                .unwrap();
+        }
+        view.update(&trie_db, address, account).unwrap();
+        trie.update(&trie_db, address, account).unwrap();
+        assert_eq!(
+            view.commit(&mut trie_db).unwrap(),
+            trie.commit(&mut trie_db).unwrap()
+        );
+
+        assert_eq!(view.get(&trie_db, address).unwrap(), Some(account));
+        assert!(view.contains(&trie_db, address).unwrap());
+        assert_eq!(
+            view.prove(&trie_db, address).unwrap(),
+            trie.prove(&trie_db, address).unwrap()
+        );
+        // the untyped trie takes other encodings of the address as other keys
+        let padded = address.into_word();
+        assert_eq!(trie.get::<_, Account, _>(&trie_db, padded).unwrap(), None);
+        let checksummed = address.to_checksum(None);
+        assert!(trie.get::<_, Account, _>(&trie_db, checksummed).is_err());
+
+        assert!(view.delete(&trie_db, address).unwrap());
+        assert!(!view.delete(&trie_db, address).unwrap());
+        assert_eq!(view.get(&trie_db, address).unwrap(), None);
+        let root = view.commit(&mut trie_db).unwrap();
+        let view =
+            AccountTrieView::from(ZkTrie::new_with_root(&trie_db, NoCacheHasher, root).unwrap());
+        assert_eq!(
+            view.get(&trie_db, Address::repeat_byte(1)).unwrap(),
+            Some(account)
+        );
+        assert_eq!(*view.root().unwrap_ref(), root.hash());
+    }
 }
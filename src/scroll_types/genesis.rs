@@ -0,0 +1,107 @@
+//! Build the genesis state of a Scroll network, see [`GenesisBuilder`].
+use super::{Account, AccountTrie, Result, StorageTrie};
+use crate::db::{kv::KVDatabase, NodeDb};
+use crate::trie::CommittedRoot;
+use alloy_primitives::{Address, U256};
+use revm_primitives::AccountInfo;
+use std::collections::BTreeMap;
+
+/// An account of a genesis allocation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GenesisAccount {
+    /// balance
+    pub balance: U256,
+    /// nonce
+    pub nonce: u64,
+    /// contract bytecode, empty for externally owned accounts
+    pub code: Vec<u8>,
+    /// storage slots, zero values are skipped
+    pub storage: BTreeMap<U256, U256>,
+}
+
+impl GenesisAccount {
+    /// Create an externally owned account holding `balance`.
+    pub fn with_balance(balance: U256) -> Self {
+        Self {
+            balance,
+            ..Default::default()
+        }
+    }
+
+    /// Set the contract bytecode.
+    pub fn with_code(mut self, code: impl Into<Vec<u8>>) -> Self {
+        self.code = code.into();
+        self
+    }
+
+    /// Set a storage slot.
+    pub fn with_slot(mut self, slot: U256, value: U256) -> Self {
+        self.storage.insert(slot, value);
+        self
+    }
+}
+
+/// Builds and persists the tries of a genesis allocation.
+///
+/// The output only depends on the allocation: accounts are written in address order
+/// and slots in slot order, whatever the order they were added in.
+/// Only the code hashes and sizes are committed, the bytecode itself is not stored.
+#[derive(Clone, Debug, Default)]
+pub struct GenesisBuilder {
+    alloc: BTreeMap<Address, GenesisAccount>,
+}
+
+impl GenesisBuilder {
+    /// Create a builder with an empty allocation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an account, replacing a previous one at `address`.
+    pub fn with_account(mut self, address: Address, account: GenesisAccount) -> Self {
+        self.alloc.insert(address, account);
+        self
+    }
+
+    /// Get the allocation.
+    #[inline]
+    pub fn alloc(&self) -> &BTreeMap<Address, GenesisAccount> {
+        &self.alloc
+    }
+
+    /// Build every storage trie and the account trie, commit them into `db`,
+    /// and return the genesis state root.
+    pub fn build<Db: KVDatabase>(self, db: &mut NodeDb<Db>) -> Result<CommittedRoot, Db> {
+        let mut account_trie = AccountTrie::default();
+        for (address, genesis) in self.alloc.iter() {
+            let mut storage_trie = StorageTrie::default();
+            for (slot, value) in genesis.storage.iter().filter(|(_, v)| !v.is_zero()) {
+                storage_trie.update(db, slot.to_be_bytes::<32>(), value)?;
+            }
+            let storage_root = storage_trie.commit(db)?.hash();
+            let mut account = Account::from_revm_account_with_storage_root(
+                AccountInfo {
+                    balance: genesis.balance,
+                    nonce: genesis.nonce,
+                    ..Default::default()
+                },
+                storage_root,
+            );
+            if !genesis.code.is_empty() {
+                account.set_code(&genesis.code);
+            }
+            account_trie.update(db, address, account)?;
+        }
+        let root = account_trie.commit(db)?;
+        trace!(accounts = self.alloc.len(), "built genesis {}", root.hash());
+        Ok(root)
+    }
+}
+
+impl FromIterator<(Address, GenesisAccount)> for GenesisBuilder {
+    fn from_iter<T: IntoIterator<Item = (Address, GenesisAccount)>>(iter: T) -> Self {
+        Self {
+            alloc: iter.into_iter().collect(),
+        }
+    }
+}
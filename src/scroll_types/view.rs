@@ -0,0 +1,117 @@
+//! An account trie keyed by [`Address`], see [`AccountTrieView`].
+use super::{Account, AccountTrie, Result};
+use crate::db::{kv::KVDatabase, NodeDb};
+use crate::hash::{
+    key_hasher::{KeyHasher, NoCacheHasher},
+    poseidon::Poseidon,
+};
+use crate::trie::{CommittedRoot, LazyNodeHash};
+use alloy_primitives::Address;
+use std::fmt::{Debug, Formatter};
+
+/// An [`AccountTrie`] taking [`Address`]es instead of raw key bytes.
+///
+/// Accounts are keyed by the 20 address bytes. The generic trie accepts any bytes,
+/// so passing e.g. a 32 bytes padded address compiles too, and silently reads or writes
+/// another leaf, while an address string only fails at runtime.
+pub struct AccountTrieView<K = NoCacheHasher> {
+    trie: AccountTrie<K>,
+}
+
+impl<K: KeyHasher<Poseidon>> Debug for AccountTrieView<K> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("AccountTrieView").field(&self.trie).finish()
+    }
+}
+
+impl Default for AccountTrieView {
+    fn default() -> Self {
+        Self::new(AccountTrie::default())
+    }
+}
+
+impl<K> From<AccountTrie<K>> for AccountTrieView<K> {
+    fn from(trie: AccountTrie<K>) -> Self {
+        Self::new(trie)
+    }
+}
+
+impl<K> AccountTrieView<K> {
+    /// Wrap an account trie.
+    pub fn new(trie: AccountTrie<K>) -> Self {
+        Self { trie }
+    }
+
+    /// Get the account trie.
+    #[inline]
+    pub fn trie(&self) -> &AccountTrie<K> {
+        &self.trie
+    }
+
+    /// Into the account trie.
+    #[inline]
+    pub fn into_inner(self) -> AccountTrie<K> {
+        self.trie
+    }
+}
+
+impl<K: KeyHasher<Poseidon>> AccountTrieView<K> {
+    /// The key of an account in the trie.
+    #[inline]
+    pub fn key(address: &Address) -> &[u8] {
+        address.as_slice()
+    }
+
+    /// Get the root of the trie.
+    #[inline]
+    pub fn root(&self) -> &LazyNodeHash {
+        self.trie.root()
+    }
+
+    /// Get an account.
+    pub fn get<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        address: Address,
+    ) -> Result<Option<Account>, Db> {
+        self.trie.get(db, Self::key(&address))
+    }
+
+    /// Check if an account exists.
+    pub fn contains<Db: KVDatabase>(&self, db: &NodeDb<Db>, address: Address) -> Result<bool, Db> {
+        self.trie.contains_key(db, Self::key(&address))
+    }
+
+    /// Insert or update an account.
+    pub fn update<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        address: Address,
+        account: Account,
+    ) -> Result<(), Db> {
+        self.trie.update(db, Self::key(&address), account)
+    }
+
+    /// Delete an account, returns `false` if it doesn't exist.
+    pub fn delete<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        address: Address,
+    ) -> Result<bool, Db> {
+        self.trie.delete(db, Self::key(&address))
+    }
+
+    /// Prove an account, or its absence, see [`ZkTrie::prove`](crate::trie::ZkTrie::prove).
+    pub fn prove<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        address: Address,
+    ) -> Result<Vec<Vec<u8>>, Db> {
+        self.trie.prove(db, Self::key(&address))
+    }
+
+    /// Commit the trie into `db`.
+    pub fn commit<Db: KVDatabase>(&mut self, db: &mut NodeDb<Db>) -> Result<CommittedRoot, Db> {
+        self.trie.commit(db)
+    }
+}
@@ -0,0 +1,369 @@
+//! Deterministic test vectors, for other implementations to check against this crate,
+//! e.g. the Go zkTrie or a Solidity proof verifier.
+//!
+//! A [`TestVector`] is a sequence of operations on an empty [`ZkTrie`] with the root after each,
+//! followed by proofs of some keys in the final trie, as returned by [`ZkTrie::prove`].
+//! Vectors are generated from a seed, so the same seed always gives the same vector,
+//! and are exchanged as JSON golden files with hex encoded bytes.
+//! The golden files of this crate are checked in under `testvectors/`:
+//!
+//! ```json
+//! {
+//!   "name": "mixed",
+//!   "seed": 42,
+//!   "steps": [
+//!     { "op": "update", "key": "0x..", "values": ["0x.."], "compression_flags": 1, "root": "0x.." },
+//!     { "op": "delete", "key": "0x..", "root": "0x.." }
+//!   ],
+//!   "proofs": [{ "key": "0x..", "nodes": ["0x..", "0x.."] }]
+//! }
+//! ```
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use zktrie_ng::testvectors::TestVector;
+//!
+//! let vector = TestVector::generate("mixed", 42, 100);
+//! vector.save("testvectors/mixed.json").unwrap();
+//!
+//! // e.g. in CI, after changing the hashing or the node encoding
+//! TestVector::load("testvectors/mixed.json").unwrap().check().unwrap();
+//! ```
+use crate::{
+    db::{kv::HashMapDb, NodeDb},
+    hash::{poseidon::PoseidonError, ZkHash},
+    trie::{ZkTrie, ZkTrieError},
+};
+use alloy_primitives::{keccak256, Bytes, B256};
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+/// Number of keys proved at the end of a generated vector, including absent keys.
+const PROVED_KEYS: usize = 8;
+
+/// Errors that can occur when loading or checking a [`TestVector`].
+#[derive(Debug, thiserror::Error)]
+pub enum VectorError {
+    /// IO error
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The golden file is not a valid vector
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    /// Error when replaying an operation
+    #[error("Trie error: {0}")]
+    Trie(#[from] ZkTrieError<PoseidonError, Infallible>),
+    /// The root after a step differs
+    #[error("Root mismatch at step {step}: expected {expected}, got {actual}")]
+    RootMismatch {
+        /// Index of the step
+        step: usize,
+        /// The root in the vector
+        expected: ZkHash,
+        /// The root computed by this crate
+        actual: ZkHash,
+    },
+    /// A proof differs
+    #[error("Proof mismatch for key {0}")]
+    ProofMismatch(B256),
+}
+
+/// An operation of a [`TestVector`].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum VectorOp {
+    /// Insert or update a key
+    Update {
+        /// The key, before hashing
+        key: B256,
+        /// The value preimages
+        values: Vec<B256>,
+        /// The compression flags of the values
+        compression_flags: u32,
+    },
+    /// Delete a key, does nothing if the key is absent
+    Delete {
+        /// The key, before hashing
+        key: B256,
+    },
+}
+
+/// An operation with the root after it.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct VectorStep {
+    /// The operation
+    #[serde(flatten)]
+    pub op: VectorOp,
+    /// The root after the operation
+    pub root: ZkHash,
+}
+
+/// The proof of a key in the final trie.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct VectorProof {
+    /// The key, before hashing
+    pub key: B256,
+    /// The proof nodes, ending with the magic bytes
+    pub nodes: Vec<Bytes>,
+}
+
+/// A sequence of operations with the expected roots and proofs.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TestVector {
+    /// Name of the vector
+    pub name: String,
+    /// The seed the vector is generated from
+    pub seed: u64,
+    /// The operations in order
+    pub steps: Vec<VectorStep>,
+    /// Proofs against the final root
+    pub proofs: Vec<VectorProof>,
+}
+
+impl TestVector {
+    /// Generate a vector of `ops` operations from `seed`.
+    ///
+    /// Operations insert new keys, update existing keys and delete existing keys,
+    /// with one to four values mixing compressed and field element values.
+    pub fn generate(name: impl Into<String>, seed: u64, ops: usize) -> Self {
+        let mut rng = VectorRng::new(seed);
+        let mut keys = Vec::new();
+        let mut ops_list = Vec::with_capacity(ops);
+        for _ in 0..ops {
+            let op = match rng.next_u64() % 10 {
+                0 | 1 if !keys.is_empty() => {
+                    let index = rng.next_u64() as usize % keys.len();
+                    VectorOp::Delete {
+                        key: keys.swap_remove(index),
+                    }
+                }
+                2 | 3 if !keys.is_empty() => {
+                    let key = keys[rng.next_u64() as usize % keys.len()];
+                    rng.update(key)
+                }
+                _ => {
+                    let key = rng.next_b256();
+                    keys.push(key);
+                    rng.update(key)
+                }
+            };
+            ops_list.push(op);
+        }
+
+        let mut proved: Vec<_> = keys.iter().take(PROVED_KEYS - 2).copied().collect();
+        proved.extend([rng.next_b256(), rng.next_b256()]);
+        Self::from_ops(name, seed, ops_list, proved).expect("generated operations are always valid")
+    }
+
+    /// Build a vector by applying `ops` and proving `proved` keys in the final trie.
+    pub fn from_ops(
+        name: impl Into<String>,
+        seed: u64,
+        ops: impl IntoIterator<Item = VectorOp>,
+        proved: impl IntoIterator<Item = B256>,
+    ) -> Result<Self, VectorError> {
+        let mut db = NodeDb::default();
+        let mut trie = ZkTrie::default();
+        let mut steps = Vec::new();
+        for op in ops {
+            apply(&mut trie, &db, &op)?;
+            let root = trie.commit(&mut db)?.hash();
+            steps.push(VectorStep { op, root });
+        }
+        let proofs = proved
+            .into_iter()
+            .map(|key| {
+                let nodes = trie.prove(&db, key)?;
+                Ok(VectorProof {
+                    key,
+                    nodes: nodes.into_iter().map(Bytes::from).collect(),
+                })
+            })
+            .collect::<Result<_, VectorError>>()?;
+        Ok(Self {
+            name: name.into(),
+            seed,
+            steps,
+            proofs,
+        })
+    }
+
+    /// Replay the operations and check every root and proof.
+    ///
+    /// Returns the first mismatch found.
+    pub fn check(&self) -> Result<(), VectorError> {
+        let mut db = NodeDb::default();
+        let mut trie = ZkTrie::default();
+        for (step, VectorStep { op, root }) in self.steps.iter().enumerate() {
+            apply(&mut trie, &db, op)?;
+            let actual = trie.commit(&mut db)?.hash();
+            if actual != *root {
+                return Err(VectorError::RootMismatch {
+                    step,
+                    expected: *root,
+                    actual,
+                });
+            }
+        }
+        for proof in self.proofs.iter() {
+            let nodes = trie.prove(&db, proof.key)?;
+            if !nodes
+                .iter()
+                .map(Vec::as_slice)
+                .eq(proof.nodes.iter().map(|n| &n[..]))
+            {
+                return Err(VectorError::ProofMismatch(proof.key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the root after the last step, the empty root if there's no step.
+    pub fn final_root(&self) -> ZkHash {
+        self.steps
+            .last()
+            .map_or(<ZkTrie>::EMPTY_ROOT, |step| step.root)
+    }
+
+    /// Write the vector as a pretty printed JSON golden file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), VectorError> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Read a vector from a JSON golden file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, VectorError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// Apply an operation without committing.
+fn apply(trie: &mut ZkTrie, db: &NodeDb<HashMapDb>, op: &VectorOp) -> Result<(), VectorError> {
+    match op {
+        VectorOp::Update {
+            key,
+            values,
+            compression_flags,
+        } => {
+            let values = values.iter().map(|v| v.0).collect();
+            trie.raw_update(db, key, values, *compression_flags)?;
+        }
+        VectorOp::Delete { key } => {
+            trie.delete(db, key)?;
+        }
+    }
+    Ok(())
+}
+
+/// A keccak counter stream, portable to other languages.
+struct VectorRng {
+    seed: u64,
+    counter: u64,
+}
+
+impl VectorRng {
+    fn new(seed: u64) -> Self {
+        Self { seed, counter: 0 }
+    }
+
+    /// `keccak256(seed_le || counter_le)`
+    fn next_b256(&mut self) -> B256 {
+        let mut input = [0u8; 16];
+        input[..8].copy_from_slice(&self.seed.to_le_bytes());
+        input[8..].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter += 1;
+        keccak256(input)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.next_b256()[..8].try_into().unwrap())
+    }
+
+    /// An update of `key` with random values, uncompressed values are valid field elements.
+    fn update(&mut self, key: B256) -> VectorOp {
+        let len = 1 + self.next_u64() as usize % 4;
+        let compression_flags = self.next_u64() as u32 % (1 << len);
+        let values = (0..len)
+            .map(|i| {
+                let mut value = self.next_b256();
+                if compression_flags & (1 << i) == 0 {
+                    value[0] = 0;
+                }
+                value
+            })
+            .collect();
+        VectorOp::Update {
+            key,
+            values,
+            compression_flags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let vector = TestVector::generate("mixed", 42, 100);
+        assert_eq!(vector, TestVector::generate("mixed", 42, 100));
+        assert_ne!(vector.steps, TestVector::generate("mixed", 43, 100).steps);
+        assert_eq!(vector.steps.len(), 100);
+        assert_eq!(vector.proofs.len(), PROVED_KEYS);
+        assert!(vector
+            .steps
+            .iter()
+            .any(|step| matches!(step.op, VectorOp::Delete { .. })));
+        vector.check().unwrap();
+    }
+
+    /// Golden files checked in under `testvectors/`, with the seed and length they're generated from.
+    const GOLDEN_FILES: [(&str, u64, usize); 2] = [("small", 7, 16), ("mixed", 42, 100)];
+
+    fn golden_path(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testvectors")
+            .join(format!("{name}.json"))
+    }
+
+    #[test]
+    fn test_golden_files() {
+        for (name, seed, ops) in GOLDEN_FILES {
+            let golden = TestVector::load(golden_path(name)).unwrap();
+            golden.check().unwrap();
+            assert_eq!(golden, TestVector::generate(name, seed, ops));
+        }
+    }
+
+    #[test]
+    fn test_golden_file() {
+        let vector = TestVector::generate("golden", 7, 50);
+        let path = std::env::temp_dir().join(format!("zktrie-vector-{}.json", std::process::id()));
+        vector.save(&path).unwrap();
+        let loaded = TestVector::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, vector);
+        loaded.check().unwrap();
+
+        let loaded = TestVector::load(golden_path("mixed")).unwrap();
+
+        let mut tampered = loaded.clone();
+        tampered.steps[10].root = ZkHash::ZERO;
+        assert!(matches!(
+            tampered.check(),
+            Err(VectorError::RootMismatch { step: 10, .. })
+        ));
+        let mut tampered = loaded;
+        tampered.proofs[0].nodes.pop();
+        assert!(matches!(
+            tampered.check(),
+            Err(VectorError::ProofMismatch(_))
+        ));
+    }
+}
@@ -24,3 +24,59 @@ impl<const LEN: usize> DecodeValueBytes for [[u8; 32]; LEN] {
         Some(*values)
     }
 }
+
+/// A value decoded as `A`, or as `B` if that fails,
+/// e.g. for tries holding leafs of two layouts during a format migration.
+///
+/// The variant reports which decoder matched. Nest it to try more layouts in order,
+/// e.g. `Fallback<V2, Fallback<V1, V0>>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fallback<A, B> {
+    /// Decoded by `A`
+    Primary(A),
+    /// Decoded by `B`, `A` didn't match
+    Secondary(B),
+}
+
+impl<A, B> Fallback<A, B> {
+    /// Check if the value was decoded by `A`.
+    #[inline]
+    pub fn is_primary(&self) -> bool {
+        matches!(self, Self::Primary(_))
+    }
+
+    /// Get the value if decoded by `A`.
+    #[inline]
+    pub fn primary(self) -> Option<A> {
+        match self {
+            Self::Primary(a) => Some(a),
+            Self::Secondary(_) => None,
+        }
+    }
+
+    /// Get the value if decoded by `B`.
+    #[inline]
+    pub fn secondary(self) -> Option<B> {
+        match self {
+            Self::Primary(_) => None,
+            Self::Secondary(b) => Some(b),
+        }
+    }
+}
+
+impl<A: DecodeValueBytes, B: DecodeValueBytes> DecodeValueBytes for Fallback<A, B> {
+    fn decode_values_bytes(values: &[[u8; 32]]) -> Option<Self> {
+        A::decode_values_bytes(values)
+            .map(Self::Primary)
+            .or_else(|| B::decode_values_bytes(values).map(Self::Secondary))
+    }
+}
+
+impl<A: EncodeValueBytes, B: EncodeValueBytes> EncodeValueBytes for Fallback<A, B> {
+    fn encode_values_bytes(&self) -> (Vec<[u8; 32]>, u32) {
+        match self {
+            Self::Primary(a) => a.encode_values_bytes(),
+            Self::Secondary(b) => b.encode_values_bytes(),
+        }
+    }
+}
@@ -16,6 +16,21 @@ impl From<&ZkHash> for LazyNodeHash {
     }
 }
 
+impl LazyBranchHash {
+    /// The index of the branch in the dirty branch nodes of its trie,
+    /// see [`ZkTrie::dirty_branch`](crate::trie::ZkTrie::dirty_branch).
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Get the hash value if the branch is resolved.
+    #[inline]
+    pub fn resolved(&self) -> Option<&ZkHash> {
+        self.resolved.get()
+    }
+}
+
 impl LazyNodeHash {
     /// Check if the hash value is zero.
     #[inline]
@@ -263,6 +278,36 @@ impl<H: HashScheme> Node<H> {
         }
     }
 
+    /// Check the value preimages and compression flags of a leaf before hashing them.
+    ///
+    /// Compression flags may only be set for the first [`MAX_COMPRESSED_VALUES`] preimages,
+    /// and every uncompressed preimage must be a valid hash of the scheme.
+    pub fn check_leaf_values(
+        value_preimages: &[[u8; 32]],
+        compress_flags: u32,
+    ) -> Result<(), LeafValuesError> {
+        let len = value_preimages.len();
+        if len == 0 {
+            return Err(LeafValuesError::Empty);
+        }
+        if len > MAX_VALUE_PREIMAGES {
+            return Err(LeafValuesError::TooMany(len));
+        }
+        let compressible = len.min(MAX_COMPRESSED_VALUES);
+        if compress_flags >> compressible != 0 {
+            return Err(LeafValuesError::CompressionFlags {
+                flags: compress_flags,
+                compressible,
+            });
+        }
+        for (i, bytes) in value_preimages.iter().enumerate() {
+            if i >= compressible || compress_flags & (1 << i) == 0 {
+                H::new_hash_try_from_bytes(bytes).map_err(|_| LeafValuesError::InvalidValue(i))?;
+            }
+        }
+        Ok(())
+    }
+
     /// Create a new leaf node.
     pub fn new_leaf(
         node_key: ZkHash,
@@ -308,6 +353,26 @@ impl<H: HashScheme> Node<H> {
             .get_or_try_init(|| H::hash(branch.node_type() as u64, [*left, *right]))
     }
 
+    /// Map the children of a branch node, sharing the node hash with `self`.
+    ///
+    /// The mapped children must refer to the same nodes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node is not a branch node.
+    pub(crate) fn map_children(&self, mut f: impl FnMut(&LazyNodeHash) -> LazyNodeHash) -> Self {
+        let branch = self.data.as_branch().expect("not a branch node");
+        Node {
+            node_hash: self.node_hash.clone(),
+            data: Arc::new(NodeKind::Branch(BranchNode {
+                node_type: branch.node_type,
+                child_left: f(&branch.child_left),
+                child_right: f(&branch.child_right),
+            })),
+            _hash_scheme: std::marker::PhantomData,
+        }
+    }
+
     /// Get the node hash unchecked
     ///
     /// # Safety
@@ -452,7 +517,18 @@ impl<H: HashScheme> Debug for Node<H> {
 impl<H: HashScheme> TryFrom<&[u8]> for Node<H> {
     type Error = ParseNodeError<H::Error>;
 
-    fn try_from(mut bytes: &[u8]) -> Result<Self, Self::Error> {
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Self::try_from_bytes_with_limit(bytes, MAX_VALUE_PREIMAGES)
+    }
+}
+
+impl<H: HashScheme> Node<H> {
+    /// Parse a node, rejecting leafs with more than `max_value_preimages` value preimages
+    /// before reading them.
+    pub fn try_from_bytes_with_limit(
+        mut bytes: &[u8],
+        max_value_preimages: usize,
+    ) -> Result<Self, ParseNodeError<H::Error>> {
         use ParseNodeError::*;
 
         let raw_node_type = read_u8(&mut bytes)?;
@@ -471,6 +547,12 @@ impl<H: HashScheme> TryFrom<&[u8]> for Node<H> {
                 let mark = read_u32_le(&mut bytes)?;
                 let preimage_len = (mark & 255) as usize;
                 let compress_flags = mark >> 8;
+                if preimage_len > max_value_preimages {
+                    return Err(TooManyValues {
+                        len: preimage_len,
+                        limit: max_value_preimages,
+                    });
+                }
 
                 let mut value_preimages = Vec::with_capacity(preimage_len);
                 for _ in 0..preimage_len {
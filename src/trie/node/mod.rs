@@ -11,15 +11,22 @@ mod imp;
 mod rkyv_imp;
 use crate::hash::poseidon::Poseidon;
 pub use rkyv_imp::{
-    ArchivedBranchNode, ArchivedLeafNode, ArchivedNode, IBranchNode, ILeafNode, INode, NodeViewer,
+    ArchivedBranchNode, ArchivedLeafNode, ArchivedNode, IBranchNode, ILeafNode, INode, NodeArena,
+    NodeViewer,
 };
 
-#[cfg(test)]
+#[cfg(all(test, feature = "compat-tests"))]
 mod tests;
 
 /// The magic bytes for the zkTrie node proof.
 pub const MAGIC_NODE_BYTES: &[u8] = b"THIS IS SOME MAGIC BYTES FOR SMT m1rRXgP2xpDI";
 
+/// The max number of value preimages of a leaf, limited by the node encoding.
+pub const MAX_VALUE_PREIMAGES: usize = u8::MAX as usize;
+
+/// The number of leading value preimages that can be compressed, limited by the node encoding.
+pub const MAX_COMPRESSED_VALUES: usize = 24;
+
 /// NodeType is the type of node in the merkle tree.
 ///
 /// Note there are some legacy types are not used anymore:
@@ -118,6 +125,36 @@ pub struct Node<H = Poseidon> {
     _hash_scheme: std::marker::PhantomData<H>,
 }
 
+/// Errors of malformed leaf values, see [`Node::check_leaf_values`].
+#[derive(Debug, thiserror::Error)]
+pub enum LeafValuesError {
+    /// No value preimage is given
+    #[error("Leaf has no value preimage")]
+    Empty,
+    /// More value preimages than [`MAX_VALUE_PREIMAGES`]
+    #[error("Leaf has {0} value preimages, at most {MAX_VALUE_PREIMAGES} are supported")]
+    TooMany(usize),
+    /// More value preimages than the limit of the trie, see [`ZkTrie::with_max_value_preimages`](crate::trie::ZkTrie::with_max_value_preimages)
+    #[error("Leaf has {len} value preimages, exceeding the limit of {limit}")]
+    ExceedsLimit {
+        /// The number of value preimages
+        len: usize,
+        /// The configured limit
+        limit: usize,
+    },
+    /// A compression flag is set for a value preimage that doesn't exist or can't be compressed
+    #[error("Compression flags {flags:#b} set beyond the first {compressible} value preimages")]
+    CompressionFlags {
+        /// The compression flags
+        flags: u32,
+        /// The number of value preimages that can be compressed
+        compressible: usize,
+    },
+    /// An uncompressed value preimage is not a valid hash, e.g. not a field element
+    #[error("Uncompressed value preimage {0} is not a valid hash")]
+    InvalidValue(usize),
+}
+
 /// Errors that can occur when parsing a node.
 #[derive(Debug, thiserror::Error)]
 pub enum ParseNodeError<E> {
@@ -127,6 +164,14 @@ pub enum ParseNodeError<E> {
     /// Invalid node type, may occur when reading legacy data
     #[error("Invalid node type: {0}, are you reading legacy data?")]
     InvalidNodeType(u8),
+    /// A leaf has more value preimages than allowed, see [`Node::try_from_bytes_with_limit`]
+    #[error("Leaf has {len} value preimages, exceeding the limit of {limit}")]
+    TooManyValues {
+        /// The number of value preimages
+        len: usize,
+        /// The limit
+        limit: usize,
+    },
     /// Error when hashing
     #[error(transparent)]
     HashError(E),
@@ -5,7 +5,11 @@ use super::*;
 use alloy_primitives::bytes::Bytes;
 use rkyv::rancor;
 use rkyv::util::AlignedVec;
-use std::fmt::Debug;
+use std::fmt::{Debug, Formatter};
+use std::ops::Range;
+
+/// Initial arena capacity per node, about the size of an archived leaf with a few values.
+const ARENA_NODE_CAPACITY: usize = 128;
 
 /// An archived [`Node`].
 #[derive(Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -306,6 +310,15 @@ impl ArchivedBranchNode {
         NodeType::from_u8(self.node_type).expect("invalid node type")
     }
 
+    /// Get the node type, `Err` with the raw byte if it isn't a branch type.
+    #[inline]
+    pub fn try_node_type(&self) -> Result<NodeType, u8> {
+        match NodeType::from_u8(self.node_type) {
+            Some(node_type @ (BranchLTRT | BranchLTRB | BranchLBRT | BranchLBRB)) => Ok(node_type),
+            _ => Err(self.node_type),
+        }
+    }
+
     /// Get the left child hash.
     #[inline]
     pub fn child_left(&self) -> LazyNodeHash {
@@ -325,7 +338,52 @@ impl ArchivedBranchNode {
     }
 }
 
+impl<H: HashScheme> From<&ArchivedNode> for Node<H> {
+    fn from(node: &ArchivedNode) -> Self {
+        let data = match &node.data {
+            ArchivedNodeKind::Empty => return Node::empty(),
+            ArchivedNodeKind::Leaf(leaf) => NodeKind::Leaf(LeafNode {
+                node_key: leaf.node_key(),
+                node_key_preimage: leaf.node_key_preimage().copied(),
+                value_preimages: leaf.value_preimages().to_vec(),
+                compress_flags: leaf.compress_flags(),
+                value_hash: leaf
+                    .value_hash()
+                    .map(OnceCell::with_value)
+                    .unwrap_or_default(),
+            }),
+            ArchivedNodeKind::Branch(branch) => NodeKind::Branch(BranchNode {
+                node_type: branch.node_type(),
+                child_left: branch.child_left(),
+                child_right: branch.child_right(),
+            }),
+        };
+        Node {
+            node_hash: Arc::new(
+                node.node_hash
+                    .as_ref()
+                    .map(|hash| OnceCell::with_value(hash.into()))
+                    .unwrap_or_default(),
+            ),
+            data: Arc::new(data),
+            _hash_scheme: std::marker::PhantomData,
+        }
+    }
+}
+
 impl ArchivedNode {
+    /// Convert into an owned [`Node`], restoring the cached node hash and value hash.
+    #[inline]
+    pub fn to_owned<H: HashScheme>(&self) -> Node<H> {
+        self.into()
+    }
+
+    /// Get the node hash cached in the archived bytes, if any.
+    #[inline]
+    pub fn cached_node_hash(&self) -> Option<ZkHash> {
+        self.node_hash.as_ref().map(|hash| hash.into())
+    }
+
     /// Get the node type.
     #[inline]
     pub fn node_type(&self) -> NodeType {
@@ -405,7 +463,7 @@ impl ArchivedNode {
     /// Calculate the node hash.
     pub fn calculate_node_hash<H: HashScheme>(&self) -> Result<ZkHash, H::Error> {
         if self.data.is_empty() {
-            return Ok(ZkHash::ZERO);
+            return Ok(H::EMPTY_ROOT);
         }
         if let Some(leaf) = self.as_leaf() {
             let value_hash = leaf.calc_value_hash::<H>()?;
@@ -427,7 +485,112 @@ impl NodeViewer {
     }
 }
 
+/// The archived bytes of several nodes in one contiguous buffer,
+/// see [`ZkTrie::commit_with_arena`](crate::trie::ZkTrie::commit_with_arena).
+///
+/// Every node starts at an aligned offset, so the slice of a node is valid archived bytes
+/// on its own and is stored or viewed without copying.
+#[derive(Clone, Default)]
+pub struct NodeArena {
+    bytes: Bytes,
+    entries: Vec<(ZkHash, Range<usize>)>,
+}
+
+impl NodeArena {
+    /// Archive `nodes` into one arena, in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the hash of a node is not calculated.
+    pub fn new<H>(nodes: impl IntoIterator<Item = Node<H>>) -> Self {
+        let nodes = nodes.into_iter();
+        let (hint, _) = nodes.size_hint();
+        let mut buffer = AlignedVec::<16>::with_capacity(hint * ARENA_NODE_CAPACITY);
+        let mut entries = Vec::with_capacity(hint);
+        for node in nodes {
+            let node_hash = *node.node_hash.get().expect("Node hash not calculated");
+            buffer.resize(
+                buffer.len().next_multiple_of(AlignedVec::<16>::ALIGNMENT),
+                0,
+            );
+            let start = buffer.len();
+            buffer = rkyv::api::high::to_bytes_in::<_, rancor::Error>(
+                &NodeForArchive::from(node),
+                buffer,
+            )
+            .expect("infallible");
+            entries.push((node_hash, start..buffer.len()));
+        }
+        Self {
+            bytes: Bytes::from_owner(buffer),
+            entries,
+        }
+    }
+
+    /// Number of nodes
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if there's no node
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the whole arena, including the padding between nodes
+    #[inline]
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Iterate over the node hashes and the archived bytes of the nodes, in order.
+    ///
+    /// The bytes are slices of the arena.
+    pub fn iter(&self) -> impl Iterator<Item = (ZkHash, Bytes)> + '_ {
+        self.entries
+            .iter()
+            .map(|(node_hash, range)| (*node_hash, self.bytes.slice(range.clone())))
+    }
+
+    /// View the archived nodes, in order.
+    pub fn viewers(&self) -> impl Iterator<Item = NodeViewer> + '_ {
+        self.iter()
+            .map(|(node_hash, data)| NodeViewer { data, node_hash })
+    }
+
+    /// Encode the nodes into canonical bytes, e.g. to emit them into a witness.
+    pub fn canonical_nodes(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.viewers()
+            .map(|viewer| viewer.view().canonical_value(true))
+    }
+}
+
+impl Debug for NodeArena {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NodeArena")
+            .field("nodes", &self.entries.len())
+            .field("bytes", &self.bytes.len())
+            .finish()
+    }
+}
+
 impl<H: HashScheme> INode<H> {
+    /// Convert into an owned [`Node`], copying the data out of archived bytes.
+    ///
+    /// The node hash is kept, so the node can be retained or put into a trie without rehashing.
+    pub fn into_owned(self) -> Node<H> {
+        match self {
+            INode::Owned(node) => node,
+            INode::Archived(viewer) => {
+                let node = viewer.view().to_owned::<H>();
+                node.node_hash.get_or_init(|| viewer.node_hash);
+                node
+            }
+        }
+    }
+
     /// Try get node hash.
     #[inline]
     pub fn node_hash(&self) -> Option<&ZkHash> {
@@ -519,4 +682,19 @@ impl<H: HashScheme> INode<H> {
             INode::Archived(node) => node.view().canonical_value(include_key_preimage),
         }
     }
+
+    /// Length of the [canonical value](INode::canonical_value), without encoding the node.
+    pub fn canonical_len(&self, include_key_preimage: bool) -> usize {
+        match self.as_leaf() {
+            None if self.is_branch() => 1 + 2 * HASH_SIZE,
+            None => 1,
+            Some(leaf) => {
+                let key_preimage = match leaf.node_key_preimage() {
+                    Some(preimage) if include_key_preimage => 1 + preimage.len(),
+                    _ => 1,
+                };
+                1 + HASH_SIZE + size_of::<u32>() + 32 * leaf.value_preimages().len() + key_preimage
+            }
+        }
+    }
 }
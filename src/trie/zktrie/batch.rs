@@ -0,0 +1,342 @@
+//! Optimistic parallel execution against a common base root.
+//!
+//! Each worker executes on its own [`BatchWorker`], which records the keys it reads and the
+//! leafs it writes into a [`RwSet`]. [`ZkTrie::merge_rw_sets`] then applies the sets in order,
+//! skipping every set that read a key written by a set applied before it, and commits once.
+//! The skipped sets are reported so they can be re-executed on top of the merged root.
+use super::{
+    imp::Result, JournalOp, LsbFirst, OpJournal, OpReceipt, PathOrder, ZkTrie, ZkTrieError,
+};
+use crate::{
+    db::{kv::KVDatabase, NodeDb},
+    hash::{
+        key_hasher::{KeyHasher, NoCacheHasher},
+        poseidon::Poseidon,
+        HashScheme, ZkHash,
+    },
+    trie::{CommittedRoot, DecodeValueBytes, EncodeValueBytes, NodeType},
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+
+/// The node keys read and the leafs written by a [`BatchWorker`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RwSet {
+    base_root: ZkHash,
+    reads: BTreeSet<ZkHash>,
+    writes: BTreeMap<ZkHash, JournalOp>,
+}
+
+impl RwSet {
+    /// Create an empty set against `base_root`.
+    pub fn new(base_root: CommittedRoot) -> Self {
+        Self {
+            base_root: base_root.hash(),
+            reads: BTreeSet::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// The root the set was recorded against.
+    #[inline]
+    pub fn base_root(&self) -> ZkHash {
+        self.base_root
+    }
+
+    /// The node keys read from the base root, excluding keys read after writing them.
+    #[inline]
+    pub fn reads(&self) -> &BTreeSet<ZkHash> {
+        &self.reads
+    }
+
+    /// The last write of every written node key, in node key order.
+    pub fn writes(&self) -> impl Iterator<Item = &JournalOp> {
+        self.writes.values()
+    }
+
+    /// Check if the set writes nothing.
+    #[inline]
+    pub fn is_read_only(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Record a read, unless the key was written before.
+    pub fn record_read(&mut self, node_key: ZkHash) {
+        if !self.writes.contains_key(&node_key) {
+            self.reads.insert(node_key);
+        }
+    }
+
+    /// Record a write, replacing any previous write of the key.
+    pub fn record_write(&mut self, op: JournalOp) {
+        let node_key = match &op {
+            JournalOp::Update { node_key, .. } | JournalOp::Delete { node_key } => *node_key,
+        };
+        self.writes.insert(node_key, op);
+    }
+}
+
+/// A trie opened at a base root, recording a [`RwSet`] of the operations done through it.
+///
+/// The writes are applied to the worker's own trie, so later reads see them,
+/// but the worker is never committed, see [`ZkTrie::merge_rw_sets`].
+pub struct BatchWorker<H = Poseidon, K = NoCacheHasher, P = LsbFirst> {
+    trie: ZkTrie<H, K, P>,
+    rw_set: RwSet,
+}
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> Debug for BatchWorker<H, K, P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BatchWorker")
+            .field("base_root", &self.rw_set.base_root)
+            .field("reads", &self.rw_set.reads.len())
+            .field("writes", &self.rw_set.writes.len())
+            .finish()
+    }
+}
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> BatchWorker<H, K, P> {
+    /// Open a worker at `base_root`.
+    pub fn new<Db: KVDatabase>(
+        db: &NodeDb<Db>,
+        key_hasher: K,
+        base_root: CommittedRoot,
+    ) -> Result<Self, H, Db> {
+        Ok(Self {
+            trie: ZkTrie::new_with_root(db, key_hasher, base_root)?,
+            rw_set: RwSet::new(base_root),
+        })
+    }
+
+    /// Get a value, recording the read.
+    pub fn get<Db: KVDatabase, T: DecodeValueBytes, KEY: AsRef<[u8]>>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: KEY,
+    ) -> Result<Option<T>, H, Db> {
+        let node_key = self.trie.key_hasher().hash(key.as_ref())?;
+        self.rw_set.record_read(node_key);
+        let node = self.trie.get_node_by_key(db, &node_key)?;
+        match node.node_type() {
+            NodeType::Empty => Ok(None),
+            NodeType::Leaf => T::decode_values_bytes(node.as_leaf().unwrap().value_preimages())
+                .map(Some)
+                .ok_or(ZkTrieError::UnexpectValue),
+            _ => Err(ZkTrieError::ExpectLeafNode),
+        }
+    }
+
+    /// Update a value, recording the write, see [`ZkTrie::update`].
+    pub fn update<Db: KVDatabase, T: EncodeValueBytes, KEY: AsRef<[u8]>>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: KEY,
+        value: T,
+    ) -> Result<(), H, Db> {
+        let (values, compression_flags) = value.encode_values_bytes();
+        self.raw_update(db, key, values, compression_flags)
+    }
+
+    /// Update the values of a key, recording the write, see [`ZkTrie::raw_update`].
+    pub fn raw_update<Db: KVDatabase, KEY: AsRef<[u8]>>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: KEY,
+        value_preimages: Vec<[u8; 32]>,
+        compression_flags: u32,
+    ) -> Result<(), H, Db> {
+        let node_key = self.trie.key_hasher().hash(key.as_ref())?;
+        self.trie
+            .raw_update(db, key, value_preimages.clone(), compression_flags)?;
+        self.rw_set.record_write(JournalOp::Update {
+            node_key,
+            value_preimages,
+            compression_flags,
+        });
+        Ok(())
+    }
+
+    /// Delete a key, recording the write, or only a read if the key is not found.
+    pub fn delete<Db: KVDatabase, KEY: AsRef<[u8]>>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: KEY,
+    ) -> Result<bool, H, Db> {
+        let node_key = self.trie.key_hasher().hash(key.as_ref())?;
+        let deleted = self.trie.delete_by_node_key(db, node_key)?;
+        if deleted {
+            self.rw_set.record_write(JournalOp::Delete { node_key });
+        } else {
+            self.rw_set.record_read(node_key);
+        }
+        Ok(deleted)
+    }
+
+    /// Get the recorded set.
+    #[inline]
+    pub fn rw_set(&self) -> &RwSet {
+        &self.rw_set
+    }
+
+    /// Drop the worker's trie, returning the recorded set.
+    pub fn finish(self) -> RwSet {
+        self.rw_set
+    }
+}
+
+/// The result of [`ZkTrie::merge_rw_sets`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeOutcome {
+    /// The committed root
+    pub root: CommittedRoot,
+    /// Indices of the applied sets
+    pub applied: Vec<usize>,
+    /// Indices of the skipped sets, with the node keys they read after an applied set wrote them
+    pub conflicts: Vec<(usize, Vec<ZkHash>)>,
+}
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> ZkTrie<H, K, P> {
+    /// Apply the sets recorded by [`BatchWorker`]s in order, then commit once.
+    ///
+    /// A set conflicts if it read a node key written by a set applied before it,
+    /// its writes are then skipped. Writes to the same key without reading it don't conflict,
+    /// the later set wins.
+    ///
+    /// The trie must be committed, and every set recorded against its root.
+    /// The values are checked before anything is applied, and if applying a write fails,
+    /// e.g. on a missing node, the trie is rolled back to the base root.
+    pub fn merge_rw_sets<Db: KVDatabase>(
+        &mut self,
+        db: &mut NodeDb<Db>,
+        sets: impl IntoIterator<Item = RwSet>,
+    ) -> Result<MergeOutcome, H, Db> {
+        if self.is_dirty() {
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let base_root = *self.root.unwrap_ref();
+        let sets: Vec<RwSet> = sets.into_iter().collect();
+        if let Some(set) = sets.iter().find(|set| set.base_root != base_root) {
+            return Err(ZkTrieError::RootMismatch {
+                expected: set.base_root,
+                actual: base_root,
+            });
+        }
+
+        let mut written = BTreeSet::new();
+        let mut writes = Vec::new();
+        let mut applied = Vec::new();
+        let mut conflicts = Vec::new();
+        for (index, set) in sets.into_iter().enumerate() {
+            let conflicting: Vec<ZkHash> = set.reads.intersection(&written).copied().collect();
+            if !conflicting.is_empty() {
+                trace!(index, keys = conflicting.len(), "conflicting rw set");
+                conflicts.push((index, conflicting));
+                continue;
+            }
+            for op in set.writes.into_values() {
+                match &op {
+                    JournalOp::Update {
+                        node_key,
+                        value_preimages,
+                        compression_flags,
+                    } => {
+                        self.check_leaf_values::<Db>(value_preimages, *compression_flags)?;
+                        written.insert(*node_key);
+                    }
+                    JournalOp::Delete { node_key } => {
+                        written.insert(*node_key);
+                    }
+                }
+                writes.push(op);
+            }
+            applied.push(index);
+        }
+
+        // the secondary commitment only sees the writes once they are all applied
+        let rollback = (
+            self.root.clone(),
+            self.gc_nodes.clone(),
+            self.leaf_changes.clone(),
+            self.leaf_count,
+            self.journal.as_ref().map(OpJournal::len),
+        );
+        let secondary = self.secondary.take();
+        let result = self.apply_writes(db, &writes);
+        self.secondary = secondary;
+        let deleted = match result {
+            Ok(deleted) => deleted,
+            Err(e) => {
+                warn!(error = ?e, "failed to apply rw sets, rolled back");
+                let (root, gc_nodes, leaf_changes, leaf_count, journal_len) = rollback;
+                self.root = root;
+                self.gc_nodes = gc_nodes;
+                self.leaf_changes = leaf_changes;
+                self.leaf_count = leaf_count;
+                if let (Some(journal), Some(len)) = (self.journal.as_mut(), journal_len) {
+                    journal.truncate(len);
+                }
+                self.dirty_leafs.clear();
+                self.dirty_branch_nodes.clear();
+                return Err(e);
+            }
+        };
+        if let Some(secondary) = self.secondary.as_mut() {
+            for (op, deleted) in writes.iter().zip(deleted) {
+                match op {
+                    JournalOp::Update {
+                        node_key,
+                        value_preimages,
+                        compression_flags,
+                    } => secondary.update(None, node_key, value_preimages, *compression_flags),
+                    JournalOp::Delete { node_key } if deleted => secondary.delete(None, node_key),
+                    JournalOp::Delete { .. } => {}
+                }
+            }
+        }
+
+        let root = self.commit(db)?;
+        trace!(
+            applied = applied.len(),
+            conflicts = conflicts.len(),
+            "merged rw sets"
+        );
+        Ok(MergeOutcome {
+            root,
+            applied,
+            conflicts,
+        })
+    }
+
+    /// Apply the writes in order, returning if each one deleted a leaf
+    fn apply_writes<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        writes: &[JournalOp],
+    ) -> Result<Vec<bool>, H, Db> {
+        let mut deleted = Vec::with_capacity(writes.len());
+        for op in writes {
+            match op {
+                JournalOp::Update {
+                    node_key,
+                    value_preimages,
+                    compression_flags,
+                } => {
+                    self.add_leaf_by_node_key(
+                        db,
+                        None,
+                        *node_key,
+                        value_preimages.clone(),
+                        *compression_flags,
+                        &mut None,
+                        &mut OpReceipt::default(),
+                    )?;
+                    deleted.push(false);
+                }
+                JournalOp::Delete { node_key } => {
+                    deleted.push(self.delete_by_node_key(db, *node_key)?);
+                }
+            }
+        }
+        Ok(deleted)
+    }
+}
@@ -0,0 +1,155 @@
+//! Many proofs against the same root, see [`ZkTrie::proof_builder`].
+use super::{imp::Result, LsbFirst, PathOrder, ZkTrie, ZkTrieError};
+use crate::{
+    db::{kv::KVDatabase, NodeDb},
+    hash::{key_hasher::KeyHasher, HashScheme, ZkHash},
+    HashMap,
+};
+use alloy_primitives::bytes::Bytes;
+use std::fmt::{Debug, Formatter};
+
+/// The default depth of the node cache of a [`ProofBuilder`].
+pub const DEFAULT_PROOF_CACHE_DEPTH: usize = 12;
+
+/// Builds proofs for many keys of a committed trie, e.g. for an RPC server
+/// answering `eth_getProof` requests against the latest block.
+///
+/// The encoded nodes of the top levels and the key hashes are cached for the whole session,
+/// so later proofs only read and encode the nodes below the cached levels.
+/// Proofs are the same as [`ZkTrie::prove`].
+pub struct ProofBuilder<'a, H, Db, K, P = LsbFirst> {
+    trie: &'a ZkTrie<H, K, P>,
+    db: &'a NodeDb<Db>,
+    root: ZkHash,
+    cache_depth: usize,
+    /// Nodes above `cache_depth`
+    nodes: HashMap<ZkHash, CachedNode>,
+    node_keys: HashMap<Box<[u8]>, ZkHash>,
+}
+
+/// An encoded proof node and its children, if it's a branch.
+struct CachedNode {
+    bytes: Bytes,
+    children: Option<(ZkHash, ZkHash)>,
+}
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> ZkTrie<H, K, P> {
+    /// Start a session proving many keys against the current root, see [`ProofBuilder`].
+    ///
+    /// The trie must be committed.
+    pub fn proof_builder<'a, Db: KVDatabase>(
+        &'a self,
+        db: &'a NodeDb<Db>,
+    ) -> Result<ProofBuilder<'a, H, Db, K, P>, H, Db> {
+        if self.is_dirty() {
+            warn!("dirty nodes found, commit before building proofs");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        Ok(ProofBuilder {
+            trie: self,
+            db,
+            root: *self.root.unwrap_ref(),
+            cache_depth: DEFAULT_PROOF_CACHE_DEPTH,
+            nodes: HashMap::new(),
+            node_keys: HashMap::new(),
+        })
+    }
+}
+
+impl<H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> ProofBuilder<'_, H, Db, K, P> {
+    /// Cache the nodes of the top `depth` levels, defaults to [`DEFAULT_PROOF_CACHE_DEPTH`].
+    pub fn with_cache_depth(mut self, depth: usize) -> Self {
+        self.cache_depth = depth;
+        self
+    }
+
+    /// Get the depth of the node cache
+    #[inline]
+    pub fn cache_depth(&self) -> usize {
+        self.cache_depth
+    }
+
+    /// Number of cached nodes
+    #[inline]
+    pub fn cached_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Number of cached key hashes
+    #[inline]
+    pub fn cached_keys(&self) -> usize {
+        self.node_keys.len()
+    }
+
+    /// Prove `key`, see [`ZkTrie::prove`].
+    pub fn prove<KEY: AsRef<[u8]>>(&mut self, key: KEY) -> Result<Vec<Vec<u8>>, H, Db> {
+        let key = key.as_ref();
+        let node_key = match self.node_keys.get(key) {
+            Some(node_key) => *node_key,
+            None => {
+                let node_key = self.trie.key_hasher.hash(key)?;
+                self.node_keys.insert(key.into(), node_key);
+                node_key
+            }
+        };
+        self.prove_node_key(&node_key)
+    }
+
+    /// Prove the leaf of `node_key`, see [`ZkTrie::prove`].
+    pub fn prove_node_key(&mut self, node_key: &ZkHash) -> Result<Vec<Vec<u8>>, H, Db> {
+        let mut proof = Vec::new();
+        let mut node_hash = self.root;
+        for level in 0..H::TRIE_MAX_LEVELS {
+            let children = match self.nodes.get(&node_hash) {
+                Some(cached) => {
+                    proof.push(cached.bytes.to_vec());
+                    cached.children
+                }
+                None => {
+                    let node = self.trie.get_node_by_hash(self.db, node_hash)?;
+                    let bytes = self
+                        .db
+                        .canonical_value(&node, self.trie.proof_key_preimages);
+                    let children = node.as_branch().map(|branch| {
+                        (
+                            *branch.child_left().unwrap_ref(),
+                            *branch.child_right().unwrap_ref(),
+                        )
+                    });
+                    if level < self.cache_depth {
+                        let cached = CachedNode {
+                            bytes: bytes.clone(),
+                            children,
+                        };
+                        self.nodes.insert(node_hash, cached);
+                    }
+                    proof.push(bytes.into());
+                    children
+                }
+            };
+            match children {
+                Some((left, right)) => {
+                    node_hash = if P::path_bit::<H>(node_key, level) {
+                        right
+                    } else {
+                        left
+                    };
+                }
+                None => break,
+            }
+        }
+        proof.push(self.trie.proof_magic().to_vec());
+        Ok(proof)
+    }
+}
+
+impl<H, Db, K, P> Debug for ProofBuilder<'_, H, Db, K, P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProofBuilder")
+            .field("root", &self.root)
+            .field("cache_depth", &self.cache_depth)
+            .field("cached_nodes", &self.nodes.len())
+            .field("cached_keys", &self.node_keys.len())
+            .finish()
+    }
+}
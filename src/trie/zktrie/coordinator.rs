@@ -0,0 +1,153 @@
+//! Commit several tries sharing a database at once, see [`CommitCoordinator`].
+use super::{imp::Result, CommitSet, PathOrder, ZkTrie, ZkTrieError};
+use crate::{
+    db::{kv::KVDatabase, DbError, NodeDb},
+    hash::{key_hasher::KeyHasher, poseidon::Poseidon, HashScheme, ZkHash},
+    trie::{CommittedRoot, LazyNodeHash, NodeArena},
+    HashSet,
+};
+use std::fmt::{Debug, Formatter};
+
+/// Commits several tries into a shared database, updating all roots or none,
+/// e.g. the account trie and the storage tries of a block.
+///
+/// The changes of every trie are [staged](CommitCoordinator::stage), then
+/// [`commit`](CommitCoordinator::commit) writes the nodes of all tries in one
+/// [`KVDatabase::extend`] and only then moves the roots.
+/// A failed write leaves every trie at its previous root, with its changes still dirty.
+///
+/// # Note
+///
+/// The write is only atomic on backends where [`KVDatabase::is_extend_atomic`] holds,
+/// e.g. [`SledDb`](crate::db::kv::SledDb) and [`TxDb`](crate::db::kv::TxDb).
+/// On other backends a failed write may leave some nodes written, unreachable from any root.
+pub struct CommitCoordinator<H = Poseidon> {
+    staged: Vec<CommitSet<H>>,
+}
+
+impl<H> Default for CommitCoordinator<H> {
+    fn default() -> Self {
+        Self { staged: Vec::new() }
+    }
+}
+
+impl<H> Debug for CommitCoordinator<H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommitCoordinator")
+            .field("staged", &self.staged)
+            .finish()
+    }
+}
+
+impl<H: HashScheme> CommitCoordinator<H> {
+    /// Create an empty coordinator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stage the changes of a trie, see [`ZkTrie::prepare_commit`].
+    ///
+    /// Returns the index of the trie in the tries passed to [`commit`](CommitCoordinator::commit).
+    pub fn stage<K: KeyHasher<H>, P: PathOrder>(
+        &mut self,
+        trie: &ZkTrie<H, K, P>,
+    ) -> std::result::Result<usize, H::Error> {
+        self.staged.push(trie.prepare_commit()?);
+        Ok(self.staged.len() - 1)
+    }
+
+    /// Number of staged tries.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.staged.len()
+    }
+
+    /// Check if no trie is staged.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+
+    /// Get the staged commit sets, in staging order.
+    #[inline]
+    pub fn staged(&self) -> &[CommitSet<H>] {
+        &self.staged
+    }
+
+    /// Write the nodes of all staged tries, then update their roots.
+    ///
+    /// `tries` must be the staged tries in staging order.
+    /// Returns [`ZkTrieError::RootMismatch`] without writing anything
+    /// if a trie was changed since it was staged.
+    ///
+    /// Once the nodes are written all roots are updated,
+    /// the first error of the bookkeeping following a commit is returned afterwards,
+    /// see [`ZkTrie::commit`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of tries differs from the number of staged tries.
+    pub fn commit<Db: KVDatabase, K: KeyHasher<H>, P: PathOrder>(
+        self,
+        db: &mut NodeDb<Db>,
+        tries: &mut [&mut ZkTrie<H, K, P>],
+    ) -> Result<Vec<CommittedRoot>, H, Db> {
+        assert_eq!(
+            tries.len(),
+            self.staged.len(),
+            "tries must match the staged tries"
+        );
+        if !db.inner().is_extend_atomic() {
+            warn!("backend database can't write atomically, a failed commit may leave stray nodes");
+        }
+        let mut nodes = Vec::new();
+        let mut roots = Vec::with_capacity(tries.len());
+        for (trie, commit_set) in tries.iter().zip(self.staged) {
+            let root = trie.resolve_hash(&trie.root).map_err(ZkTrieError::Hash)?;
+            if root != commit_set.root {
+                return Err(ZkTrieError::RootMismatch {
+                    expected: commit_set.root,
+                    actual: root,
+                });
+            }
+            if let Some(id) = db.hash_scheme().filter(|id| *id != H::ID) {
+                return Err(ZkTrieError::HashSchemeMismatch(id));
+            }
+            for node in commit_set.nodes.iter() {
+                node.get_or_calculate_node_hash()
+                    .map_err(ZkTrieError::Hash)?;
+            }
+            nodes.extend(commit_set.nodes);
+            roots.push((root, trie.is_dirty() || !trie.gc_nodes.is_empty()));
+        }
+
+        let arena = NodeArena::new(nodes);
+        db.put_arena_atomic(&arena)
+            .map_err(DbError::classify::<Db>)?;
+        trace!(
+            tries = tries.len(),
+            nodes = arena.len(),
+            "committed tries at once"
+        );
+
+        let written: HashSet<ZkHash> = arena.iter().map(|(node_hash, _)| node_hash).collect();
+        for (trie, (root, _)) in tries.iter_mut().zip(roots.iter()) {
+            trie.root = LazyNodeHash::Hash(*root);
+        }
+        let mut result = Ok(());
+        for (trie, (_, changed)) in tries.iter_mut().zip(roots.iter()) {
+            if *changed {
+                let finished = trie.finish_commit(db, written.clone());
+                if result.is_ok() {
+                    result = finished.map(drop);
+                }
+            }
+        }
+        result.map(|_| {
+            roots
+                .into_iter()
+                .map(|(root, _)| CommittedRoot::new_unchecked(root))
+                .collect()
+        })
+    }
+}
@@ -0,0 +1,117 @@
+//! Per-depth node occupancy, see [`NodeDb::with_depth_histogram`].
+use super::{imp::Result, PathOrder, ZkTrie, ZkTrieError};
+use crate::{
+    db::{histogram::DepthHistogram, kv::KVDatabase, NodeDb},
+    hash::{key_hasher::KeyHasher, HashScheme, ZkHash},
+    trie::NodeType,
+};
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> ZkTrie<H, K, P> {
+    /// Count the nodes of the trie at each depth by traversing it,
+    /// e.g. to seed [`NodeDb::with_depth_histogram`] for an existing database.
+    ///
+    /// The trie must be committed.
+    pub fn compute_depth_histogram<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+    ) -> Result<DepthHistogram, H, Db> {
+        if self.is_dirty() {
+            warn!("dirty nodes found, commit before computing depth histogram");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let mut histogram = DepthHistogram::default();
+        let mut changes = Vec::new();
+        self.diff_depths(db, None, Some(*self.root.unwrap_ref()), &mut changes)?;
+        for (is_branch, depth, _) in changes {
+            histogram.add(is_branch, depth);
+        }
+        Ok(histogram)
+    }
+
+    /// Apply the change from `old_root` to the committed root to the histogram tracked by `db`.
+    ///
+    /// Only subtrees whose hash differs at the same position are walked,
+    /// so the cost is proportional to the changed nodes.
+    pub(super) fn update_depth_histogram<Db: KVDatabase>(
+        &self,
+        db: &mut NodeDb<Db>,
+        old_root: ZkHash,
+    ) -> Result<(), H, Db> {
+        if db.depth_histogram().is_none() {
+            return Ok(());
+        }
+        let mut changes = Vec::new();
+        self.diff_depths(
+            db,
+            Some(old_root),
+            Some(*self.root.unwrap_ref()),
+            &mut changes,
+        )?;
+        trace!(changes = changes.len(), "updating depth histogram");
+        db.update_depth_histogram(|histogram| {
+            for (is_branch, depth, added) in changes {
+                if added {
+                    histogram.add(is_branch, depth);
+                } else {
+                    histogram.remove(is_branch, depth);
+                }
+            }
+        })
+        .map_err(crate::db::DbError::classify::<Db>)?;
+        Ok(())
+    }
+
+    /// Collect the `(is_branch, depth, added)` nodes differing between two subtrees.
+    fn diff_depths<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        old: Option<ZkHash>,
+        new: Option<ZkHash>,
+        changes: &mut Vec<(bool, usize, bool)>,
+    ) -> Result<(), H, Db> {
+        let mut stack = vec![(old, new, 0)];
+        while let Some((old, new, depth)) = stack.pop() {
+            if old == new {
+                continue;
+            }
+            let old = old.map(|h| self.get_node_by_hash(db, h)).transpose()?;
+            let new = new.map(|h| self.get_node_by_hash(db, h)).transpose()?;
+            if let (Some(old), Some(new)) = (&old, &new) {
+                if let (Some(old), Some(new)) = (old.as_branch(), new.as_branch()) {
+                    let (old_left, old_right) = (old.child_left(), old.child_right());
+                    let (new_left, new_right) = (new.child_left(), new.child_right());
+                    stack.push((
+                        Some(*old_left.unwrap_ref()),
+                        Some(*new_left.unwrap_ref()),
+                        depth + 1,
+                    ));
+                    stack.push((
+                        Some(*old_right.unwrap_ref()),
+                        Some(*new_right.unwrap_ref()),
+                        depth + 1,
+                    ));
+                    continue;
+                }
+            }
+            for (node, added) in [(old, false), (new, true)] {
+                let Some(node) = node else {
+                    continue;
+                };
+                match node.node_type() {
+                    NodeType::Empty => {}
+                    NodeType::Leaf => changes.push((false, depth, added)),
+                    _ => {
+                        changes.push((true, depth, added));
+                        let branch = node.as_branch().unwrap();
+                        for child in [branch.child_left(), branch.child_right()] {
+                            let child = Some(*child.unwrap_ref());
+                            let pair = if added { (None, child) } else { (child, None) };
+                            stack.push((pair.0, pair.1, depth + 1));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
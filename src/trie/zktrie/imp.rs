@@ -1,13 +1,22 @@
 use super::*;
 
-use crate::trie::INode;
+use super::proof::assert_proof_magic;
+use crate::trie::{INode, NodeArena};
 use crate::{
-    db::kv::KVDatabase,
+    db::{
+        kv::KVDatabase,
+        snapshot::{SnapshotManifest, SnapshotWriter},
+        GcSkipped, NodeEncoding,
+    },
+    hash::BranchInput,
     trie::{DecodeValueBytes, EncodeValueBytes, LazyBranchHash, MAGIC_NODE_BYTES},
 };
+use alloy_primitives::{bytes::Bytes, keccak256};
+use std::collections::btree_map::Entry;
+use std::convert::Infallible;
 use std::fmt::{Debug, Formatter};
 
-type Result<T, H, DB> =
+pub(super) type Result<T, H, DB> =
     std::result::Result<T, ZkTrieError<<H as HashScheme>::Error, <DB as KVDatabase>::Error>>;
 
 impl Default for ZkTrie {
@@ -16,52 +25,484 @@ impl Default for ZkTrie {
     }
 }
 
-impl<H: HashScheme, K: KeyHasher<H>> Debug for ZkTrie<H, K> {
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> Debug for ZkTrie<H, K, P> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ZkTrie")
             .field("MAX_LEVEL", &H::TRIE_MAX_LEVELS)
             .field("hash_scheme", &std::any::type_name::<H>())
+            .field("path_order", &P::default())
             .field("root", &self.root)
             .field("is_dirty", &self.is_dirty())
+            .field("root_version", &self.root_version)
+            .field("retired_nodes", &self.retired_nodes.len())
+            .field("path_cache_depth", &self.path_cache_depth())
+            .field("has_leaf_observer", &self.leaf_observer.is_some())
+            .field("has_value_index", &self.has_value_index())
+            .field("leaf_versions", &self.leaf_versions)
+            .field("secondary_root", &self.secondary_root)
+            .field("journal", &self.journal.as_ref().map(OpJournal::len))
+            .field("max_value_preimages", &self.max_value_preimages)
+            .field("proof_key_preimages", &self.proof_key_preimages)
+            .field("proof_magic", &String::from_utf8_lossy(self.proof_magic))
+            .field("audit", &self.audit)
+            .field("root_checkpoints", &self.root_checkpoints.len())
             .finish()
     }
 }
 
-impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> ZkTrie<H, K, P> {
+    /// The root of an empty trie, see [`HashScheme::EMPTY_ROOT`].
+    pub const EMPTY_ROOT: ZkHash = H::EMPTY_ROOT;
+
     /// Create a new zkTrie
     #[inline(always)]
     pub fn new(key_hasher: K) -> Self {
         Self {
             key_hasher,
-            root: ZkHash::default().into(),
+            root: H::EMPTY_ROOT.into(),
+            committed_root: H::EMPTY_ROOT,
             dirty_branch_nodes: Vec::new(),
             dirty_leafs: HashMap::new(),
             gc_nodes: HashSet::new(),
+            retired_nodes: BTreeMap::new(),
+            root_version: 0,
+            path_cache: None,
+            leaf_observer: None,
+            value_index: None,
+            leaf_versions: false,
+            secondary: None,
+            secondary_root: None,
+            leaf_changes: HashMap::new(),
+            leaf_count: Some(0),
+            journal: None,
+            max_value_preimages: MAX_VALUE_PREIMAGES,
+            proof_key_preimages: false,
+            proof_magic: MAGIC_NODE_BYTES,
+            audit: AuditMode::Off,
+            root_checkpoints: Vec::new(),
             _hash_scheme: std::marker::PhantomData,
+            _path_order: std::marker::PhantomData,
         }
     }
 
-    /// Create a new zkTrie with a given root hash
+    /// Create a new zkTrie with a given committed root, see [`NodeDb::committed_root`]
+    ///
+    /// The [empty root](ZkTrie::EMPTY_ROOT) is accepted without reading `db`.
     #[inline]
     pub fn new_with_root<Db: KVDatabase>(
         db: &NodeDb<Db>,
         key_hasher: K,
-        root: ZkHash,
+        root: CommittedRoot,
     ) -> Result<Self, H, Db> {
-        let this = Self {
+        check_hash_scheme::<H, Db>(db)?;
+        let root = root.hash();
+        let mut this = Self {
             key_hasher,
             root: root.into(),
+            committed_root: root,
             dirty_branch_nodes: Vec::new(),
             dirty_leafs: HashMap::new(),
             gc_nodes: HashSet::new(),
+            retired_nodes: BTreeMap::new(),
+            root_version: 0,
+            path_cache: None,
+            leaf_observer: None,
+            value_index: None,
+            leaf_versions: false,
+            secondary: None,
+            secondary_root: None,
+            leaf_changes: HashMap::new(),
+            leaf_count: None,
+            journal: None,
+            max_value_preimages: MAX_VALUE_PREIMAGES,
+            proof_key_preimages: false,
+            proof_magic: MAGIC_NODE_BYTES,
+            audit: AuditMode::Off,
+            root_checkpoints: Vec::new(),
             _hash_scheme: std::marker::PhantomData,
+            _path_order: std::marker::PhantomData,
         };
 
-        this.get_node_by_hash(db, root)?;
+        if root == H::EMPTY_ROOT {
+            this.leaf_count = Some(0);
+        } else {
+            this.get_node_by_hash(db, root)?;
+            if let Some(version) = db.root_version(&root)? {
+                this.root_version = version;
+                this.leaf_versions = true;
+            }
+        }
+
+        Ok(this)
+    }
 
+    /// Same as [`new_with_root`](ZkTrie::new_with_root), but also checks the nodes selected
+    /// by `check` are in `db` and hash to their keys, to catch a truncated or
+    /// partially synced database on open rather than in the middle of a block.
+    ///
+    /// Returns [`ZkTrieError::NodeNotFound`] or [`ZkTrieError::CorruptedNode`]
+    /// for the first bad node found.
+    #[instrument(level = "trace", skip(db, key_hasher, root))]
+    pub fn open_verified<Db: KVDatabase>(
+        db: &NodeDb<Db>,
+        key_hasher: K,
+        root: CommittedRoot,
+        check: OpenCheck,
+    ) -> Result<Self, H, Db> {
+        let this = Self::new_with_root(db, key_hasher, root)?;
+        let root = root.hash();
+        if root == H::EMPTY_ROOT {
+            return Ok(this);
+        }
+        let mut checked = 0;
+        match check {
+            OpenCheck::Levels(_) | OpenCheck::Full => {
+                let depth = match check {
+                    OpenCheck::Levels(depth) => depth,
+                    _ => usize::MAX,
+                };
+                let mut stack = vec![(root, 0)];
+                while let Some((node_hash, level)) = stack.pop() {
+                    if node_hash.is_zero() || level >= depth {
+                        continue;
+                    }
+                    let node = this.verify_node(db, node_hash)?;
+                    checked += 1;
+                    if let Some(branch) = node.as_branch() {
+                        stack.push((*branch.child_right().unwrap_ref(), level + 1));
+                        stack.push((*branch.child_left().unwrap_ref(), level + 1));
+                    }
+                }
+            }
+            OpenCheck::Sample { paths } => {
+                for i in 0..paths as u64 {
+                    let node_key = keccak256(i.to_le_bytes());
+                    let mut node_hash = root;
+                    for level in 0..H::TRIE_MAX_LEVELS {
+                        if node_hash.is_zero() {
+                            break;
+                        }
+                        let node = this.verify_node(db, node_hash)?;
+                        checked += 1;
+                        let Some(branch) = node.as_branch() else {
+                            break;
+                        };
+                        let child = if P::path_bit::<H>(&node_key, level) {
+                            branch.child_right()
+                        } else {
+                            branch.child_left()
+                        };
+                        node_hash = *child.unwrap_ref();
+                    }
+                }
+            }
+        }
+        trace!("verified {checked} nodes");
         Ok(this)
     }
 
+    /// Read a node from `db` and check it hashes to `node_hash`.
+    fn verify_node<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_hash: ZkHash,
+    ) -> Result<INode<H>, H, Db> {
+        let node = self.get_node_by_hash(db, node_hash).inspect_err(|e| {
+            if matches!(e, ZkTrieError::NodeNotFound) {
+                warn!(node_hash = ?node_hash, "node not found");
+            }
+        })?;
+        check_node_hash::<H, Db>(node_hash, &node)?;
+        Ok(node)
+    }
+
+    /// Check a node read from the database or the path cache, see [`ZkTrie::with_audit`].
+    fn audit_node<Db: KVDatabase>(&self, node_hash: ZkHash, node: &INode<H>) -> Result<(), H, Db> {
+        match self.audit {
+            AuditMode::Off => Ok(()),
+            AuditMode::Log => {
+                // mismatches are logged by the check
+                let _ = check_node_hash::<H, Db>(node_hash, node);
+                Ok(())
+            }
+            AuditMode::Error => check_node_hash::<H, Db>(node_hash, node),
+        }
+    }
+
+    /// Cache the nodes of the top `depth` levels, refreshed on every commit.
+    ///
+    /// Lookups on a committed trie then jump straight to the subtree at `depth`,
+    /// and updates read the cached branch nodes instead of the database.
+    ///
+    /// The trie must be committed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is larger than [`MAX_PATH_CACHE_DEPTH`].
+    pub fn with_path_cache<Db: KVDatabase>(
+        mut self,
+        db: &NodeDb<Db>,
+        depth: usize,
+    ) -> Result<Self, H, Db> {
+        assert!(
+            depth <= MAX_PATH_CACHE_DEPTH,
+            "path cache depth must be at most {MAX_PATH_CACHE_DEPTH}"
+        );
+        if self.is_dirty() {
+            warn!("dirty nodes found, commit before building path cache");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        self.path_cache = Some(self.build_path_cache(db, depth)?);
+        Ok(self)
+    }
+
+    /// Limit the number of value preimages of new leafs, e.g. to what a circuit can handle.
+    ///
+    /// Updates with more value preimages fail with [`LeafValuesError::ExceedsLimit`].
+    /// Defaults to [`MAX_VALUE_PREIMAGES`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero or larger than [`MAX_VALUE_PREIMAGES`].
+    pub fn with_max_value_preimages(mut self, max: usize) -> Self {
+        assert!(
+            (1..=MAX_VALUE_PREIMAGES).contains(&max),
+            "max value preimages must be in 1..={MAX_VALUE_PREIMAGES}"
+        );
+        self.max_value_preimages = max;
+        self
+    }
+
+    /// Get the max number of value preimages of new leafs
+    #[inline]
+    pub fn max_value_preimages(&self) -> usize {
+        self.max_value_preimages
+    }
+
+    /// Include the key preimages of leafs in proofs.
+    ///
+    /// Preimages are not part of the node hash, and are excluded by default
+    /// so proofs don't reveal the original keys.
+    pub fn with_key_preimages_in_proofs(mut self, include: bool) -> Self {
+        self.proof_key_preimages = include;
+        self
+    }
+
+    /// Check if proofs include the key preimages of leafs
+    #[inline]
+    pub fn key_preimages_in_proofs(&self) -> bool {
+        self.proof_key_preimages
+    }
+
+    /// Terminate proofs with `magic` instead of [`MAGIC_NODE_BYTES`],
+    /// e.g. to brand the proofs of a non-Scroll deployment.
+    ///
+    /// Verifiers must expect the same bytes, see [`Proof::parse_with_magic`]
+    /// and [`ProofReader::with_magic`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `magic` is empty or starts with a [`NodeType`] byte,
+    /// so it can't be told apart from a node in a proof stream.
+    pub fn with_proof_magic(mut self, magic: &'static [u8]) -> Self {
+        assert_proof_magic(magic);
+        self.proof_magic = magic;
+        self
+    }
+
+    /// Get the bytes terminating proofs
+    #[inline]
+    pub fn proof_magic(&self) -> &'static [u8] {
+        self.proof_magic
+    }
+
+    /// Cross-check every node read from the database or the path cache,
+    /// to catch database corruption and serializer bugs early, e.g. in staging.
+    ///
+    /// # Note
+    ///
+    /// Every read then parses and rehashes the node, which costs a hash per branch
+    /// and a few more per leaf, making reads several times slower.
+    /// In-memory dirty nodes are not checked.
+    pub fn with_audit(mut self, mode: AuditMode) -> Self {
+        self.audit = mode;
+        self
+    }
+
+    /// Get the audit mode
+    #[inline]
+    pub fn audit(&self) -> AuditMode {
+        self.audit
+    }
+
+    /// Get the depth of the path cache, if enabled
+    #[inline]
+    pub fn path_cache_depth(&self) -> Option<usize> {
+        self.path_cache.as_ref().map(|cache| cache.depth)
+    }
+
+    /// Index the node keys of the leafs by value hash, updated on every commit,
+    /// see [`find_by_value_hash`](ZkTrie::find_by_value_hash).
+    ///
+    /// The index is built by reading every leaf once and is kept in memory.
+    /// The trie must be committed.
+    pub fn with_value_index<Db: KVDatabase>(mut self, db: &NodeDb<Db>) -> Result<Self, H, Db> {
+        if self.is_dirty() {
+            warn!("dirty nodes found, commit before building value index");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let mut index = ValueIndex::default();
+        for leaf in self.leafs(db) {
+            let leaf = leaf?;
+            let leaf = leaf.as_leaf().unwrap();
+            let value_hash = leaf
+                .get_or_calc_value_hash::<H>()
+                .map_err(ZkTrieError::Hash)?;
+            index.insert(value_hash, leaf.node_key());
+        }
+        trace!(values = index.node_keys.len(), "built value index");
+        self.value_index = Some(index);
+        Ok(self)
+    }
+
+    /// Check if the leafs are indexed by value hash
+    #[inline]
+    pub fn has_value_index(&self) -> bool {
+        self.value_index.is_some()
+    }
+
+    /// Get the node keys of the committed leafs holding the value of `value_hash`, in order.
+    ///
+    /// Returns `None` if the [value index](ZkTrie::with_value_index) is not enabled.
+    /// Uncommitted changes are not reflected.
+    pub fn find_by_value_hash(&self, value_hash: &ZkHash) -> Option<Vec<ZkHash>> {
+        let index = self.value_index.as_ref()?;
+        Some(
+            index
+                .node_keys
+                .get(value_hash)
+                .map(|node_keys| node_keys.iter().copied().collect())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Record the [root version](ZkTrie::root_version) each leaf last changed in,
+    /// updated on every commit, see [`last_modified`](ZkTrie::last_modified).
+    ///
+    /// Only changes committed afterwards are recorded. The versions are persisted in the `NodeDb`
+    /// by node key together with the version of each committed root, and
+    /// [`new_with_root`](ZkTrie::new_with_root) reloads them, see [`NodeDb::root_version`].
+    /// Tries recording leaf versions need a `NodeDb` of their own, e.g. a
+    /// [`NamespacedNodeDb`](crate::db::NamespacedNodeDb).
+    pub fn with_leaf_versions(mut self) -> Self {
+        self.leaf_versions = true;
+        self
+    }
+
+    /// Check if the leaf versions are recorded
+    #[inline]
+    pub fn has_leaf_versions(&self) -> bool {
+        self.leaf_versions
+    }
+
+    /// Get the root version in which the leaf of `key` last changed.
+    ///
+    /// Deleted leafs keep the version they were deleted in.
+    /// Returns `None` if the [leaf versions](ZkTrie::with_leaf_versions) are not recorded,
+    /// or the leaf hasn't changed since.
+    pub fn last_modified<Db: KVDatabase, KEY: AsRef<[u8]>>(
+        &self,
+        db: &NodeDb<Db>,
+        key: KEY,
+    ) -> Result<Option<u64>, H, Db> {
+        let node_key = self.key_hasher.hash(key.as_ref())?;
+        self.last_modified_by_node_key(db, &node_key)
+    }
+
+    /// Same as [`last_modified`](ZkTrie::last_modified), but by node key.
+    #[inline]
+    pub fn last_modified_by_node_key<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_key: &ZkHash,
+    ) -> Result<Option<u64>, H, Db> {
+        if !self.leaf_versions {
+            return Ok(None);
+        }
+        Ok(db.leaf_version(node_key)?)
+    }
+
+    /// Get the node keys of the leafs changed after root version `version`,
+    /// including deleted ones, with the version they last changed in, in node key order.
+    ///
+    /// Returns `None` if the [leaf versions](ZkTrie::with_leaf_versions) are not recorded.
+    /// Fails if the backend database can't enumerate its keys, see [`NodeDb::leaf_versions_since`].
+    pub fn changed_since<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        version: u64,
+    ) -> Result<Option<Vec<(ZkHash, u64)>>, H, Db> {
+        if !self.leaf_versions {
+            return Ok(None);
+        }
+        Ok(Some(db.leaf_versions_since(version)?))
+    }
+
+    /// Register an observer notified of every leaf change on commit.
+    ///
+    /// Changes made before the observer is registered are not reported.
+    pub fn set_leaf_observer<O: LeafObserver + 'static>(&mut self, observer: O) {
+        self.leaf_observer = Some(Box::new(observer));
+    }
+
+    /// Remove the leaf observer, pending changes are discarded.
+    pub fn take_leaf_observer(&mut self) -> Option<Box<dyn LeafObserver>> {
+        // still needed to update the value index or leaf versions
+        if self.value_index.is_none() && !self.leaf_versions {
+            self.leaf_changes.clear();
+        }
+        self.leaf_observer.take()
+    }
+
+    /// Feed every following mutation into a secondary commitment, committed together with the trie.
+    ///
+    /// Mutations made before it's set are not reported.
+    pub fn set_secondary_commitment<C: SecondaryCommitment + 'static>(&mut self, commitment: C) {
+        self.secondary = Some(Box::new(commitment));
+        self.secondary_root = None;
+    }
+
+    /// Remove the secondary commitment.
+    pub fn take_secondary_commitment(&mut self) -> Option<Box<dyn SecondaryCommitment>> {
+        self.secondary_root = None;
+        self.secondary.take()
+    }
+
+    /// Get the root of the secondary commitment as of the last commit.
+    #[inline]
+    pub fn secondary_root(&self) -> Option<ZkHash> {
+        self.secondary_root
+    }
+
+    /// Record every following mutation with the roots around it, see [`OpJournal`].
+    ///
+    /// Roots of dirty tries are hashed after each mutation, which makes updates slower.
+    /// Mutations recorded so far are kept.
+    pub fn enable_journal(&mut self) {
+        self.journal.get_or_insert_with(OpJournal::new);
+    }
+
+    /// Get the operation journal, if enabled
+    #[inline]
+    pub fn journal(&self) -> Option<&OpJournal> {
+        self.journal.as_ref()
+    }
+
+    /// Stop journaling and take the recorded mutations.
+    pub fn take_journal(&mut self) -> Option<OpJournal> {
+        self.journal.take()
+    }
+
     /// Get the underlying key hasher
     #[inline(always)]
     pub fn key_hasher(&self) -> &K {
@@ -74,38 +515,146 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
         !self.dirty_branch_nodes.is_empty() || !self.dirty_leafs.is_empty()
     }
 
+    /// Number of commits that changed the trie since it was opened,
+    /// or since it was created if the [leaf versions](ZkTrie::with_leaf_versions) are recorded
+    #[inline]
+    pub fn root_version(&self) -> u64 {
+        self.root_version
+    }
+
+    /// Iterate over the persisted nodes waiting for [`gc`](ZkTrie::gc),
+    /// with the root version that retired them.
+    pub fn retired_nodes(&self) -> impl Iterator<Item = (u64, ZkHash)> + '_ {
+        self.retired_nodes
+            .iter()
+            .map(|(node_hash, version)| (*version, *node_hash))
+    }
+
     /// Get the root hash of the trie, may be unresolved if the trie is dirty
     #[inline(always)]
     pub fn root(&self) -> &LazyNodeHash {
         &self.root
     }
 
+    /// Resolve a lazy hash of this trie without committing.
+    ///
+    /// The dirty branch and its dirty descendants are hashed,
+    /// the hashes are shared with the trie so they're not computed again on commit.
+    ///
+    /// Returns [`ZkTrieError::UnresolvedHashUsed`] if `node_hash` is a lazy branch that is not
+    /// a [dirty branch](Self::dirty_branch) of this trie, e.g. one taken before a commit.
+    pub fn resolve(
+        &self,
+        node_hash: &LazyNodeHash,
+    ) -> std::result::Result<ZkHash, ZkTrieError<H::Error, Infallible>> {
+        if let LazyNodeHash::LazyBranch(lazy) = node_hash {
+            if self.dirty_branch(lazy).is_none() {
+                warn!(
+                    "lazy branch {} is not a dirty branch of this trie",
+                    lazy.index()
+                );
+                return Err(ZkTrieError::UnresolvedHashUsed);
+            }
+        }
+        self.resolve_hash(node_hash).map_err(ZkTrieError::Hash)
+    }
+
+    /// Record the current root, e.g. after every transaction of a block,
+    /// and return the index of the checkpoint.
+    ///
+    /// Nothing is hashed until the roots are [resolved](ZkTrie::checkpoint_roots) or on commit,
+    /// so per-transaction roots can be collected without committing after every transaction.
+    /// Checkpoints are kept across commits until [cleared](ZkTrie::clear_checkpoints).
+    pub fn checkpoint_root(&mut self) -> usize {
+        self.root_checkpoints.push(self.root.clone());
+        self.root_checkpoints.len() - 1
+    }
+
+    /// Resolve the recorded roots, in checkpoint order.
+    ///
+    /// The hashes are shared with the trie, so resolving before a commit costs no extra hashing.
+    pub fn checkpoint_roots(&self) -> std::result::Result<Vec<ZkHash>, H::Error> {
+        self.root_checkpoints
+            .iter()
+            .map(|root| self.resolve_hash(root))
+            .collect()
+    }
+
+    /// Drop the recorded roots.
+    pub fn clear_checkpoints(&mut self) {
+        self.root_checkpoints.clear();
+    }
+
+    /// Get the dirty branch node referenced by a lazy branch hash.
+    ///
+    /// Returns `None` if the lazy hash does not belong to this trie,
+    /// or the branch was committed or dropped by [`ZkTrie::shrink_dirty`] since.
+    pub fn dirty_branch(&self, lazy: &LazyBranchHash) -> Option<&Node<H>> {
+        self.dirty_branch_nodes
+            .get(lazy.index)
+            .filter(|node| std::sync::Arc::ptr_eq(&node.node_hash, &lazy.resolved))
+    }
+
+    /// Iterate over the dirty branch nodes with their lazy hashes, in index order.
+    ///
+    /// A branch always comes after its dirty children, so resolving the hashes in this order
+    /// never recurses. Branches replaced by later updates are included until
+    /// [`ZkTrie::shrink_dirty`] or commit.
+    pub fn dirty_branches(&self) -> impl Iterator<Item = (LazyBranchHash, &Node<H>)> + '_ {
+        self.dirty_branch_nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let lazy = LazyBranchHash {
+                    index,
+                    resolved: node.node_hash.clone(),
+                };
+                (lazy, node)
+            })
+    }
+
     /// Get a value from the trie, which can be decoded from bytes
     ///
+    /// Leafs of several layouts can be decoded with [`Fallback`](crate::trie::Fallback).
+    ///
     /// # Returns
     ///
     /// - `Ok(Some(value))` if the key is found
     /// - `Ok(None)` if the key is not found
     /// - `Err(e)` if other error occurs
-    #[instrument(level = "trace", skip_all)]
+    #[inline]
     pub fn get<Db: KVDatabase, T: DecodeValueBytes, KEY: AsRef<[u8]>>(
         &self,
         db: &NodeDb<Db>,
         key: KEY,
     ) -> Result<Option<T>, H, Db> {
+        self.get_traced(db, key).map(|(value, _)| value)
+    }
+
+    /// Same as [`get`](ZkTrie::get), also reports where the node reads were served from.
+    #[instrument(level = "trace", skip_all)]
+    pub fn get_traced<Db: KVDatabase, T: DecodeValueBytes, KEY: AsRef<[u8]>>(
+        &self,
+        db: &NodeDb<Db>,
+        key: KEY,
+    ) -> Result<(Option<T>, NodeReadStats), H, Db> {
         let key = key.as_ref();
         trace!(key = hex::encode(key));
         let node_key = self.key_hasher.hash(key)?;
         trace!(node_key = ?node_key);
-        let node = self.get_node_by_key(db, &node_key)?;
+        let mut stats = NodeReadStats::default();
+        let node = self.get_node_by_key_traced(db, &node_key, &mut stats)?;
+        trace!(stats = ?stats);
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_db_reads("get", &stats);
         match node.node_type() {
-            NodeType::Empty => Ok(None),
+            NodeType::Empty => Ok((None, stats)),
             NodeType::Leaf => {
                 let leaf = node.as_leaf().unwrap();
                 let values = leaf.value_preimages();
 
                 if let Some(t) = T::decode_values_bytes(values) {
-                    Ok(Some(t))
+                    Ok((Some(t), stats))
                 } else {
                     Err(ZkTrieError::UnexpectValue)
                 }
@@ -114,6 +663,52 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
         }
     }
 
+    /// Check if the trie contains a key, without decoding the value.
+    #[instrument(level = "trace", skip_all)]
+    pub fn contains_key<Db: KVDatabase, KEY: AsRef<[u8]>>(
+        &self,
+        db: &NodeDb<Db>,
+        key: KEY,
+    ) -> Result<bool, H, Db> {
+        let key = key.as_ref();
+        trace!(key = hex::encode(key));
+        let node_key = self.key_hasher.hash(key)?;
+        trace!(node_key = ?node_key);
+        self.contains_node_key(db, &node_key)
+    }
+
+    /// Check if the trie contains a leaf with the node key.
+    ///
+    /// The walk stops at the first terminal node.
+    pub fn contains_node_key<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_key: &ZkHash,
+    ) -> Result<bool, H, Db> {
+        if !self.is_dirty() && !db.may_contain_leaf(node_key) {
+            return Ok(false);
+        }
+        let (mut next_hash, start) = self.walk_start(node_key, &mut NodeReadStats::default());
+        for i in start..H::TRIE_MAX_LEVELS {
+            if next_hash.is_zero().unwrap_or(false) {
+                return Ok(false);
+            }
+            let n = self.get_node_by_hash(db, next_hash)?;
+            if let Some(leaf) = n.as_leaf() {
+                return Ok(leaf.node_key() == *node_key);
+            }
+            let Some(branch) = n.as_branch() else {
+                return Ok(false);
+            };
+            next_hash = if P::path_bit::<H>(node_key, i) {
+                branch.child_right()
+            } else {
+                branch.child_left()
+            };
+        }
+        Err(ZkTrieError::NodeNotFound)
+    }
+
     /// Update the trie with a new key-value pair, which value can be encoded to bytes
     #[inline(always)]
     #[instrument(level = "trace", skip_all)]
@@ -128,7 +723,10 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
     }
 
     /// Update the trie with a new key-values pair
-    #[instrument(level = "trace", skip_all)]
+    ///
+    /// The values are checked by [`Node::check_leaf_values`],
+    /// returning [`ZkTrieError::InvalidLeafValues`] before the trie is changed.
+    #[inline]
     pub fn raw_update<Db: KVDatabase, KEY: AsRef<[u8]>>(
         &mut self,
         db: &NodeDb<Db>,
@@ -136,14 +734,64 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
         value_preimages: Vec<[u8; 32]>,
         compression_flags: u32,
     ) -> Result<(), H, Db> {
-        let key = key.as_ref();
-        trace!(key = hex::encode(key));
-        let node_key = self.key_hasher.hash(key)?;
-        trace!(node_key = ?node_key);
-        let new_leaf = Node::new_leaf(node_key, value_preimages, compression_flags, None)
-            .map_err(ZkTrieError::Hash)?;
-        self.root = self.add_leaf(db, new_leaf, self.root.clone(), 0)?.0;
-        Ok(())
+        self.raw_update_with_receipt(db, key, value_preimages, compression_flags)
+            .map(|_| ())
+    }
+
+    /// Same as [`raw_update`](ZkTrie::raw_update), also reports the work done.
+    #[instrument(level = "trace", skip_all)]
+    pub fn raw_update_with_receipt<Db: KVDatabase, KEY: AsRef<[u8]>>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: KEY,
+        value_preimages: Vec<[u8; 32]>,
+        compression_flags: u32,
+    ) -> Result<OpReceipt, H, Db> {
+        let mut receipt = OpReceipt::default();
+        self.add_leaf_by_key(
+            db,
+            key.as_ref(),
+            value_preimages,
+            compression_flags,
+            &mut None,
+            &mut receipt,
+        )?;
+        trace!(receipt = ?receipt);
+        Ok(receipt)
+    }
+
+    /// Insert a key-value pair, returning the previous value if the key was present,
+    /// like [`HashMap::insert`](std::collections::HashMap::insert).
+    ///
+    /// The previous value is collected in the same traversal as the update.
+    ///
+    /// # Errors
+    ///
+    /// [`ZkTrieError::UnexpectValue`] if the previous value cannot be decoded as `T`,
+    /// the trie is updated regardless.
+    #[instrument(level = "trace", skip_all)]
+    pub fn insert<Db: KVDatabase, T: EncodeValueBytes + DecodeValueBytes, KEY: AsRef<[u8]>>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: KEY,
+        value: T,
+    ) -> Result<Option<T>, H, Db> {
+        let (values, compression_flags) = value.encode_values_bytes();
+        let mut replaced = None;
+        self.add_leaf_by_key(
+            db,
+            key.as_ref(),
+            values,
+            compression_flags,
+            &mut replaced,
+            &mut OpReceipt::default(),
+        )?;
+        match replaced {
+            Some(old) => T::decode_values_bytes(old.as_leaf().unwrap().value_preimages())
+                .map(Some)
+                .ok_or(ZkTrieError::UnexpectValue),
+            None => Ok(None),
+        }
     }
 
     /// Delete a key from the trie
@@ -153,18 +801,33 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
     /// - `Ok(true)` if the key is found and deleted
     /// - `Ok(false)` if the key is not found
     /// - `Err(e)` if other error occurs
-    #[instrument(level = "trace", skip_all)]
     #[inline]
     pub fn delete<Db: KVDatabase, KEY: AsRef<[u8]>>(
         &mut self,
         db: &NodeDb<Db>,
         key: KEY,
     ) -> Result<bool, H, Db> {
+        self.delete_with_receipt(db, key)
+            .map(|(deleted, _)| deleted)
+    }
+
+    /// Same as [`delete`](ZkTrie::delete), also reports the work done.
+    #[instrument(level = "trace", skip_all)]
+    pub fn delete_with_receipt<Db: KVDatabase, KEY: AsRef<[u8]>>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: KEY,
+    ) -> Result<(bool, OpReceipt), H, Db> {
         let key = key.as_ref();
         trace!(key = hex::encode(key));
         let node_key = self.key_hasher.hash(key)?;
         trace!(node_key = ?node_key);
-        self.delete_by_node_key(db, node_key)
+        let mut receipt = OpReceipt::default();
+        receipt.charge_key(key.len());
+        let key = self.key_hasher.knows_preimages().then_some(key);
+        let deleted = self.delete_by_node_key_traced(db, key, node_key, &mut receipt)?;
+        trace!(receipt = ?receipt);
+        Ok((deleted, receipt))
     }
 
     /// Delete a key from the trie by node key
@@ -172,38 +835,332 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
     /// # See also
     ///
     /// [`delete`](ZkTrie::delete)
+    #[inline]
     pub fn delete_by_node_key<Db: KVDatabase>(
         &mut self,
         db: &NodeDb<Db>,
         node_key: ZkHash,
     ) -> Result<bool, H, Db> {
-        match self.delete_node(db, self.root.clone(), node_key, 0) {
-            Ok((new_root, _)) => {
-                self.root = new_root;
-                Ok(true)
+        self.delete_by_node_key_traced(db, None, node_key, &mut OpReceipt::default())
+    }
+
+    /// Replay a journal recorded by another trie, see [`enable_journal`](ZkTrie::enable_journal).
+    ///
+    /// Stops at the first entry whose roots don't match and returns its index,
+    /// or `None` if every root matches.
+    /// The entry is applied unless the roots already differ before it,
+    /// so the diverging state can be inspected.
+    pub fn replay<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        journal: &OpJournal,
+    ) -> Result<Option<usize>, H, Db> {
+        for (index, entry) in journal.entries().iter().enumerate() {
+            let pre_root = self.resolve_hash(&self.root).map_err(ZkTrieError::Hash)?;
+            if pre_root != entry.pre_root {
+                warn!(index, expected = ?entry.pre_root, ?pre_root, "journal pre root mismatch");
+                return Ok(Some(index));
+            }
+            match &entry.op {
+                JournalOp::Update {
+                    node_key,
+                    value_preimages,
+                    compression_flags,
+                } => self.add_leaf_by_node_key(
+                    db,
+                    None,
+                    *node_key,
+                    value_preimages.clone(),
+                    *compression_flags,
+                    &mut None,
+                    &mut OpReceipt::default(),
+                )?,
+                JournalOp::Delete { node_key } => {
+                    self.delete_by_node_key_traced(db, None, *node_key, &mut OpReceipt::default())?;
+                }
+            }
+            let post_root = self.resolve_hash(&self.root).map_err(ZkTrieError::Hash)?;
+            if post_root != entry.post_root {
+                warn!(index, expected = ?entry.post_root, ?post_root, "journal post root mismatch");
+                return Ok(Some(index));
             }
-            Err(ZkTrieError::NodeNotFound) => Ok(false),
-            Err(e) => Err(e),
         }
+        Ok(None)
     }
 
     /// Commit changes of the trie to the database
-    pub fn commit<Db: KVDatabase>(&mut self, db: &mut NodeDb<Db>) -> Result<(), H, Db> {
-        if !self.is_dirty() {
-            return Ok(());
-        }
+    #[inline]
+    pub fn commit<Db: KVDatabase>(&mut self, db: &mut NodeDb<Db>) -> Result<CommittedRoot, H, Db> {
+        self.commit_with_arena(db).map(|(root, _)| root)
+    }
 
-        // resolve all unresolved branch nodes
-        self.root = LazyNodeHash::Hash(self.resolve_commit(db, self.root.clone())?);
+    /// Commit changes of the trie to the database, and return the arena of the new nodes.
+    ///
+    /// The new nodes are archived into one [`NodeArena`] and stored as slices of it,
+    /// the arena can be used to emit the nodes, e.g. into a witness, without reading them back.
+    /// The arena is empty if there's nothing to commit.
+    pub fn commit_with_arena<Db: KVDatabase>(
+        &mut self,
+        db: &mut NodeDb<Db>,
+    ) -> Result<(CommittedRoot, NodeArena), H, Db> {
+        self.commit_reported(db)
+            .map(|(root, arena, _)| (root, arena))
+    }
+
+    /// Commit changes of the trie to the database, and report the work done,
+    /// e.g. to tune batch sizes.
+    pub fn commit_with_report<Db: KVDatabase>(
+        &mut self,
+        db: &mut NodeDb<Db>,
+    ) -> Result<(CommittedRoot, CommitReport), H, Db> {
+        let (root, arena, mut report) = self.commit_reported(db)?;
+        report.bytes_written = match db.node_encoding() {
+            NodeEncoding::Archived => arena.iter().map(|(_, bytes)| bytes.len()).sum(),
+            NodeEncoding::Canonical => arena
+                .viewers()
+                .map(|viewer| viewer.view().canonical_value(true).len())
+                .sum(),
+        };
+        trace!(report = ?report, "committed");
+        Ok((root, report))
+    }
+
+    /// Commit, reporting everything but the written bytes.
+    fn commit_reported<Db: KVDatabase>(
+        &mut self,
+        db: &mut NodeDb<Db>,
+    ) -> Result<(CommittedRoot, NodeArena, CommitReport), H, Db> {
+        // deletions may leave no dirty nodes but still replace nodes
+        if !self.is_dirty() && self.gc_nodes.is_empty() {
+            self.commit_secondary::<Db>()?;
+            return Ok((
+                CommittedRoot::new_unchecked(*self.root.unwrap_ref()),
+                NodeArena::default(),
+                CommitReport::default(),
+            ));
+        }
+        check_hash_scheme::<H, Db>(db)?;
+        let start = std::time::Instant::now();
+        let mut report = CommitReport {
+            replaced_nodes: self
+                .gc_nodes
+                .iter()
+                .filter(|node_hash| node_hash.try_as_hash().is_some_and(|h| !h.is_zero()))
+                .count(),
+            ..Default::default()
+        };
+
+        // resolve all unresolved branch nodes
+        report.hash_invocations = self
+            .hash_dirty_branches(&self.root)
+            .map_err(ZkTrieError::Hash)?;
+        let mut nodes = Vec::with_capacity(self.dirty_leafs.len() + self.dirty_branch_nodes.len());
+        let root = self.resolve_commit(db, self.root.clone(), &mut nodes)?;
+        self.root = LazyNodeHash::Hash(root);
+        let arena = NodeArena::new(nodes);
+        db.put_arena(&arena).map_err(DbError::classify::<Db>)?;
+        report.new_nodes = arena.len();
+        report.freed_candidates =
+            self.finish_commit(db, arena.iter().map(|(node_hash, _)| node_hash).collect())?;
+        report.duration = start.elapsed();
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_commit(report.duration, self.root_version);
+        Ok((CommittedRoot::new_unchecked(root), arena, report))
+    }
+
+    /// Resolve the hashes of the dirty nodes and collect them with the new root,
+    /// without writing anything, see [`apply_commit`](ZkTrie::apply_commit).
+    ///
+    /// The trie is left as is, so the commit set can be computed on a worker thread
+    /// while the trie is shared.
+    #[instrument(level = "trace", skip_all)]
+    pub fn prepare_commit(&self) -> std::result::Result<CommitSet<H>, H::Error> {
+        self.hash_dirty_branches(&self.root)?;
+        let mut nodes = Vec::with_capacity(self.dirty_leafs.len() + self.dirty_branch_nodes.len());
+        let root = self.collect_commit(&self.root, &mut nodes)?;
+        trace!(root = ?root, nodes = nodes.len(), "prepared commit");
+        Ok(CommitSet { root, nodes })
+    }
+
+    /// Write a commit set prepared by [`prepare_commit`](ZkTrie::prepare_commit)
+    /// and finish the commit, same as [`commit`](ZkTrie::commit).
+    ///
+    /// Returns [`ZkTrieError::RootMismatch`] if the trie was changed since the set was prepared,
+    /// without writing anything.
+    pub fn apply_commit<Db: KVDatabase>(
+        &mut self,
+        db: &mut NodeDb<Db>,
+        commit_set: CommitSet<H>,
+    ) -> Result<CommittedRoot, H, Db> {
+        let root = self.resolve_hash(&self.root).map_err(ZkTrieError::Hash)?;
+        if root != commit_set.root {
+            return Err(ZkTrieError::RootMismatch {
+                expected: commit_set.root,
+                actual: root,
+            });
+        }
+        if !self.is_dirty() && self.gc_nodes.is_empty() {
+            self.commit_secondary::<Db>()?;
+            return Ok(CommittedRoot::new_unchecked(root));
+        }
+        check_hash_scheme::<H, Db>(db)?;
+        #[cfg(feature = "metrics")]
+        let start = std::time::Instant::now();
+
+        for node in commit_set.nodes.iter() {
+            node.get_or_calculate_node_hash()
+                .map_err(ZkTrieError::Hash)?;
+        }
+        let arena = NodeArena::new(commit_set.nodes);
+        db.put_arena(&arena).map_err(DbError::classify::<Db>)?;
+        self.root = LazyNodeHash::Hash(root);
+        self.finish_commit(db, arena.iter().map(|(node_hash, _)| node_hash).collect())?;
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_commit(start.elapsed(), self.root_version);
+        Ok(CommittedRoot::new_unchecked(root))
+    }
+
+    /// Update the bookkeeping once the dirty nodes in `written` are persisted.
+    ///
+    /// Returns the number of newly retired nodes.
+    pub(super) fn finish_commit<Db: KVDatabase>(
+        &mut self,
+        db: &mut NodeDb<Db>,
+        written: HashSet<ZkHash>,
+    ) -> Result<usize, H, Db> {
+        self.root_version += 1;
+        let old_root = std::mem::replace(&mut self.committed_root, *self.root.unwrap_ref());
+        self.update_depth_histogram(db, old_root)?;
+
+        // replaced nodes that were never resolved were never persisted either,
+        // and nodes written again are live
+        let mut retired = 0;
+        for node_hash in self.gc_nodes.drain() {
+            if let Some(node_hash) = node_hash.try_as_hash() {
+                if !node_hash.is_zero() && !written.contains(node_hash) {
+                    if let Entry::Vacant(entry) = self.retired_nodes.entry(*node_hash) {
+                        entry.insert(self.root_version);
+                        retired += 1;
+                    }
+                }
+            }
+        }
+        self.retired_nodes
+            .retain(|node_hash, _| !written.contains(node_hash));
+
+        // superseded roots are not reachable from the committed root, resolve them while we can
+        for index in 0..self.root_checkpoints.len() {
+            let root = self
+                .resolve_hash(&self.root_checkpoints[index])
+                .map_err(ZkTrieError::Hash)?;
+            self.root_checkpoints[index] = LazyNodeHash::Hash(root);
+        }
 
         // clear dirty nodes
         self.dirty_branch_nodes.clear();
         self.dirty_leafs.clear();
-        self.gc_nodes.retain(|node_hash| node_hash.is_resolved());
 
+        self.update_path_cache(db, old_root)?;
+
+        if self.tracks_leaf_changes() {
+            let mut observer = self.leaf_observer.take();
+            let result = self.apply_leaf_changes(db, observer.as_mut());
+            self.leaf_observer = observer;
+            let changed = result?;
+            if self.leaf_versions {
+                db.put_versions(&self.committed_root, self.root_version, &changed)?;
+            }
+        }
+
+        self.commit_secondary::<Db>()?;
+        Ok(retired)
+    }
+
+    /// Commit the secondary commitment, if any.
+    ///
+    /// This also runs on commits without trie changes, the commitment may have pending updates,
+    /// e.g. a value written again.
+    fn commit_secondary<Db: KVDatabase>(&mut self) -> Result<(), H, Db> {
+        if let Some(secondary) = self.secondary.as_mut() {
+            self.secondary_root = Some(secondary.commit().map_err(ZkTrieError::other)?);
+        }
         Ok(())
     }
 
+    /// Drop dirty nodes that are no longer reachable from the root, e.g. replaced by later updates,
+    /// nor from a [checkpoint](ZkTrie::checkpoint_root), and compact the indices of the remaining dirty branch nodes.
+    ///
+    /// Returns the number of dropped nodes.
+    pub fn shrink_dirty(&mut self) -> usize {
+        let (dirty_leafs, dirty_branch_nodes) =
+            (self.dirty_leafs.len(), self.dirty_branch_nodes.len());
+
+        let mut reachable_leafs = HashSet::new();
+        let mut reachable_branches = vec![false; dirty_branch_nodes];
+        // checkpointed roots keep their nodes
+        let mut stack = vec![self.root.clone()];
+        stack.extend(self.root_checkpoints.iter().cloned());
+        while let Some(node_hash) = stack.pop() {
+            match node_hash {
+                LazyNodeHash::Hash(node_hash) => {
+                    // persisted nodes never refer to dirty nodes
+                    if self.dirty_leafs.contains_key(&node_hash) {
+                        reachable_leafs.insert(node_hash);
+                    }
+                }
+                LazyNodeHash::LazyBranch(LazyBranchHash { index, .. }) => {
+                    if reachable_branches[index] {
+                        continue;
+                    }
+                    reachable_branches[index] = true;
+                    let branch = self.dirty_branch_nodes[index].as_branch().unwrap();
+                    stack.push(branch.child_left());
+                    stack.push(branch.child_right());
+                }
+            }
+        }
+        self.dirty_leafs
+            .retain(|node_hash, _| reachable_leafs.contains(node_hash));
+
+        let mut indices = vec![usize::MAX; dirty_branch_nodes];
+        let mut new_index = 0;
+        for (index, reachable) in reachable_branches.iter().enumerate() {
+            if *reachable {
+                indices[index] = new_index;
+                new_index += 1;
+            }
+        }
+        let remap = |node_hash: &LazyNodeHash| match node_hash {
+            LazyNodeHash::LazyBranch(LazyBranchHash { index, resolved }) => {
+                LazyNodeHash::LazyBranch(LazyBranchHash {
+                    index: indices[*index],
+                    resolved: resolved.clone(),
+                })
+            }
+            node_hash => node_hash.clone(),
+        };
+        self.dirty_branch_nodes = std::mem::take(&mut self.dirty_branch_nodes)
+            .into_iter()
+            .zip(reachable_branches)
+            .filter(|(_, reachable)| *reachable)
+            .map(|(node, _)| node.map_children(&remap))
+            .collect();
+        self.root = remap(&self.root);
+        self.root_checkpoints = self.root_checkpoints.iter().map(remap).collect();
+        // unresolved ones are dropped on commit anyway
+        self.gc_nodes = std::mem::take(&mut self.gc_nodes)
+            .into_iter()
+            .filter_map(|node_hash| node_hash.try_as_hash().map(LazyNodeHash::from))
+            .collect();
+
+        let dropped = dirty_leafs - self.dirty_leafs.len() + dirty_branch_nodes
+            - self.dirty_branch_nodes.len();
+        trace!(dropped, "shrunk dirty nodes");
+        dropped
+    }
+
     /// Prove constructs a merkle proof for key.
     /// The result contains all encoded nodes on the path to the value at key.
     /// The value itself is also included in the last node and can be retrieved by verifying the proof.
@@ -214,71 +1171,279 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
     ///
     /// If the trie contain a non-empty leaf for key, the returned proof contains all
     /// nodes on the path to the leaf node, ending with the leaf node.
-    #[instrument(level = "trace", skip_all)]
+    ///
+    /// The nodes are always ordered from the root down to the terminal node.
+    ///
+    /// Key preimages are only included if enabled by
+    /// [`with_key_preimages_in_proofs`](ZkTrie::with_key_preimages_in_proofs).
+    #[inline]
     pub fn prove<Db: KVDatabase, KEY: AsRef<[u8]>>(
         &self,
         db: &NodeDb<Db>,
         key: KEY,
     ) -> Result<Vec<Vec<u8>>, H, Db> {
-        let key = key.as_ref();
+        self.prove_with_receipt(db, key).map(|(proof, _)| proof)
+    }
+
+    /// Same as [`prove`](ZkTrie::prove), also reports the work done.
+    #[instrument(level = "trace", skip_all)]
+    pub fn prove_with_receipt<Db: KVDatabase, KEY: AsRef<[u8]>>(
+        &self,
+        db: &NodeDb<Db>,
+        key: KEY,
+    ) -> Result<(Vec<Vec<u8>>, OpReceipt), H, Db> {
+        let mut proof = Vec::with_capacity(H::TRIE_MAX_LEVELS + 1);
+        let receipt = self.prove_with(db, key.as_ref(), |bytes| {
+            proof.push(bytes.into());
+            Ok(())
+        })?;
+        Ok((proof, receipt))
+    }
+
+    /// Same as [`prove`](ZkTrie::prove), but writes the proof nodes into `writer` as they're read.
+    ///
+    /// Nodes in the [canonical cache](NodeDb::with_canonical_cache) are written without copying.
+    /// The output is the concatenation of the proof nodes,
+    /// which can be verified by [`verify_proof_stream`] without buffering the whole proof.
+    ///
+    /// Returns the number of bytes written.
+    #[instrument(level = "trace", skip_all)]
+    pub fn prove_into<Db: KVDatabase, KEY: AsRef<[u8]>, W: std::io::Write>(
+        &self,
+        db: &NodeDb<Db>,
+        key: KEY,
+        writer: &mut W,
+    ) -> Result<usize, H, Db> {
+        let mut written = 0;
+        self.prove_with(db, key.as_ref(), |bytes| {
+            written += bytes.len();
+            writer.write_all(&bytes).map_err(ZkTrieError::other)
+        })?;
+        Ok(written)
+    }
+
+    /// Walk the path of `key` and emit every proof node, followed by the magic bytes.
+    fn prove_with<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        key: &[u8],
+        mut emit: impl FnMut(Bytes) -> Result<(), H, Db>,
+    ) -> Result<OpReceipt, H, Db> {
         trace!(key = hex::encode(key));
         let node_key = self.key_hasher.hash(key)?;
         trace!(node_key = ?node_key);
+        let mut receipt = OpReceipt::default();
+        receipt.charge_key(key.len());
+        self.prove_node_key_with(db, &node_key, &mut receipt, &mut emit)?;
+        emit(Bytes::from_static(self.proof_magic))?;
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_db_reads("prove", &receipt.reads);
+        trace!(receipt = ?receipt);
+        Ok(receipt)
+    }
+
+    /// Walk the path of `node_key` and emit every proof node, without the magic bytes.
+    pub(super) fn prove_node_key_with<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_key: &ZkHash,
+        receipt: &mut OpReceipt,
+        mut emit: impl FnMut(Bytes) -> Result<(), H, Db>,
+    ) -> Result<(), H, Db> {
+        self.walk_path(db, node_key, &mut receipt.reads, |_, n| {
+            emit(db.canonical_value(&n, self.proof_key_preimages))
+        })
+    }
+
+    /// Same as [`prove`](ZkTrie::prove), but collects the proof nodes into `nodes`,
+    /// skipping nodes already collected by previous proofs.
+    ///
+    /// Returns the indices of the proof nodes, see [`SharedProofNodes::proof`].
+    /// Use [`SharedProofNodes::into_preorder`] for a deterministic order of the nodes.
+    #[instrument(level = "trace", skip_all)]
+    pub fn prove_shared<Db: KVDatabase, KEY: AsRef<[u8]>>(
+        &self,
+        db: &NodeDb<Db>,
+        key: KEY,
+        nodes: &mut SharedProofNodes,
+    ) -> Result<Vec<usize>, H, Db> {
+        let node_key = self.key_hasher.hash(key.as_ref())?;
+        self.prove_shared_node_key(db, node_key, nodes)
+    }
+
+    /// Same as [`prove_shared`](ZkTrie::prove_shared) for an already hashed key,
+    /// e.g. hashed in a batch by [`KeyHasher::hash_many`].
+    pub(crate) fn prove_shared_node_key<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_key: ZkHash,
+        nodes: &mut SharedProofNodes,
+    ) -> Result<Vec<usize>, H, Db> {
+        let mut indices = Vec::new();
+        let mut root = None;
+        let mut path = NodePath::default();
+        self.walk_path(
+            db,
+            &node_key,
+            &mut NodeReadStats::default(),
+            |node_hash, n| {
+                let index = nodes.insert(node_hash.try_as_hash().copied(), root, path, || {
+                    db.canonical_value(&n, self.proof_key_preimages).into()
+                });
+                root = Some(nodes.root_of(index));
+                path = path.child(P::path_bit::<H>(&node_key, path.depth()));
+                indices.push(index);
+                Ok(())
+            },
+        )?;
+        Ok(indices)
+    }
 
+    /// Estimate the witness proving all `keys`, i.e. the unique nodes of their proofs.
+    ///
+    /// Nodes are counted by their canonical bytes as returned by [`prove`](ZkTrie::prove),
+    /// without the magic bytes. No proof bytes are encoded.
+    /// The keys are hashed in a batch, see [`KeyHasher::hash_many`].
+    #[instrument(level = "trace", skip_all)]
+    pub fn estimate_witness<Db: KVDatabase, KEY: AsRef<[u8]>>(
+        &self,
+        db: &NodeDb<Db>,
+        keys: impl IntoIterator<Item = KEY>,
+    ) -> Result<WitnessEstimate, H, Db> {
+        let keys: Vec<KEY> = keys.into_iter().collect();
+        let keys: Vec<&[u8]> = keys.iter().map(AsRef::as_ref).collect();
+        let mut seen = HashSet::new();
+        let mut estimate = WitnessEstimate::default();
+        for node_key in self.key_hasher.hash_many(&keys)? {
+            self.walk_path(
+                db,
+                &node_key,
+                &mut NodeReadStats::default(),
+                |node_hash, n| {
+                    if seen.insert(node_hash.clone()) {
+                        estimate.nodes += 1;
+                        estimate.bytes += n.canonical_len(self.proof_key_preimages);
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+        trace!(estimate = ?estimate);
+        Ok(estimate)
+    }
+
+    /// Walk the path of `node_key` from the root, visiting every node until a terminal one.
+    fn walk_path<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_key: &ZkHash,
+        stats: &mut NodeReadStats,
+        mut visit: impl FnMut(&LazyNodeHash, INode<H>) -> Result<(), H, Db>,
+    ) -> Result<(), H, Db> {
         let mut next_hash = self.root.clone();
-        let mut proof = Vec::with_capacity(H::TRIE_MAX_LEVELS + 1);
         for i in 0..H::TRIE_MAX_LEVELS {
-            let n = self.get_node_by_hash(db, next_hash)?;
-            proof.push(n.canonical_value(true));
-            match n.node_type() {
-                NodeType::Empty | NodeType::Leaf => break,
-                _ => {
-                    let (_, child_left, child_right) = n.as_branch().unwrap().as_parts();
-                    next_hash = if get_path(&node_key, i) {
-                        child_right.clone()
-                    } else {
-                        child_left.clone()
-                    };
+            let n = self.get_node_by_hash_traced(db, next_hash.clone(), stats)?;
+            let child = n.as_branch().map(|branch| {
+                if P::path_bit::<H>(node_key, i) {
+                    branch.child_right()
+                } else {
+                    branch.child_left()
                 }
+            });
+            visit(&next_hash, n)?;
+            match child {
+                Some(child) => next_hash = child,
+                None => break,
             }
         }
-        proof.push(MAGIC_NODE_BYTES.to_vec());
-        Ok(proof)
+        Ok(())
     }
 
-    /// Garbage collect the trie
+    /// Garbage collect the trie, removing the [retired nodes](ZkTrie::retired_nodes) from `db`.
+    ///
+    /// Nodes replaced since the last commit are collected once committed.
+    /// Nodes reachable from a [pinned](NodeDb::pin) root are kept until it's unpinned.
+    ///
+    /// If `db` can't remove nodes, the [`GcPolicy`](crate::db::GcPolicy) of `db` applies.
     pub fn gc<Db: KVDatabase>(&mut self, db: &mut NodeDb<Db>) -> Result<(), H, Db> {
-        if !db.gc_enabled() {
-            warn!("garbage collection is disabled");
+        if let Some(reason) = db.gc_blocker() {
+            db.skip_gc(reason)?;
             return Ok(());
         }
-        let is_dirty = self.is_dirty();
+        let pins = db.pins();
+        let mut pins = pins.lock();
+        db.walk_pinned::<H>(&mut pins)?;
         let mut removed = 0;
-        self.gc_nodes
-            .retain(|node_hash| match node_hash.try_as_hash() {
-                Some(node_hash) => match db.remove_node(node_hash) {
-                    Ok(_) => {
-                        removed += 1;
-                        false
-                    }
-                    Err(e) => {
-                        warn!("Failed to remove node from db: {}", e);
-                        true
-                    }
-                },
-                None => {
-                    if is_dirty {
-                        warn!("Unresolved hash found in gc_nodes, commit before run gc");
-                        true
-                    } else {
-                        false
-                    }
+        self.retired_nodes.retain(|node_hash, _| {
+            if pins.contains(node_hash) {
+                return true;
+            }
+            match db.remove_node(node_hash) {
+                Ok(_) => {
+                    removed += 1;
+                    false
                 }
-            });
-        trace!("garbage collection done, removed {removed} nodes");
+                Err(e) => {
+                    warn!("Failed to remove node from db: {}", e);
+                    true
+                }
+            }
+        });
+        trace!(
+            pinned = pins.len(),
+            "garbage collection done, removed {removed} nodes"
+        );
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_gc(removed);
         Ok(())
     }
 
+    /// Remove at most `max_nodes` [retired nodes](ZkTrie::retired_nodes) from `db`, oldest first,
+    /// so garbage collection can be spread over time, see [`gc`](ZkTrie::gc).
+    /// Nodes reachable from a [pinned](NodeDb::pin) root are kept until it's unpinned.
+    ///
+    /// If `db` can't remove nodes, the [`GcPolicy`](crate::db::GcPolicy) of `db` applies.
+    ///
+    /// Returns the number of removed nodes.
+    pub fn gc_incremental<Db: KVDatabase>(
+        &mut self,
+        db: &mut NodeDb<Db>,
+        max_nodes: usize,
+    ) -> Result<usize, H, Db> {
+        if let Some(reason) = db.gc_blocker() {
+            db.skip_gc(reason)?;
+            return Ok(0);
+        }
+        let pins = db.pins();
+        let mut pins = pins.lock();
+        db.walk_pinned::<H>(&mut pins)?;
+        let mut batch: Vec<_> = self
+            .retired_nodes()
+            .filter(|(_, node_hash)| !pins.contains(node_hash))
+            .collect();
+        if batch.len() > max_nodes {
+            batch.select_nth_unstable(max_nodes);
+            batch.truncate(max_nodes);
+        }
+        let mut removed = 0;
+        for (_, node_hash) in batch {
+            match db.remove_node(&node_hash) {
+                Ok(_) => {
+                    self.retired_nodes.remove(&node_hash);
+                    removed += 1;
+                }
+                Err(e) => warn!("Failed to remove node from db: {}", e),
+            }
+        }
+        trace!(
+            remaining = self.retired_nodes.len(),
+            "incremental garbage collection done, removed {removed} nodes"
+        );
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_gc(removed);
+        Ok(removed)
+    }
+
     /// Run full garbage collection
     ///
     /// If a temporary purge store is provided,
@@ -291,128 +1456,756 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
     ///
     /// This method will traverse the trie and collect all nodes,
     /// then remove all nodes that are not in the trie.
+    /// Use [`NodeDb::full_gc`] if other tries share the database.
+    ///
+    /// If the trie is dirty or `db` doesn't support garbage collection,
+    /// the [`GcPolicy`](crate::db::GcPolicy) of `db` applies.
     pub fn full_gc<Db: KVDatabase, T: KVDatabase>(
         &mut self,
-        db: &mut NodeDb<Db>,
-        mut tmp_purge_store: T,
+        db: &mut NodeDb<Db>,
+        tmp_purge_store: T,
+    ) -> Result<(), H, Db> {
+        if self.is_dirty() {
+            db.skip_gc(GcSkipped::Dirty)?;
+            return Ok(());
+        }
+        db.full_gc::<H, T>(&[*self.root.unwrap_ref()], tmp_purge_store)?;
+        self.retired_nodes.clear();
+
+        Ok(())
+    }
+
+    /// Export all nodes of the trie into a [snapshot](crate::db::snapshot).
+    ///
+    /// The trie must be committed. Returns the number of nodes written.
+    pub fn export_snapshot<Db: KVDatabase, W: std::io::Write>(
+        &self,
+        db: &NodeDb<Db>,
+        writer: W,
+    ) -> Result<usize, H, Db> {
+        self.export_snapshot_with_manifest(db, writer)
+            .map(|manifest| manifest.nodes)
+    }
+
+    /// Export all nodes of the trie into a [snapshot](crate::db::snapshot),
+    /// returning its manifest to be stored alongside,
+    /// see [`verify_backup`](crate::db::snapshot::verify_backup).
+    ///
+    /// The trie must be committed.
+    pub fn export_snapshot_with_manifest<Db: KVDatabase, W: std::io::Write>(
+        &self,
+        db: &NodeDb<Db>,
+        writer: W,
+    ) -> Result<SnapshotManifest, H, Db> {
+        if self.is_dirty() {
+            warn!("dirty nodes found, commit before export snapshot");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let mut writer = SnapshotWriter::new(writer).map_err(ZkTrieError::other)?;
+        for node in self.iter(db) {
+            let INode::Archived(viewer) = node? else {
+                // only empty nodes are owned in a committed trie
+                continue;
+            };
+            writer
+                .write_node(&viewer.node_hash, viewer.data.as_ref())
+                .map_err(ZkTrieError::other)?;
+        }
+        let (_, manifest) = writer
+            .finish_with_manifest(*self.root.unwrap_ref())
+            .map_err(ZkTrieError::other)?;
+        trace!("exported {} nodes", manifest.nodes);
+        Ok(manifest)
+    }
+
+    /// Rewrite leafs missing a `node_key_preimage` with the preimage from `lookup`.
+    ///
+    /// `lookup` maps a node key to its preimage, e.g. backed by the preimage table
+    /// of a legacy database. The preimage is not part of the node hash,
+    /// so the leafs are overwritten in place and the root stays the same.
+    ///
+    /// The trie must be committed. Returns the number of leafs rewritten.
+    pub fn recover_key_preimages<Db: KVDatabase>(
+        &self,
+        db: &mut NodeDb<Db>,
+        mut lookup: impl FnMut(&ZkHash) -> Option<[u8; 32]>,
+    ) -> Result<usize, H, Db> {
+        if self.is_dirty() {
+            warn!("dirty nodes found, commit before recovering preimages");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+
+        let mut recovered = Vec::new();
+        for node in self.leafs(db) {
+            let node = node?;
+            let leaf = node.as_leaf().unwrap();
+            if leaf.node_key_preimage().is_some() {
+                continue;
+            }
+            if let Some(preimage) = lookup(&leaf.node_key()) {
+                recovered.push((
+                    *node
+                        .get_or_calculate_node_hash()
+                        .map_err(ZkTrieError::Hash)?,
+                    preimage,
+                ));
+            }
+        }
+
+        for (node_hash, preimage) in recovered.iter() {
+            let node = self.get_node_by_hash(db, *node_hash)?;
+            let leaf = node.as_leaf().unwrap();
+            let new_leaf = Node::<H>::new_leaf(
+                leaf.node_key(),
+                leaf.value_preimages().to_vec(),
+                leaf.compress_flags(),
+                Some(*preimage),
+            )
+            .map_err(ZkTrieError::Hash)?;
+            let new_hash = *new_leaf
+                .get_or_calculate_node_hash()
+                .map_err(ZkTrieError::Hash)?;
+            debug_assert_eq!(new_hash, *node_hash);
+            db.put_node(new_leaf).map_err(DbError::classify::<Db>)?;
+        }
+        trace!("recovered {} key preimages", recovered.len());
+        Ok(recovered.len())
+    }
+
+    /// Get an iterator of the trie
+    ///
+    /// See [`ZkTrieIterator`] for the visiting order.
+    pub fn iter<'a, Db: KVDatabase>(
+        &'a self,
+        db: &'a NodeDb<Db>,
+    ) -> ZkTrieIterator<'a, H, Db, K, P> {
+        ZkTrieIterator {
+            trie: self,
+            db,
+            stack: vec![(self.root.clone(), NodePath::default())],
+        }
+    }
+
+    /// Get an iterator over the leaf nodes of the trie, in path order
+    ///
+    /// The size hint is exact if the [leaf count](ZkTrie::leaf_count) is maintained.
+    pub fn leafs<'a, Db: KVDatabase>(
+        &'a self,
+        db: &'a NodeDb<Db>,
+    ) -> ZkTrieLeafIterator<'a, H, Db, K, P> {
+        ZkTrieLeafIterator {
+            inner: self.iter(db),
+            remaining: self.leaf_count,
+        }
+    }
+
+    /// Get an [`ExactSizeIterator`] over the leaf nodes of the trie, in path order
+    ///
+    /// Returns `None` if the [leaf count](ZkTrie::leaf_count) is not maintained.
+    pub fn exact_leafs<'a, Db: KVDatabase>(
+        &'a self,
+        db: &'a NodeDb<Db>,
+    ) -> Option<ExactLeafIterator<'a, H, Db, K, P>> {
+        self.leaf_count.map(|_| ExactLeafIterator(self.leafs(db)))
+    }
+
+    /// Get an iterator over the node keys and decoded values of the leafs, in path order.
+    ///
+    /// Leafs that can't be decoded into `T` are handled according to `policy`.
+    pub fn iter_typed<'a, T: DecodeValueBytes, Db: KVDatabase>(
+        &'a self,
+        db: &'a NodeDb<Db>,
+        policy: DecodePolicy,
+    ) -> impl Iterator<Item = Result<(ZkHash, T), H, Db>> + 'a {
+        self.leafs(db).filter_map(move |leaf| {
+            let leaf = match leaf {
+                Ok(leaf) => leaf,
+                Err(e) => return Some(Err(e)),
+            };
+            let leaf = leaf.as_leaf().expect("infalible");
+            match T::decode_values_bytes(leaf.value_preimages()) {
+                Some(value) => Some(Ok((leaf.node_key(), value))),
+                None if policy == DecodePolicy::Skip => {
+                    trace!(node_key = ?leaf.node_key(), "skipped undecodable leaf");
+                    None
+                }
+                None => Some(Err(ZkTrieError::UnexpectValue)),
+            }
+        })
+    }
+
+    /// Get an iterator over the key preimages, node keys and values of the leafs, in path order,
+    /// e.g. to show readable keys without a separate preimage database.
+    ///
+    /// Leafs store their key preimage only if it was known when written,
+    /// see [`recover_key_preimages`](ZkTrie::recover_key_preimages).
+    /// If `only_with_preimage` is set, leafs without one are skipped.
+    pub fn iter_preimages<'a, Db: KVDatabase>(
+        &'a self,
+        db: &'a NodeDb<Db>,
+        only_with_preimage: bool,
+    ) -> impl Iterator<Item = Result<PreimageEntry, H, Db>> + 'a {
+        self.leafs(db).filter_map(move |leaf| {
+            let leaf = match leaf {
+                Ok(leaf) => leaf,
+                Err(e) => return Some(Err(e)),
+            };
+            let leaf = leaf.as_leaf().expect("infalible");
+            let preimage = leaf.node_key_preimage().copied();
+            if preimage.is_none() && only_with_preimage {
+                return None;
+            }
+            Some(Ok((
+                preimage,
+                leaf.node_key(),
+                leaf.value_preimages().to_vec(),
+            )))
+        })
+    }
+
+    /// Get the number of leafs in the trie, if maintained.
+    ///
+    /// The count is maintained for tries created empty,
+    /// tries opened from a root need a [`count_leafs`](ZkTrie::count_leafs) first.
+    #[inline]
+    pub fn leaf_count(&self) -> Option<usize> {
+        self.leaf_count
+    }
+
+    /// Count the leafs by walking the whole trie, the count is maintained afterward.
+    pub fn count_leafs<Db: KVDatabase>(&mut self, db: &NodeDb<Db>) -> Result<usize, H, Db> {
+        self.leaf_count = None;
+        let mut count = 0;
+        for node in self.iter(db) {
+            if node?.node_type() == NodeType::Leaf {
+                count += 1;
+            }
+        }
+        self.leaf_count = Some(count);
+        Ok(count)
+    }
+
+    /// Collect the distribution of the leaf values by walking the whole trie,
+    /// e.g. to evaluate value codecs.
+    pub fn value_stats<Db: KVDatabase>(&self, db: &NodeDb<Db>) -> Result<ValueStats, H, Db> {
+        let mut stats = ValueStats::default();
+        for node in self.leafs(db) {
+            let node = node?;
+            let leaf = node.as_leaf().unwrap();
+            stats.record(leaf.value_preimages(), leaf.compress_flags());
+        }
+        Ok(stats)
+    }
+
+    /// Dump the trie as a [Graphviz](https://graphviz.org) DOT graph.
+    ///
+    /// Nodes are visited left child first, so the output is deterministic for a given trie.
+    /// Subtrees below `max_depth` are collapsed into a single `...` node,
+    /// dirty nodes are drawn dashed.
+    pub fn to_dot<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        max_depth: usize,
+    ) -> Result<String, H, Db> {
+        let mut dot = String::from("digraph ZkTrie {\n    node [fontname=\"monospace\"];\n");
+        let mut next_id = 0;
+        self.write_dot_node(db, &mut dot, &mut next_id, self.root.clone(), 0, max_depth)?;
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    /// Get a node from the trie by node hash
+    #[inline]
+    pub fn get_node_by_hash<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_hash: impl Into<LazyNodeHash>,
+    ) -> Result<INode<H>, H, Db> {
+        self.get_node_by_hash_traced(db, node_hash, &mut NodeReadStats::default())
+    }
+
+    /// Get a node from the trie by node key
+    #[inline]
+    pub fn get_node_by_key<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_key: &ZkHash,
+    ) -> Result<INode<H>, H, Db> {
+        self.get_node_by_key_traced(db, node_key, &mut NodeReadStats::default())
+    }
+
+    /// Get every node on the path of `node_key` with its depth, from the root to the terminal node.
+    ///
+    /// The last node is the leaf of `node_key`, an empty node, or a leaf of another key
+    /// proving the absence of `node_key`.
+    pub fn traverse<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_key: &ZkHash,
+    ) -> Result<Vec<(usize, INode<H>)>, H, Db> {
+        let mut nodes = Vec::new();
+        self.walk_path(db, node_key, &mut NodeReadStats::default(), |_, n| {
+            nodes.push((nodes.len(), n));
+            Ok(())
+        })?;
+        Ok(nodes)
+    }
+
+    /// Get a node from the trie by node hash, recording where it was served from
+    #[instrument(level = "trace", skip(self, db, node_hash, stats))]
+    fn get_node_by_hash_traced<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_hash: impl Into<LazyNodeHash>,
+        stats: &mut NodeReadStats,
+    ) -> Result<INode<H>, H, Db> {
+        let node_hash = node_hash.into();
+        if node_hash.is_zero().unwrap_or(false) {
+            stats.empty += 1;
+            return Ok(INode::Owned(Node::<H>::empty()));
+        }
+        trace!(node_hash = ?node_hash);
+        match node_hash {
+            LazyNodeHash::Hash(node_hash) => {
+                if let Some(node) = self.dirty_leafs.get(&node_hash) {
+                    trace!("Found node in dirty leafs");
+                    stats.dirty_leafs += 1;
+                    Ok(INode::Owned(node.clone()))
+                } else if let Some(node) = self
+                    .path_cache
+                    .as_ref()
+                    .and_then(|cache| cache.branches.get(&node_hash))
+                {
+                    trace!("Found node in path cache");
+                    stats.path_cache += 1;
+                    self.audit_node::<Db>(node_hash, node)?;
+                    Ok(node.clone())
+                } else {
+                    let node_view = db
+                        .get_node::<H>(&node_hash)?
+                        .ok_or(ZkTrieError::NodeNotFound)?;
+                    stats.db += 1;
+                    let node = INode::Archived(node_view);
+                    self.audit_node::<Db>(node_hash, &node)?;
+                    Ok(node)
+                }
+            }
+            LazyNodeHash::LazyBranch(LazyBranchHash { index, .. }) => {
+                let node = self
+                    .dirty_branch_nodes
+                    .get(index)
+                    .cloned()
+                    .ok_or(ZkTrieError::NodeNotFound)?;
+                stats.dirty_branch_nodes += 1;
+                Ok(INode::Owned(node))
+            }
+        }
+    }
+
+    /// Get a node from the trie by node key, recording where the nodes were served from
+    #[instrument(level = "trace", skip(self, db, node_key, stats))]
+    fn get_node_by_key_traced<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        node_key: &ZkHash,
+        stats: &mut NodeReadStats,
+    ) -> Result<INode<H>, H, Db> {
+        // dirty leafs are not recorded in the filter yet
+        if !self.is_dirty() && !db.may_contain_leaf(node_key) {
+            trace!("leaf key filter reports absence");
+            return Ok(INode::Owned(Node::<H>::empty()));
+        }
+        let (mut next_hash, start) = self.walk_start(node_key, stats);
+        for i in start..H::TRIE_MAX_LEVELS {
+            let n = self.get_node_by_hash_traced(db, next_hash, stats)?;
+            match n.node_type() {
+                NodeType::Empty => return Ok(INode::Owned(Node::<H>::empty())),
+                NodeType::Leaf => {
+                    let leaf = n.as_leaf().unwrap();
+                    return if leaf.node_key() == *node_key {
+                        Ok(n)
+                    } else if i != H::TRIE_MAX_LEVELS - 1 {
+                        // the node is compressed, we just reached another leaf node
+                        Ok(INode::Owned(Node::<H>::empty()))
+                    } else {
+                        Err(ZkTrieError::NodeNotFound)
+                    };
+                }
+                _ => {
+                    let branch = n.as_branch().unwrap();
+                    if P::path_bit::<H>(node_key, i) {
+                        next_hash = branch.child_right();
+                    } else {
+                        next_hash = branch.child_left();
+                    }
+                }
+            }
+        }
+        Err(ZkTrieError::NodeNotFound)
+    }
+
+    /// Hash the key and add a new leaf, charging the work to `receipt`
+    fn add_leaf_by_key<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: &[u8],
+        value_preimages: Vec<[u8; 32]>,
+        compression_flags: u32,
+        replaced: &mut Option<INode<H>>,
+        receipt: &mut OpReceipt,
+    ) -> Result<(), H, Db> {
+        trace!(key = hex::encode(key));
+        let node_key = self.key_hasher.hash(key)?;
+        trace!(node_key = ?node_key);
+        receipt.charge_key(key.len());
+        self.add_leaf_by_node_key(
+            db,
+            self.key_hasher.knows_preimages().then_some(key),
+            node_key,
+            value_preimages,
+            compression_flags,
+            replaced,
+            receipt,
+        )
+    }
+
+    /// Add a new leaf, charging the work to `receipt`
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn add_leaf_by_node_key<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: Option<&[u8]>,
+        node_key: ZkHash,
+        value_preimages: Vec<[u8; 32]>,
+        compression_flags: u32,
+        replaced: &mut Option<INode<H>>,
+        receipt: &mut OpReceipt,
+    ) -> Result<(), H, Db> {
+        self.check_leaf_values::<Db>(&value_preimages, compression_flags)?;
+        receipt.charge_leaf(&value_preimages, compression_flags);
+        let journal_op = self.journal.is_some().then(|| JournalOp::Update {
+            node_key,
+            value_preimages: value_preimages.clone(),
+            compression_flags,
+        });
+        let pre_root = self.journal_pre_root::<Db>()?;
+        let secondary_values = self.secondary.is_some().then(|| value_preimages.clone());
+
+        let (dirty_leafs, dirty_branch_nodes) =
+            (self.dirty_leafs.len(), self.dirty_branch_nodes.len());
+        let new_leaf = Node::new_leaf(node_key, value_preimages, compression_flags, None)
+            .map_err(ZkTrieError::Hash)?;
+        self.root = self
+            .add_leaf(
+                db,
+                new_leaf,
+                self.root.clone(),
+                0,
+                replaced,
+                &mut receipt.reads,
+            )?
+            .0;
+        receipt.charge_written(
+            self.dirty_leafs.len() - dirty_leafs,
+            self.dirty_branch_nodes.len() - dirty_branch_nodes,
+        );
+        #[cfg(feature = "metrics")]
+        self.record_op_metrics("update", &receipt.reads);
+        if let (Some(op), Some(pre_root)) = (journal_op, pre_root) {
+            self.record_journal::<Db>(op, pre_root)?;
+        }
+        if let (Some(secondary), Some(values)) = (self.secondary.as_mut(), secondary_values) {
+            secondary.update(key, &node_key, &values, compression_flags);
+        }
+        Ok(())
+    }
+
+    /// Check the values of a new leaf, including the [limit](ZkTrie::with_max_value_preimages)
+    pub(super) fn check_leaf_values<Db: KVDatabase>(
+        &self,
+        value_preimages: &[[u8; 32]],
+        compression_flags: u32,
+    ) -> Result<(), H, Db> {
+        Node::<H>::check_leaf_values(value_preimages, compression_flags)?;
+        if value_preimages.len() > self.max_value_preimages {
+            return Err(LeafValuesError::ExceedsLimit {
+                len: value_preimages.len(),
+                limit: self.max_value_preimages,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Delete a leaf by node key, charging the work to `receipt`
+    fn delete_by_node_key_traced<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: Option<&[u8]>,
+        node_key: ZkHash,
+        receipt: &mut OpReceipt,
+    ) -> Result<bool, H, Db> {
+        let pre_root = self.journal_pre_root::<Db>()?;
+        let dirty_branch_nodes = self.dirty_branch_nodes.len();
+        let result = self.delete_node(db, self.root.clone(), node_key, 0, &mut receipt.reads);
+        receipt.charge_written(0, self.dirty_branch_nodes.len() - dirty_branch_nodes);
+        #[cfg(feature = "metrics")]
+        self.record_op_metrics("delete", &receipt.reads);
+        let deleted = match result {
+            Ok((new_root, _)) => {
+                self.root = new_root;
+                true
+            }
+            Err(ZkTrieError::NodeNotFound) => false,
+            Err(e) => return Err(e),
+        };
+        if let Some(pre_root) = pre_root {
+            self.record_journal::<Db>(JournalOp::Delete { node_key }, pre_root)?;
+        }
+        if let (true, Some(secondary)) = (deleted, self.secondary.as_mut()) {
+            secondary.delete(key, &node_key);
+        }
+        Ok(deleted)
+    }
+
+    /// Get the root before a mutation if the journal is enabled
+    #[inline]
+    fn journal_pre_root<Db: KVDatabase>(&self) -> Result<Option<ZkHash>, H, Db> {
+        match self.journal {
+            Some(_) => self
+                .resolve_hash(&self.root)
+                .map(Some)
+                .map_err(ZkTrieError::Hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Journal a mutation with the root before it
+    fn record_journal<Db: KVDatabase>(
+        &mut self,
+        op: JournalOp,
+        pre_root: ZkHash,
     ) -> Result<(), H, Db> {
-        if !db.is_gc_supported() {
-            warn!("backend database does not support garbage collection, skipping");
-            return Ok(());
+        let post_root = self.resolve_hash(&self.root).map_err(ZkTrieError::Hash)?;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.push(JournalEntry {
+                op,
+                pre_root,
+                post_root,
+            });
         }
-        if self.is_dirty() {
-            warn!("dirty nodes found, commit before run full_gc");
-            return Ok(());
+        Ok(())
+    }
+
+    /// Hash a dirty branch node and its dirty descendants without committing.
+    ///
+    /// The hashes are shared with the lazy hashes, so they're not computed again on commit.
+    pub(super) fn resolve_hash(
+        &self,
+        node_hash: &LazyNodeHash,
+    ) -> std::result::Result<ZkHash, H::Error> {
+        match node_hash {
+            LazyNodeHash::LazyBranch(LazyBranchHash { index, resolved })
+                if resolved.get().is_none() =>
+            {
+                let node = &self.dirty_branch_nodes[*index];
+                let branch = node.as_branch().unwrap();
+                self.resolve_hash(&branch.child_left())?;
+                self.resolve_hash(&branch.child_right())?;
+                node.get_or_calculate_node_hash().copied()
+            }
+            node_hash => Ok(*node_hash.unwrap_ref()),
         }
-        let gc_enabled = db.gc_enabled();
-        db.set_gc_enabled(true);
+    }
 
-        // traverse the trie and collect all nodes
-        for node in self.iter(db) {
-            let node = node?;
-            let node_hash = *node
-                .get_or_calculate_node_hash()
-                .map_err(ZkTrieError::Hash)?;
-            tmp_purge_store
-                .put(node_hash.as_slice(), &[])
-                .map_err(|e| ZkTrieError::Other(Box::new(e)))?;
+    /// Report the db reads of an operation and the dirty nodes left by it
+    #[cfg(feature = "metrics")]
+    fn record_op_metrics(&self, op: &'static str, stats: &NodeReadStats) {
+        crate::metrics::record_db_reads(op, stats);
+        crate::metrics::record_dirty_nodes(self.dirty_leafs.len() + self.dirty_branch_nodes.len());
+    }
+
+    /// Check if leaf changes are needed on commit
+    #[inline]
+    fn tracks_leaf_changes(&self) -> bool {
+        self.leaf_observer.is_some() || self.value_index.is_some() || self.leaf_versions
+    }
+
+    /// Remember the committed value of a leaf before its first change
+    #[inline]
+    fn record_leaf_change(&mut self, node_key: ZkHash, old_leaf: Option<INode<H>>) {
+        if self.tracks_leaf_changes() {
+            self.leaf_changes.entry(node_key).or_insert(old_leaf);
         }
+    }
 
-        db.retain(|k| match tmp_purge_store.get(k) {
-            Ok(Some(_)) => true,
-            Ok(None) => false,
-            Err(e) => {
-                error!("Failed to check node in purge store: {}", e);
-                true
+    /// Update the value index with the recorded leaf changes against the committed trie,
+    /// and report them to `observer` in node key order
+    ///
+    /// Returns the node keys of the leafs whose value changed.
+    fn apply_leaf_changes<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        mut observer: Option<&mut Box<dyn LeafObserver>>,
+    ) -> Result<Vec<ZkHash>, H, Db> {
+        let mut changed = Vec::new();
+        let mut changes: Vec<_> = self.leaf_changes.drain().collect();
+        changes.sort_unstable_by_key(|(node_key, _)| *node_key);
+        for (node_key, old_leaf) in changes {
+            let new_leaf = self.get_node_by_key(db, &node_key)?;
+            let old_leaf = old_leaf.as_ref().and_then(|n| n.as_leaf());
+            let new_leaf = new_leaf.as_leaf();
+            if let Some(index) = self.value_index.as_mut() {
+                if let Some(leaf) = old_leaf.as_ref() {
+                    let value_hash = leaf
+                        .get_or_calc_value_hash::<H>()
+                        .map_err(ZkTrieError::Hash)?;
+                    index.remove(&value_hash, &node_key);
+                }
+                if let Some(leaf) = new_leaf.as_ref() {
+                    let value_hash = leaf
+                        .get_or_calc_value_hash::<H>()
+                        .map_err(ZkTrieError::Hash)?;
+                    index.insert(value_hash, node_key);
+                }
             }
-        })
-        .map_err(ZkTrieError::Db)?;
-        db.set_gc_enabled(gc_enabled);
-
-        Ok(())
+            let old_value = old_leaf.as_ref().map(|leaf| leaf.value_preimages());
+            let new_value = new_leaf.as_ref().map(|leaf| leaf.value_preimages());
+            if old_value == new_value {
+                continue;
+            }
+            changed.push(node_key);
+            if let Some(observer) = observer.as_mut() {
+                observer.on_leaf_change(&node_key, old_value, new_value);
+            }
+        }
+        Ok(changed)
     }
 
-    /// Get an iterator of the trie
-    pub fn iter<'a, Db: KVDatabase>(&'a self, db: &'a NodeDb<Db>) -> ZkTrieIterator<'a, H, Db, K> {
-        ZkTrieIterator {
-            trie: self,
-            db,
-            stack: vec![self.root.clone()],
+    /// Where a walk down to `node_key` starts, the root or a subtree from the path cache.
+    ///
+    /// The path cache is only consulted when the trie is not dirty.
+    #[inline]
+    fn walk_start(&self, node_key: &ZkHash, stats: &mut NodeReadStats) -> (LazyNodeHash, usize) {
+        match &self.path_cache {
+            Some(cache) if !self.is_dirty() => {
+                let (node_hash, level) = cache.subtrees[path_prefix::<H, P>(node_key, cache.depth)];
+                stats.path_cache += level;
+                (LazyNodeHash::Hash(node_hash), level)
+            }
+            _ => (self.root.clone(), 0),
         }
     }
 
-    /// Get a node from the trie by node hash
-    #[instrument(level = "trace", skip(self, db, node_hash))]
-    pub fn get_node_by_hash<Db: KVDatabase>(
+    /// Read the top `depth` levels of the committed trie
+    fn build_path_cache<Db: KVDatabase>(
         &self,
         db: &NodeDb<Db>,
-        node_hash: impl Into<LazyNodeHash>,
-    ) -> Result<INode<H>, H, Db> {
-        let node_hash = node_hash.into();
-        if node_hash.is_zero().unwrap_or(false) {
-            return Ok(INode::Owned(Node::<H>::empty()));
-        }
-        trace!(node_hash = ?node_hash);
-        match node_hash {
-            LazyNodeHash::Hash(node_hash) => {
-                if let Some(node) = self.dirty_leafs.get(&node_hash) {
-                    trace!("Found node in dirty leafs");
-                    Ok(INode::Owned(node.clone()))
-                } else {
-                    let node_view = db
-                        .get_node::<H>(&node_hash)
-                        .map_err(ZkTrieError::Db)?
-                        .ok_or(ZkTrieError::NodeNotFound)?;
-                    Ok(INode::Archived(node_view))
+        depth: usize,
+    ) -> Result<PathCache<H>, H, Db> {
+        let mut cache = PathCache {
+            depth,
+            subtrees: vec![(ZkHash::ZERO, 0); 1 << depth],
+            branches: HashMap::new(),
+        };
+        let mut stack = vec![(*self.root.unwrap_ref(), 0, 0)];
+        while let Some((node_hash, level, prefix)) = stack.pop() {
+            if level < depth && !node_hash.is_zero() {
+                let node = self.get_node_by_hash(db, node_hash)?;
+                if let Some(branch) = node.as_branch() {
+                    stack.push((*branch.child_left().unwrap_ref(), level + 1, prefix));
+                    stack.push((
+                        *branch.child_right().unwrap_ref(),
+                        level + 1,
+                        prefix | (1 << level),
+                    ));
+                    cache.branches.insert(node_hash, node);
+                    continue;
                 }
             }
-            LazyNodeHash::LazyBranch(LazyBranchHash { index, .. }) => self
-                .dirty_branch_nodes
-                .get(index)
-                .cloned()
-                .map(INode::Owned)
-                .ok_or(ZkTrieError::NodeNotFound),
+            // a terminal above `depth` covers every prefix sharing its path
+            for high in 0..1 << (depth - level) {
+                cache.subtrees[prefix | (high << level)] = (node_hash, level);
+            }
         }
+        trace!(
+            "path cache built with {} branch nodes",
+            cache.branches.len()
+        );
+        Ok(cache)
     }
 
-    /// Get a node from the trie by node key
-    #[instrument(level = "trace", skip(self, db, node_key))]
-    pub fn get_node_by_key<Db: KVDatabase>(
-        &self,
+    /// Update the path cache built for `old_root` to the committed root,
+    /// only reading the branches on changed paths.
+    ///
+    /// The cache is dropped if a branch can't be read.
+    fn update_path_cache<Db: KVDatabase>(
+        &mut self,
         db: &NodeDb<Db>,
-        node_key: &ZkHash,
-    ) -> Result<INode<H>, H, Db> {
-        let mut next_hash = self.root.clone();
-        for i in 0..H::TRIE_MAX_LEVELS {
-            let n = self.get_node_by_hash(db, next_hash)?;
-            match n.node_type() {
-                NodeType::Empty => return Ok(INode::Owned(Node::<H>::empty())),
-                NodeType::Leaf => {
-                    let leaf = n.as_leaf().unwrap();
-                    return if leaf.node_key() == *node_key {
-                        Ok(n)
-                    } else if i != H::TRIE_MAX_LEVELS - 1 {
-                        // the node is compressed, we just reached another leaf node
-                        Ok(INode::Owned(Node::<H>::empty()))
-                    } else {
-                        Err(ZkTrieError::NodeNotFound)
-                    };
+        old_root: ZkHash,
+    ) -> Result<(), H, Db> {
+        let Some(mut cache) = self.path_cache.take() else {
+            return Ok(());
+        };
+        let depth = cache.depth;
+
+        // a cached branch is unchanged with everything below it, and stays at its place
+        let mut kept = HashSet::new();
+        let mut added = HashMap::new();
+        let mut stack = vec![(*self.root.unwrap_ref(), 0, 0)];
+        while let Some((node_hash, level, prefix)) = stack.pop() {
+            if level < depth && !node_hash.is_zero() {
+                if cache.branches.contains_key(&node_hash) {
+                    kept.insert(node_hash);
+                    continue;
                 }
-                _ => {
-                    let branch = n.as_branch().unwrap();
-                    if get_path(node_key, i) {
-                        next_hash = branch.child_right().clone();
-                    } else {
-                        next_hash = branch.child_left().clone();
-                    }
+                let node = self.get_node_by_hash(db, node_hash).inspect_err(|e| {
+                    warn!(error = ?e, "failed to read a changed branch, path cache dropped");
+                })?;
+                if let Some(branch) = node.as_branch() {
+                    stack.push((*branch.child_left().unwrap_ref(), level + 1, prefix));
+                    stack.push((
+                        *branch.child_right().unwrap_ref(),
+                        level + 1,
+                        prefix | (1 << level),
+                    ));
+                    added.insert(node_hash, node);
+                    continue;
                 }
             }
+            for high in 0..1 << (depth - level) {
+                cache.subtrees[prefix | (high << level)] = (node_hash, level);
+            }
         }
-        Err(ZkTrieError::NodeNotFound)
+
+        // the replaced branches are the ones of the old root not kept
+        let mut replaced = 0;
+        let mut stack = vec![(old_root, 0)];
+        while let Some((node_hash, level)) = stack.pop() {
+            if level >= depth || kept.contains(&node_hash) {
+                continue;
+            }
+            if let Some(node) = cache.branches.remove(&node_hash) {
+                replaced += 1;
+                let branch = node.as_branch().expect("cached nodes are branches");
+                stack.push((*branch.child_left().unwrap_ref(), level + 1));
+                stack.push((*branch.child_right().unwrap_ref(), level + 1));
+            }
+        }
+        trace!(
+            "path cache updated with {} new branch nodes, {} replaced",
+            added.len(),
+            replaced
+        );
+        cache.branches.extend(added);
+        self.path_cache = Some(cache);
+        Ok(())
     }
 
     /// Recursively adds a new leaf in the MT while updating the path
     ///
+    /// The leaf previously stored under the same node key is written into `replaced`.
+    ///
     /// # Returns
     /// The new added node hash, and a boolean indicating if added node is terminal
     #[instrument(level = "trace", skip_all, ret)]
@@ -422,16 +2215,22 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
         leaf: Node<H>,
         curr_node_hash: LazyNodeHash,
         level: usize,
+        replaced: &mut Option<INode<H>>,
+        stats: &mut NodeReadStats,
     ) -> Result<(LazyNodeHash, bool), H, Db> {
         if level >= H::TRIE_MAX_LEVELS {
             return Err(ZkTrieError::MaxLevelReached);
         }
-        let n = self.get_node_by_hash(db, curr_node_hash.clone())?;
+        let n = self.get_node_by_hash_traced(db, curr_node_hash.clone(), stats)?;
         match n.node_type() {
             NodeType::Empty => {
                 let node_hash = *leaf
                     .get_or_calculate_node_hash()
                     .map_err(ZkTrieError::Hash)?;
+                self.record_leaf_change(leaf.as_leaf().unwrap().node_key(), None);
+                if let Some(count) = self.leaf_count.as_mut() {
+                    *count += 1;
+                }
                 self.dirty_leafs.insert(node_hash, leaf);
 
                 Ok((LazyNodeHash::Hash(node_hash), true))
@@ -446,12 +2245,19 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
                 let current_leaf_node_key = *n.as_leaf().unwrap().node_key();
                 if curr_node_hash == new_leaf_node_hash {
                     // leaf already stored
+                    *replaced = Some(n);
                     Ok((LazyNodeHash::Hash(new_leaf_node_hash), true))
                 } else if new_leaf_node_key == current_leaf_node_key {
+                    self.record_leaf_change(new_leaf_node_key.into(), Some(n.clone()));
+                    *replaced = Some(n);
                     self.dirty_leafs.insert(new_leaf_node_hash, leaf);
                     self.gc_nodes.insert(curr_node_hash.into());
                     Ok((LazyNodeHash::Hash(new_leaf_node_hash), true))
                 } else {
+                    self.record_leaf_change(new_leaf_node_key.into(), None);
+                    if let Some(count) = self.leaf_count.as_mut() {
+                        *count += 1;
+                    }
                     Ok((self.push_leaf(db, n, leaf, level)?, false))
                 }
             }
@@ -461,10 +2267,16 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
                     n.as_branch().unwrap().as_parts();
                 let leaf_node_key = leaf.as_leaf().unwrap().node_key();
 
-                let new_parent_node = if get_path(&leaf_node_key, level) {
+                let new_parent_node = if P::path_bit::<H>(&leaf_node_key, level) {
                     // go right
-                    let (new_node_hash, is_terminal) =
-                        self.add_leaf(db, leaf, current_node_right_child.clone(), level + 1)?;
+                    let (new_node_hash, is_terminal) = self.add_leaf(
+                        db,
+                        leaf,
+                        current_node_right_child.clone(),
+                        level + 1,
+                        replaced,
+                        stats,
+                    )?;
                     let new_node_type = if !is_terminal {
                         match current_node_type {
                             NodeType::BranchLTRT => NodeType::BranchLTRB,
@@ -483,8 +2295,14 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
                     )
                 } else {
                     // go left
-                    let (new_node_hash, is_terminal) =
-                        self.add_leaf(db, leaf, current_node_left_child.clone(), level + 1)?;
+                    let (new_node_hash, is_terminal) = self.add_leaf(
+                        db,
+                        leaf,
+                        current_node_left_child.clone(),
+                        level + 1,
+                        replaced,
+                        stats,
+                    )?;
                     let new_node_type = if !is_terminal {
                         match current_node_type {
                             NodeType::BranchLTRT => NodeType::BranchLBRT,
@@ -535,8 +2353,8 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
         let old_leaf_node_key = old_leaf.as_leaf().unwrap().node_key();
         let new_leaf_node_key = new_leaf.as_leaf().unwrap().node_key();
 
-        let old_leaf_path = get_path(&old_leaf_node_key, level);
-        let new_leaf_path = get_path(&new_leaf_node_key, level);
+        let old_leaf_path = P::path_bit::<H>(&old_leaf_node_key, level);
+        let new_leaf_path = P::path_bit::<H>(&new_leaf_node_key, level);
 
         let new_parent = if old_leaf_path == new_leaf_path {
             // Need to go deeper
@@ -584,23 +2402,28 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
         root_hash: LazyNodeHash,
         node_key: ZkHash,
         level: usize,
+        stats: &mut NodeReadStats,
     ) -> Result<(LazyNodeHash, bool), H, Db> {
         if level >= H::TRIE_MAX_LEVELS {
             return Err(ZkTrieError::MaxLevelReached);
         }
-        let root = self.get_node_by_hash(db, root_hash.clone())?;
+        let root = self.get_node_by_hash_traced(db, root_hash.clone(), stats)?;
         match root.node_type() {
             NodeType::Empty => Err(ZkTrieError::NodeNotFound),
             NodeType::Leaf => {
                 if root.as_leaf().unwrap().node_key() != node_key {
                     Err(ZkTrieError::NodeNotFound)
                 } else {
+                    self.record_leaf_change(node_key, Some(root.clone()));
+                    if let Some(count) = self.leaf_count.as_mut() {
+                        *count -= 1;
+                    }
                     self.gc_nodes.insert(root_hash);
                     Ok((LazyNodeHash::Hash(ZkHash::ZERO), true))
                 }
             }
             _ => {
-                let path = get_path(&node_key, level);
+                let path = P::path_bit::<H>(&node_key, level);
                 let (node_type, child_left, child_right) = root.as_branch().unwrap().as_parts();
                 let (child_hash, sibling_hash) = if path {
                     (child_right.clone(), child_left.clone())
@@ -616,7 +2439,7 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
                 );
 
                 let (new_child_hash, is_new_child_terminal) =
-                    self.delete_node(db, child_hash, node_key, level + 1)?;
+                    self.delete_node(db, child_hash, node_key, level + 1, stats)?;
 
                 let (left_child, right_child, is_left_terminal, is_right_terminal) = if path {
                     (
@@ -640,6 +2463,7 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
                     // If both children are terminal and one of them is empty, prune the root node
                     // and return the non-empty child
                     if left_is_empty || right_is_empty {
+                        self.gc_nodes.insert(root_hash);
                         if left_is_empty {
                             return Ok((right_child, true));
                         }
@@ -670,28 +2494,97 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
         }
     }
 
-    #[instrument(level = "trace", skip(self, db), ret)]
+    /// Hash the dirty branches under `node_hash` in one [`HashScheme::hash_branches`] call,
+    /// returning the number of hashes computed.
+    fn hash_dirty_branches(
+        &self,
+        node_hash: &LazyNodeHash,
+    ) -> std::result::Result<usize, H::Error> {
+        let mut branches = Vec::new();
+        let mut indices = Vec::new();
+        self.collect_unhashed(node_hash, &mut branches, &mut indices);
+        if branches.is_empty() {
+            return Ok(0);
+        }
+        let hashes = H::hash_branches(&branches)?;
+        for (index, hash) in indices.into_iter().zip(hashes) {
+            // already set if the branch is referenced twice
+            let _ = self.dirty_branch_nodes[index].node_hash.set(hash);
+        }
+        Ok(branches.len())
+    }
+
+    /// Collect the dirty branches under `node_hash` without a hash, children first.
+    fn collect_unhashed(
+        &self,
+        node_hash: &LazyNodeHash,
+        branches: &mut Vec<(u64, [BranchInput; 2])>,
+        indices: &mut Vec<usize>,
+    ) -> BranchInput {
+        match node_hash {
+            LazyNodeHash::LazyBranch(LazyBranchHash { index, resolved })
+                if resolved.get().is_none() =>
+            {
+                let branch = self.dirty_branch_nodes[*index].as_branch().unwrap();
+                let left = self.collect_unhashed(&branch.child_left(), branches, indices);
+                let right = self.collect_unhashed(&branch.child_right(), branches, indices);
+                branches.push((branch.node_type() as u64, [left, right]));
+                indices.push(*index);
+                BranchInput::Output(branches.len() - 1)
+            }
+            _ => BranchInput::Hash(*node_hash.unwrap_ref()),
+        }
+    }
+
+    /// Resolve a dirty subtree and collect its nodes, children first
+    fn collect_commit(
+        &self,
+        node_hash: &LazyNodeHash,
+        nodes: &mut Vec<Node<H>>,
+    ) -> std::result::Result<ZkHash, H::Error> {
+        match node_hash {
+            LazyNodeHash::Hash(node_hash) => {
+                if let Some(node) = self.dirty_leafs.get(node_hash) {
+                    nodes.push(node.clone());
+                }
+                Ok(*node_hash)
+            }
+            LazyNodeHash::LazyBranch(LazyBranchHash { index, .. }) => {
+                let node = &self.dirty_branch_nodes[*index];
+                let branch = node.as_branch().unwrap();
+                self.collect_commit(&branch.child_left(), nodes)?;
+                self.collect_commit(&branch.child_right(), nodes)?;
+                let node_hash = *node.get_or_calculate_node_hash()?;
+                nodes.push(node.clone());
+                Ok(node_hash)
+            }
+        }
+    }
+
+    /// Resolve a dirty subtree and take its nodes, children first
+    #[instrument(level = "trace", skip(self, db, nodes), ret)]
     fn resolve_commit<Db: KVDatabase>(
         &mut self,
-        db: &mut NodeDb<Db>,
+        db: &NodeDb<Db>,
         node_hash: LazyNodeHash,
+        nodes: &mut Vec<Node<H>>,
     ) -> Result<ZkHash, H, Db> {
         match node_hash {
             LazyNodeHash::Hash(node_hash) => {
                 if let Some(node) = self.dirty_leafs.remove(&node_hash) {
-                    db.put_node(node).map_err(ZkTrieError::Db)?;
+                    nodes.push(node);
                 }
                 Ok(node_hash)
             }
             _ => match self.get_node_by_hash(db, node_hash)? {
                 INode::Owned(node) => {
                     let branch = node.as_branch().unwrap();
-                    self.resolve_commit(db, branch.child_left().clone())?;
-                    self.resolve_commit(db, branch.child_right().clone())?;
+                    self.resolve_commit(db, branch.child_left(), nodes)?;
+                    self.resolve_commit(db, branch.child_right(), nodes)?;
                     let node_hash = *node
                         .get_or_calculate_node_hash()
                         .map_err(ZkTrieError::Hash)?;
-                    db.put_node(node).map_err(ZkTrieError::Db)?;
+                    nodes.push(node);
                     Ok(node_hash)
                 }
                 INode::Archived(viewer) => Ok(viewer.node_hash),
@@ -700,7 +2593,155 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
     }
 }
 
-impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>> Debug for ZkTrieIterator<'a, H, Db, K> {
+impl<H: HashScheme> ZkTrie<H> {
+    /// Insert the nodes of a proof, as generated by [`prove`](ZkTrie::prove), into `db`.
+    ///
+    /// The proof is parsed by [`Proof::parse`], it must start at `root`
+    /// and every node must be referenced by the previous one,
+    /// otherwise [`ZkTrieError::InvalidProof`] is returned and nothing is written.
+    /// Nodes already in `db` are skipped.
+    ///
+    /// This is used to fill gaps in a partially synced trie, so it doesn't need a trie
+    /// instance: the root node itself may be missing locally.
+    /// Returns the number of nodes inserted.
+    #[instrument(level = "trace", skip_all)]
+    pub fn heal<Db: KVDatabase, P: AsRef<[u8]>>(
+        db: &mut NodeDb<Db>,
+        root: ZkHash,
+        proof: &[P],
+    ) -> Result<usize, H, Db> {
+        let proof = Proof::<H>::parse(proof)?;
+        proof
+            .verify_path(&root)
+            .inspect_err(|e| trace!(error = ?e, "invalid proof"))?;
+
+        let mut inserted = 0;
+        for node in proof.into_nodes() {
+            if node.node_type() == NodeType::Empty {
+                continue;
+            }
+            let node_hash = *node
+                .get_or_calculate_node_hash()
+                .map_err(ZkTrieError::Hash)?;
+            if db
+                .inner()
+                .contains_key(node_hash.as_slice())
+                .map_err(DbError::classify::<Db>)?
+            {
+                continue;
+            }
+            db.put_node(node).map_err(DbError::classify::<Db>)?;
+            inserted += 1;
+        }
+        trace!("healed {inserted} nodes");
+        Ok(inserted)
+    }
+}
+
+impl OpReceipt {
+    /// Charge hashing a key with the key hasher
+    #[inline]
+    fn charge_key(&mut self, key_len: usize) {
+        self.hashes += 1;
+        self.bytes_hashed += key_len;
+    }
+
+    /// Charge hashing the values and node hash of a new leaf
+    #[inline]
+    fn charge_leaf(&mut self, value_preimages: &[[u8; 32]], compression_flags: u32) {
+        let compressed = (0..value_preimages.len().min(25))
+            .filter(|i| compression_flags & (1 << i) != 0)
+            .count();
+        self.hashes += compressed + value_preimages.len().saturating_sub(1) + 1;
+        self.bytes_hashed += value_preimages.len() * 32;
+    }
+
+    /// Charge new dirty nodes, branch hashes are deferred until commit
+    #[inline]
+    fn charge_written(&mut self, leafs: usize, branches: usize) {
+        self.nodes_written += leafs + branches;
+        self.hashes += branches;
+    }
+}
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> ZkTrie<H, K, P> {
+    /// Recursively writes the node and its children in DOT format
+    ///
+    /// # Returns
+    /// The id of the written node
+    fn write_dot_node<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        dot: &mut String,
+        next_id: &mut usize,
+        node_hash: LazyNodeHash,
+        depth: usize,
+        max_depth: usize,
+    ) -> Result<usize, H, Db> {
+        use std::fmt::Write;
+
+        let id = *next_id;
+        *next_id += 1;
+        if depth > max_depth {
+            writeln!(dot, "    n{id} [label=\"...\", shape=plaintext];").unwrap();
+            return Ok(id);
+        }
+
+        let node = self.get_node_by_hash(db, node_hash.clone())?;
+        let hash = node_hash
+            .try_as_hash()
+            .map(short_hex)
+            .unwrap_or_else(|| "unresolved".to_string());
+        let style = if matches!(node, INode::Owned(_)) {
+            "dashed"
+        } else {
+            "solid"
+        };
+        match node.node_type() {
+            NodeType::Empty => {
+                writeln!(dot, "    n{id} [label=\"\", shape=point];").unwrap();
+            }
+            NodeType::Leaf => {
+                let leaf = node.as_leaf().unwrap();
+                write!(
+                    dot,
+                    "    n{id} [label=\"Leaf\\nhash: {hash}\\nkey: {}",
+                    short_hex(leaf.node_key())
+                )
+                .unwrap();
+                if let Some(preimage) = leaf.node_key_preimage() {
+                    write!(dot, "\\npreimage: {}", short_hex(preimage)).unwrap();
+                }
+                writeln!(
+                    dot,
+                    "\\nvalues: {}\\nflags: {:#x}\", shape=box, style={style}];",
+                    leaf.value_preimages().len(),
+                    leaf.compress_flags()
+                )
+                .unwrap();
+            }
+            node_type => {
+                writeln!(
+                    dot,
+                    "    n{id} [label=\"{node_type}\\nhash: {hash}\", style={style}];"
+                )
+                .unwrap();
+                let branch = node.as_branch().unwrap();
+                let (left, right) = (branch.child_left(), branch.child_right());
+                for (path, child) in [(0, left), (1, right)] {
+                    let child_id =
+                        self.write_dot_node(db, dot, next_id, child, depth + 1, max_depth)?;
+                    writeln!(dot, "    n{id} -> n{child_id} [label=\"{path}\"];").unwrap();
+                }
+            }
+        }
+        Ok(id)
+    }
+}
+
+impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> Debug
+    for ZkTrieIterator<'a, H, Db, K, P>
+{
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ZkTrieIterator")
             .field("trie", &self.trie)
@@ -708,28 +2749,210 @@ impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>> Debug for ZkTrieIterato
     }
 }
 
-impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>> Iterator for ZkTrieIterator<'a, H, Db, K> {
+impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder>
+    ZkTrieIterator<'a, H, Db, K, P>
+{
+    /// Advance the iterator, yielding the path of the node alongside it.
+    pub fn next_with_path(&mut self) -> Option<Result<(NodePath, INode<H>), H, Db>> {
+        let (node_hash, path) = self.stack.pop()?;
+        Some(self.trie.get_node_by_hash(self.db, node_hash).map(|node| {
+            if node.is_branch() {
+                let branch = node.as_branch().expect("infalible");
+                // pushed right first, so the left subtree is visited first
+                self.stack.push((branch.child_right(), path.child(true)));
+                self.stack.push((branch.child_left(), path.child(false)));
+            }
+            (path, node)
+        }))
+    }
+
+    /// Turn into an iterator yielding the path of each node alongside it.
+    #[inline]
+    pub fn with_paths(self) -> impl Iterator<Item = Result<(NodePath, INode<H>), H, Db>> + 'a {
+        let mut this = self;
+        std::iter::from_fn(move || this.next_with_path())
+    }
+}
+
+impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> Iterator
+    for ZkTrieIterator<'a, H, Db, K, P>
+{
+    type Item = Result<INode<H>, H, Db>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_path()
+            .map(|result| result.map(|(_, node)| node))
+    }
+}
+
+impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> Debug
+    for ZkTrieLeafIterator<'a, H, Db, K, P>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZkTrieLeafIterator")
+            .field("trie", &self.inner.trie)
+            .field("remaining", &self.remaining)
+            .finish()
+    }
+}
+
+impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> Iterator
+    for ZkTrieLeafIterator<'a, H, Db, K, P>
+{
     type Item = Result<INode<H>, H, Db>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(node_hash) = self.stack.pop() {
-            return match self.trie.get_node_by_hash(self.db, node_hash) {
-                Ok(node) => {
-                    if node.is_branch() {
-                        let branch = node.as_branch().expect("infalible");
-                        self.stack.push(branch.child_left().clone());
-                        self.stack.push(branch.child_right().clone());
+        loop {
+            match self.inner.next()? {
+                Ok(node) if node.node_type() != NodeType::Leaf => continue,
+                result => {
+                    if let Some(remaining) = self.remaining.as_mut() {
+                        *remaining = remaining.saturating_sub(1);
                     }
-                    Some(Ok(node))
+                    return Some(result);
                 }
-                Err(e) => Some(Err(e)),
-            };
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.remaining {
+            Some(remaining) => (remaining, Some(remaining)),
+            None => (0, None),
+        }
+    }
+}
+
+impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> Debug
+    for ExactLeafIterator<'a, H, Db, K, P>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ExactLeafIterator").field(&self.0).finish()
+    }
+}
+
+impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> Iterator
+    for ExactLeafIterator<'a, H, Db, K, P>
+{
+    type Item = Result<INode<H>, H, Db>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> ExactSizeIterator
+    for ExactLeafIterator<'a, H, Db, K, P>
+{
+}
+
+impl NodePath {
+    /// Build the path taking the branches `bits` from the root, `true` for the right child.
+    pub fn from_bits(bits: impl IntoIterator<Item = bool>) -> Self {
+        bits.into_iter()
+            .fold(Self::default(), |path, right| path.child(right))
+    }
+
+    /// The depth of the node, zero for the root.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The branch taken at `level`, `true` for the right child.
+    #[inline]
+    pub fn bit(&self, level: usize) -> bool {
+        debug_assert!(level < self.depth);
+        get_path(&self.bits, level)
+    }
+
+    /// The path of the left (`false`) or right (`true`) child.
+    #[inline]
+    pub fn child(&self, right: bool) -> Self {
+        let mut bits = self.bits;
+        if right {
+            bits[HASH_SIZE - self.depth / 8 - 1] |= 1 << (self.depth % 8);
+        }
+        Self {
+            depth: self.depth + 1,
+            bits,
         }
-        None
     }
+
+    /// Compare the positions of two nodes in preorder,
+    /// i.e. parents before their children and left subtrees before right subtrees.
+    pub fn preorder_cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (0..self.depth.min(other.depth))
+            .map(|level| self.bit(level).cmp(&other.bit(level)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| self.depth.cmp(&other.depth))
+    }
+
+    /// Check if a leaf with `node_key` would live under this path,
+    /// in a trie with the default [`LsbFirst`] path order.
+    pub fn is_prefix_of(&self, node_key: &ZkHash) -> bool {
+        (0..self.depth).all(|level| get_path(&self.bits, level) == get_path(node_key, level))
+    }
+
+    /// Check if a leaf with `node_key` would live under this path,
+    /// in a trie of `H` with the path order `P`.
+    pub fn is_prefix_of_ordered<H: HashScheme, P: PathOrder>(&self, node_key: &ZkHash) -> bool {
+        (0..self.depth)
+            .all(|level| get_path(&self.bits, level) == P::path_bit::<H>(node_key, level))
+    }
+}
+
+#[inline]
+fn short_hex(bytes: impl AsRef<[u8]>) -> String {
+    format!("0x{}..", hex::encode(&bytes.as_ref()[..4]))
+}
+
+/// The first `depth` path bits of the node key
+#[inline]
+fn path_prefix<H: HashScheme, P: PathOrder>(node_key: &ZkHash, depth: usize) -> usize {
+    (0..depth).fold(0, |prefix, level| {
+        prefix | ((P::path_bit::<H>(node_key, level) as usize) << level)
+    })
+}
+
+/// Check `db` was not opened for another hash scheme.
+#[inline]
+fn check_hash_scheme<H: HashScheme, Db: KVDatabase>(db: &NodeDb<Db>) -> Result<(), H, Db> {
+    match db.hash_scheme() {
+        Some(id) if id != H::ID => Err(ZkTrieError::HashSchemeMismatch(id)),
+        _ => Ok(()),
+    }
+}
+
+/// Check `node` hashes to `node_hash`, logging a mismatch.
+fn check_node_hash<H: HashScheme, Db: KVDatabase>(
+    node_hash: ZkHash,
+    node: &INode<H>,
+) -> Result<(), H, Db> {
+    let bytes = node.canonical_value(true);
+    let actual = Node::<H>::try_from(bytes.as_slice())
+        .map_err(ZkTrieError::from)
+        .and_then(|node| {
+            node.get_or_calculate_node_hash()
+                .copied()
+                .map_err(ZkTrieError::Hash)
+        })
+        .inspect_err(|e| warn!(node_hash = ?node_hash, "corrupted node: {e}"))?;
+    if actual != node_hash {
+        warn!(node_hash = ?node_hash, actual = ?actual, "corrupted node");
+        return Err(ZkTrieError::CorruptedNode(node_hash));
+    }
+    Ok(())
 }
 
 #[inline(always)]
-fn get_path(node_key: &ZkHash, level: usize) -> bool {
+pub(super) fn get_path(node_key: &ZkHash, level: usize) -> bool {
     node_key.as_slice()[HASH_SIZE - level / 8 - 1] & (1 << (level % 8)) != 0
 }
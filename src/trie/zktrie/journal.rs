@@ -0,0 +1,88 @@
+//! Operation journal of a [`ZkTrie`](super::ZkTrie), see [`ZkTrie::enable_journal`](super::ZkTrie::enable_journal).
+//!
+//! With the `serde` feature the journal can be exported with any serde format,
+//! e.g. JSON or CBOR, and replayed elsewhere with [`ZkTrie::replay`](super::ZkTrie::replay)
+//! to find the operation where two tries diverge.
+use crate::hash::ZkHash;
+
+/// A mutation recorded in an [`OpJournal`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JournalOp {
+    /// Update a leaf
+    Update {
+        /// The hashed key
+        node_key: ZkHash,
+        /// The value preimages
+        value_preimages: Vec<[u8; 32]>,
+        /// The compression flags of the value preimages
+        compression_flags: u32,
+    },
+    /// Delete a leaf, recorded even if the key is not found
+    Delete {
+        /// The hashed key
+        node_key: ZkHash,
+    },
+}
+
+/// A journaled mutation with the trie roots around it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JournalEntry {
+    /// The mutation
+    pub op: JournalOp,
+    /// The root before the mutation
+    pub pre_root: ZkHash,
+    /// The root after the mutation
+    pub post_root: ZkHash,
+}
+
+/// The mutations of a trie in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpJournal {
+    entries: Vec<JournalEntry>,
+}
+
+impl OpJournal {
+    /// Create an empty journal
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the journaled mutations in order
+    #[inline]
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Number of journaled mutations
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the journal is empty
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Append a mutation
+    #[inline]
+    pub fn push(&mut self, entry: JournalEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Drop the mutations after the first `len`
+    #[inline]
+    pub(super) fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+}
+
+impl From<Vec<JournalEntry>> for OpJournal {
+    fn from(entries: Vec<JournalEntry>) -> Self {
+        Self { entries }
+    }
+}
@@ -0,0 +1,79 @@
+//! Export into the key-value layout of the legacy zkTrie databases, see [`ZkTrie::export_legacy`].
+use super::{imp::Result, PathOrder, ZkTrie, ZkTrieError};
+use crate::{
+    db::{kv::KVDatabase, NodeDb},
+    hash::{key_hasher::KeyHasher, HashScheme},
+    trie::INode,
+};
+
+/// The key prefix of key preimages in the legacy preimage table, followed by the node key.
+pub const LEGACY_PREIMAGE_PREFIX: &[u8] = b"secure-key-";
+
+/// The entries written by [`ZkTrie::export_legacy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LegacyExport {
+    /// Number of node entries
+    pub nodes: usize,
+    /// Number of preimage entries
+    pub preimages: usize,
+}
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> ZkTrie<H, K, P> {
+    /// Export all nodes of the trie in the layout of the legacy `SimpleDb`,
+    /// so tooling reading legacy databases keeps working.
+    ///
+    /// Every node is written to `out` keyed by its node hash, with its
+    /// [canonical bytes](crate::trie::Node::canonical_value) including the key preimage,
+    /// the same pairs the legacy implementation puts into its database.
+    /// Empty nodes are not stored, as in the legacy implementation.
+    ///
+    /// If `with_preimages` is set, the key preimage of every leaf having one is also written,
+    /// keyed by [`LEGACY_PREIMAGE_PREFIX`] followed by the node key,
+    /// as in the preimage table sharing the legacy node database.
+    ///
+    /// The trie must be committed.
+    pub fn export_legacy<Db: KVDatabase, Out: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        out: &mut Out,
+        with_preimages: bool,
+    ) -> Result<LegacyExport, H, Db> {
+        if self.is_dirty() {
+            warn!("dirty nodes found, commit before legacy export");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let mut export = LegacyExport::default();
+        for node in self.iter(db) {
+            let node = node?;
+            let (node_hash, value, preimage) = match &node {
+                INode::Archived(viewer) => {
+                    let view = viewer.view();
+                    let preimage = view.as_leaf().and_then(|leaf| {
+                        leaf.node_key_preimage()
+                            .map(|preimage| (leaf.node_key(), preimage))
+                    });
+                    (viewer.node_hash, view.canonical_value(true), preimage)
+                }
+                // only empty nodes are owned in a committed trie
+                INode::Owned(_) => continue,
+            };
+            out.put_owned(node_hash.0, value)
+                .map_err(ZkTrieError::other)?;
+            export.nodes += 1;
+            if let Some((node_key, preimage)) = preimage.filter(|_| with_preimages) {
+                let mut key = Vec::with_capacity(LEGACY_PREIMAGE_PREFIX.len() + node_key.len());
+                key.extend_from_slice(LEGACY_PREIMAGE_PREFIX);
+                key.extend_from_slice(node_key.as_slice());
+                out.put_owned(key, preimage.to_vec())
+                    .map_err(ZkTrieError::other)?;
+                export.preimages += 1;
+            }
+        }
+        trace!(
+            nodes = export.nodes,
+            preimages = export.preimages,
+            "exported legacy entries"
+        );
+        Ok(export)
+    }
+}
@@ -0,0 +1,249 @@
+//! A typed map over a [`ZkTrie`], storing values through a [`ValueCodec`].
+//!
+//! Encoded values are packed into leaf value preimages by [`encode_value_bytes`]:
+//! the first preimage holds the length, the next [`MAX_COMPRESSED_VALUES`] `- 1` hold 32 bytes
+//! each and are compressed, the rest hold 31 bytes each so they're always valid field elements.
+use super::{imp::Result, LsbFirst, PathOrder, ZkTrie, ZkTrieError};
+use crate::{
+    db::{kv::KVDatabase, NodeDb},
+    hash::{
+        key_hasher::{KeyHasher, NoCacheHasher},
+        poseidon::Poseidon,
+        HashScheme,
+    },
+    trie::{CommittedRoot, LeafValuesError, NodeType, MAX_COMPRESSED_VALUES, MAX_VALUE_PREIMAGES},
+};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+/// Bytes held by an uncompressed value preimage, the first byte is always zero.
+const UNCOMPRESSED_CHUNK: usize = 31;
+
+/// The max length of bytes packed by [`encode_value_bytes`].
+pub const MAX_VALUE_BYTES: usize = (MAX_COMPRESSED_VALUES - 1) * 32
+    + (MAX_VALUE_PREIMAGES - MAX_COMPRESSED_VALUES) * UNCOMPRESSED_CHUNK;
+
+/// Encodes values of `V` into bytes stored by a [`ZkMap`].
+pub trait ValueCodec<V> {
+    /// Error when encoding or decoding
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Encode a value.
+    fn encode(&self, value: &V) -> std::result::Result<Vec<u8>, Self::Error>;
+
+    /// Decode a value.
+    fn decode(&self, bytes: &[u8]) -> std::result::Result<V, Self::Error>;
+}
+
+/// Stores `Vec<u8>` values as is.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BytesCodec;
+
+impl ValueCodec<Vec<u8>> for BytesCodec {
+    type Error = std::convert::Infallible;
+
+    fn encode(&self, value: &Vec<u8>) -> std::result::Result<Vec<u8>, Self::Error> {
+        Ok(value.clone())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> std::result::Result<Vec<u8>, Self::Error> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Stores any serde value as CBOR.
+#[cfg(feature = "cbor")]
+#[cfg_attr(docsrs, doc(cfg(feature = "cbor")))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CborCodec;
+
+/// Errors of [`CborCodec`].
+#[cfg(feature = "cbor")]
+#[cfg_attr(docsrs, doc(cfg(feature = "cbor")))]
+#[derive(Debug, thiserror::Error)]
+pub enum CborCodecError {
+    /// Error when encoding
+    #[error("CBOR encode error: {0}")]
+    Encode(#[from] ciborium::ser::Error<std::io::Error>),
+    /// Error when decoding
+    #[error("CBOR decode error: {0}")]
+    Decode(#[from] ciborium::de::Error<std::io::Error>),
+}
+
+#[cfg(feature = "cbor")]
+impl<V: serde::Serialize + serde::de::DeserializeOwned> ValueCodec<V> for CborCodec {
+    type Error = CborCodecError;
+
+    fn encode(&self, value: &V) -> std::result::Result<Vec<u8>, Self::Error> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> std::result::Result<V, Self::Error> {
+        Ok(ciborium::from_reader(bytes)?)
+    }
+}
+
+/// Pack bytes into leaf value preimages and compression flags.
+///
+/// Fails with [`LeafValuesError::TooMany`] if `bytes` is longer than [`MAX_VALUE_BYTES`].
+pub fn encode_value_bytes(
+    bytes: &[u8],
+) -> std::result::Result<(Vec<[u8; 32]>, u32), LeafValuesError> {
+    let compressed_len = bytes.len().min((MAX_COMPRESSED_VALUES - 1) * 32);
+    let (compressed, uncompressed) = bytes.split_at(compressed_len);
+    let len = 1 + compressed.len().div_ceil(32) + uncompressed.len().div_ceil(UNCOMPRESSED_CHUNK);
+    if len > MAX_VALUE_PREIMAGES {
+        return Err(LeafValuesError::TooMany(len));
+    }
+
+    let mut values = Vec::with_capacity(len);
+    let mut header = [0u8; 32];
+    header[28..].copy_from_slice(&(bytes.len() as u32).to_be_bytes());
+    values.push(header);
+    for chunk in compressed.chunks(32) {
+        let mut value = [0u8; 32];
+        value[..chunk.len()].copy_from_slice(chunk);
+        values.push(value);
+    }
+    for chunk in uncompressed.chunks(UNCOMPRESSED_CHUNK) {
+        let mut value = [0u8; 32];
+        value[1..1 + chunk.len()].copy_from_slice(chunk);
+        values.push(value);
+    }
+    let compressed_values = values.len().min(MAX_COMPRESSED_VALUES);
+    let compression_flags = ((1u32 << compressed_values) - 1) & !1;
+    Ok((values, compression_flags))
+}
+
+/// Unpack bytes packed by [`encode_value_bytes`], e.g. from a verified proof.
+///
+/// Returns `None` if the value preimages are not packed bytes.
+pub fn decode_value_bytes(values: &[[u8; 32]]) -> Option<Vec<u8>> {
+    let (header, chunks) = values.split_first()?;
+    if header[..28].iter().any(|b| *b != 0) {
+        return None;
+    }
+    let len = u32::from_be_bytes(header[28..].try_into().unwrap()) as usize;
+    if len > MAX_VALUE_BYTES {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(len);
+    for (i, value) in chunks.iter().enumerate() {
+        if i + 1 < MAX_COMPRESSED_VALUES {
+            bytes.extend_from_slice(value);
+        } else if value[0] == 0 {
+            bytes.extend_from_slice(&value[1..]);
+        } else {
+            return None;
+        }
+    }
+    if bytes.len() < len || bytes.len() - len >= 32 || bytes[len..].iter().any(|b| *b != 0) {
+        return None;
+    }
+    bytes.truncate(len);
+    Some(bytes)
+}
+
+/// A map from keys to values of `V`, authenticated by a [`ZkTrie`].
+///
+/// Values are encoded by the codec `C`, then packed with [`encode_value_bytes`],
+/// so any value can be stored without implementing
+/// [`EncodeValueBytes`](crate::trie::EncodeValueBytes).
+pub struct ZkMap<KEY, V, C, H = Poseidon, K = NoCacheHasher, P = LsbFirst> {
+    trie: ZkTrie<H, K, P>,
+    codec: C,
+    _marker: PhantomData<fn(KEY) -> V>,
+}
+
+impl<KEY, V, C: Debug, H: HashScheme, K: KeyHasher<H>, P: PathOrder> Debug
+    for ZkMap<KEY, V, C, H, K, P>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZkMap")
+            .field("trie", &self.trie)
+            .field("codec", &self.codec)
+            .finish()
+    }
+}
+
+impl<KEY, V, C, H, K, P> ZkMap<KEY, V, C, H, K, P>
+where
+    KEY: AsRef<[u8]>,
+    C: ValueCodec<V>,
+    H: HashScheme,
+    K: KeyHasher<H>,
+    P: PathOrder,
+{
+    /// Create a map over `trie`, which should only hold values stored by a map with the same codec.
+    pub fn new(trie: ZkTrie<H, K, P>, codec: C) -> Self {
+        Self {
+            trie,
+            codec,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get the underlying trie, e.g. to prove a key.
+    #[inline]
+    pub fn trie(&self) -> &ZkTrie<H, K, P> {
+        &self.trie
+    }
+
+    /// Into the underlying trie.
+    pub fn into_trie(self) -> ZkTrie<H, K, P> {
+        self.trie
+    }
+
+    /// Get the codec.
+    #[inline]
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Get the value of a key.
+    pub fn get<Db: KVDatabase>(&self, db: &NodeDb<Db>, key: &KEY) -> Result<Option<V>, H, Db> {
+        let node_key = self.trie.key_hasher().hash(key.as_ref())?;
+        let node = self.trie.get_node_by_key(db, &node_key)?;
+        match node.node_type() {
+            NodeType::Empty => Ok(None),
+            NodeType::Leaf => {
+                let bytes = decode_value_bytes(node.as_leaf().unwrap().value_preimages())
+                    .ok_or(ZkTrieError::UnexpectValue)?;
+                self.codec
+                    .decode(&bytes)
+                    .map(Some)
+                    .map_err(ZkTrieError::other)
+            }
+            _ => Err(ZkTrieError::ExpectLeafNode),
+        }
+    }
+
+    /// Check if the map contains a key.
+    pub fn contains_key<Db: KVDatabase>(&self, db: &NodeDb<Db>, key: &KEY) -> Result<bool, H, Db> {
+        self.trie.contains_key(db, key)
+    }
+
+    /// Insert a value, replacing the previous one.
+    pub fn insert<Db: KVDatabase>(
+        &mut self,
+        db: &NodeDb<Db>,
+        key: &KEY,
+        value: &V,
+    ) -> Result<(), H, Db> {
+        let bytes = self.codec.encode(value).map_err(ZkTrieError::other)?;
+        let (values, compression_flags) = encode_value_bytes(&bytes)?;
+        self.trie.raw_update(db, key, values, compression_flags)
+    }
+
+    /// Remove a key, returns `false` if the key was not found.
+    pub fn remove<Db: KVDatabase>(&mut self, db: &NodeDb<Db>, key: &KEY) -> Result<bool, H, Db> {
+        self.trie.delete(db, key)
+    }
+
+    /// Commit the changes, see [`ZkTrie::commit`].
+    pub fn commit<Db: KVDatabase>(&mut self, db: &mut NodeDb<Db>) -> Result<CommittedRoot, H, Db> {
+        self.trie.commit(db)
+    }
+}
@@ -1,36 +1,632 @@
 use crate::{
-    db::NodeDb,
+    db::{DbError, GcSkipped, NodeDb, NodeDbError},
     hash::{
         key_hasher::{KeyHasher, KeyHasherError, NoCacheHasher},
         poseidon::Poseidon,
         HashScheme, ZkHash, HASH_SIZE,
     },
-    trie::{LazyNodeHash, Node, NodeType, ParseNodeError},
+    trie::{
+        INode, LazyNodeHash, LeafValuesError, Node, NodeType, ParseNodeError, MAX_VALUE_PREIMAGES,
+    },
     HashMap, HashSet,
 };
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
+use std::fmt::{Debug, Formatter};
 
+mod batch;
+pub use batch::{BatchWorker, MergeOutcome, RwSet};
+mod builder;
+pub use builder::{ProofBuilder, DEFAULT_PROOF_CACHE_DEPTH};
+mod coordinator;
+pub use coordinator::CommitCoordinator;
+mod histogram;
 mod imp;
+mod journal;
+pub use journal::{JournalEntry, JournalOp, OpJournal};
+mod legacy;
+pub use legacy::{LegacyExport, LEGACY_PREIMAGE_PREFIX};
+mod map;
+pub use map::{
+    decode_value_bytes, encode_value_bytes, BytesCodec, ValueCodec, ZkMap, MAX_VALUE_BYTES,
+};
+#[cfg(feature = "cbor")]
+pub use map::{CborCodec, CborCodecError};
+mod proof;
+pub use proof::{
+    apply_update_from_proof, verify_legacy_proof, verify_proof, verify_proof_reader,
+    verify_proof_stream, KeyProof, Proof, ProofError, ProofReader,
+};
+mod path;
+pub use path::{LsbFirst, MsbFirst, PathOrder};
+mod range;
+pub use range::{verify_range_proof, RangeProof, VerifiedRange};
+mod rebuild;
+pub use rebuild::RecomputeRootError;
+mod stream;
+pub use stream::{TraversalOrder, ZkTrieStream};
+mod subtree;
+pub use subtree::{SUBTREE_MAGIC, SUBTREE_VERSION};
 #[cfg(test)]
 mod tests;
+pub mod witness;
 
 /// A zkTrie implementation.
-pub struct ZkTrie<H = Poseidon, K = NoCacheHasher> {
+pub struct ZkTrie<H = Poseidon, K = NoCacheHasher, P = LsbFirst> {
     key_hasher: K,
 
     root: LazyNodeHash,
+    /// The root of the last commit, or the root the trie was opened at
+    committed_root: ZkHash,
     dirty_branch_nodes: Vec<Node<H>>,
     dirty_leafs: HashMap<ZkHash, Node<H>>,
+    /// Nodes replaced since the last commit, resolved into `retired_nodes` on commit
     gc_nodes: HashSet<LazyNodeHash>,
+    /// Persisted nodes no longer reachable from the root, with the root version retiring them
+    retired_nodes: BTreeMap<ZkHash, u64>,
+    root_version: u64,
+    path_cache: Option<PathCache<H>>,
+    leaf_observer: Option<Box<dyn LeafObserver>>,
+    value_index: Option<ValueIndex>,
+    /// Persist the root version each leaf last changed in, see [`ZkTrie::with_leaf_versions`]
+    leaf_versions: bool,
+    secondary: Option<Box<dyn SecondaryCommitment>>,
+    secondary_root: Option<ZkHash>,
+    /// The committed leaf of every node key changed since the last commit
+    leaf_changes: HashMap<ZkHash, Option<INode<H>>>,
+    leaf_count: Option<usize>,
+    journal: Option<OpJournal>,
+    max_value_preimages: usize,
+    proof_key_preimages: bool,
+    proof_magic: &'static [u8],
+    audit: AuditMode,
+    /// Roots recorded by [`ZkTrie::checkpoint_root`], resolved on commit
+    root_checkpoints: Vec<LazyNodeHash>,
 
     _hash_scheme: std::marker::PhantomData<H>,
+    _path_order: std::marker::PhantomData<P>,
+}
+
+/// Observes committed leaf changes, see [`ZkTrie::set_leaf_observer`].
+pub trait LeafObserver: Send + Sync {
+    /// Called on commit for every leaf whose value changed, in node key order.
+    ///
+    /// `None` means the leaf doesn't exist before or after the commit.
+    fn on_leaf_change(
+        &mut self,
+        node_key: &ZkHash,
+        old_value: Option<&[[u8; 32]]>,
+        new_value: Option<&[[u8; 32]]>,
+    );
+}
+
+/// Another commitment scheme fed with every mutation of the trie,
+/// e.g. a keccak MPT maintained during a migration, see [`ZkTrie::set_secondary_commitment`].
+pub trait SecondaryCommitment: Send + Sync {
+    /// Called after a leaf is updated.
+    ///
+    /// `key` is `None` if the leaf is updated by node key, e.g. by [`ZkTrie::replay`].
+    fn update(
+        &mut self,
+        key: Option<&[u8]>,
+        node_key: &ZkHash,
+        value_preimages: &[[u8; 32]],
+        compression_flags: u32,
+    );
+
+    /// Called after a leaf is deleted, missing keys are not reported.
+    ///
+    /// `key` is `None` if the leaf is deleted by node key.
+    fn delete(&mut self, key: Option<&[u8]>, node_key: &ZkHash);
+
+    /// Called after every commit of the trie, also one without trie changes,
+    /// returns the root of the secondary commitment.
+    fn commit(&mut self) -> Result<ZkHash, Box<dyn Error + Send + Sync>>;
+}
+
+/// The max depth of the path cache, see [`ZkTrie::with_path_cache`].
+pub const MAX_PATH_CACHE_DEPTH: usize = 16;
+
+/// The top levels of a committed trie, indexed by path.
+struct PathCache<H> {
+    depth: usize,
+    /// Subtree root hash and its level, indexed by the first `depth` path bits
+    subtrees: Vec<(ZkHash, usize)>,
+    /// Branch nodes above `depth`
+    branches: HashMap<ZkHash, INode<H>>,
+}
+
+/// Node keys of the committed leafs by value hash, see [`ZkTrie::with_value_index`].
+#[derive(Default)]
+struct ValueIndex {
+    node_keys: HashMap<ZkHash, BTreeSet<ZkHash>>,
+}
+
+impl ValueIndex {
+    fn insert(&mut self, value_hash: ZkHash, node_key: ZkHash) {
+        self.node_keys
+            .entry(value_hash)
+            .or_default()
+            .insert(node_key);
+    }
+
+    fn remove(&mut self, value_hash: &ZkHash, node_key: &ZkHash) {
+        if let Some(node_keys) = self.node_keys.get_mut(value_hash) {
+            node_keys.remove(node_key);
+            if node_keys.is_empty() {
+                self.node_keys.remove(value_hash);
+            }
+        }
+    }
 }
 
 /// An iterator over the zkTrie.
-pub struct ZkTrieIterator<'a, H, Db, K> {
-    trie: &'a ZkTrie<H, K>,
+///
+/// Nodes are visited depth first in pre-order, the left child before the right one.
+/// So branch nodes come before their children and leafs come in ascending path order,
+/// see [`PathOrder`].
+pub struct ZkTrieIterator<'a, H, Db, K, P = LsbFirst> {
+    trie: &'a ZkTrie<H, K, P>,
     db: &'a NodeDb<Db>,
-    stack: Vec<LazyNodeHash>,
+    stack: Vec<(LazyNodeHash, NodePath)>,
+}
+
+/// An iterator over the leaf nodes of the zkTrie, see [`ZkTrie::leafs`].
+pub struct ZkTrieLeafIterator<'a, H, Db, K, P = LsbFirst> {
+    inner: ZkTrieIterator<'a, H, Db, K, P>,
+    remaining: Option<usize>,
+}
+
+/// An [`ExactSizeIterator`] over the leaf nodes of the zkTrie, see [`ZkTrie::exact_leafs`].
+pub struct ExactLeafIterator<'a, H, Db, K, P = LsbFirst>(ZkTrieLeafIterator<'a, H, Db, K, P>);
+
+/// The key preimage, node key and value preimages of a leaf, see [`ZkTrie::iter_preimages`].
+pub type PreimageEntry = (Option<[u8; 32]>, ZkHash, Vec<[u8; 32]>);
+
+/// What [`ZkTrie::iter_typed`] does with leafs whose values can't be decoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecodePolicy {
+    /// Skip the leaf
+    #[default]
+    Skip,
+    /// Yield [`ZkTrieError::UnexpectValue`] and go on with the next leaf
+    Error,
+}
+
+/// What a trie does on every node read, see [`ZkTrie::with_audit`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AuditMode {
+    /// Trust the stored and cached node hashes
+    #[default]
+    Off,
+    /// Recompute the node hash and log a mismatch
+    Log,
+    /// Recompute the node hash and fail with [`ZkTrieError::CorruptedNode`] on mismatch
+    Error,
+}
+
+/// The nodes checked by [`ZkTrie::open_verified`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenCheck {
+    /// Every node of the top levels, `Levels(1)` only checks the root
+    Levels(usize),
+    /// The nodes on `paths` pseudo-random paths from the root to a leaf,
+    /// the same paths for every open
+    Sample {
+        /// Number of paths
+        paths: usize,
+    },
+    /// Every node of the trie
+    Full,
+}
+
+/// The path from the root to a node, as yielded by [`ZkTrieIterator::next_with_path`].
+///
+/// The branch bits are stored the same way as [`LsbFirst`] reads them from a node key,
+/// whatever the path order of the trie.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct NodePath {
+    depth: usize,
+    bits: ZkHash,
+}
+
+/// Counts of where node reads were served from during an operation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NodeReadStats {
+    /// Reads served from dirty leaf nodes
+    pub dirty_leafs: usize,
+    /// Reads served from dirty branch nodes
+    pub dirty_branch_nodes: usize,
+    /// Reads served from the backend database
+    pub db: usize,
+    /// Reads of empty nodes, which never touch any storage
+    pub empty: usize,
+    /// Reads served from, or skipped by, the path cache
+    pub path_cache: usize,
+}
+
+impl NodeReadStats {
+    /// Total number of node reads.
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.dirty_leafs + self.dirty_branch_nodes + self.db + self.empty + self.path_cache
+    }
+}
+
+/// The work done by a single trie operation, for deterministic metering.
+///
+/// Hashes are charged by the operation that makes them necessary,
+/// e.g. the hash of a new branch node is charged to the update even though it's computed on commit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpReceipt {
+    /// Node reads, by where they were served from
+    pub reads: NodeReadStats,
+    /// Number of new dirty nodes
+    pub nodes_written: usize,
+    /// Number of hash invocations, including key hashing
+    pub hashes: usize,
+    /// Number of preimage bytes hashed, i.e. keys and leaf values
+    pub bytes_hashed: usize,
+}
+
+/// The work done by a commit, see [`ZkTrie::commit_with_report`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommitReport {
+    /// Number of nodes written
+    pub new_nodes: usize,
+    /// Number of persisted nodes replaced since the previous commit
+    pub replaced_nodes: usize,
+    /// Number of replaced nodes retired by the commit, candidates for garbage collection,
+    /// see [`ZkTrie::retired_nodes`]
+    pub freed_candidates: usize,
+    /// Bytes of the written nodes, in the [node encoding](crate::db::NodeEncoding) of the database
+    pub bytes_written: usize,
+    /// Number of hashes computed by the commit, leaf hashes are computed on update
+    pub hash_invocations: usize,
+    /// Time spent committing
+    pub duration: std::time::Duration,
+}
+
+/// The estimated size of a witness, see [`ZkTrie::estimate_witness`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WitnessEstimate {
+    /// Number of unique nodes
+    pub nodes: usize,
+    /// Total bytes of the unique nodes
+    pub bytes: usize,
+}
+
+/// Distribution of the leaf values of a trie, see [`ZkTrie::value_stats`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueStats {
+    /// Number of leafs
+    pub leafs: usize,
+    /// Number of leafs by their number of value preimages
+    pub preimage_counts: BTreeMap<usize, usize>,
+    /// Number of leafs by their number of compressed value preimages
+    pub compressed_counts: BTreeMap<usize, usize>,
+    /// Occurrences of every byte value in all value preimages
+    pub byte_histogram: [u64; 256],
+}
+
+impl Default for ValueStats {
+    fn default() -> Self {
+        Self {
+            leafs: 0,
+            preimage_counts: BTreeMap::new(),
+            compressed_counts: BTreeMap::new(),
+            byte_histogram: [0; 256],
+        }
+    }
+}
+
+impl ValueStats {
+    /// Total number of value preimages
+    pub fn total_preimages(&self) -> usize {
+        self.preimage_counts
+            .iter()
+            .map(|(n, leafs)| n * leafs)
+            .sum()
+    }
+
+    /// Total number of compressed value preimages
+    pub fn total_compressed(&self) -> usize {
+        self.compressed_counts
+            .iter()
+            .map(|(n, leafs)| n * leafs)
+            .sum()
+    }
+
+    /// Shannon entropy of the value bytes, in bits per byte.
+    ///
+    /// Returns `0.0` if there's no value.
+    pub fn byte_entropy(&self) -> f64 {
+        let total: u64 = self.byte_histogram.iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        self.byte_histogram
+            .iter()
+            .filter(|n| **n != 0)
+            .map(|n| {
+                let p = *n as f64 / total as f64;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// Add a leaf
+    fn record(&mut self, value_preimages: &[[u8; 32]], compression_flags: u32) {
+        self.leafs += 1;
+        *self
+            .preimage_counts
+            .entry(value_preimages.len())
+            .or_default() += 1;
+        let mask = 1u32
+            .checked_shl(value_preimages.len() as u32)
+            .map_or(u32::MAX, |bit| bit - 1);
+        *self
+            .compressed_counts
+            .entry((compression_flags & mask).count_ones() as usize)
+            .or_default() += 1;
+        for byte in value_preimages.iter().flatten() {
+            self.byte_histogram[*byte as usize] += 1;
+        }
+    }
+}
+
+/// A root hash whose node is persisted in a [`NodeDb`].
+///
+/// Returned by [`ZkTrie::commit`], or checked by [`NodeDb::committed_root`] for roots
+/// obtained elsewhere, e.g. a storage root read from an account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CommittedRoot(ZkHash);
+
+impl CommittedRoot {
+    /// The root of an empty trie, valid for every database.
+    pub const EMPTY: Self = Self(ZkHash::ZERO);
+
+    /// The root of an empty trie of `H`, see [`HashScheme::EMPTY_ROOT`].
+    #[inline]
+    pub fn empty<H: HashScheme>() -> Self {
+        Self(H::EMPTY_ROOT)
+    }
+
+    /// Check if this is the root of an empty trie.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0 == Self::EMPTY.0
+    }
+
+    /// Wrap a root hash without checking the database.
+    #[inline]
+    pub(crate) fn new_unchecked(root: ZkHash) -> Self {
+        Self(root)
+    }
+
+    /// Get the root hash.
+    #[inline]
+    pub fn hash(&self) -> ZkHash {
+        self.0
+    }
+}
+
+impl Default for CommittedRoot {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl From<CommittedRoot> for ZkHash {
+    fn from(root: CommittedRoot) -> Self {
+        root.0
+    }
+}
+
+impl AsRef<ZkHash> for CommittedRoot {
+    fn as_ref(&self) -> &ZkHash {
+        &self.0
+    }
+}
+
+/// The nodes written by a commit and the new root, see [`ZkTrie::prepare_commit`].
+#[derive(Clone)]
+pub struct CommitSet<H = Poseidon> {
+    root: ZkHash,
+    /// Children before their parents
+    nodes: Vec<Node<H>>,
+}
+
+impl<H: HashScheme> CommitSet<H> {
+    /// Get the root after the commit
+    #[inline]
+    pub fn root(&self) -> ZkHash {
+        self.root
+    }
+
+    /// Get the nodes to write, children before their parents
+    #[inline]
+    pub fn nodes(&self) -> &[Node<H>] {
+        &self.nodes
+    }
+
+    /// Number of nodes to write
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if there's no node to write
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Encode the nodes into canonical bytes, e.g. to replicate them to followers.
+    pub fn canonical_nodes(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.nodes.iter().map(|node| node.canonical_value(true))
+    }
+}
+
+impl<H> Debug for CommitSet<H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommitSet")
+            .field("root", &self.root)
+            .field("nodes", &self.nodes.len())
+            .finish()
+    }
+}
+
+/// Unique proof nodes shared by several proofs, see [`ZkTrie::prove_shared`].
+///
+/// Nodes are deduplicated by node hash, so proofs from different tries sharing subtrees
+/// can be collected into the same set.
+#[derive(Clone, Debug, Default)]
+pub struct SharedProofNodes {
+    nodes: Vec<Vec<u8>>,
+    index: HashMap<ZkHash, usize>,
+    /// The ordinal of the root and the path of every node, where it's first visited
+    positions: Vec<(usize, NodePath)>,
+    roots: usize,
+}
+
+/// The unique proof nodes in the order circuits assign them,
+/// see [`SharedProofNodes::into_preorder`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PreorderProofNodes {
+    /// The unique nodes, trie by trie in the order their roots are first visited,
+    /// each trie from the root down to the leafs, left before right
+    pub nodes: Vec<Vec<u8>>,
+    /// The position in `nodes` of every node, by the index returned by
+    /// [`ZkTrie::prove_shared`]
+    pub index_map: Vec<usize>,
+}
+
+impl PreorderProofNodes {
+    /// Reassemble the proof of the nodes at `indices` as returned by [`ZkTrie::prove_shared`],
+    /// in the format of [`ZkTrie::prove`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if an index is out of bounds.
+    pub fn proof(&self, indices: &[usize]) -> Vec<Vec<u8>> {
+        self.proof_with_magic(indices, crate::trie::MAGIC_NODE_BYTES)
+    }
+
+    /// Same as [`PreorderProofNodes::proof`], terminated by `magic`,
+    /// see [`ZkTrie::with_proof_magic`].
+    pub fn proof_with_magic(&self, indices: &[usize], magic: &[u8]) -> Vec<Vec<u8>> {
+        let mut proof: Vec<_> = indices
+            .iter()
+            .map(|i| self.nodes[self.index_map[*i]].clone())
+            .collect();
+        proof.push(magic.to_vec());
+        proof
+    }
+}
+
+impl SharedProofNodes {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the unique nodes, in the order they're first visited
+    #[inline]
+    pub fn nodes(&self) -> &[Vec<u8>] {
+        &self.nodes
+    }
+
+    /// Number of unique nodes
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if no node is collected
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Reassemble the proof of the nodes at `indices`, in the format of [`ZkTrie::prove`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if an index is out of bounds.
+    pub fn proof(&self, indices: &[usize]) -> Vec<Vec<u8>> {
+        self.proof_with_magic(indices, crate::trie::MAGIC_NODE_BYTES)
+    }
+
+    /// Same as [`SharedProofNodes::proof`], terminated by `magic`,
+    /// see [`ZkTrie::with_proof_magic`].
+    pub fn proof_with_magic(&self, indices: &[usize], magic: &[u8]) -> Vec<Vec<u8>> {
+        let mut proof: Vec<_> = indices.iter().map(|i| self.nodes[*i].clone()).collect();
+        proof.push(magic.to_vec());
+        proof
+    }
+
+    /// Take the unique nodes
+    pub fn into_nodes(self) -> Vec<Vec<u8>> {
+        self.nodes
+    }
+
+    /// Take the unique nodes in a deterministic order: trie by trie in the order their roots
+    /// are first visited, and each trie in preorder, i.e. from the root down to the leafs,
+    /// left before right.
+    ///
+    /// The order of the nodes of a trie doesn't depend on the order the keys are proved in.
+    /// A node found at several positions, e.g. the empty node or a subtree shared by two tries,
+    /// is placed at the position it's first visited at.
+    pub fn into_preorder(self) -> PreorderProofNodes {
+        let mut order: Vec<usize> = (0..self.nodes.len()).collect();
+        order.sort_by(|a, b| {
+            let ((root_a, path_a), (root_b, path_b)) = (&self.positions[*a], &self.positions[*b]);
+            root_a.cmp(root_b).then_with(|| path_a.preorder_cmp(path_b))
+        });
+        let mut index_map = vec![0; order.len()];
+        for (position, index) in order.iter().enumerate() {
+            index_map[*index] = position;
+        }
+        let mut nodes: Vec<_> = self.nodes.into_iter().map(Some).collect();
+        PreorderProofNodes {
+            nodes: order.iter().map(|i| nodes[*i].take().unwrap()).collect(),
+            index_map,
+        }
+    }
+
+    /// Add a node found at `path` of the trie of `root`, returning its index.
+    ///
+    /// `root` is `None` for root nodes, the ordinal of the root is then assigned here,
+    /// see [`SharedProofNodes::root_of`].
+    fn insert(
+        &mut self,
+        node_hash: Option<ZkHash>,
+        root: Option<usize>,
+        path: NodePath,
+        bytes: impl FnOnce() -> Vec<u8>,
+    ) -> usize {
+        if let Some(index) = node_hash.and_then(|h| self.index.get(&h)) {
+            return *index;
+        }
+        let index = self.nodes.len();
+        self.nodes.push(bytes());
+        let root = root.unwrap_or_else(|| {
+            self.roots += 1;
+            self.roots - 1
+        });
+        self.positions.push((root, path));
+        if let Some(node_hash) = node_hash {
+            self.index.insert(node_hash, index);
+        }
+        index
+    }
+
+    /// Get the ordinal of the trie of the node at `index`
+    #[inline]
+    fn root_of(&self, index: usize) -> usize {
+        self.positions[index].0
+    }
 }
 
 /// Errors that can occur when using a zkTrie.
@@ -41,29 +637,138 @@ pub enum ZkTrieError<HashErr, DbErr> {
     Hash(HashErr),
     /// Error when accessing the database
     #[error("Database error: {0}")]
-    Db(DbErr),
+    Db(#[from] DbError<DbErr>),
     /// Error when hashing the key
     #[error("Key hasher error: {0}")]
     KeyHasher(#[from] KeyHasherError<HashErr>),
     /// Error when parsing a node
     #[error("Invalid node bytes: {0}")]
     InvalidNodeBytes(#[from] ParseNodeError<HashErr>),
+    /// Error when the values of a new leaf are malformed
+    #[error("Invalid leaf values: {0}")]
+    InvalidLeafValues(#[from] LeafValuesError),
+    /// The `NodeDb` was opened for another hash scheme
+    #[error("NodeDb opened for hash scheme {0}")]
+    HashSchemeMismatch(&'static str),
     /// Error when trying to use an unresolved hash
     #[error("Trying to use unresolved hash")]
     UnresolvedHashUsed,
+    /// The operation needs a committed trie, but the trie has uncommitted changes
+    #[error("Trie has uncommitted changes, commit first")]
+    UncommittedChanges,
     /// Error when a node is not found
     #[error("Node not found")]
     NodeNotFound,
+    /// A node stored in the database doesn't hash to its key
+    #[error("Node {0} is corrupted")]
+    CorruptedNode(ZkHash),
     /// Error when the max level is reached
     #[error("Max level reached")]
     MaxLevelReached,
     /// Expect a leaf node but got others
     #[error("Expect a leaf node but got others")]
     ExpectLeafNode,
+    /// Proof nodes don't link up from the root
+    #[error("Invalid proof")]
+    InvalidProof,
+    /// The trie is not at the expected root
+    #[error("Root mismatch: expected {expected}, got {actual}")]
+    RootMismatch {
+        /// The expected root
+        expected: ZkHash,
+        /// The root of the trie
+        actual: ZkHash,
+    },
     /// Unexpect value length
     #[error("Unexpect value, cannot decode")]
     UnexpectValue,
-    /// Other errors
+    /// Garbage collection couldn't run, see [`GcPolicy::Error`](crate::db::GcPolicy::Error)
+    #[error("Garbage collection skipped: {0}")]
+    GcSkipped(#[from] GcSkipped),
+    /// Other errors, see [`ZkTrieError::other`]
     #[error(transparent)]
-    Other(Box<dyn Error + Send + Sync>),
+    Other(Box<dyn Error + Send + Sync + 'static>),
+}
+
+impl<HashErr, DbErr> ZkTrieError<HashErr, DbErr> {
+    /// Wrap an error into [`ZkTrieError::Other`].
+    ///
+    /// With the `backtrace` feature, the backtrace is captured here,
+    /// see [`ZkTrieError::backtrace`].
+    pub fn other(e: impl Into<Box<dyn Error + Send + Sync + 'static>>) -> Self {
+        #[cfg(feature = "backtrace")]
+        return ZkTrieError::Other(Box::new(BacktracedError {
+            source: e.into(),
+            backtrace: std::backtrace::Backtrace::capture(),
+        }));
+        #[cfg(not(feature = "backtrace"))]
+        ZkTrieError::Other(e.into())
+    }
+
+    /// Get the backtrace captured by [`ZkTrieError::other`].
+    ///
+    /// Backtraces are only captured if enabled by `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`,
+    /// see [`std::backtrace::Backtrace::capture`].
+    #[cfg(feature = "backtrace")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "backtrace")))]
+    pub fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        match self {
+            ZkTrieError::Other(e) => e
+                .downcast_ref::<BacktracedError>()
+                .map(|e| &e.backtrace)
+                .filter(|b| b.status() == std::backtrace::BacktraceStatus::Captured),
+            _ => None,
+        }
+    }
+}
+
+/// An error wrapped by [`ZkTrieError::other`] together with its backtrace,
+/// displayed as the wrapped error.
+#[cfg(feature = "backtrace")]
+#[derive(Debug)]
+struct BacktracedError {
+    source: Box<dyn Error + Send + Sync + 'static>,
+    backtrace: std::backtrace::Backtrace,
+}
+
+#[cfg(feature = "backtrace")]
+impl std::fmt::Display for BacktracedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.source, f)
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl Error for BacktracedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.source()
+    }
+}
+
+impl<HashErr, DbErr> From<ProofError<HashErr>> for ZkTrieError<HashErr, DbErr> {
+    fn from(e: ProofError<HashErr>) -> Self {
+        match e {
+            ProofError::Hash(e) => ZkTrieError::Hash(e),
+            ProofError::InvalidNodeBytes(e) => ZkTrieError::InvalidNodeBytes(e),
+            ProofError::InvalidLeafValues(e) => ZkTrieError::InvalidLeafValues(e),
+            ProofError::MaxLevelReached => ZkTrieError::MaxLevelReached,
+            ProofError::Io(e) => ZkTrieError::other(e),
+            _ => ZkTrieError::InvalidProof,
+        }
+    }
+}
+
+impl<HashErr, DbErr> From<NodeDbError<DbErr>> for ZkTrieError<HashErr, DbErr>
+where
+    DbErr: Error + Send + Sync + 'static,
+{
+    fn from(e: NodeDbError<DbErr>) -> Self {
+        match e {
+            NodeDbError::Db(e) => ZkTrieError::Db(e),
+            NodeDbError::NodeNotFound(_) => ZkTrieError::NodeNotFound,
+            NodeDbError::CorruptedNode(node_hash) => ZkTrieError::CorruptedNode(node_hash),
+            NodeDbError::GcSkipped(reason) => ZkTrieError::GcSkipped(reason),
+            e => ZkTrieError::other(e),
+        }
+    }
 }
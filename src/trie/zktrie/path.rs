@@ -0,0 +1,41 @@
+//! How a node key is turned into a path from the root, see [`PathOrder`].
+use super::imp::get_path;
+use crate::hash::{HashScheme, ZkHash};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// Derives the branch taken at each level from the node key.
+///
+/// Only the low [`HashScheme::TRIE_MAX_LEVELS`] bits of a node key are used for the path,
+/// orders differ in which of them comes first.
+/// A trie must always be opened with the order it was built with,
+/// otherwise lookups follow the wrong paths.
+pub trait PathOrder:
+    Clone + Copy + Debug + Default + PartialEq + Eq + Hash + Send + Sync + 'static
+{
+    /// The branch taken by `node_key` at `level`, `true` for the right child.
+    fn path_bit<H: HashScheme>(node_key: &ZkHash, level: usize) -> bool;
+}
+
+/// The path starts from the least significant bit of the node key, the default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LsbFirst;
+
+impl PathOrder for LsbFirst {
+    #[inline(always)]
+    fn path_bit<H: HashScheme>(node_key: &ZkHash, level: usize) -> bool {
+        get_path(node_key, level)
+    }
+}
+
+/// The path starts from the most significant of the [`HashScheme::TRIE_MAX_LEVELS`] low bits
+/// of the node key, so subtrees hold contiguous ranges of node keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MsbFirst;
+
+impl PathOrder for MsbFirst {
+    #[inline(always)]
+    fn path_bit<H: HashScheme>(node_key: &ZkHash, level: usize) -> bool {
+        LsbFirst::path_bit::<H>(node_key, H::TRIE_MAX_LEVELS - level - 1)
+    }
+}
@@ -0,0 +1,782 @@
+//! Proof parsing and verification, see [`ZkTrie::prove`](super::ZkTrie::prove)
+//! and [`ZkTrie::prove_into`](super::ZkTrie::prove_into).
+//!
+//! Proofs are untrusted input, every node is parsed strictly by the same rules:
+//! it must be canonically encoded, hold valid hashes, and leaf values must pass
+//! [`Node::check_leaf_values`].
+//!
+//! ## Legacy proofs
+//!
+//! Proofs of the legacy Go zkTrie share the magic bytes and the node encoding,
+//! but nodes written before the branch types were introduced may carry the legacy type bytes,
+//! see [`NodeType`]. [`Proof::parse_legacy`], [`verify_legacy_proof`] and
+//! [`ProofReader::with_legacy_node_types`] read the legacy leaf and empty nodes as their
+//! replacements, as the Go implementation does. Legacy parent nodes don't record whether
+//! their children are branches, which the branch hash commits to, so each is read as the
+//! branch type whose hash is referenced by its parent, or is the root for the first node.
+use super::{imp::get_path, LsbFirst, PathOrder};
+use crate::{
+    hash::{HashScheme, ZkHash, HASH_SIZE},
+    trie::{
+        LeafValuesError, Node, NodeType, ParseNodeError, MAGIC_NODE_BYTES, MAX_VALUE_PREIMAGES,
+    },
+};
+use num_traits::FromPrimitive;
+use std::fmt::{Debug, Formatter};
+use std::io::Read;
+use std::marker::PhantomData;
+
+/// Errors that can occur when reading or verifying a proof stream.
+#[derive(Debug, thiserror::Error)]
+pub enum ProofError<HashErr> {
+    /// Error when reading the stream
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    /// Error when hashing
+    #[error(transparent)]
+    Hash(HashErr),
+    /// Error when parsing a node
+    #[error("Invalid node bytes: {0}")]
+    InvalidNodeBytes(#[from] ParseNodeError<HashErr>),
+    /// The values of a leaf are malformed
+    #[error("Invalid leaf values: {0}")]
+    InvalidLeafValues(#[from] LeafValuesError),
+    /// A node is not in the canonical encoding, e.g. has trailing bytes
+    #[error("Proof node {0} is not canonically encoded")]
+    NonCanonicalNode(usize),
+    /// A node has a type not allowed at its position,
+    /// or a branch marks the child on the path with the wrong type
+    #[error("Unexpected node type of proof node {0}")]
+    UnexpectedNodeType(usize),
+    /// The stream doesn't end with the magic bytes
+    #[error("Invalid proof magic bytes")]
+    InvalidMagic,
+    /// A node doesn't match the hash referenced by its parent
+    #[error("Node hash mismatch, expected {expected}, got {actual}")]
+    HashMismatch {
+        /// The hash referenced by the parent, or the root
+        expected: ZkHash,
+        /// The hash of the node in the proof
+        actual: ZkHash,
+    },
+    /// The proof ends before reaching a leaf or an empty node
+    #[error("Proof ends before reaching a terminal node")]
+    Incomplete,
+    /// A node needed to verify a range is not in the proof
+    #[error("Node {0} missing from proof")]
+    MissingNode(ZkHash),
+    /// Range leaves are not sorted, out of range, or don't match the trie
+    #[error("Invalid range leaves")]
+    InvalidLeaves,
+    /// Error when the max level is reached
+    #[error("Max level reached")]
+    MaxLevelReached,
+    /// The leaf ending the proof at this level is not on the path of its own key
+    #[error("Leaf at level {0} is not on the path of its key")]
+    MisplacedLeaf(usize),
+    /// A legacy parent node can't be translated into a branch, see the [module docs](self)
+    #[error("Proof node {0} is a legacy parent node of no referenced branch type")]
+    LegacyParentNode(usize),
+}
+
+/// The legacy node type bytes, see [`NodeType`].
+const LEGACY_PARENT: u8 = 0;
+const LEGACY_LEAF: u8 = 1;
+const LEGACY_EMPTY: u8 = 2;
+
+/// What a proof shows about a key, see [`Proof::verify_key`].
+#[derive(Clone, Debug)]
+pub enum KeyProof<H: HashScheme> {
+    /// The key is in the trie
+    Present(Node<H>),
+    /// The key is not in the trie
+    Absent {
+        /// The other leaf the path of the key ends at, sharing the path up to its level,
+        /// `None` if the path ends at an empty node
+        conflicting_leaf: Option<Node<H>>,
+    },
+}
+
+impl<H: HashScheme> KeyProof<H> {
+    /// Check if the key is in the trie
+    #[inline]
+    pub fn is_present(&self) -> bool {
+        matches!(self, KeyProof::Present(_))
+    }
+
+    /// Take the leaf of the key, if present
+    #[inline]
+    pub fn into_present(self) -> Option<Node<H>> {
+        match self {
+            KeyProof::Present(leaf) => Some(leaf),
+            KeyProof::Absent { .. } => None,
+        }
+    }
+}
+
+/// Reads proof nodes one by one from a stream.
+///
+/// Only a single node is buffered at a time.
+#[derive(Debug)]
+pub struct ProofReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    finished: bool,
+    max_value_preimages: usize,
+    read_nodes: usize,
+    magic: &'static [u8],
+    legacy_node_types: bool,
+}
+
+impl<R: Read> ProofReader<R> {
+    /// Create a new reader over a proof stream.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::with_capacity(1 + 2 * HASH_SIZE),
+            finished: false,
+            max_value_preimages: MAX_VALUE_PREIMAGES,
+            read_nodes: 0,
+            magic: MAGIC_NODE_BYTES,
+            legacy_node_types: false,
+        }
+    }
+
+    /// Expect proofs terminated by `magic`, see [`ZkTrie::with_proof_magic`](super::ZkTrie::with_proof_magic).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `magic` is empty or starts with a [`NodeType`] byte.
+    pub fn with_magic(mut self, magic: &'static [u8]) -> Self {
+        assert_proof_magic(magic);
+        self.magic = magic;
+        self
+    }
+
+    /// Accept the legacy node types of the Go zkTrie,
+    /// see the [module docs](self#legacy-proofs).
+    pub fn with_legacy_node_types(mut self, enabled: bool) -> Self {
+        self.legacy_node_types = enabled;
+        self
+    }
+
+    /// Reject leafs with more than `max` value preimages before reading them.
+    pub fn with_max_value_preimages(mut self, max: usize) -> Self {
+        self.max_value_preimages = max;
+        self
+    }
+
+    /// Read the next proof node, `None` once the magic bytes are read.
+    ///
+    /// Legacy parent nodes are rejected, since the hash they're referenced by is unknown,
+    /// see [`verify_proof_reader`].
+    #[inline]
+    pub fn next_node<H: HashScheme>(&mut self) -> Result<Option<Node<H>>, ProofError<H::Error>> {
+        self.next_node_referenced_by(None)
+    }
+
+    /// Same as [`next_node`](ProofReader::next_node), a legacy parent node is read as
+    /// the branch type hashing to `expected`.
+    fn next_node_referenced_by<H: HashScheme>(
+        &mut self,
+        expected: Option<&ZkHash>,
+    ) -> Result<Option<Node<H>>, ProofError<H::Error>> {
+        if self.finished {
+            return Ok(None);
+        }
+        self.buf.clear();
+        let node_type = self.read(1)?[0];
+        if node_type == self.magic[0] {
+            let expected = self.magic;
+            let magic = self.read(expected.len() - 1)?;
+            if magic != &expected[1..] {
+                return Err(ProofError::InvalidMagic);
+            }
+            self.finished = true;
+            return Ok(None);
+        }
+        if self.legacy_node_types && node_type == LEGACY_PARENT {
+            self.read(2 * HASH_SIZE)?;
+            let expected = expected.ok_or(ProofError::LegacyParentNode(self.read_nodes))?;
+            let node = translate_legacy_parent(
+                &self.buf,
+                self.max_value_preimages,
+                self.read_nodes,
+                std::slice::from_ref(expected),
+            )?;
+            self.read_nodes += 1;
+            return Ok(Some(node));
+        }
+        let node_type = if self.legacy_node_types {
+            let node_type = legacy_node_type(node_type, self.read_nodes)?;
+            self.buf[0] = node_type;
+            node_type
+        } else {
+            node_type
+        };
+        match NodeType::from_u8(node_type) {
+            Some(NodeType::Empty) => {}
+            Some(NodeType::Leaf) => {
+                let header = self.read(HASH_SIZE + size_of::<u32>())?;
+                let mark = u32::from_le_bytes(header[HASH_SIZE..].try_into().unwrap());
+                let preimage_len = (mark & 255) as usize;
+                if preimage_len > self.max_value_preimages {
+                    return Err(ParseNodeError::TooManyValues {
+                        len: preimage_len,
+                        limit: self.max_value_preimages,
+                    }
+                    .into());
+                }
+                self.read(32 * preimage_len)?;
+                let key_preimage_size = self.read(1)?[0];
+                if key_preimage_size > 0 {
+                    self.read(32)?;
+                }
+            }
+            Some(_) => {
+                self.read(2 * HASH_SIZE)?;
+            }
+            None => return Err(ParseNodeError::InvalidNodeType(node_type).into()),
+        }
+        let node = parse_node(&self.buf, self.max_value_preimages, self.read_nodes)?;
+        self.read_nodes += 1;
+        Ok(Some(node))
+    }
+
+    /// Into the inner reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Append `len` bytes from the stream to the buffer and return them.
+    fn read(&mut self, len: usize) -> std::io::Result<&[u8]> {
+        let start = self.buf.len();
+        self.buf.resize(start + len, 0);
+        self.reader.read_exact(&mut self.buf[start..])?;
+        Ok(&self.buf[start..])
+    }
+}
+
+/// A proof of a single key as generated by [`ZkTrie::prove`](super::ZkTrie::prove),
+/// checked by [`Proof::parse`].
+///
+/// The path of a key is followed with the path order `P` of the trie.
+#[derive(Clone)]
+pub struct Proof<H, P = LsbFirst> {
+    nodes: Vec<Node<H>>,
+    _path_order: PhantomData<P>,
+}
+
+impl<H: HashScheme, P> Debug for Proof<H, P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.nodes.iter()).finish()
+    }
+}
+
+impl<H: HashScheme, P: PathOrder> Proof<H, P> {
+    /// Parse the proof nodes, see [`Proof::parse_with_limit`].
+    pub fn parse<B: AsRef<[u8]>>(proof: &[B]) -> Result<Self, ProofError<H::Error>> {
+        Self::parse_with_limit(proof, MAX_VALUE_PREIMAGES)
+    }
+
+    /// Parse the proof nodes, rejecting leafs with more than `max_value_preimages` values.
+    ///
+    /// The proof must be branch nodes followed by a single leaf or empty node,
+    /// at most [`HashScheme::TRIE_MAX_LEVELS`] levels deep, and terminated by the magic bytes.
+    /// Every node is strictly parsed, see the [module docs](self).
+    ///
+    /// The nodes are not checked against a root, see [`Proof::verify`].
+    pub fn parse_with_limit<B: AsRef<[u8]>>(
+        proof: &[B],
+        max_value_preimages: usize,
+    ) -> Result<Self, ProofError<H::Error>> {
+        Self::parse_framed(proof, max_value_preimages, MAGIC_NODE_BYTES, None)
+    }
+
+    /// Parse the proof nodes of a proof terminated by `magic`,
+    /// see [`ZkTrie::with_proof_magic`](super::ZkTrie::with_proof_magic).
+    pub fn parse_with_magic<B: AsRef<[u8]>>(
+        proof: &[B],
+        magic: &[u8],
+    ) -> Result<Self, ProofError<H::Error>> {
+        Self::parse_framed(proof, MAX_VALUE_PREIMAGES, magic, None)
+    }
+
+    /// Parse the proof nodes of a proof generated by the legacy Go zkTrie,
+    /// which may use the legacy node types, see the [module docs](self#legacy-proofs).
+    ///
+    /// `root` is only used to translate a legacy parent node at the root,
+    /// the nodes are not checked against it, see [`Proof::verify`].
+    pub fn parse_legacy<B: AsRef<[u8]>>(
+        proof: &[B],
+        root: &ZkHash,
+    ) -> Result<Self, ProofError<H::Error>> {
+        Self::parse_framed(proof, MAX_VALUE_PREIMAGES, MAGIC_NODE_BYTES, Some(root))
+    }
+
+    /// Parse the proof nodes, with the legacy node types if `legacy_root` is given.
+    fn parse_framed<B: AsRef<[u8]>>(
+        proof: &[B],
+        max_value_preimages: usize,
+        expected_magic: &[u8],
+        legacy_root: Option<&ZkHash>,
+    ) -> Result<Self, ProofError<H::Error>> {
+        let (magic, proof) = proof.split_last().ok_or(ProofError::Incomplete)?;
+        if magic.as_ref() != expected_magic {
+            return Err(ProofError::InvalidMagic);
+        }
+        if proof.is_empty() {
+            return Err(ProofError::Incomplete);
+        }
+        if proof.len() > H::TRIE_MAX_LEVELS + 1 {
+            return Err(ProofError::MaxLevelReached);
+        }
+
+        let mut nodes: Vec<Node<H>> = Vec::with_capacity(proof.len());
+        for (index, bytes) in proof.iter().map(AsRef::as_ref).enumerate() {
+            if bytes == expected_magic {
+                return Err(ProofError::InvalidMagic);
+            }
+            let node = match (bytes.split_first(), legacy_root) {
+                (Some((&LEGACY_PARENT, _)), Some(root)) => {
+                    // referenced by the root, or either child of the parent
+                    let expected = match nodes.last().and_then(|parent| parent.as_branch()) {
+                        Some(parent) => vec![
+                            *parent.child_left().unwrap_ref(),
+                            *parent.child_right().unwrap_ref(),
+                        ],
+                        None => vec![*root],
+                    };
+                    translate_legacy_parent(bytes, max_value_preimages, index, &expected)?
+                }
+                (Some((node_type, rest)), Some(_)) => {
+                    let mut translated = Vec::with_capacity(bytes.len());
+                    translated.push(legacy_node_type(*node_type, index)?);
+                    translated.extend_from_slice(rest);
+                    parse_node::<H>(&translated, max_value_preimages, index)?
+                }
+                _ => parse_node::<H>(bytes, max_value_preimages, index)?,
+            };
+            let terminal = node.as_branch().is_none();
+            if terminal != (index == proof.len() - 1) {
+                return Err(ProofError::UnexpectedNodeType(index));
+            }
+            nodes.push(node);
+        }
+        Ok(Self {
+            nodes,
+            _path_order: PhantomData,
+        })
+    }
+
+    /// The proof of any key in an empty trie, a single empty node.
+    pub fn empty() -> Self {
+        Self {
+            nodes: vec![Node::empty()],
+            _path_order: PhantomData,
+        }
+    }
+
+    /// The root the proof starts at, i.e. the hash of the first node.
+    ///
+    /// It's [`ZkHash::ZERO`] for the proof of an empty trie,
+    /// and the leaf hash for the proof of a single leaf trie.
+    /// Comparing it with a trusted root is what [`Proof::verify_path`] starts with.
+    pub fn root(&self) -> Result<ZkHash, H::Error> {
+        self.nodes[0].get_or_calculate_node_hash().copied()
+    }
+
+    /// Encode the proof as generated by [`ZkTrie::prove`](super::ZkTrie::prove),
+    /// terminated by the magic bytes.
+    pub fn encode(&self) -> Vec<Vec<u8>> {
+        self.encode_with_magic(MAGIC_NODE_BYTES)
+    }
+
+    /// Encode the proof terminated by `magic`,
+    /// see [`ZkTrie::with_proof_magic`](super::ZkTrie::with_proof_magic).
+    pub fn encode_with_magic(&self, magic: &[u8]) -> Vec<Vec<u8>> {
+        self.nodes
+            .iter()
+            .map(|node| node.canonical_value(true))
+            .chain([magic.to_vec()])
+            .collect()
+    }
+
+    /// Get the proof nodes, from the root to the terminal node
+    #[inline]
+    pub fn nodes(&self) -> &[Node<H>] {
+        &self.nodes
+    }
+
+    /// Take the proof nodes
+    #[inline]
+    pub fn into_nodes(self) -> Vec<Node<H>> {
+        self.nodes
+    }
+
+    /// Check the proof is a path from `root` down to the terminal node.
+    pub fn verify_path(&self, root: &ZkHash) -> Result<(), ProofError<H::Error>> {
+        self.check(root, None)
+    }
+
+    /// Check the proof is the path of `node_key` from `root`.
+    ///
+    /// Returns the leaf of `node_key`, or `None` if the proof shows the key is absent.
+    #[inline]
+    pub fn verify(
+        &self,
+        root: &ZkHash,
+        node_key: &ZkHash,
+    ) -> Result<Option<Node<H>>, ProofError<H::Error>> {
+        self.verify_key(root, node_key).map(KeyProof::into_present)
+    }
+
+    /// Check the proof is the path of `node_key` from `root`, and tell how the key is proven.
+    ///
+    /// If the path ends at another leaf, that leaf must share the path of `node_key`
+    /// down to its level, otherwise [`ProofError::MisplacedLeaf`] is returned.
+    pub fn verify_key(
+        &self,
+        root: &ZkHash,
+        node_key: &ZkHash,
+    ) -> Result<KeyProof<H>, ProofError<H::Error>> {
+        self.check(root, Some(node_key))?;
+        let level = self.nodes.len() - 1;
+        let terminal = self.nodes.last().unwrap();
+        let Some(leaf) = terminal.as_leaf() else {
+            return Ok(KeyProof::Absent {
+                conflicting_leaf: None,
+            });
+        };
+        let leaf_key = leaf.node_key();
+        if leaf_key == *node_key {
+            return Ok(KeyProof::Present(terminal.clone()));
+        }
+        if (0..level).any(|l| P::path_bit::<H>(&leaf_key, l) != P::path_bit::<H>(node_key, l)) {
+            return Err(ProofError::MisplacedLeaf(level));
+        }
+        Ok(KeyProof::Absent {
+            conflicting_leaf: Some(terminal.clone()),
+        })
+    }
+
+    /// Check the proof is the path of `node_key` from `old_root`, and compute the root
+    /// after setting the values of `node_key`, or deleting it if `new_value` is `None`.
+    ///
+    /// The update is replayed on the proof nodes alone, the same way
+    /// [`ZkTrie::raw_update`](super::ZkTrie::raw_update) and
+    /// [`ZkTrie::delete_by_node_key`](super::ZkTrie::delete_by_node_key) apply it,
+    /// e.g. to check a state transition proposed by an untrusted party without a database.
+    /// Deleting an absent key leaves the root unchanged.
+    pub fn apply_update(
+        &self,
+        old_root: &ZkHash,
+        node_key: &ZkHash,
+        new_value: Option<(Vec<[u8; 32]>, u32)>,
+    ) -> Result<ZkHash, ProofError<H::Error>> {
+        let key_proof = self.verify_key(old_root, node_key)?;
+        let level = self.nodes.len() - 1;
+        if level >= H::TRIE_MAX_LEVELS {
+            return Err(ProofError::MaxLevelReached);
+        }
+        let deleting = new_value.is_none();
+        let (mut node_hash, mut is_terminal) = match (new_value, key_proof) {
+            (Some((values, flags)), key_proof) => {
+                Node::<H>::check_leaf_values(&values, flags)?;
+                let leaf = Node::<H>::new_leaf(*node_key, values, flags, None)
+                    .map_err(ProofError::Hash)?;
+                let leaf_hash = *leaf
+                    .get_or_calculate_node_hash()
+                    .map_err(ProofError::Hash)?;
+                match key_proof {
+                    KeyProof::Absent {
+                        conflicting_leaf: Some(other),
+                    } => (self.push_leaf(level, node_key, leaf_hash, &other)?, false),
+                    _ => (leaf_hash, true),
+                }
+            }
+            (None, KeyProof::Present(_)) => (ZkHash::ZERO, true),
+            (None, KeyProof::Absent { .. }) => return Ok(*old_root),
+        };
+
+        for (level, node) in self.nodes[..level].iter().enumerate().rev() {
+            let (node_type, left, right) = node.as_branch().unwrap().as_parts();
+            let right_path = P::path_bit::<H>(node_key, level);
+            let (sibling, is_sibling_terminal) = if right_path {
+                (
+                    left,
+                    matches!(node_type, NodeType::BranchLTRT | NodeType::BranchLTRB),
+                )
+            } else {
+                (
+                    right,
+                    matches!(node_type, NodeType::BranchLTRT | NodeType::BranchLBRT),
+                )
+            };
+            let sibling = *sibling.unwrap_ref();
+            // a deletion prunes branches left with a single terminal child
+            if deleting && is_terminal && is_sibling_terminal {
+                if node_hash.is_zero() {
+                    node_hash = sibling;
+                    continue;
+                }
+                if sibling.is_zero() {
+                    continue;
+                }
+            }
+            let (left, right, is_left_terminal, is_right_terminal) = if right_path {
+                (sibling, node_hash, is_sibling_terminal, is_terminal)
+            } else {
+                (node_hash, sibling, is_terminal, is_sibling_terminal)
+            };
+            let node_type = match (is_left_terminal, is_right_terminal) {
+                (true, true) => NodeType::BranchLTRT,
+                (true, false) => NodeType::BranchLTRB,
+                (false, true) => NodeType::BranchLBRT,
+                (false, false) => NodeType::BranchLBRB,
+            };
+            node_hash = H::hash(node_type as u64, [left, right]).map_err(ProofError::Hash)?;
+            is_terminal = false;
+        }
+        Ok(node_hash)
+    }
+
+    /// The hash of the branches splitting the new leaf of `node_key` from the `other` leaf
+    /// its path ends at, from `level` down to where their paths diverge.
+    fn push_leaf(
+        &self,
+        level: usize,
+        node_key: &ZkHash,
+        leaf_hash: ZkHash,
+        other: &Node<H>,
+    ) -> Result<ZkHash, ProofError<H::Error>> {
+        let other_key = other.as_leaf().unwrap().node_key();
+        let other_hash = *other
+            .get_or_calculate_node_hash()
+            .map_err(ProofError::Hash)?;
+        let diverged = (level..H::TRIE_MAX_LEVELS.saturating_sub(1))
+            .find(|l| P::path_bit::<H>(node_key, *l) != P::path_bit::<H>(&other_key, *l))
+            .ok_or(ProofError::MaxLevelReached)?;
+        let children = if P::path_bit::<H>(node_key, diverged) {
+            [other_hash, leaf_hash]
+        } else {
+            [leaf_hash, other_hash]
+        };
+        let mut node_hash =
+            H::hash(NodeType::BranchLTRT as u64, children).map_err(ProofError::Hash)?;
+        for level in (level..diverged).rev() {
+            node_hash = if P::path_bit::<H>(node_key, level) {
+                H::hash(NodeType::BranchLTRB as u64, [ZkHash::ZERO, node_hash])
+            } else {
+                H::hash(NodeType::BranchLBRT as u64, [node_hash, ZkHash::ZERO])
+            }
+            .map_err(ProofError::Hash)?;
+        }
+        Ok(node_hash)
+    }
+
+    /// Check the hash links and the child types, following `node_key` if given.
+    fn check(&self, root: &ZkHash, node_key: Option<&ZkHash>) -> Result<(), ProofError<H::Error>> {
+        let mut expected = *root;
+        for (level, node) in self.nodes.iter().enumerate() {
+            let actual = *node
+                .get_or_calculate_node_hash()
+                .map_err(ProofError::Hash)?;
+            if actual != expected {
+                return Err(ProofError::HashMismatch { expected, actual });
+            }
+            let Some(branch) = node.as_branch() else {
+                break;
+            };
+            let next = &self.nodes[level + 1];
+            let right = match node_key {
+                Some(node_key) => P::path_bit::<H>(node_key, level),
+                None => {
+                    let next_hash = next
+                        .get_or_calculate_node_hash()
+                        .map_err(ProofError::Hash)?;
+                    branch.child_right().unwrap_ref() == next_hash
+                }
+            };
+            let child_is_branch = match node.node_type() {
+                NodeType::BranchLTRT => false,
+                NodeType::BranchLTRB => right,
+                NodeType::BranchLBRT => !right,
+                _ => true,
+            };
+            if child_is_branch != next.as_branch().is_some() {
+                return Err(ProofError::UnexpectedNodeType(level));
+            }
+            expected = if right {
+                *branch.child_right().unwrap_ref()
+            } else {
+                *branch.child_left().unwrap_ref()
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Check the magic bytes can't be mistaken for the start of a node.
+pub(super) fn assert_proof_magic(magic: &[u8]) {
+    assert!(
+        magic
+            .first()
+            .is_some_and(|first| NodeType::from_u8(*first).is_none()),
+        "proof magic bytes must not be empty or start with a node type"
+    );
+}
+
+/// The node type replacing a legacy node type, `index` is the position reported in errors.
+fn legacy_node_type<E>(node_type: u8, index: usize) -> Result<u8, ProofError<E>> {
+    match node_type {
+        LEGACY_PARENT => Err(ProofError::LegacyParentNode(index)),
+        LEGACY_LEAF => Ok(NodeType::Leaf as u8),
+        LEGACY_EMPTY => Ok(NodeType::Empty as u8),
+        node_type => Ok(node_type),
+    }
+}
+
+/// Parse a legacy parent node as the branch type hashing to one of `expected`,
+/// `index` is the position reported in errors.
+///
+/// The children types of a legacy parent node are unknown, so every branch type is tried.
+fn translate_legacy_parent<H: HashScheme>(
+    bytes: &[u8],
+    max_value_preimages: usize,
+    index: usize,
+    expected: &[ZkHash],
+) -> Result<Node<H>, ProofError<H::Error>> {
+    let mut translated = bytes.to_vec();
+    for node_type in [
+        NodeType::BranchLTRT,
+        NodeType::BranchLTRB,
+        NodeType::BranchLBRT,
+        NodeType::BranchLBRB,
+    ] {
+        translated[0] = node_type as u8;
+        let node = parse_node::<H>(&translated, max_value_preimages, index)?;
+        let node_hash = node
+            .get_or_calculate_node_hash()
+            .map_err(ProofError::Hash)?;
+        if expected.contains(node_hash) {
+            return Ok(node);
+        }
+    }
+    Err(ProofError::LegacyParentNode(index))
+}
+
+/// Strictly parse a proof node, `index` is the position reported in errors.
+pub(super) fn parse_node<H: HashScheme>(
+    bytes: &[u8],
+    max_value_preimages: usize,
+    index: usize,
+) -> Result<Node<H>, ProofError<H::Error>> {
+    let node = Node::<H>::try_from_bytes_with_limit(bytes, max_value_preimages)?;
+    if let Some(leaf) = node.as_leaf() {
+        Node::<H>::check_leaf_values(leaf.value_preimages(), leaf.compress_flags())?;
+    }
+    if node.canonical_value(true) != bytes {
+        return Err(ProofError::NonCanonicalNode(index));
+    }
+    Ok(node)
+}
+
+/// Parse and verify a proof of `node_key` against `root`, see [`Proof::verify_key`].
+///
+/// The path is followed with the default [`LsbFirst`] order,
+/// parse the proof with [`Proof`] to verify proofs of other orders.
+pub fn verify_proof<H: HashScheme, B: AsRef<[u8]>>(
+    root: &ZkHash,
+    node_key: &ZkHash,
+    proof: &[B],
+) -> Result<KeyProof<H>, ProofError<H::Error>> {
+    Proof::<H>::parse(proof)?.verify_key(root, node_key)
+}
+
+/// Parse a proof of `node_key` against `old_root` and compute the root after
+/// setting the values and compression flags of `node_key`, or deleting it if `None`,
+/// see [`Proof::apply_update`].
+///
+/// The path is followed with the default [`LsbFirst`] order,
+/// parse the proof with [`Proof`] to replay updates of other orders.
+pub fn apply_update_from_proof<H: HashScheme, B: AsRef<[u8]>>(
+    old_root: &ZkHash,
+    proof: &[B],
+    node_key: &ZkHash,
+    new_value: Option<(Vec<[u8; 32]>, u32)>,
+) -> Result<ZkHash, ProofError<H::Error>> {
+    Proof::<H>::parse(proof)?.apply_update(old_root, node_key, new_value)
+}
+
+/// Same as [`verify_proof`] for proofs generated by the legacy Go zkTrie,
+/// see the [module docs](self#legacy-proofs).
+pub fn verify_legacy_proof<H: HashScheme, B: AsRef<[u8]>>(
+    root: &ZkHash,
+    node_key: &ZkHash,
+    proof: &[B],
+) -> Result<KeyProof<H>, ProofError<H::Error>> {
+    Proof::<H>::parse_legacy(proof, root)?.verify_key(root, node_key)
+}
+
+/// Verify a proof of `node_key` against `root` while reading it from a stream.
+///
+/// Each node is checked against the hash referenced by its parent as soon as it arrives,
+/// so memory use doesn't grow with the proof depth.
+///
+/// Returns the leaf of `node_key`, or `None` if the proof shows the key is absent.
+///
+/// The path is followed with the default [`LsbFirst`] order,
+/// parse the proof with [`Proof`] to verify proofs of other orders.
+pub fn verify_proof_stream<H: HashScheme, R: Read>(
+    root: &ZkHash,
+    node_key: &ZkHash,
+    reader: R,
+) -> Result<Option<Node<H>>, ProofError<H::Error>> {
+    verify_proof_reader(root, node_key, ProofReader::new(reader))
+}
+
+/// Same as [`verify_proof_stream`] with a configured [`ProofReader`],
+/// e.g. for proofs with [custom magic bytes](ProofReader::with_magic).
+pub fn verify_proof_reader<H: HashScheme, R: Read>(
+    root: &ZkHash,
+    node_key: &ZkHash,
+    mut reader: ProofReader<R>,
+) -> Result<Option<Node<H>>, ProofError<H::Error>> {
+    let mut expected = *root;
+    let mut result = None;
+    for level in 0..=H::TRIE_MAX_LEVELS {
+        let node = reader
+            .next_node_referenced_by::<H>(Some(&expected))?
+            .ok_or(ProofError::Incomplete)?;
+        let actual = *node
+            .get_or_calculate_node_hash()
+            .map_err(ProofError::Hash)?;
+        if actual != expected {
+            return Err(ProofError::HashMismatch { expected, actual });
+        }
+        match node.node_type() {
+            NodeType::Empty => break,
+            NodeType::Leaf => {
+                if node.as_leaf().unwrap().node_key() == *node_key {
+                    result = Some(node);
+                }
+                break;
+            }
+            _ if level == H::TRIE_MAX_LEVELS => return Err(ProofError::MaxLevelReached),
+            _ => {
+                let branch = node.as_branch().unwrap();
+                let child = if get_path(node_key, level) {
+                    branch.child_right()
+                } else {
+                    branch.child_left()
+                };
+                expected = *child.unwrap_ref();
+            }
+        }
+    }
+    if reader.next_node::<H>()?.is_some() {
+        return Err(ProofError::InvalidMagic);
+    }
+    Ok(result)
+}
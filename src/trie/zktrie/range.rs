@@ -0,0 +1,385 @@
+//! Range proofs for state sync, see [`ZkTrie::prove_range`].
+use super::{
+    imp::{get_path, Result},
+    proof::parse_node,
+    OpReceipt, ProofError, ZkTrie, ZkTrieError,
+};
+use crate::{
+    db::{kv::KVDatabase, DbError, NodeDb},
+    hash::{key_hasher::KeyHasher, HashScheme, ZkHash, HASH_SIZE},
+    trie::{LazyNodeHash, Node, NodeType, MAGIC_NODE_BYTES, MAX_VALUE_PREIMAGES},
+    HashMap, HashSet,
+};
+use std::fmt::{Debug, Formatter};
+
+/// Consecutive leafs of a trie, with the boundary proofs showing that none are omitted.
+///
+/// See [`ZkTrie::prove_range`] and [`verify_range_proof`].
+pub struct RangeProof<H> {
+    /// The leafs in path order
+    pub leaves: Vec<Node<H>>,
+    /// The nodes on the paths of the first and the last key of the range
+    pub proof: Vec<Vec<u8>>,
+}
+
+impl<H: HashScheme> Debug for RangeProof<H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RangeProof")
+            .field("leaves", &self.leaves.len())
+            .field("proof", &self.proof.len())
+            .finish()
+    }
+}
+
+/// A range of leafs checked by [`verify_range_proof`], ready for [`ZkTrie::absorb_range`].
+pub struct VerifiedRange<H> {
+    root: ZkHash,
+    /// Boundary nodes from the proof and subtrees built from the leafs, children first
+    nodes: Vec<Node<H>>,
+}
+
+impl<H: HashScheme> Debug for VerifiedRange<H> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VerifiedRange")
+            .field("root", &self.root)
+            .field("nodes", &self.nodes.len())
+            .finish()
+    }
+}
+
+impl<H> VerifiedRange<H> {
+    /// The root of the trie the range was verified against.
+    #[inline]
+    pub fn root(&self) -> &ZkHash {
+        &self.root
+    }
+
+    /// Number of nodes the range covers, see [`ZkTrie::absorb_range`].
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if the range covers no nodes.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
+    /// Collect the leafs from node key `start` to `end` in path order,
+    /// see [`ZkTrieIterator`](super::ZkTrieIterator) for the order.
+    ///
+    /// Like snap sync, the range ends with the first leaf at or after `end`,
+    /// so the last leaf may be past `end`.
+    /// If there's no leaf at or after `start`, the leaves are empty and the proof shows that.
+    ///
+    /// The trie must be committed, and use the default [`LsbFirst`](super::LsbFirst) path order.
+    #[instrument(level = "trace", skip_all)]
+    pub fn prove_range<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        start: &ZkHash,
+        end: &ZkHash,
+    ) -> Result<RangeProof<H>, H, Db> {
+        if self.is_dirty() {
+            warn!("dirty nodes found, commit before proving a range");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let (start_order, end_order) = (path_order(start), path_order(end));
+
+        let mut leaves = Vec::new();
+        let mut stack = vec![(self.root.clone(), 0, [0u8; HASH_SIZE])];
+        while let Some((node_hash, level, prefix)) = stack.pop() {
+            if subtree_bounds(&prefix, level).1 < start_order {
+                continue;
+            }
+            let node = self.get_node_by_hash(db, node_hash)?;
+            match node.node_type() {
+                NodeType::Empty => {}
+                NodeType::Leaf => {
+                    let leaf = node.as_leaf().unwrap();
+                    let order = path_order(&leaf.node_key());
+                    if order < start_order {
+                        continue;
+                    }
+                    leaves.push(
+                        Node::new_leaf(
+                            leaf.node_key(),
+                            leaf.value_preimages().to_vec(),
+                            leaf.compress_flags(),
+                            leaf.node_key_preimage().copied(),
+                        )
+                        .map_err(ZkTrieError::Hash)?,
+                    );
+                    if order >= end_order {
+                        break;
+                    }
+                }
+                _ => {
+                    let (_, child_left, child_right) = node.as_branch().unwrap().as_parts();
+                    stack.push((child_right, level + 1, with_bit(prefix, level)));
+                    stack.push((child_left, level + 1, prefix));
+                }
+            }
+        }
+
+        let mut receipt = OpReceipt::default();
+        let mut seen = HashSet::new();
+        let mut proof = Vec::new();
+        let mut boundaries = vec![*start];
+        if let Some(last) = leaves.last() {
+            boundaries.push(last.as_leaf().unwrap().node_key());
+        }
+        for node_key in boundaries.iter() {
+            self.prove_node_key_with(db, node_key, &mut receipt, |bytes| {
+                if seen.insert(bytes.clone()) {
+                    proof.push(bytes.into());
+                }
+                Ok(())
+            })?;
+        }
+        trace!("proved {} leaves with {} nodes", leaves.len(), proof.len());
+        Ok(RangeProof { leaves, proof })
+    }
+}
+
+impl<H: HashScheme> ZkTrie<H> {
+    /// Insert a verified range into `db`.
+    ///
+    /// The subtrees that lie entirely within the range are built bottom up from the leafs,
+    /// and stitched to the rest of the trie with the boundary nodes of the proof.
+    /// Once the ranges cover all node keys, `db` holds the whole trie at [`VerifiedRange::root`].
+    ///
+    /// Nodes already in `db` are skipped. Returns the number of nodes inserted.
+    #[instrument(level = "trace", skip_all)]
+    pub fn absorb_range<Db: KVDatabase>(
+        db: &mut NodeDb<Db>,
+        range: VerifiedRange<H>,
+    ) -> Result<usize, H, Db> {
+        let mut inserted = 0;
+        for node in range.nodes {
+            let node_hash = *node
+                .get_or_calculate_node_hash()
+                .map_err(ZkTrieError::Hash)?;
+            if db
+                .inner()
+                .contains_key(node_hash.as_slice())
+                .map_err(DbError::classify::<Db>)?
+            {
+                continue;
+            }
+            db.put_node(node).map_err(DbError::classify::<Db>)?;
+            inserted += 1;
+        }
+        trace!("absorbed {inserted} nodes");
+        Ok(inserted)
+    }
+}
+
+/// Verify that `leaves` are all the leafs of the trie at `root`,
+/// from node key `start` up to the last leaf, as returned by [`ZkTrie::prove_range`].
+///
+/// If `leaves` is empty, the proof must show there's no leaf at or after `start`.
+pub fn verify_range_proof<H: HashScheme, P: AsRef<[u8]>>(
+    root: &ZkHash,
+    start: &ZkHash,
+    leaves: &[Node<H>],
+    proof: &[P],
+) -> std::result::Result<VerifiedRange<H>, ProofError<H::Error>> {
+    let mut nodes = HashMap::new();
+    for (index, bytes) in proof.iter().map(AsRef::as_ref).enumerate() {
+        if bytes == MAGIC_NODE_BYTES {
+            continue;
+        }
+        let node = parse_node::<H>(bytes, MAX_VALUE_PREIMAGES, index)?;
+        let node_hash = *node
+            .get_or_calculate_node_hash()
+            .map_err(ProofError::Hash)?;
+        nodes.insert(node_hash, node);
+    }
+
+    let mut ordered = Vec::with_capacity(leaves.len());
+    for leaf in leaves {
+        let node_key = leaf.as_leaf().ok_or(ProofError::InvalidLeaves)?.node_key();
+        ordered.push((path_order(&node_key), leaf));
+    }
+    let start = path_order(start);
+    if ordered.windows(2).any(|pair| pair[0].0 >= pair[1].0)
+        || ordered.first().is_some_and(|(order, _)| *order < start)
+    {
+        return Err(ProofError::InvalidLeaves);
+    }
+    let end = ordered
+        .last()
+        .map(|(order, _)| *order)
+        .unwrap_or([u8::MAX; HASH_SIZE]);
+
+    let verifier = RangeVerifier { start, end, nodes };
+    let mut nodes = Vec::new();
+    verifier.verify(*root, [0u8; HASH_SIZE], 0, &ordered, &mut nodes)?;
+    Ok(VerifiedRange { root: *root, nodes })
+}
+
+struct RangeVerifier<H> {
+    start: [u8; HASH_SIZE],
+    end: [u8; HASH_SIZE],
+    nodes: HashMap<ZkHash, Node<H>>,
+}
+
+impl<H: HashScheme> RangeVerifier<H> {
+    /// Check the subtree at `prefix` hashes to `expected`, given the range leaves under it.
+    ///
+    /// The nodes of the subtree that are known after the check are pushed to `out`.
+    fn verify(
+        &self,
+        expected: ZkHash,
+        prefix: [u8; HASH_SIZE],
+        level: usize,
+        leaves: &[([u8; HASH_SIZE], &Node<H>)],
+        out: &mut Vec<Node<H>>,
+    ) -> std::result::Result<(), ProofError<H::Error>> {
+        let (lo, hi) = subtree_bounds(&prefix, level);
+        if hi < self.start || lo > self.end {
+            // no range leaf can be here, keep the hash from the parent
+            return Ok(());
+        }
+        if self.start <= lo && hi <= self.end {
+            let actual = build_subtree(leaves, level, out)?;
+            return match actual == expected {
+                true => Ok(()),
+                false => Err(ProofError::HashMismatch { expected, actual }),
+            };
+        }
+        if expected.is_zero() {
+            return match leaves.is_empty() {
+                true => Ok(()),
+                false => Err(ProofError::InvalidLeaves),
+            };
+        }
+        if level >= H::TRIE_MAX_LEVELS {
+            return Err(ProofError::MaxLevelReached);
+        }
+
+        let node = self
+            .nodes
+            .get(&expected)
+            .ok_or(ProofError::MissingNode(expected))?;
+        match node.as_branch() {
+            Some(branch) => {
+                let split = leaves.partition_point(|(order, _)| !path_bit(order, level));
+                let (left, right) = leaves.split_at(split);
+                self.verify(
+                    *branch.child_left().unwrap_ref(),
+                    prefix,
+                    level + 1,
+                    left,
+                    out,
+                )?;
+                self.verify(
+                    *branch.child_right().unwrap_ref(),
+                    with_bit(prefix, level),
+                    level + 1,
+                    right,
+                    out,
+                )?;
+                out.push(node.clone());
+                Ok(())
+            }
+            None => {
+                // a terminal node authenticated by its parent
+                let order = path_order(&node.as_leaf().unwrap().node_key());
+                let in_range = self.start <= order && order <= self.end;
+                match leaves {
+                    [] if !in_range => Ok(()),
+                    [(_, leaf)] if in_range => {
+                        let actual = *leaf
+                            .get_or_calculate_node_hash()
+                            .map_err(ProofError::Hash)?;
+                        if actual != expected {
+                            return Err(ProofError::HashMismatch { expected, actual });
+                        }
+                        out.push((*leaf).clone());
+                        Ok(())
+                    }
+                    _ => Err(ProofError::InvalidLeaves),
+                }
+            }
+        }
+    }
+}
+
+/// Build the subtree holding exactly `leaves` bottom up, pushing its nodes to `out`.
+///
+/// Returns the root hash of the subtree.
+fn build_subtree<H: HashScheme>(
+    leaves: &[([u8; HASH_SIZE], &Node<H>)],
+    level: usize,
+    out: &mut Vec<Node<H>>,
+) -> std::result::Result<ZkHash, ProofError<H::Error>> {
+    match leaves {
+        [] => Ok(ZkHash::ZERO),
+        [(_, leaf)] => {
+            let node_hash = *leaf
+                .get_or_calculate_node_hash()
+                .map_err(ProofError::Hash)?;
+            out.push((*leaf).clone());
+            Ok(node_hash)
+        }
+        _ if level >= H::TRIE_MAX_LEVELS => Err(ProofError::MaxLevelReached),
+        _ => {
+            let split = leaves.partition_point(|(order, _)| !path_bit(order, level));
+            let (left, right) = leaves.split_at(split);
+            let node_type = match (left.len() <= 1, right.len() <= 1) {
+                (true, true) => NodeType::BranchLTRT,
+                (true, false) => NodeType::BranchLTRB,
+                (false, true) => NodeType::BranchLBRT,
+                (false, false) => NodeType::BranchLBRB,
+            };
+            let branch = Node::<H>::new_branch(
+                node_type,
+                LazyNodeHash::Hash(build_subtree(left, level + 1, out)?),
+                LazyNodeHash::Hash(build_subtree(right, level + 1, out)?),
+            );
+            let node_hash = *branch
+                .get_or_calculate_node_hash()
+                .map_err(ProofError::Hash)?;
+            out.push(branch);
+            Ok(node_hash)
+        }
+    }
+}
+
+/// The position of `node_key` in path order,
+/// comparing these compares the paths from the root bit by bit.
+#[inline]
+fn path_order(node_key: &ZkHash) -> [u8; HASH_SIZE] {
+    let mut order = [0u8; HASH_SIZE];
+    for (byte, key_byte) in order.iter_mut().zip(node_key.as_slice().iter().rev()) {
+        *byte = key_byte.reverse_bits();
+    }
+    debug_assert!((0..8).all(|level| path_bit(&order, level) == get_path(node_key, level)));
+    order
+}
+
+#[inline]
+fn path_bit(order: &[u8; HASH_SIZE], level: usize) -> bool {
+    order[level / 8] & (0x80 >> (level % 8)) != 0
+}
+
+#[inline]
+fn with_bit(mut order: [u8; HASH_SIZE], level: usize) -> [u8; HASH_SIZE] {
+    order[level / 8] |= 0x80 >> (level % 8);
+    order
+}
+
+/// The first and the last path order under `prefix`, which keeps its first `level` bits.
+fn subtree_bounds(prefix: &[u8; HASH_SIZE], level: usize) -> ([u8; HASH_SIZE], [u8; HASH_SIZE]) {
+    let (mut lo, mut hi) = (*prefix, *prefix);
+    for i in level..HASH_SIZE * 8 {
+        lo[i / 8] &= !(0x80 >> (i % 8));
+        hi[i / 8] |= 0x80 >> (i % 8);
+    }
+    (lo, hi)
+}
@@ -0,0 +1,164 @@
+//! Recompute a root from leafs only, see [`ZkTrie::recompute_root`].
+use super::{PathOrder, ZkTrie};
+use crate::{
+    hash::{HashScheme, ZkHash, HASH_SIZE},
+    trie::{Node, NodeType},
+};
+use std::thread;
+
+/// Subtrees with fewer leafs are never split across threads.
+const MIN_PARALLEL_LEAFS: usize = 64;
+
+/// Errors when recomputing a root with [`ZkTrie::recompute_root`].
+#[derive(Debug, thiserror::Error)]
+pub enum RecomputeRootError<HashErr> {
+    /// Error when hashing
+    #[error(transparent)]
+    Hash(HashErr),
+    /// The node at this index is not a leaf
+    #[error("Node {0} is not a leaf")]
+    NotALeaf(usize),
+    /// Two leafs have the same node key
+    #[error("Duplicate node key {0}")]
+    DuplicateNodeKey(ZkHash),
+    /// Two leafs share the whole path
+    #[error("Max level reached")]
+    MaxLevelReached,
+}
+
+type Result<T, H> = std::result::Result<T, RecomputeRootError<<H as HashScheme>::Error>>;
+
+/// A leaf reduced to its path, node key and hash
+type PathLeaf = ([u8; HASH_SIZE], ZkHash, ZkHash);
+
+impl<H: HashScheme, K, P: PathOrder> ZkTrie<H, K, P> {
+    /// Recompute the root of the trie holding exactly `leaves`, in any order,
+    /// e.g. the [leafs](ZkTrie::leafs) exported from a [`NodeDb`](crate::db::NodeDb),
+    /// see [`INode::into_owned`](crate::trie::INode::into_owned).
+    ///
+    /// Nothing is read from a database and no cached hash is trusted:
+    /// leaf hashes are computed from the node keys and values, branches bottom-up,
+    /// on up to `threads` threads (`0` for the available parallelism).
+    /// Comparing the result with the stored root audits the stored branch nodes.
+    pub fn recompute_root(leaves: &[Node<H>], threads: usize) -> Result<ZkHash, H>
+    where
+        H::Error: Send,
+    {
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let mut path_leaves = hash_leaves::<H, P>(leaves, threads)?;
+        path_leaves.sort_unstable_by_key(|(path, node_key, _)| (*path, *node_key));
+        if let Some(pair) = path_leaves.windows(2).find(|pair| pair[0].1 == pair[1].1) {
+            return Err(RecomputeRootError::DuplicateNodeKey(pair[0].1));
+        }
+        let split_levels = threads.next_power_of_two().trailing_zeros() as usize;
+        let root = subtree_root::<H>(&path_leaves, 0, split_levels)?;
+        trace!(leaves = leaves.len(), threads, "recomputed root {root}");
+        Ok(root)
+    }
+}
+
+/// Hash every leaf from its node key and values, split in chunks over `threads`.
+fn hash_leaves<H: HashScheme, P: PathOrder>(
+    leaves: &[Node<H>],
+    threads: usize,
+) -> Result<Vec<PathLeaf>, H>
+where
+    H::Error: Send,
+{
+    let mut inputs = Vec::with_capacity(leaves.len());
+    for (index, leaf) in leaves.iter().enumerate() {
+        let leaf = leaf.as_leaf().ok_or(RecomputeRootError::NotALeaf(index))?;
+        inputs.push((
+            leaf.node_key(),
+            leaf.value_preimages(),
+            leaf.compress_flags(),
+        ));
+    }
+    let chunk_size = inputs.len().div_ceil(threads).max(MIN_PARALLEL_LEAFS);
+    thread::scope(|s| {
+        let handles: Vec<_> = inputs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(node_key, values, flags)| {
+                            let value_hash = H::hash_bytes_array(values, *flags)?;
+                            let leaf_hash =
+                                H::hash(NodeType::Leaf as u64, [*node_key, value_hash])?;
+                            Ok((path_key::<H, P>(node_key), *node_key, leaf_hash))
+                        })
+                        .collect::<std::result::Result<Vec<_>, H::Error>>()
+                })
+            })
+            .collect();
+        let mut hashed = Vec::with_capacity(inputs.len());
+        for handle in handles {
+            hashed.extend(handle.join().unwrap().map_err(RecomputeRootError::Hash)?);
+        }
+        Ok(hashed)
+    })
+}
+
+/// The hash of the subtree at `level` holding `leaves`, sorted by path.
+///
+/// The subtrees of the first `split_levels` levels are hashed on their own threads.
+fn subtree_root<H: HashScheme>(
+    leaves: &[PathLeaf],
+    level: usize,
+    split_levels: usize,
+) -> Result<ZkHash, H>
+where
+    H::Error: Send,
+{
+    match leaves {
+        [] => Ok(ZkHash::ZERO),
+        [(_, _, leaf_hash)] => Ok(*leaf_hash),
+        _ if level >= H::TRIE_MAX_LEVELS.min(HASH_SIZE * 8) => {
+            Err(RecomputeRootError::MaxLevelReached)
+        }
+        _ => {
+            let split = leaves.partition_point(|(path, _, _)| !path_bit(path, level));
+            let (left, right) = leaves.split_at(split);
+            let (left_hash, right_hash) =
+                if level < split_levels && leaves.len() >= MIN_PARALLEL_LEAFS {
+                    thread::scope(|s| {
+                        let left = s.spawn(|| subtree_root::<H>(left, level + 1, split_levels));
+                        let right = subtree_root::<H>(right, level + 1, split_levels);
+                        (left.join().unwrap(), right)
+                    })
+                } else {
+                    (
+                        subtree_root::<H>(left, level + 1, split_levels),
+                        subtree_root::<H>(right, level + 1, split_levels),
+                    )
+                };
+            let node_type = match (left.len() <= 1, right.len() <= 1) {
+                (true, true) => NodeType::BranchLTRT,
+                (true, false) => NodeType::BranchLTRB,
+                (false, true) => NodeType::BranchLBRT,
+                (false, false) => NodeType::BranchLBRB,
+            };
+            H::hash(node_type as u64, [left_hash?, right_hash?]).map_err(RecomputeRootError::Hash)
+        }
+    }
+}
+
+/// The path bits of `node_key`, comparing these compares the paths from the root.
+fn path_key<H: HashScheme, P: PathOrder>(node_key: &ZkHash) -> [u8; HASH_SIZE] {
+    let mut path = [0u8; HASH_SIZE];
+    for level in 0..H::TRIE_MAX_LEVELS.min(HASH_SIZE * 8) {
+        if P::path_bit::<H>(node_key, level) {
+            path[level / 8] |= 0x80 >> (level % 8);
+        }
+    }
+    path
+}
+
+#[inline(always)]
+fn path_bit(path: &[u8; HASH_SIZE], level: usize) -> bool {
+    path[level / 8] & (0x80 >> (level % 8)) != 0
+}
@@ -0,0 +1,132 @@
+//! Streaming traversal for very large tries, see [`ZkTrie::stream`].
+//!
+//! [`ZkTrieIterator`](super::ZkTrieIterator) yields owned nodes, so every node the caller keeps
+//! keeps its bytes alive. [`ZkTrieStream`] lends each node until it is advanced instead,
+//! holding a single node at a time besides the hashes of the pending subtrees.
+use super::{imp::Result, NodePath, PathOrder, ZkTrie};
+use crate::{
+    db::{kv::KVDatabase, NodeDb},
+    hash::{key_hasher::KeyHasher, HashScheme},
+    trie::{ILeafNode, INode, LazyNodeHash},
+};
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::ops::ControlFlow;
+
+/// A node lent by [`ZkTrieStream::next_node`] with its path.
+type LentNode<'s, H, Db> = Result<(NodePath, &'s INode<H>), H, Db>;
+
+/// The order [`ZkTrieStream`] visits nodes in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraversalOrder {
+    /// Depth first in pre-order, leafs in path order.
+    /// Pending subtrees are bounded by the depth of the trie.
+    #[default]
+    DepthFirst,
+    /// Level by level, left to right.
+    /// Pending subtrees are bounded by the widest level, about half the leafs.
+    BreadthFirst,
+}
+
+/// A lending iterator over the nodes of a trie, see the [module docs](self).
+pub struct ZkTrieStream<'a, H, Db, K, P> {
+    trie: &'a ZkTrie<H, K, P>,
+    db: &'a NodeDb<Db>,
+    order: TraversalOrder,
+    pending: VecDeque<(LazyNodeHash, NodePath)>,
+    current: Option<INode<H>>,
+}
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> ZkTrie<H, K, P> {
+    /// Get a streaming iterator over the nodes of the trie, see the [module docs](self).
+    pub fn stream<'a, Db: KVDatabase>(
+        &'a self,
+        db: &'a NodeDb<Db>,
+        order: TraversalOrder,
+    ) -> ZkTrieStream<'a, H, Db, K, P> {
+        ZkTrieStream {
+            trie: self,
+            db,
+            order,
+            pending: VecDeque::from([(self.root.clone(), NodePath::default())]),
+            current: None,
+        }
+    }
+
+    /// Call `f` with every leaf of the trie in path order, until it breaks,
+    /// e.g. to dump a trie too large to collect.
+    ///
+    /// The leaf is only borrowed, no node outlives the call.
+    /// Returns the number of visited leafs.
+    pub fn for_each_leaf<Db: KVDatabase>(
+        &self,
+        db: &NodeDb<Db>,
+        mut f: impl FnMut(NodePath, ILeafNode<'_>) -> ControlFlow<()>,
+    ) -> Result<usize, H, Db> {
+        let mut stream = self.stream(db, TraversalOrder::DepthFirst);
+        let mut visited = 0;
+        while let Some(next) = stream.next_node() {
+            let (path, node) = next?;
+            if let Some(leaf) = node.as_leaf() {
+                visited += 1;
+                if f(path, leaf).is_break() {
+                    break;
+                }
+            }
+        }
+        Ok(visited)
+    }
+}
+
+impl<H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> ZkTrieStream<'_, H, Db, K, P> {
+    /// Advance to the next node and lend it with its path.
+    ///
+    /// The previous node is dropped first, so its bytes are released.
+    pub fn next_node(&mut self) -> Option<LentNode<'_, H, Db>> {
+        self.current = None;
+        let (node_hash, path) = match self.order {
+            TraversalOrder::DepthFirst => self.pending.pop_back()?,
+            TraversalOrder::BreadthFirst => self.pending.pop_front()?,
+        };
+        let node = match self.trie.get_node_by_hash(self.db, node_hash) {
+            Ok(node) => node,
+            Err(e) => return Some(Err(e)),
+        };
+        if let Some(branch) = node.as_branch() {
+            let (left, right) = (
+                (branch.child_left(), path.child(false)),
+                (branch.child_right(), path.child(true)),
+            );
+            match self.order {
+                // pushed right first, so the left subtree is visited first
+                TraversalOrder::DepthFirst => self.pending.extend([right, left]),
+                TraversalOrder::BreadthFirst => self.pending.extend([left, right]),
+            }
+        }
+        Some(Ok((path, self.current.insert(node))))
+    }
+
+    /// Number of subtrees waiting to be visited.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Get the traversal order.
+    #[inline]
+    pub fn order(&self) -> TraversalOrder {
+        self.order
+    }
+}
+
+impl<H: HashScheme, Db: KVDatabase, K: KeyHasher<H>, P: PathOrder> Debug
+    for ZkTrieStream<'_, H, Db, K, P>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZkTrieStream")
+            .field("trie", &self.trie)
+            .field("order", &self.order)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
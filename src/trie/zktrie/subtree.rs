@@ -0,0 +1,253 @@
+//! Export and import of the subtree under a path prefix, see [`ZkTrie::export_subtree`].
+//!
+//! An export holds the branches on the path from the root to the subtree,
+//! followed by the nodes of the subtree:
+//!
+//! ```text
+//! header: magic (8 bytes) | version (u32 le) | depth (u32 le) | anchor (u32 le) | reserved (4 bytes)
+//!         | root | prefix
+//! record: length (u32 le) | canonical node bytes
+//! ```
+//!
+//! The first `anchor` records are the branches from the root down the prefix,
+//! the subtree nodes come children first and end with the subtree root.
+use super::{imp::Result, proof::parse_node, NodePath, PathOrder, ProofError, ZkTrie, ZkTrieError};
+use crate::{
+    db::{kv::KVDatabase, DbError, NodeDb},
+    hash::{key_hasher::KeyHasher, HashScheme, ZkHash, HASH_SIZE},
+    trie::{Node, MAX_VALUE_PREIMAGES},
+    HashSet,
+};
+use std::io::{self, Read, Write};
+
+/// The magic bytes at the start of a subtree export.
+pub const SUBTREE_MAGIC: &[u8; 8] = b"ZKSUBTR\0";
+
+/// The current subtree export format version.
+pub const SUBTREE_VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 24 + 2 * HASH_SIZE;
+
+/// A node read from an export, with its hash.
+type Record<H> = (ZkHash, Node<H>);
+
+impl<H: HashScheme, K: KeyHasher<H>, P: PathOrder> ZkTrie<H, K, P> {
+    /// Write the nodes under `prefix` into `writer`, with the branches anchoring them to the root,
+    /// to be grafted into another copy of the trie with [`ZkTrie::import_subtree`].
+    ///
+    /// If the path ends in a leaf or an empty node above the prefix depth,
+    /// that node is the whole subtree.
+    ///
+    /// The trie must be committed. Returns the number of nodes written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix` is deeper than [`HashScheme::TRIE_MAX_LEVELS`].
+    #[instrument(level = "trace", skip_all)]
+    pub fn export_subtree<Db: KVDatabase, W: Write>(
+        &self,
+        db: &NodeDb<Db>,
+        prefix: &NodePath,
+        mut writer: W,
+    ) -> Result<usize, H, Db> {
+        assert!(
+            prefix.depth() <= H::TRIE_MAX_LEVELS,
+            "prefix must be at most {} levels deep",
+            H::TRIE_MAX_LEVELS
+        );
+        if self.is_dirty() {
+            warn!("dirty nodes found, commit before exporting a subtree");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let root = *self.root.unwrap_ref();
+        let mut anchor = Vec::new();
+        let mut subtree_root = root;
+        for level in 0..prefix.depth() {
+            let node = self.get_node_by_hash(db, subtree_root)?;
+            let Some(branch) = node.as_branch() else {
+                break;
+            };
+            let child = if prefix.bit(level) {
+                branch.child_right()
+            } else {
+                branch.child_left()
+            };
+            subtree_root = *child.unwrap_ref();
+            anchor.push(node.canonical_value(true));
+        }
+
+        let io_err = |e: io::Error| ZkTrieError::other(e);
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(SUBTREE_MAGIC);
+        header.extend_from_slice(&SUBTREE_VERSION.to_le_bytes());
+        header.extend_from_slice(&(prefix.depth() as u32).to_le_bytes());
+        header.extend_from_slice(&(anchor.len() as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(root.as_slice());
+        header.extend_from_slice(prefix.bits.as_slice());
+        writer.write_all(&header).map_err(io_err)?;
+        let mut nodes = anchor.len();
+        for bytes in anchor {
+            write_record(&mut writer, &bytes).map_err(io_err)?;
+        }
+
+        // children first, `true` once the children of the node are written
+        let mut stack = vec![(subtree_root, false)];
+        while let Some((node_hash, expanded)) = stack.pop() {
+            if node_hash.is_zero() {
+                continue;
+            }
+            let node = self.get_node_by_hash(db, node_hash)?;
+            match node.as_branch() {
+                Some(branch) if !expanded => {
+                    stack.push((node_hash, true));
+                    stack.push((*branch.child_right().unwrap_ref(), false));
+                    stack.push((*branch.child_left().unwrap_ref(), false));
+                }
+                _ => {
+                    write_record(&mut writer, &node.canonical_value(true)).map_err(io_err)?;
+                    nodes += 1;
+                }
+            }
+        }
+        writer.flush().map_err(io_err)?;
+        trace!("exported {nodes} subtree nodes");
+        Ok(nodes)
+    }
+}
+
+impl<H: HashScheme> ZkTrie<H> {
+    /// Insert the nodes of a subtree written by [`ZkTrie::export_subtree`] into `db`.
+    ///
+    /// The export must be of the trie at `root`: the anchoring branches must link up
+    /// from `root` along the prefix, and the subtree nodes must hash up to the subtree root
+    /// they point to, otherwise [`ZkTrieError::InvalidProof`] is returned and nothing is written.
+    /// Nodes already in `db` are skipped.
+    ///
+    /// Returns the subtree root and the number of nodes inserted.
+    #[instrument(level = "trace", skip_all)]
+    pub fn import_subtree<Db: KVDatabase, R: Read>(
+        db: &mut NodeDb<Db>,
+        root: ZkHash,
+        mut reader: R,
+    ) -> Result<(ZkHash, usize), H, Db> {
+        let mut header = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header).map_err(ProofError::Io)?;
+        let word = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap()) as usize;
+        if &header[..SUBTREE_MAGIC.len()] != SUBTREE_MAGIC || word(8) != SUBTREE_VERSION as usize {
+            return Err(ProofError::InvalidMagic.into());
+        }
+        let (depth, anchor) = (word(12), word(16));
+        if depth > H::TRIE_MAX_LEVELS {
+            return Err(ProofError::MaxLevelReached.into());
+        }
+        if anchor > depth {
+            return Err(ProofError::InvalidMagic.into());
+        }
+        let found_root = ZkHash::from_slice(&header[24..24 + HASH_SIZE]);
+        if found_root != root {
+            return Err(ProofError::HashMismatch {
+                expected: root,
+                actual: found_root,
+            }
+            .into());
+        }
+        let prefix = NodePath {
+            depth,
+            bits: ZkHash::from_slice(&header[24 + HASH_SIZE..]),
+        };
+
+        let mut nodes = Vec::new();
+        let mut expected = root;
+        for level in 0..anchor {
+            let (node_hash, node) =
+                read_record::<H, _>(&mut reader, nodes.len())?.ok_or(ProofError::Incomplete)?;
+            if node_hash != expected {
+                return Err(ProofError::HashMismatch {
+                    expected,
+                    actual: node_hash,
+                }
+                .into());
+            }
+            let branch = node
+                .as_branch()
+                .ok_or(ProofError::UnexpectedNodeType(level))?;
+            let child = if prefix.bit(level) {
+                branch.child_right()
+            } else {
+                branch.child_left()
+            };
+            expected = *child.unwrap_ref();
+            nodes.push((node_hash, node));
+        }
+        let subtree_root = expected;
+
+        let mut subtree = HashSet::new();
+        while let Some((node_hash, node)) = read_record::<H, _>(&mut reader, nodes.len())? {
+            if let Some(branch) = node.as_branch() {
+                for child in [branch.child_left(), branch.child_right()] {
+                    let child = child.unwrap_ref();
+                    if !child.is_zero() && !subtree.remove(child) {
+                        return Err(ProofError::MissingNode(*child).into());
+                    }
+                }
+            }
+            subtree.insert(node_hash);
+            nodes.push((node_hash, node));
+        }
+        // every node is consumed by its parent, except the subtree root
+        match subtree.into_iter().collect::<Vec<_>>().as_slice() {
+            [] if subtree_root.is_zero() => {}
+            [node_hash] if *node_hash == subtree_root => {}
+            _ => return Err(ProofError::InvalidLeaves.into()),
+        }
+        // the anchor only stops above the prefix depth at a terminal node
+        if anchor < depth
+            && nodes
+                .iter()
+                .any(|(node_hash, node)| *node_hash == subtree_root && node.is_branch())
+        {
+            return Err(ProofError::UnexpectedNodeType(anchor).into());
+        }
+
+        let mut inserted = 0;
+        for (node_hash, node) in nodes {
+            if db
+                .inner()
+                .contains_key(node_hash.as_slice())
+                .map_err(DbError::classify::<Db>)?
+            {
+                continue;
+            }
+            db.put_node(node).map_err(DbError::classify::<Db>)?;
+            inserted += 1;
+        }
+        trace!("imported {inserted} subtree nodes");
+        Ok((subtree_root, inserted))
+    }
+}
+
+fn write_record<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+/// Read and hash the next record, `None` at the end of the stream.
+fn read_record<H: HashScheme, R: Read>(
+    reader: &mut R,
+    index: usize,
+) -> std::result::Result<Option<Record<H>>, ProofError<H::Error>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    let node = parse_node::<H>(&bytes, MAX_VALUE_PREIMAGES, index)?;
+    let node_hash = *node
+        .get_or_calculate_node_hash()
+        .map_err(ProofError::Hash)?;
+    Ok(Some((node_hash, node)))
+}
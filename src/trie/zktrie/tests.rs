@@ -1,21 +1,21 @@
 use super::*;
 use crate::db::kv::{HashMapDb, KVDatabase};
-use crate::hash::poseidon::tests::gen_random_bytes;
 use rand::random;
-use rand::seq::SliceRandom;
-use zktrie::HashField;
-use zktrie_rust::{db::SimpleDb, hash::AsHash, types::TrieHashScheme};
-
-type NodeOld = zktrie_rust::types::Node<AsHash<HashField>>;
-type TrieOld =
-    zktrie_rust::raw::ZkTrieImpl<AsHash<HashField>, SimpleDb, { Poseidon::TRIE_MAX_LEVELS }>;
-fn new_trie_old() -> TrieOld {
-    TrieOld::new_zktrie_impl(SimpleDb::new()).unwrap()
-}
+#[cfg(feature = "compat-tests")]
+use {
+    crate::compat::{
+        assert_same_root, new_legacy_trie, LegacyNode as NodeOld, LegacyTrie as TrieOld, Op,
+    },
+    crate::hash::poseidon::tests::gen_random_bytes,
+    rand::seq::SliceRandom,
+    zktrie::HashField,
+    zktrie_rust::{hash::AsHash, types::TrieHashScheme},
+};
 
+#[cfg(feature = "compat-tests")]
 #[test]
 fn test_simple() {
-    let mut old_trie = new_trie_old();
+    let mut old_trie = new_legacy_trie();
 
     let mut trie_db = NodeDb::default();
     let mut trie = ZkTrie::default();
@@ -33,6 +33,23 @@ fn test_simple() {
     assert_eq!(old_trie.root().as_ref(), trie.root.unwrap_ref().as_slice());
 }
 
+#[cfg(feature = "compat-tests")]
+#[test]
+fn test_simple_ops() {
+    let k = [1u8; 32];
+    let v = vec![[1u8; 32], [2u8; 32], [3u8; 32]];
+    assert_same_root([
+        Op::Update {
+            key: k,
+            values: v,
+            compression_flags: 1,
+        },
+        Op::Delete { key: [2u8; 32] },
+        Op::Delete { key: k },
+    ]);
+}
+
+#[cfg(feature = "compat-tests")]
 #[test]
 fn test_randoms() {
     for _ in 0..10 {
@@ -40,8 +57,9 @@ fn test_randoms() {
     }
 }
 
+#[cfg(feature = "compat-tests")]
 fn test_random() {
-    let mut old_trie = new_trie_old();
+    let mut old_trie = new_legacy_trie();
 
     let mut trie_db = NodeDb::default();
     let mut trie = ZkTrie::default();
@@ -141,6 +159,7 @@ fn test_random() {
     assert_eq!(old_trie.root().as_ref(), trie.root.unwrap_ref().as_slice());
 }
 
+#[cfg(feature = "compat-tests")]
 #[allow(dead_code)]
 fn print_old_trie(trie: &TrieOld, hash: AsHash<HashField>, level: usize) {
     use zktrie_rust::types::NodeType::*;
@@ -216,3 +235,4337 @@ impl<H: HashScheme, K: KeyHasher<H>> ZkTrie<H, K> {
         Ok(())
     }
 }
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_snapshot() {
+    use crate::db::kv::MmapDb;
+    use alloy_primitives::bytes::Bytes;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    assert!(matches!(
+        trie.export_snapshot(&trie_db, Vec::new()),
+        Err(ZkTrieError::UncommittedChanges)
+    ));
+    trie.commit(&mut trie_db).unwrap();
+
+    let mut buffer = Vec::new();
+    let nodes = trie.export_snapshot(&trie_db, &mut buffer).unwrap();
+    assert_eq!(
+        nodes,
+        trie.iter(&trie_db)
+            .filter(|n| matches!(n, Ok(crate::trie::INode::Archived(_))))
+            .count()
+    );
+
+    // snapshot must be viewed from an aligned address
+    let mut aligned = rkyv::util::AlignedVec::<16>::new();
+    aligned.extend_from_slice(&buffer);
+    let mut snapshot_db = NodeDb::new(MmapDb::from_bytes(Bytes::from_owner(aligned)).unwrap());
+    assert_eq!(snapshot_db.inner().len(), nodes);
+
+    let root = *trie.root().unwrap_ref();
+    let mut snapshot_trie = ZkTrie::<Poseidon>::new_with_root(
+        &snapshot_db,
+        NoCacheHasher,
+        snapshot_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    for k in keys.iter() {
+        let value: Option<[[u8; 32]; 1]> = snapshot_trie.get(&snapshot_db, k).unwrap();
+        assert_eq!(value, Some([*k]));
+    }
+
+    // writes are rejected, and not worth retrying
+    snapshot_trie
+        .raw_update(&snapshot_db, [1u8; 32], vec![[1u8; 32]], 1)
+        .unwrap();
+    match snapshot_trie.commit(&mut snapshot_db) {
+        Err(ZkTrieError::Db(e)) => {
+            assert_eq!(e.kind(), crate::db::DbErrorKind::Other);
+            assert!(!e.is_retryable());
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+}
+
+#[test]
+fn test_to_dot() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    let empty = trie.to_dot(&trie_db, usize::MAX).unwrap();
+    assert!(empty.starts_with("digraph ZkTrie {"));
+    assert!(empty.contains("n0 [label=\"\", shape=point];"));
+
+    for _ in 0..10 {
+        let k: [u8; 32] = random();
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+    }
+    let dirty = trie.to_dot(&trie_db, usize::MAX).unwrap();
+    assert!(dirty.contains("style=dashed"));
+
+    trie.commit(&mut trie_db).unwrap();
+    let dot = trie.to_dot(&trie_db, usize::MAX).unwrap();
+    assert!(!dot.contains("style=dashed"));
+    assert_eq!(dot.matches("shape=box").count(), 10);
+    assert_eq!(dot, trie.to_dot(&trie_db, usize::MAX).unwrap());
+
+    let root_only = trie.to_dot(&trie_db, 0).unwrap();
+    assert_eq!(root_only.matches("label=\"...\"").count(), 2);
+}
+
+#[test]
+fn test_get_traced() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    let keys: Vec<[u8; 32]> = (0..10).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+    }
+
+    let (value, stats) = trie
+        .get_traced::<_, [[u8; 32]; 1], _>(&trie_db, keys[0])
+        .unwrap();
+    assert_eq!(value, Some([[1u8; 32]]));
+    assert_eq!(stats.db, 0);
+    assert_eq!(stats.dirty_leafs, 1);
+    assert!(stats.dirty_branch_nodes > 0);
+
+    trie.commit(&mut trie_db).unwrap();
+    let (value, stats) = trie
+        .get_traced::<_, [[u8; 32]; 1], _>(&trie_db, keys[0])
+        .unwrap();
+    assert_eq!(value, Some([[1u8; 32]]));
+    assert_eq!(stats.dirty_leafs + stats.dirty_branch_nodes, 0);
+    assert_eq!(stats.db, stats.total());
+}
+
+#[test]
+fn test_contains_key() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        assert!(!trie.contains_key(&trie_db, k).unwrap());
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+        assert!(trie.contains_key(&trie_db, k).unwrap());
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    for k in keys.iter() {
+        assert!(trie.contains_key(&trie_db, k).unwrap());
+    }
+    for _ in 0..20 {
+        let k: [u8; 32] = random();
+        assert!(!trie.contains_key(&trie_db, k).unwrap());
+    }
+
+    trie.delete(&trie_db, keys[0]).unwrap();
+    assert!(!trie.contains_key(&trie_db, keys[0]).unwrap());
+}
+
+#[test]
+fn test_op_receipt() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    let k1: [u8; 32] = random();
+    let receipt = trie
+        .raw_update_with_receipt(&trie_db, k1, vec![[1u8; 32]], 1)
+        .unwrap();
+    assert_eq!(receipt.reads.empty, 1);
+    assert_eq!(receipt.nodes_written, 1);
+    // key hash, compressed value, leaf node hash
+    assert_eq!(receipt.hashes, 3);
+    assert_eq!(receipt.bytes_hashed, 64);
+
+    let k2: [u8; 32] = random();
+    let receipt = trie
+        .raw_update_with_receipt(&trie_db, k2, vec![[1u8; 32]], 1)
+        .unwrap();
+    assert_eq!(receipt.reads.dirty_leafs, 1);
+    assert!(receipt.nodes_written >= 2);
+    assert_eq!(receipt.hashes, 3 + receipt.nodes_written - 1);
+    trie.commit(&mut trie_db).unwrap();
+
+    let (_, receipt) = trie.prove_with_receipt(&trie_db, k1).unwrap();
+    assert_eq!(receipt.nodes_written, 0);
+    assert_eq!(receipt.hashes, 1);
+    assert_eq!(receipt.reads.db, receipt.reads.total());
+
+    let (deleted, receipt) = trie.delete_with_receipt(&trie_db, k1).unwrap();
+    assert!(deleted);
+    assert!(receipt.reads.db > 0);
+    assert_eq!(receipt.nodes_written, 0);
+    assert_eq!(receipt.hashes, 1);
+}
+
+#[test]
+fn test_path_cache() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    let keys: Vec<[u8; 32]> = (0..100).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    let root = *trie.root().unwrap_ref();
+    let mut trie = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap()
+    .with_path_cache(&trie_db, 4)
+    .unwrap();
+    assert_eq!(trie.path_cache_depth(), Some(4));
+
+    for k in keys.iter() {
+        let (value, stats) = trie.get_traced::<_, [[u8; 32]; 1], _>(&trie_db, k).unwrap();
+        assert_eq!(value, Some([[1u8; 32]]));
+        assert!(stats.path_cache > 0);
+    }
+    for _ in 0..20 {
+        let k: [u8; 32] = random();
+        assert!(!trie.contains_key(&trie_db, k).unwrap());
+    }
+
+    // cache is refreshed on commit
+    for k in keys.iter().take(50) {
+        trie.raw_update(&trie_db, k, vec![[2u8; 32]], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let mut reference = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    for k in keys.iter().take(50) {
+        reference
+            .raw_update(&trie_db, k, vec![[2u8; 32]], 1)
+            .unwrap();
+    }
+    reference.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.root().unwrap_ref(), reference.root().unwrap_ref());
+    for (i, k) in keys.iter().enumerate() {
+        let value: Option<[[u8; 32]; 1]> = trie.get(&trie_db, k).unwrap();
+        let expected = if i < 50 { [2u8; 32] } else { [1u8; 32] };
+        assert_eq!(value, Some([expected]));
+    }
+
+    // the cache is updated from the changed paths, same as building it again
+    let assert_rebuilt = |trie: &ZkTrie, trie_db: &NodeDb<HashMapDb>| {
+        let rebuilt = ZkTrie::<Poseidon>::new_with_root(
+            trie_db,
+            NoCacheHasher,
+            trie_db.committed_root(*trie.root().unwrap_ref()).unwrap(),
+        )
+        .unwrap()
+        .with_path_cache(trie_db, 4)
+        .unwrap();
+        let (cache, rebuilt) = (
+            trie.path_cache.as_ref().unwrap(),
+            rebuilt.path_cache.unwrap(),
+        );
+        assert_eq!(cache.subtrees, rebuilt.subtrees);
+        let branches: HashSet<_> = cache.branches.keys().collect();
+        assert_eq!(branches, rebuilt.branches.keys().collect());
+    };
+    assert_rebuilt(&trie, &trie_db);
+    for k in keys.iter().skip(10) {
+        trie.delete(&trie_db, k).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    assert_rebuilt(&trie, &trie_db);
+    for k in keys.iter().skip(10).take(5) {
+        trie.raw_update(&trie_db, k, vec![[3u8; 32]], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    assert_rebuilt(&trie, &trie_db);
+    for k in keys.iter().take(15) {
+        trie.delete(&trie_db, k).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    assert!(trie.root().unwrap_ref().is_zero());
+    assert_rebuilt(&trie, &trie_db);
+}
+
+#[test]
+fn test_leaf_observer() {
+    use std::sync::{Arc, Mutex};
+
+    type Changes = Arc<Mutex<Vec<(ZkHash, Option<Vec<[u8; 32]>>, Option<Vec<[u8; 32]>>)>>>;
+    struct Recorder(Changes);
+    impl LeafObserver for Recorder {
+        fn on_leaf_change(
+            &mut self,
+            node_key: &ZkHash,
+            old_value: Option<&[[u8; 32]]>,
+            new_value: Option<&[[u8; 32]]>,
+        ) {
+            self.0.lock().unwrap().push((
+                *node_key,
+                old_value.map(|v| v.to_vec()),
+                new_value.map(|v| v.to_vec()),
+            ));
+        }
+    }
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let changes = Changes::default();
+    trie.set_leaf_observer(Recorder(changes.clone()));
+
+    let keys: Vec<[u8; 32]> = (0..10).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+        trie.raw_update(&trie_db, k, vec![[2u8; 32]], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    {
+        let mut changes = changes.lock().unwrap();
+        assert_eq!(changes.len(), keys.len());
+        assert!(changes.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(changes
+            .iter()
+            .all(|(_, old, new)| old.is_none() && new == &Some(vec![[2u8; 32]])));
+        changes.clear();
+    }
+
+    // no-op changes are not reported
+    trie.raw_update(&trie_db, keys[0], vec![[3u8; 32]], 1)
+        .unwrap();
+    trie.raw_update(&trie_db, keys[0], vec![[2u8; 32]], 1)
+        .unwrap();
+    trie.delete(&trie_db, keys[1]).unwrap();
+    trie.commit(&mut trie_db).unwrap();
+
+    let changes = changes.lock().unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].1, Some(vec![[2u8; 32]]));
+    assert_eq!(changes[0].2, None);
+}
+
+#[test]
+fn test_iter_order() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    let keys: Vec<[u8; 32]> = (0..50).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+    }
+    trie.delete(&trie_db, keys[0]).unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.leaf_count(), Some(keys.len() - 1));
+
+    let mut leaf_paths = vec![];
+    for item in trie.iter(&trie_db).with_paths() {
+        let (path, node) = item.unwrap();
+        if let Some(leaf) = node.as_leaf() {
+            assert!(path.is_prefix_of(&leaf.node_key()));
+            leaf_paths.push((0..path.depth()).map(|l| path.bit(l)).collect::<Vec<_>>());
+        }
+    }
+    assert_eq!(leaf_paths.len(), keys.len() - 1);
+    assert!(leaf_paths.windows(2).all(|w| w[0] < w[1]));
+
+    let leafs = trie.exact_leafs(&trie_db).unwrap();
+    assert_eq!(leafs.len(), keys.len() - 1);
+    assert_eq!(leafs.count(), keys.len() - 1);
+
+    let root = *trie.root().unwrap_ref();
+    let mut reopened = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    assert!(reopened.exact_leafs(&trie_db).is_none());
+    assert_eq!(reopened.count_leafs(&trie_db).unwrap(), keys.len() - 1);
+    assert_eq!(reopened.leafs(&trie_db).size_hint().1, Some(keys.len() - 1));
+}
+
+#[test]
+fn test_recover_key_preimages() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    let mut preimages = HashMap::new();
+    for _ in 0..20 {
+        let k: [u8; 32] = random();
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+        let node_key = <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &k).unwrap();
+        preimages.insert(node_key, k);
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let root = *trie.root().unwrap_ref();
+
+    let recovered = trie
+        .recover_key_preimages(&mut trie_db, |node_key| preimages.get(node_key).copied())
+        .unwrap();
+    assert_eq!(recovered, preimages.len());
+
+    let trie = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    for (node_key, k) in preimages.iter() {
+        let node = trie.get_node_by_key(&trie_db, node_key).unwrap();
+        assert_eq!(node.as_leaf().unwrap().node_key_preimage(), Some(k));
+    }
+    // already recovered leafs are skipped
+    let recovered = trie
+        .recover_key_preimages(&mut trie_db, |node_key| preimages.get(node_key).copied())
+        .unwrap();
+    assert_eq!(recovered, 0);
+}
+
+#[test]
+fn test_shared_node_db() {
+    let account_db = NodeDb::new(HashMapDb::default()).into_shared();
+    let mut account_trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..10).map(|_| random()).collect();
+    for k in keys.iter() {
+        account_trie
+            .raw_update(&account_db, *k, vec![[0u8; 32]], 1)
+            .unwrap();
+    }
+    let mut account_db = account_db;
+    account_trie.commit(&mut account_db).unwrap();
+
+    // commit storage tries through a second handle while iterating the account trie
+    let mut storage_db = account_db.handle();
+    let mut storage_roots = Vec::new();
+    for leaf in account_trie.leafs(&account_db) {
+        let leaf = leaf.unwrap();
+        let mut storage_trie = ZkTrie::default();
+        let value = leaf.as_leaf().unwrap().value_preimages()[0];
+        storage_trie
+            .raw_update(&storage_db, [1u8; 32], vec![value, [2u8; 32]], 1)
+            .unwrap();
+        storage_trie.commit(&mut storage_db).unwrap();
+        storage_roots.push(*storage_trie.root().unwrap_ref());
+    }
+
+    for root in storage_roots {
+        let storage_trie = ZkTrie::<Poseidon>::new_with_root(
+            &account_db,
+            NoCacheHasher,
+            account_db.committed_root(root).unwrap(),
+        )
+        .unwrap();
+        let node = storage_trie
+            .get_node_by_key(
+                &account_db,
+                &<NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &[1u8; 32]).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(node.as_leaf().unwrap().value_preimages()[1], [2u8; 32]);
+    }
+}
+
+#[test]
+#[should_panic(expected = "leaf key filter")]
+fn test_shared_node_db_with_filter() {
+    let _ = NodeDb::new(HashMapDb::default())
+        .with_leaf_filter(crate::db::filter::LeafKeyFilter::new(16, 0.01))
+        .into_shared();
+}
+
+#[test]
+fn test_prove_into() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let root = *trie.root().unwrap_ref();
+
+    let hash = |k: &[u8; 32]| <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, k);
+    for k in keys.iter() {
+        let mut stream = Vec::new();
+        let written = trie.prove_into(&trie_db, k, &mut stream).unwrap();
+        assert_eq!(written, stream.len());
+        assert_eq!(stream, trie.prove(&trie_db, k).unwrap().concat());
+
+        let leaf = verify_proof_stream::<Poseidon, _>(&root, &hash(k).unwrap(), stream.as_slice())
+            .unwrap()
+            .unwrap();
+        assert_eq!(leaf.as_leaf().unwrap().value_preimages(), &[*k]);
+
+        // tampered proofs are rejected
+        let mut tampered = stream.clone();
+        tampered[1] ^= 1;
+        assert!(
+            verify_proof_stream::<Poseidon, _>(&root, &hash(k).unwrap(), tampered.as_slice())
+                .is_err()
+        );
+        let truncated = &stream[..stream.len() - 1];
+        assert!(verify_proof_stream::<Poseidon, _>(&root, &hash(k).unwrap(), truncated).is_err());
+    }
+
+    let absent = [0u8; 32];
+    let mut stream = Vec::new();
+    trie.prove_into(&trie_db, absent, &mut stream).unwrap();
+    let result =
+        verify_proof_stream::<Poseidon, _>(&root, &hash(&absent).unwrap(), stream.as_slice());
+    assert!(result.unwrap().is_none());
+}
+
+#[test]
+fn test_estimate_witness() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..50).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k; 3], 0b111).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    let witness_keys = keys.iter().step_by(3).chain([&[0u8; 32]]);
+    let mut witness = HashSet::new();
+    for k in witness_keys.clone() {
+        let proof = trie.prove(&trie_db, k).unwrap();
+        witness.extend(
+            proof
+                .into_iter()
+                .filter(|n| n != crate::trie::MAGIC_NODE_BYTES),
+        );
+    }
+    let estimate = trie.estimate_witness(&trie_db, witness_keys).unwrap();
+    assert_eq!(estimate.nodes, witness.len());
+    assert_eq!(estimate.bytes, witness.iter().map(Vec::len).sum::<usize>());
+
+    let empty = trie.estimate_witness::<_, [u8; 32]>(&trie_db, []).unwrap();
+    assert_eq!(empty, WitnessEstimate::default());
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_metrics() {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    metrics::with_local_recorder(&recorder, || {
+        crate::metrics::describe();
+        let mut trie_db = NodeDb::new(HashMapDb::new(true));
+        let mut trie = ZkTrie::default();
+        for i in 0..10u8 {
+            trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+                .unwrap();
+        }
+        trie.commit(&mut trie_db).unwrap();
+        trie.delete(&trie_db, [0u8; 32]).unwrap();
+        trie.get::<_, [[u8; 32]; 1], _>(&trie_db, [1u8; 32])
+            .unwrap();
+        trie.commit(&mut trie_db).unwrap();
+        trie.gc(&mut trie_db).unwrap();
+    });
+
+    let metrics = snapshotter.snapshot().into_vec();
+    let value = |name: &str, op: Option<&str>| {
+        metrics
+            .iter()
+            .find(|(key, ..)| {
+                key.key().name() == name
+                    && op.map_or(true, |op| key.key().labels().any(|l| l.value() == op))
+            })
+            .map(|(.., value)| value)
+            .unwrap()
+    };
+    assert_eq!(
+        value(crate::metrics::ROOT_VERSION, None),
+        &DebugValue::Gauge(2.0.into())
+    );
+    assert_eq!(
+        value(crate::metrics::DIRTY_NODES, None),
+        &DebugValue::Gauge(0.0.into())
+    );
+    assert!(matches!(
+        value(crate::metrics::GC_REMOVED_NODES, None),
+        DebugValue::Counter(removed) if *removed > 0
+    ));
+    assert!(matches!(
+        value(crate::metrics::COMMIT_DURATION, None),
+        DebugValue::Histogram(durations) if durations.len() == 2
+    ));
+    for (op, count) in [("update", 10), ("delete", 1), ("get", 1)] {
+        assert!(matches!(
+            value(crate::metrics::DB_READS, Some(op)),
+            DebugValue::Histogram(reads) if reads.len() == count
+        ));
+    }
+}
+
+#[test]
+fn test_into_owned() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    for i in 0..10u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    for node in trie.iter(&trie_db) {
+        let node = node.unwrap();
+        // empty nodes are never stored
+        let INode::Archived(viewer) = &node else {
+            continue;
+        };
+        let converted = Node::<Poseidon>::from(viewer.view());
+        let owned = node.clone().into_owned();
+        // hash cells are restored, not recomputed
+        assert_eq!(
+            owned.node_hash.get(),
+            Some(node.get_or_calculate_node_hash().unwrap())
+        );
+        if let Some(leaf) = owned.as_leaf() {
+            let archived = viewer.view().as_leaf().unwrap();
+            assert_eq!(leaf.value_hash(), archived.value_hash());
+        }
+        for n in [&converted, &owned] {
+            assert_eq!(n.canonical_value(true), node.canonical_value(true));
+            assert_eq!(
+                n.clone().get_or_calculate_node_hash().unwrap(),
+                node.get_or_calculate_node_hash().unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_gc_cycles() {
+    let mut trie_db = NodeDb::new(HashMapDb::new(true));
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..30).map(|_| random()).collect();
+    let values: Vec<[u8; 32]> = (0..3).map(|_| random()).collect();
+
+    let live_nodes = |trie: &ZkTrie, trie_db: &NodeDb<HashMapDb>| {
+        trie.iter(trie_db)
+            .map(|node| *node.unwrap().get_or_calculate_node_hash().unwrap())
+            .filter(|node_hash| !node_hash.is_zero())
+            .collect::<HashSet<_>>()
+    };
+    let stored_nodes = |trie_db: &NodeDb<HashMapDb>| {
+        trie_db
+            .inner()
+            .inner()
+            .keys()
+            .filter(|k| k.len() == HASH_SIZE)
+            .map(|k| ZkHash::from_slice(k))
+            .collect::<HashSet<_>>()
+    };
+
+    for cycle in 0..20 {
+        for _ in 0..20 {
+            let k = &keys[random::<usize>() % keys.len()];
+            if random::<u8>() % 4 == 0 {
+                trie.delete(&trie_db, k).unwrap();
+            } else {
+                let v = values[random::<usize>() % values.len()];
+                trie.raw_update(&trie_db, k, vec![v], 1).unwrap();
+            }
+        }
+        trie.commit(&mut trie_db).unwrap();
+        let live = live_nodes(&trie, &trie_db);
+        assert!(trie
+            .retired_nodes()
+            .all(|(_, node_hash)| !live.contains(&node_hash)));
+        assert!(trie
+            .retired_nodes()
+            .all(|(version, _)| version <= trie.root_version()));
+
+        // skip gc on some cycles, retired nodes are kept until the next one
+        if cycle % 3 != 0 {
+            trie.gc(&mut trie_db).unwrap();
+            assert_eq!(trie.retired_nodes().count(), 0);
+            // no live node is collected, no dead node is leaked
+            assert_eq!(stored_nodes(&trie_db), live);
+        }
+    }
+
+    // deleting everything leaves no dirty nodes
+    for k in keys.iter() {
+        trie.delete(&trie_db, k).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    trie.gc(&mut trie_db).unwrap();
+    assert!(trie.root().unwrap_ref().is_zero());
+    assert!(stored_nodes(&trie_db).is_empty());
+}
+
+#[test]
+fn test_shrink_dirty() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let mut expected = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..50).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    for k in keys.iter() {
+        expected.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+
+    for round in 0..3u8 {
+        for k in keys.iter().take(20) {
+            let mut v = *k;
+            v[0] = round;
+            trie.raw_update(&trie_db, k, vec![v], 1).unwrap();
+            if round == 2 {
+                expected.raw_update(&trie_db, k, vec![v], 1).unwrap();
+            }
+        }
+        for k in keys.iter().skip(40) {
+            trie.delete(&trie_db, k).unwrap();
+        }
+    }
+    for k in keys.iter().skip(40) {
+        expected.delete(&trie_db, k).unwrap();
+    }
+
+    let dirty = trie.dirty_leafs.len() + trie.dirty_branch_nodes.len();
+    let dropped = trie.shrink_dirty();
+    assert!(dropped > 0);
+    assert_eq!(
+        trie.dirty_leafs.len() + trie.dirty_branch_nodes.len(),
+        dirty - dropped
+    );
+    assert_eq!(trie.shrink_dirty(), 0);
+
+    // the trie still works on the compacted nodes
+    for k in keys.iter().take(40) {
+        assert!(trie.contains_key(&trie_db, k).unwrap());
+    }
+    trie.raw_update(&trie_db, keys[45], vec![keys[45]], 1)
+        .unwrap();
+    expected
+        .raw_update(&trie_db, keys[45], vec![keys[45]], 1)
+        .unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    expected.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.root().unwrap_ref(), expected.root().unwrap_ref());
+}
+
+#[test]
+fn test_traverse() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    assert!(trie.traverse(&trie_db, &ZkHash::ZERO).unwrap()[0]
+        .1
+        .is_terminal());
+
+    let keys: Vec<[u8; 32]> = (0..30).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    for k in keys.iter() {
+        let node_key = <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, k).unwrap();
+        let path = trie.traverse(&trie_db, &node_key).unwrap();
+        let proof = trie.prove(&trie_db, k).unwrap();
+        assert_eq!(path.len(), proof.len() - 1);
+        for (i, ((depth, node), bytes)) in path.iter().zip(proof.iter()).enumerate() {
+            assert_eq!(*depth, i);
+            assert_eq!(node.canonical_value(true), *bytes);
+            assert_eq!(node.is_branch(), i < path.len() - 1);
+        }
+        let (_, leaf) = path.last().unwrap();
+        assert_eq!(leaf.as_leaf().unwrap().node_key(), node_key);
+    }
+}
+
+#[test]
+fn test_heal() {
+    let mut source_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&source_db, k, vec![*k], 1).unwrap();
+    }
+    trie.commit(&mut source_db).unwrap();
+    let root = *trie.root().unwrap_ref();
+
+    let mut trie_db = NodeDb::default();
+    let proof = trie.prove(&source_db, keys[0]).unwrap();
+
+    let mut tampered = proof.clone();
+    tampered[1][1] ^= 1;
+    assert!(matches!(
+        ZkTrie::<Poseidon>::heal(&mut trie_db, root, &tampered),
+        Err(ZkTrieError::InvalidProof)
+    ));
+    assert!(trie_db.inner().inner().is_empty());
+
+    let inserted = ZkTrie::<Poseidon>::heal(&mut trie_db, root, &proof).unwrap();
+    assert_eq!(inserted, proof.len() - 1);
+    assert_eq!(
+        ZkTrie::<Poseidon>::heal(&mut trie_db, root, &proof).unwrap(),
+        0
+    );
+    let healed = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    let leaf = healed
+        .get_node_by_key(
+            &trie_db,
+            &<NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &keys[0]).unwrap(),
+        )
+        .unwrap();
+    assert_eq!(leaf.as_leaf().unwrap().value_preimages(), &[keys[0]]);
+
+    // proofs sharing a prefix only insert the missing nodes
+    let proof = trie.prove(&source_db, keys[1]).unwrap();
+    assert!(ZkTrie::<Poseidon>::heal(&mut trie_db, root, &proof).unwrap() < proof.len() - 1);
+}
+
+#[test]
+fn test_range_proof() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    for _ in 0..50 {
+        let k: [u8; 32] = random();
+        trie.raw_update(&trie_db, k, vec![k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let root = *trie.root().unwrap_ref();
+    let node_keys: Vec<ZkHash> = trie
+        .leafs(&trie_db)
+        .map(|leaf| leaf.unwrap().as_leaf().unwrap().node_key())
+        .collect();
+
+    let range = trie
+        .prove_range(&trie_db, &node_keys[10], &node_keys[20])
+        .unwrap();
+    assert_eq!(range.leaves.len(), 11);
+    verify_range_proof(&root, &node_keys[10], &range.leaves, &range.proof).unwrap();
+
+    // omitted leaves are detected
+    let mut leaves = range.leaves;
+    leaves.remove(5);
+    assert!(verify_range_proof(&root, &node_keys[10], &leaves, &range.proof).is_err());
+    assert!(verify_range_proof(&root, &node_keys[0], &leaves[1..], &range.proof).is_err());
+
+    // the whole trie
+    let range = trie
+        .prove_range(&trie_db, &ZkHash::ZERO, &ZkHash::repeat_byte(0xff))
+        .unwrap();
+    assert_eq!(range.leaves.len(), node_keys.len());
+    verify_range_proof(&root, &ZkHash::ZERO, &range.leaves, &range.proof).unwrap();
+
+    // no leaf after the start
+    let start = ZkHash::repeat_byte(0xff);
+    let range = trie.prove_range(&trie_db, &start, &start).unwrap();
+    assert!(range.leaves.is_empty());
+    verify_range_proof::<Poseidon, _>(&root, &start, &[], &range.proof).unwrap();
+    assert!(verify_range_proof::<Poseidon, _>(&root, &node_keys[40], &[], &range.proof).is_err());
+}
+
+#[test]
+fn test_absorb_range() {
+    let mut source_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    for _ in 0..50 {
+        let k: [u8; 32] = random();
+        trie.raw_update(&source_db, k, vec![k], 1).unwrap();
+    }
+    trie.commit(&mut source_db).unwrap();
+    let root = *trie.root().unwrap_ref();
+
+    // sync the trie in chunks of 16 leaves
+    let node_keys: Vec<ZkHash> = trie
+        .leafs(&source_db)
+        .map(|leaf| leaf.unwrap().as_leaf().unwrap().node_key())
+        .collect();
+    let mut trie_db = NodeDb::default();
+    for chunk in node_keys.chunks(16) {
+        let (start, end) = (chunk[0], chunk[chunk.len() - 1]);
+        let range = trie.prove_range(&source_db, &start, &end).unwrap();
+        assert_eq!(range.leaves.len(), chunk.len());
+        let verified = verify_range_proof(&root, &start, &range.leaves, &range.proof).unwrap();
+        assert_eq!(verified.root(), &root);
+        assert!(ZkTrie::<Poseidon>::absorb_range(&mut trie_db, verified).unwrap() > 0);
+    }
+
+    let synced = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    let mut synced_nodes = synced
+        .iter(&trie_db)
+        .map(|node| *node.unwrap().get_or_calculate_node_hash().unwrap())
+        .collect::<Vec<_>>();
+    let mut nodes = trie
+        .iter(&source_db)
+        .map(|node| *node.unwrap().get_or_calculate_node_hash().unwrap())
+        .collect::<Vec<_>>();
+    synced_nodes.sort();
+    nodes.sort();
+    assert_eq!(synced_nodes, nodes);
+}
+
+#[test]
+fn test_compact_to_canonical() {
+    use crate::db::NodeEncoding;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..100).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let root = *trie.root().unwrap_ref();
+    let proofs: Vec<_> = keys
+        .iter()
+        .map(|k| trie.prove(&trie_db, k).unwrap())
+        .collect();
+
+    let report = trie_db.compact_to_canonical::<Poseidon>().unwrap();
+    assert!(report.nodes > keys.len());
+    assert!(report.saved() > 0);
+    assert_eq!(trie_db.node_encoding(), NodeEncoding::Canonical);
+    assert_eq!(
+        trie_db.compact_to_canonical::<Poseidon>().unwrap(),
+        Default::default()
+    );
+
+    // the encoding is persisted
+    let mut trie_db = NodeDb::try_new(trie_db.into_inner()).unwrap();
+    assert_eq!(trie_db.node_encoding(), NodeEncoding::Canonical);
+    let mut trie = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    for (k, proof) in keys.iter().zip(proofs.iter()) {
+        assert_eq!(
+            trie.get::<_, [[u8; 32]; 1], _>(&trie_db, k).unwrap(),
+            Some([*k])
+        );
+        assert_eq!(trie.prove(&trie_db, k).unwrap(), *proof);
+    }
+    trie.delete(&trie_db, keys[0]).unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    assert!(!trie.contains_key(&trie_db, keys[0]).unwrap());
+
+    let back = trie_db
+        .convert_encoding::<Poseidon>(NodeEncoding::Archived)
+        .unwrap();
+    // nodes written by the commit are converted too
+    assert!(back.nodes > report.nodes);
+    assert!(back.saved() < 0);
+    let trie_db = NodeDb::try_new(trie_db.into_inner()).unwrap();
+    assert_eq!(trie_db.node_encoding(), NodeEncoding::Archived);
+    for k in keys.iter().skip(1) {
+        assert!(trie.contains_key(&trie_db, k).unwrap());
+    }
+}
+
+#[test]
+fn test_node_encoding_errors() {
+    use crate::db::NodeDbError;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let key = [1u8; 32];
+    trie.raw_update(&trie_db, key, vec![key], 1).unwrap();
+    let root = trie.commit(&mut trie_db).unwrap();
+    trie_db.compact_to_canonical::<Poseidon>().unwrap();
+
+    // a corrupted canonical node is an error, not a panic
+    let mut db = trie_db.into_inner();
+    db.put(root.hash().as_slice(), &[0xff; 3]).unwrap();
+    let trie_db = NodeDb::try_new(db).unwrap();
+    assert!(matches!(
+        trie_db.get_node::<Poseidon>(&root.hash()),
+        Err(NodeDbError::CorruptedNode(hash)) if hash == root.hash()
+    ));
+    let trie = ZkTrie::<Poseidon>::new(NoCacheHasher);
+    assert!(matches!(
+        trie.get_node_by_hash(&trie_db, root.hash()),
+        Err(ZkTrieError::CorruptedNode(hash)) if hash == root.hash()
+    ));
+
+    // nodes in mixed encodings are refused
+    let mut db = trie_db.into_inner();
+    db.put(b"zktrie-ng:node-encoding", b"converting").unwrap();
+    assert!(matches!(
+        NodeDb::try_new(db),
+        Err(NodeDbError::ConversionInterrupted)
+    ));
+    let mut db = HashMapDb::default();
+    db.put(b"zktrie-ng:node-encoding", b"zstd").unwrap();
+    assert!(matches!(
+        NodeDb::try_new(db),
+        Err(NodeDbError::UnknownEncoding(encoding)) if encoding == "zstd"
+    ));
+}
+
+#[test]
+fn test_hash_scheme_id() {
+    let mut trie_db = NodeDb::open::<Poseidon>(HashMapDb::default()).unwrap();
+    assert_eq!(trie_db.hash_scheme(), Some(Poseidon::ID));
+    let mut trie = ZkTrie::default();
+    trie.raw_update(&trie_db, [1u8; 32], vec![[1u8; 32]], 1)
+        .unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    let root = *trie.root().unwrap_ref();
+
+    // reopening with the same scheme keeps the nodes
+    let trie_db = NodeDb::open::<Poseidon>(trie_db.into_inner()).unwrap();
+    ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+
+    let mut kv = trie_db.into_inner();
+    kv.put(b"zktrie-ng:hash-scheme", b"other").unwrap();
+    assert!(matches!(
+        NodeDb::open::<Poseidon>(kv),
+        Err(crate::db::NodeDbError::HashSchemeMismatch { found, .. }) if found == "other"
+    ));
+}
+
+#[test]
+fn test_committed_root() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    assert_eq!(trie.commit(&mut trie_db).unwrap(), CommittedRoot::EMPTY);
+
+    trie.raw_update(&trie_db, [1u8; 32], vec![[1u8; 32]], 1)
+        .unwrap();
+    // the root of a dirty trie was never persisted
+    assert!(trie_db.committed_root(*trie.root().unwrap_ref()).is_err());
+    let root = trie.commit(&mut trie_db).unwrap();
+    assert_eq!(root.hash(), *trie.root().unwrap_ref());
+    assert_eq!(trie.commit(&mut trie_db).unwrap(), root);
+    assert_eq!(trie_db.committed_root(root.hash()).unwrap(), root);
+
+    let reopened = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+    assert_eq!(reopened.root(), trie.root());
+
+    // a hash from elsewhere is rejected before opening the trie
+    let other_db = NodeDb::default();
+    assert!(matches!(
+        other_db.committed_root(root.hash()),
+        Err(crate::db::NodeDbError::NodeNotFound(hash)) if hash == root.hash()
+    ));
+    assert_eq!(
+        other_db.committed_root(ZkHash::ZERO).unwrap(),
+        CommittedRoot::EMPTY
+    );
+}
+
+#[test]
+fn test_journal() {
+    let trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    trie.enable_journal();
+
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k, [1u8; 32]], 0b11)
+            .unwrap();
+    }
+    for k in keys.iter().take(5) {
+        trie.delete(&trie_db, k).unwrap();
+    }
+    // deleting a missing key is recorded too
+    assert!(!trie.delete(&trie_db, [0u8; 32]).unwrap());
+
+    let journal = trie.take_journal().unwrap();
+    assert_eq!(journal.len(), 26);
+    assert_eq!(journal.entries()[0].pre_root, ZkHash::ZERO);
+    assert!(journal
+        .entries()
+        .windows(2)
+        .all(|w| w[0].post_root == w[1].pre_root));
+    let last = journal.entries().last().unwrap();
+    assert_eq!(last.pre_root, last.post_root);
+
+    let mut trie_db = trie_db;
+    let root = trie.commit(&mut trie_db).unwrap();
+    assert_eq!(last.post_root, root.hash());
+
+    let mut replayed = ZkTrie::default();
+    assert_eq!(replayed.replay(&trie_db, &journal).unwrap(), None);
+    assert_eq!(replayed.commit(&mut trie_db).unwrap(), root);
+
+    // a diverging update is found at its index
+    let mut entries = journal.entries().to_vec();
+    if let JournalOp::Update {
+        value_preimages, ..
+    } = &mut entries[7].op
+    {
+        value_preimages[1] = [2u8; 32];
+    }
+    let tampered = OpJournal::from(entries);
+    let mut replayed = ZkTrie::default();
+    assert_eq!(replayed.replay(&trie_db, &tampered).unwrap(), Some(7));
+    // the replay stops before an entry not starting at the current root
+    assert_eq!(replayed.replay(&trie_db, &tampered).unwrap(), Some(0));
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&journal).unwrap();
+        assert_eq!(serde_json::from_str::<OpJournal>(&json).unwrap(), journal);
+    }
+}
+
+#[cfg(feature = "key-cache")]
+#[test]
+fn test_dyn_key_hasher() {
+    use crate::hash::key_hasher::{DynKeyHasher, SyncCachedKeyHasher};
+
+    let mut trie_db = NodeDb::default();
+    let mut roots = Vec::new();
+    for cached in [false, true] {
+        let key_hasher: DynKeyHasher = if cached {
+            Box::new(SyncCachedKeyHasher::<Poseidon>::new(HashMapDb::default()))
+        } else {
+            Box::new(NoCacheHasher)
+        };
+        let mut trie = ZkTrie::<Poseidon, DynKeyHasher>::new(key_hasher);
+        for i in 0..10u8 {
+            trie.raw_update(&trie_db, [i; 32], vec![[1u8; 32]], 1)
+                .unwrap();
+        }
+        roots.push(trie.commit(&mut trie_db).unwrap());
+        assert_eq!(
+            trie.key_hasher().hash(&[1u8; 32]).unwrap(),
+            <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &[1u8; 32]).unwrap()
+        );
+    }
+    assert_eq!(roots[0], roots[1]);
+}
+
+#[test]
+fn test_sync_node_db() {
+    use crate::db::{kv::ShardedDb, SyncNodeDb};
+
+    let trie_db: SyncNodeDb<HashMapDb> =
+        NodeDb::new(ShardedDb::new((0..4).map(|_| HashMapDb::default())));
+    let roots: Vec<_> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..4u8)
+            .map(|t| {
+                let mut trie_db = trie_db.handle();
+                s.spawn(move || {
+                    let mut trie = ZkTrie::default();
+                    for i in 0..50u8 {
+                        trie.raw_update(&trie_db, [t, i], vec![[i; 32]], 1).unwrap();
+                    }
+                    trie.commit(&mut trie_db).unwrap()
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    });
+
+    for (t, root) in roots.into_iter().enumerate() {
+        let trie = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+        for i in 0..50u8 {
+            let value: Option<[[u8; 32]; 1]> = trie.get(&trie_db, [t as u8, i]).unwrap();
+            assert_eq!(value, Some([[i; 32]]));
+        }
+    }
+}
+
+#[test]
+fn test_invalid_leaf_values() {
+    use crate::trie::LeafValuesError;
+
+    let trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let cases = [
+        (vec![], 0),
+        (vec![[1u8; 32]; 256], 0),
+        (vec![[1u8; 32]], 0b10),
+        (vec![[1u8; 32]; 30], 1 << 24),
+        (vec![[0u8; 32], [0xffu8; 32]], 0b01),
+    ];
+    for (values, flags) in cases {
+        let err = trie
+            .raw_update(&trie_db, [1u8; 32], values, flags)
+            .unwrap_err();
+        assert!(matches!(err, ZkTrieError::InvalidLeafValues(_)), "{err:?}");
+    }
+    assert!(matches!(
+        trie.raw_update(&trie_db, [1u8; 32], vec![[0u8; 32], [0xffu8; 32]], 0b01),
+        Err(ZkTrieError::InvalidLeafValues(
+            LeafValuesError::InvalidValue(1)
+        ))
+    ));
+    assert!(!trie.is_dirty());
+
+    trie.raw_update(&trie_db, [1u8; 32], vec![[0xffu8; 32]; 24], (1 << 24) - 1)
+        .unwrap();
+    assert!(trie.is_dirty());
+}
+
+#[test]
+fn test_secondary_commitment() {
+    use std::sync::{Arc, Mutex};
+
+    type Leafs = Arc<Mutex<HashMap<ZkHash, (Option<Vec<u8>>, Vec<[u8; 32]>)>>>;
+
+    #[derive(Default)]
+    struct Mirror {
+        leafs: Leafs,
+        commits: u64,
+    }
+
+    impl SecondaryCommitment for Mirror {
+        fn update(
+            &mut self,
+            key: Option<&[u8]>,
+            node_key: &ZkHash,
+            value_preimages: &[[u8; 32]],
+            _compression_flags: u32,
+        ) {
+            self.leafs.lock().unwrap().insert(
+                *node_key,
+                (key.map(|k| k.to_vec()), value_preimages.to_vec()),
+            );
+        }
+
+        fn delete(&mut self, key: Option<&[u8]>, node_key: &ZkHash) {
+            let (old_key, _) = self.leafs.lock().unwrap().remove(node_key).unwrap();
+            assert!(key.is_none() || key.map(|k| k.to_vec()) == old_key);
+        }
+
+        fn commit(&mut self) -> std::result::Result<ZkHash, Box<dyn Error + Send + Sync>> {
+            self.commits += 1;
+            Ok(ZkHash::left_padding_from(&self.commits.to_be_bytes()))
+        }
+    }
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let leafs = Leafs::default();
+    trie.set_secondary_commitment(Mirror {
+        leafs: leafs.clone(),
+        commits: 0,
+    });
+
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    for k in keys.iter().take(5) {
+        assert!(trie.delete(&trie_db, k).unwrap());
+    }
+    // missing keys are not reported
+    assert!(!trie.delete(&trie_db, [0u8; 32]).unwrap());
+    let node_key = <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &keys[5]).unwrap();
+    assert!(trie.delete_by_node_key(&trie_db, node_key).unwrap());
+
+    assert_eq!(trie.secondary_root(), None);
+    trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.secondary_root(), Some(ZkHash::with_last_byte(1)));
+
+    let leafs = leafs.lock().unwrap();
+    assert_eq!(leafs.len(), 14);
+    for k in keys.iter().skip(6) {
+        let node_key = <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, k).unwrap();
+        assert_eq!(leafs[&node_key], (Some(k.to_vec()), vec![*k]));
+    }
+    drop(leafs);
+
+    // commits without trie changes still commit the secondary
+    trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.secondary_root(), Some(ZkHash::with_last_byte(2)));
+    let commit_set = trie.prepare_commit().unwrap();
+    trie.apply_commit(&mut trie_db, commit_set).unwrap();
+    assert_eq!(trie.secondary_root(), Some(ZkHash::with_last_byte(3)));
+
+    assert!(trie.take_secondary_commitment().is_some());
+    assert_eq!(trie.secondary_root(), None);
+}
+
+#[test]
+fn test_value_stats() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    assert_eq!(trie.value_stats(&trie_db).unwrap(), ValueStats::default());
+    assert_eq!(ValueStats::default().byte_entropy(), 0.0);
+
+    for i in 0..10u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    for i in 10..15u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[0u8; 32], [i; 32]], 0b10)
+            .unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    let stats = trie.value_stats(&trie_db).unwrap();
+    assert_eq!(stats.leafs, 15);
+    assert_eq!(stats.preimage_counts, BTreeMap::from([(1, 10), (2, 5)]));
+    assert_eq!(stats.compressed_counts, BTreeMap::from([(1, 15)]));
+    assert_eq!(stats.total_preimages(), 20);
+    assert_eq!(stats.total_compressed(), 15);
+    assert_eq!(stats.byte_histogram[0], 32 * 6);
+    // every preimage repeats a single byte, zero in 6 of the 20 preimages
+    let p0 = 6.0 / 20.0;
+    let expected = -p0 * f64::log2(p0) - 14.0 * (1.0 / 20.0) * f64::log2(1.0 / 20.0);
+    assert!((stats.byte_entropy() - expected).abs() < 1e-9);
+}
+
+#[test]
+fn test_max_value_preimages() {
+    use crate::trie::{LeafValuesError, ParseNodeError};
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default().with_max_value_preimages(4);
+    assert_eq!(trie.max_value_preimages(), 4);
+    assert!(matches!(
+        trie.raw_update(&trie_db, [1u8; 32], vec![[1u8; 32]; 5], 0b11111),
+        Err(ZkTrieError::InvalidLeafValues(
+            LeafValuesError::ExceedsLimit { len: 5, limit: 4 }
+        ))
+    ));
+    assert!(!trie.is_dirty());
+    trie.raw_update(&trie_db, [1u8; 32], vec![[1u8; 32]; 4], 0b1111)
+        .unwrap();
+
+    // a leaf written without the limit can't be parsed with it
+    let mut unlimited = ZkTrie::default();
+    unlimited
+        .raw_update(&trie_db, [2u8; 32], vec![[1u8; 32]; 5], 0b11111)
+        .unwrap();
+    unlimited.commit(&mut trie_db).unwrap();
+    let proof = unlimited.prove(&trie_db, [2u8; 32]).unwrap();
+    assert!(Node::<Poseidon>::try_from_bytes_with_limit(&proof[0], 5).is_ok());
+    assert!(matches!(
+        Node::<Poseidon>::try_from_bytes_with_limit(&proof[0], 4),
+        Err(ParseNodeError::TooManyValues { len: 5, limit: 4 })
+    ));
+    let stream = proof.concat();
+    let mut reader = ProofReader::new(stream.as_slice()).with_max_value_preimages(4);
+    assert!(matches!(
+        reader.next_node::<Poseidon>(),
+        Err(ProofError::InvalidNodeBytes(
+            ParseNodeError::TooManyValues { len: 5, limit: 4 }
+        ))
+    ));
+}
+
+#[test]
+fn test_proof_key_preimages() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let k = [1u8; 32];
+    trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+    trie.raw_update(&trie_db, [2u8; 32], vec![[2u8; 32]], 1)
+        .unwrap();
+    let root = trie.commit(&mut trie_db).unwrap();
+    let node_key = <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &k).unwrap();
+    assert_eq!(
+        trie.recover_key_preimages(&mut trie_db, |n| (*n == node_key).then_some(k))
+            .unwrap(),
+        1
+    );
+
+    let trie = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+    assert!(!trie.key_preimages_in_proofs());
+    let private = trie.prove(&trie_db, k).unwrap();
+    let trie = trie.with_key_preimages_in_proofs(true);
+    let public = trie.prove(&trie_db, k).unwrap();
+
+    let private_leaf = Node::<Poseidon>::try_from(private[private.len() - 2].as_slice()).unwrap();
+    let public_leaf = Node::<Poseidon>::try_from(public[public.len() - 2].as_slice()).unwrap();
+    assert_eq!(private_leaf.as_leaf().unwrap().node_key_preimage(), None);
+    assert_eq!(public_leaf.as_leaf().unwrap().node_key_preimage(), Some(&k));
+    assert_eq!(
+        private_leaf.get_or_calculate_node_hash().unwrap(),
+        public_leaf.get_or_calculate_node_hash().unwrap()
+    );
+    for proof in [&private, &public] {
+        let found =
+            verify_proof_stream::<Poseidon, _>(&root.hash(), &node_key, proof.concat().as_slice())
+                .unwrap();
+        assert!(found.is_some());
+    }
+}
+
+#[test]
+fn test_proof_parse() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap().hash();
+
+    let k = keys[0];
+    let node_key = <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &k).unwrap();
+    let bytes = trie.prove(&trie_db, k).unwrap();
+    let proof = Proof::<Poseidon>::parse(&bytes).unwrap();
+    assert_eq!(proof.nodes().len(), bytes.len() - 1);
+    proof.verify_path(&root).unwrap();
+    let leaf = proof.verify(&root, &node_key).unwrap().unwrap();
+    assert_eq!(leaf.as_leaf().unwrap().value_preimages(), &[k]);
+    assert!(matches!(
+        proof.verify(&ZkHash::ZERO, &node_key),
+        Err(ProofError::HashMismatch { .. })
+    ));
+    // the path of another key
+    let other = <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &keys[1]).unwrap();
+    assert!(!matches!(proof.verify(&root, &other), Ok(Some(_))));
+
+    let absent = trie.prove(&trie_db, [0u8; 32]).unwrap();
+    let absent_key =
+        <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &[0u8; 32]).unwrap();
+    let absent = Proof::<Poseidon>::parse(&absent).unwrap();
+    assert!(absent.verify(&root, &absent_key).unwrap().is_none());
+
+    assert!(matches!(
+        Proof::<Poseidon>::parse::<Vec<u8>>(&[]),
+        Err(ProofError::Incomplete)
+    ));
+    assert!(matches!(
+        Proof::<Poseidon>::parse(&bytes[bytes.len() - 1..]),
+        Err(ProofError::Incomplete)
+    ));
+    assert!(matches!(
+        Proof::<Poseidon>::parse(&bytes[..bytes.len() - 1]),
+        Err(ProofError::InvalidMagic)
+    ));
+    let mut doubled = bytes.clone();
+    doubled.insert(1, crate::trie::MAGIC_NODE_BYTES.to_vec());
+    assert!(matches!(
+        Proof::<Poseidon>::parse(&doubled),
+        Err(ProofError::InvalidMagic)
+    ));
+
+    let leaf_index = bytes.len() - 2;
+    let mut trailing = bytes.clone();
+    trailing[leaf_index].push(0);
+    assert!(matches!(
+        Proof::<Poseidon>::parse(&trailing),
+        Err(ProofError::NonCanonicalNode(i)) if i == leaf_index
+    ));
+    // a key preimage must be 32 bytes
+    let mut preimage = bytes.clone();
+    *preimage[leaf_index].last_mut().unwrap() = 1;
+    preimage[leaf_index].extend_from_slice(&[0u8; 32]);
+    assert!(matches!(
+        Proof::<Poseidon>::parse(&preimage),
+        Err(ProofError::NonCanonicalNode(_))
+    ));
+
+    let mut truncated = bytes.clone();
+    truncated.remove(0);
+    truncated.remove(0);
+    truncated.insert(0, bytes[leaf_index].clone());
+    assert!(matches!(
+        Proof::<Poseidon>::parse(&truncated),
+        Err(ProofError::UnexpectedNodeType(0))
+    ));
+
+    // flip the child types of the root
+    let mut retyped = bytes.clone();
+    retyped[0][0] = NodeType::BranchLTRT as u8 + NodeType::BranchLBRB as u8 - retyped[0][0];
+    let retyped = Proof::<Poseidon>::parse(&retyped).unwrap();
+    assert!(retyped.verify(&root, &node_key).is_err());
+}
+
+#[test]
+fn test_delta() {
+    use crate::db::delta::DeltaError;
+
+    fn node_hashes<Db: KVDatabase>(db: &NodeDb<Db>, root: CommittedRoot) -> HashSet<ZkHash> {
+        let trie = ZkTrie::<Poseidon>::new_with_root(db, NoCacheHasher, root).unwrap();
+        trie.iter(db)
+            .map(|n| *n.unwrap().get_or_calculate_node_hash().unwrap())
+            .filter(|h| !h.is_zero())
+            .collect()
+    }
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..100).map(|_| random()).collect();
+    for k in keys.iter().take(60) {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let since = trie.commit(&mut trie_db).unwrap();
+    let mut backup = NodeDb::default();
+    let mut base = ZkTrie::default();
+    for k in keys.iter().take(60) {
+        base.raw_update(&backup, k, vec![*k], 1).unwrap();
+    }
+    assert_eq!(base.commit(&mut backup).unwrap(), since);
+
+    // deletions collapse leafs upward, insertions push them down
+    for k in keys.iter().take(20) {
+        trie.delete(&trie_db, k).unwrap();
+    }
+    for k in keys.iter().skip(20).take(10) {
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+    }
+    for k in keys.iter().skip(60) {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let now = trie.commit(&mut trie_db).unwrap();
+
+    let mut delta = Vec::new();
+    let written = trie_db
+        .export_delta::<Poseidon, _>(since, now, &mut delta)
+        .unwrap();
+    let expected = node_hashes(&trie_db, now)
+        .difference(&node_hashes(&trie_db, since))
+        .count();
+    assert_eq!(written, expected);
+
+    assert!(matches!(
+        NodeDb::default().import_delta::<Poseidon, _>(delta.as_slice()),
+        Err(DeltaError::NodeNotFound(root)) if root == since.hash()
+    ));
+    assert!(matches!(
+        backup.import_delta::<Poseidon, _>(&delta[..delta.len() - 1]),
+        Err(DeltaError::Io(_))
+    ));
+    let mut corrupted = delta.clone();
+    corrupted[16 + 2 * 32 + 4] = 0xff;
+    assert!(matches!(
+        backup.import_delta::<Poseidon, _>(corrupted.as_slice()),
+        Err(DeltaError::InvalidNode(0))
+    ));
+
+    let (root, inserted) = backup
+        .import_delta::<Poseidon, _>(delta.as_slice())
+        .unwrap();
+    assert_eq!(root, now);
+    assert_eq!(inserted, written);
+    let restored = ZkTrie::<Poseidon>::new_with_root(&backup, NoCacheHasher, root).unwrap();
+    for (i, k) in keys.iter().enumerate() {
+        let value: Option<[[u8; 32]; 1]> = restored.get(&backup, k).unwrap();
+        let expected = match i {
+            0..20 => None,
+            20..30 => Some([[1u8; 32]]),
+            _ => Some([*k]),
+        };
+        assert_eq!(value, expected);
+    }
+
+    // nothing changed
+    let mut delta = Vec::new();
+    assert_eq!(
+        trie_db
+            .export_delta::<Poseidon, _>(now, now, &mut delta)
+            .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn test_native_hash_scheme() {
+    use crate::hash::{BranchInput, HashOutput, NativeHashScheme};
+
+    // the byte based hashing, element conversions in between
+    fn hash_bytes(v: &[u8]) -> ZkHash {
+        let mut v_lo = [0u8; 32];
+        let mut v_hi = [0u8; 32];
+        if v.len() > 16 {
+            v_lo[16..].copy_from_slice(&v[..16]);
+            v_hi[16..v.len()].copy_from_slice(&v[16..]);
+        } else {
+            v_lo[16..16 + v.len()].copy_from_slice(v);
+        }
+        Poseidon::hash(512, [v_lo.into(), v_hi.into()]).unwrap()
+    }
+
+    for len in 0..=32 {
+        let v: Vec<u8> = (0..len).map(|_| random()).collect();
+        assert_eq!(Poseidon::hash_bytes(&v).unwrap(), hash_bytes(&v));
+    }
+    assert!(Poseidon::hash_bytes_element(&[0u8; 33]).is_err());
+
+    for len in 1..30 {
+        let values: Vec<[u8; 32]> = (0..len).map(|_| random()).collect();
+        let flags = (1u32 << len.min(24)) - 1;
+        let mut hashes: Vec<ZkHash> = values.iter().map(|v| hash_bytes(v)).collect();
+        for v in hashes.iter_mut().skip(24) {
+            *v = Poseidon::new_hash_try_from_bytes(&v[..]).unwrap();
+        }
+        let values: Vec<[u8; 32]> = values
+            .iter()
+            .zip(hashes.iter())
+            .enumerate()
+            .map(|(i, (v, h))| if i < 24 { *v } else { h.0 })
+            .collect();
+        let domain = len as u64 * 256;
+        while hashes.len() > 1 {
+            let mut next: Vec<ZkHash> = hashes
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => Poseidon::hash(domain, [*a, *b]).unwrap(),
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+            std::mem::swap(&mut hashes, &mut next);
+        }
+        assert_eq!(
+            Poseidon::hash_bytes_array(&values, flags).unwrap(),
+            hashes[0]
+        );
+    }
+
+    let a = Poseidon::element(&ZkHash::with_last_byte(1)).unwrap();
+    let b = Poseidon::element(&ZkHash::with_last_byte(2)).unwrap();
+    assert_eq!(
+        Poseidon::hash_elements(7, [a, b])
+            .unwrap()
+            .as_canonical_repr(),
+        Poseidon::hash(7, [ZkHash::with_last_byte(1), ZkHash::with_last_byte(2)]).unwrap()
+    );
+    assert!(Poseidon::element(&ZkHash::repeat_byte(0xff)).is_err());
+
+    let (c, d) = (ZkHash::with_last_byte(3), ZkHash::with_last_byte(4));
+    let branches = [
+        (6, [BranchInput::Hash(c), BranchInput::Hash(d)]),
+        (7, [BranchInput::Output(0), BranchInput::Hash(c)]),
+        (9, [BranchInput::Output(0), BranchInput::Output(1)]),
+    ];
+    let first = Poseidon::hash(6, [c, d]).unwrap();
+    let second = Poseidon::hash(7, [first, c]).unwrap();
+    assert_eq!(
+        Poseidon::hash_branches(&branches).unwrap(),
+        vec![first, second, Poseidon::hash(9, [first, second]).unwrap()]
+    );
+    assert!(Poseidon::hash_branches(&[(
+        6,
+        [
+            BranchInput::Hash(ZkHash::repeat_byte(0xff)),
+            BranchInput::Hash(c)
+        ]
+    )])
+    .is_err());
+}
+
+#[test]
+fn test_path_order() {
+    let mut trie_db = NodeDb::default();
+    let mut lsb = ZkTrie::default();
+    let mut msb = ZkTrie::<Poseidon, NoCacheHasher, MsbFirst>::new(NoCacheHasher);
+    let keys: Vec<[u8; 32]> = (0..50).map(|_| random()).collect();
+    for k in keys.iter() {
+        lsb.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+        msb.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    for k in keys.iter().take(10) {
+        lsb.delete(&trie_db, k).unwrap();
+        msb.delete(&trie_db, k).unwrap();
+    }
+    let lsb_root = lsb.commit(&mut trie_db).unwrap();
+    let root = msb.commit(&mut trie_db).unwrap();
+    assert_ne!(lsb_root.hash(), root.hash());
+
+    let msb =
+        ZkTrie::<Poseidon, NoCacheHasher, MsbFirst>::new_with_root(&trie_db, NoCacheHasher, root)
+            .unwrap()
+            .with_path_cache(&trie_db, 4)
+            .unwrap();
+    for (i, k) in keys.iter().enumerate() {
+        let value = msb.get::<_, [[u8; 32]; 1], _>(&trie_db, k).unwrap();
+        assert_eq!(value, (i >= 10).then_some([*k]));
+    }
+
+    // leafs come in ascending order of the path bits of their node keys
+    let path_bytes = HASH_SIZE - Poseidon::TRIE_MAX_LEVELS / 8;
+    let node_keys: Vec<ZkHash> = msb
+        .iter(&trie_db)
+        .with_paths()
+        .map(Result::unwrap)
+        .filter_map(|(path, node)| {
+            let node_key = node.as_leaf()?.node_key();
+            assert!(path.is_prefix_of_ordered::<Poseidon, MsbFirst>(&node_key));
+            Some(node_key)
+        })
+        .collect();
+    assert_eq!(node_keys.len(), 40);
+    assert!(node_keys
+        .windows(2)
+        .all(|w| w[0][path_bytes..] < w[1][path_bytes..]));
+
+    let k = keys[20];
+    let node_key = <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &k).unwrap();
+    let proof = Proof::<Poseidon, MsbFirst>::parse(&msb.prove(&trie_db, k).unwrap()).unwrap();
+    assert!(proof.verify(&root.hash(), &node_key).unwrap().is_some());
+}
+
+#[test]
+fn test_proof_builder() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..100).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    let mut builder = trie.proof_builder(&trie_db).unwrap().with_cache_depth(3);
+    for _ in 0..2 {
+        for k in keys.iter().chain([&[0u8; 32]]) {
+            assert_eq!(builder.prove(k).unwrap(), trie.prove(&trie_db, k).unwrap());
+        }
+    }
+    assert_eq!(builder.cached_keys(), keys.len() + 1);
+    assert!(builder.cached_nodes() > 0 && builder.cached_nodes() < 16);
+
+    trie.raw_update(&trie_db, [0u8; 32], vec![[1u8; 32]], 1)
+        .unwrap();
+    assert!(matches!(
+        trie.proof_builder(&trie_db),
+        Err(ZkTrieError::UncommittedChanges)
+    ));
+}
+
+#[test]
+fn test_empty_root() {
+    assert_eq!(ZkTrie::<Poseidon>::EMPTY_ROOT, Poseidon::empty_root());
+    assert_eq!(
+        *Node::<Poseidon>::empty()
+            .get_or_calculate_node_hash()
+            .unwrap(),
+        Poseidon::EMPTY_ROOT
+    );
+    assert_eq!(CommittedRoot::empty::<Poseidon>(), CommittedRoot::EMPTY);
+
+    let mut trie_db = NodeDb::new(HashMapDb::default());
+    let trie = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        CommittedRoot::empty::<Poseidon>(),
+    )
+    .unwrap();
+    assert_eq!(trie.leaf_count(), Some(0));
+    assert_eq!(*trie.root().unwrap_ref(), ZkTrie::<Poseidon>::EMPTY_ROOT);
+
+    let proof = Proof::<Poseidon>::parse(&trie.prove(&trie_db, [1u8; 32]).unwrap()).unwrap();
+    let node_key =
+        <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &[1u8; 32]).unwrap();
+    assert!(proof
+        .verify(&Poseidon::EMPTY_ROOT, &node_key)
+        .unwrap()
+        .is_none());
+
+    let mut trie = ZkTrie::default();
+    trie.raw_update(&trie_db, [1u8; 32], vec![[1u8; 32]], 1)
+        .unwrap();
+    assert!(!trie.commit(&mut trie_db).unwrap().is_empty());
+    trie.delete(&trie_db, [1u8; 32]).unwrap();
+    assert!(trie.commit(&mut trie_db).unwrap().is_empty());
+}
+
+#[test]
+fn test_iter_typed() {
+    let trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    for i in 0..10u8 {
+        let (values, flags) = if i % 2 == 0 {
+            (vec![[i; 32], [i; 32]], 3)
+        } else {
+            (vec![[i; 32]], 1)
+        };
+        trie.raw_update(&trie_db, [i; 32], values, flags).unwrap();
+    }
+
+    let pairs: Vec<(ZkHash, [[u8; 32]; 2])> = trie
+        .iter_typed(&trie_db, DecodePolicy::Skip)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(pairs.len(), 5);
+    for (node_key, [value, _]) in pairs {
+        let expected =
+            <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &value).unwrap();
+        assert_eq!(node_key, expected);
+    }
+
+    let results: Vec<_> = trie
+        .iter_typed::<[[u8; 32]; 2], _>(&trie_db, DecodePolicy::Error)
+        .collect();
+    assert_eq!(results.len(), 10);
+    assert_eq!(
+        results
+            .iter()
+            .filter(|r| matches!(r, Err(ZkTrieError::UnexpectValue)))
+            .count(),
+        5
+    );
+}
+
+#[test]
+fn test_subtree_export() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..100).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap();
+
+    // graft every subtree at depth 2 into a db holding nothing
+    let mut shard_db = NodeDb::default();
+    for bits in [[false, false], [false, true], [true, false], [true, true]] {
+        let prefix = NodePath::from_bits(bits);
+        let mut export = Vec::new();
+        let nodes = trie.export_subtree(&trie_db, &prefix, &mut export).unwrap();
+        let (subtree_root, inserted) =
+            ZkTrie::<Poseidon>::import_subtree(&mut shard_db, root.hash(), export.as_slice())
+                .unwrap();
+        assert!(inserted <= nodes);
+        let under_prefix = trie
+            .iter(&trie_db)
+            .with_paths()
+            .map(Result::unwrap)
+            .find(|(path, _)| *path == prefix)
+            .unwrap();
+        assert_eq!(subtree_root, *under_prefix.1.node_hash().unwrap());
+
+        assert!(matches!(
+            ZkTrie::<Poseidon>::import_subtree(
+                &mut NodeDb::default(),
+                ZkHash::ZERO,
+                export.as_slice()
+            ),
+            Err(ZkTrieError::InvalidProof)
+        ));
+        // drop a leaf record
+        let truncated = &export[..export.len() - 1];
+        assert!(
+            ZkTrie::<Poseidon>::import_subtree(&mut NodeDb::default(), root.hash(), truncated)
+                .is_err()
+        );
+    }
+
+    let shard = ZkTrie::<Poseidon>::new_with_root(&shard_db, NoCacheHasher, root).unwrap();
+    for k in keys.iter() {
+        assert_eq!(
+            shard.get::<_, [[u8; 32]; 1], _>(&shard_db, k).unwrap(),
+            Some([*k])
+        );
+    }
+}
+
+#[test]
+fn test_batch_witness() {
+    use witness::BatchWitnessBuilder;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..50).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let start_root = trie.commit(&mut trie_db).unwrap();
+
+    let mut builder = BatchWitnessBuilder::new(start_root);
+    let mut pre_roots = Vec::new();
+    for block in keys.chunks(10) {
+        pre_roots.push(builder.current_root());
+        builder.prove_block(&trie, &trie_db, block).unwrap();
+        for k in block {
+            trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+        }
+        let post_root = trie.commit(&mut trie_db).unwrap();
+        builder.end_block(post_root);
+    }
+    assert_eq!(builder.blocks(), 5);
+    // the trie of the first block is not at the current root
+    let stale = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, start_root).unwrap();
+    assert!(matches!(
+        builder.prove_block(&stale, &trie_db, &keys[..1]),
+        Err(ZkTrieError::RootMismatch { .. })
+    ));
+
+    let witness = builder.build();
+    assert_eq!(witness.start_root, start_root.hash());
+    assert_eq!(witness.end_root(), *trie.root().unwrap_ref());
+    let nodes: HashSet<_> = witness.nodes.iter().collect();
+    assert_eq!(nodes.len(), witness.nodes.len());
+    for (block, pre_root) in keys.chunks(10).zip(pre_roots) {
+        let pre_root = trie_db.committed_root(pre_root).unwrap();
+        let pre = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, pre_root).unwrap();
+        for k in block {
+            let proof = pre.prove(&trie_db, k).unwrap();
+            assert!(proof[..proof.len() - 1].iter().all(|n| nodes.contains(n)));
+        }
+    }
+}
+
+#[test]
+fn test_open_verified() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..50).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap();
+    for check in [
+        OpenCheck::Levels(1),
+        OpenCheck::Levels(4),
+        OpenCheck::Sample { paths: 8 },
+        OpenCheck::Full,
+    ] {
+        ZkTrie::<Poseidon>::open_verified(&trie_db, NoCacheHasher, root, check).unwrap();
+    }
+    ZkTrie::<Poseidon>::open_verified(
+        &trie_db,
+        NoCacheHasher,
+        CommittedRoot::EMPTY,
+        OpenCheck::Full,
+    )
+    .unwrap();
+
+    // store another leaf under the hash of the first one
+    let leafs: Vec<_> = trie.leafs(&trie_db).map(Result::unwrap).collect();
+    let victim = *leafs[0].node_hash().unwrap();
+    let other = trie_db
+        .inner()
+        .get(leafs[1].node_hash().unwrap().as_slice())
+        .unwrap()
+        .unwrap();
+    trie_db.inner_mut().put(victim.as_slice(), &other).unwrap();
+    ZkTrie::<Poseidon>::open_verified(&trie_db, NoCacheHasher, root, OpenCheck::Levels(1)).unwrap();
+    assert!(matches!(
+        ZkTrie::<Poseidon>::open_verified(&trie_db, NoCacheHasher, root, OpenCheck::Full),
+        Err(ZkTrieError::CorruptedNode(node_hash)) if node_hash == victim
+    ));
+
+    // a truncated database
+    let mut truncated = NodeDb::default();
+    let mut partial = ZkTrie::default();
+    for k in keys.iter() {
+        partial.raw_update(&truncated, k, vec![*k], 1).unwrap();
+    }
+    partial.commit(&mut truncated).unwrap();
+    truncated.set_gc_enabled(true);
+    truncated.remove_node(&victim).unwrap();
+    assert!(matches!(
+        ZkTrie::<Poseidon>::open_verified(&truncated, NoCacheHasher, root, OpenCheck::Full),
+        Err(ZkTrieError::NodeNotFound)
+    ));
+}
+
+#[test]
+fn test_value_index() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    // every value is shared by two keys
+    let value = |i: usize| vec![[(i / 2) as u8; 32]];
+    for (i, k) in keys.iter().enumerate() {
+        trie.raw_update(&trie_db, k, value(i), 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.find_by_value_hash(&ZkHash::ZERO), None);
+    let mut trie = trie.with_value_index(&trie_db).unwrap();
+    assert!(trie.has_value_index());
+
+    let value_hash = |values: Vec<[u8; 32]>| Poseidon::hash_bytes_array(&values, 1).unwrap();
+    let node_key = |k: &[u8; 32]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let mut expected = vec![node_key(&keys[0]), node_key(&keys[1])];
+    expected.sort();
+    assert_eq!(
+        trie.find_by_value_hash(&value_hash(value(0))),
+        Some(expected)
+    );
+
+    // not reflected until committed
+    trie.raw_update(&trie_db, keys[0], value(2), 1).unwrap();
+    trie.delete(&trie_db, keys[1]).unwrap();
+    assert_eq!(
+        trie.find_by_value_hash(&value_hash(value(0)))
+            .unwrap()
+            .len(),
+        2
+    );
+    trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.find_by_value_hash(&value_hash(value(0))), Some(vec![]));
+    assert_eq!(
+        trie.find_by_value_hash(&value_hash(value(2)))
+            .unwrap()
+            .len(),
+        3
+    );
+
+    let rebuilt = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(*trie.root().unwrap_ref()).unwrap(),
+    )
+    .unwrap()
+    .with_value_index(&trie_db)
+    .unwrap();
+    for i in 0..keys.len() {
+        assert_eq!(
+            rebuilt.find_by_value_hash(&value_hash(value(i))),
+            trie.find_by_value_hash(&value_hash(value(i)))
+        );
+    }
+}
+
+#[test]
+fn test_prepare_commit() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let mut expected_db = NodeDb::default();
+    let mut expected = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..50).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+        expected.raw_update(&expected_db, k, vec![*k], 1).unwrap();
+    }
+
+    // hash on another thread, write on this one
+    let commit_set =
+        std::thread::scope(|s| s.spawn(|| trie.prepare_commit().unwrap()).join()).unwrap();
+    assert!(trie.is_dirty());
+    assert_eq!(commit_set.len(), commit_set.canonical_nodes().count());
+    let root = trie.apply_commit(&mut trie_db, commit_set).unwrap();
+    assert_eq!(root, expected.commit(&mut expected_db).unwrap());
+    assert!(!trie.is_dirty());
+    for k in keys.iter() {
+        assert_eq!(
+            trie.get::<_, [[u8; 32]; 1], _>(&trie_db, k).unwrap(),
+            Some([*k])
+        );
+    }
+
+    // nothing to write
+    let commit_set = trie.prepare_commit().unwrap();
+    assert!(commit_set.is_empty());
+    assert_eq!(trie.apply_commit(&mut trie_db, commit_set).unwrap(), root);
+
+    // the trie changed after the set was prepared
+    trie.delete(&trie_db, keys[0]).unwrap();
+    let commit_set = trie.prepare_commit().unwrap();
+    let prepared_root = commit_set.root();
+    trie.delete(&trie_db, keys[1]).unwrap();
+    let changed_root = trie.resolve_hash(trie.root()).unwrap();
+    assert!(matches!(
+        trie.apply_commit(&mut trie_db, commit_set),
+        Err(ZkTrieError::RootMismatch { expected, actual })
+            if expected == prepared_root && actual == changed_root
+    ));
+    expected.delete(&expected_db, keys[0]).unwrap();
+    expected.delete(&expected_db, keys[1]).unwrap();
+    let commit_set = trie.prepare_commit().unwrap();
+    assert_eq!(
+        trie.apply_commit(&mut trie_db, commit_set).unwrap(),
+        expected.commit(&mut expected_db).unwrap()
+    );
+}
+
+#[test]
+fn test_namespaced_key_hasher() {
+    use crate::hash::key_hasher::NamespacedKeyHasher;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let (a, b) = ([1u8; 20], [2u8; 20]);
+    let hasher_a = NamespacedKeyHasher::new::<Poseidon>(NoCacheHasher, &a).unwrap();
+    let hasher_b = NamespacedKeyHasher::new::<Poseidon>(NoCacheHasher, &b).unwrap();
+    assert_ne!(hasher_a.namespace(), hasher_b.namespace());
+    assert!(NamespacedKeyHasher::new::<Poseidon>(NoCacheHasher, &[0u8; 33]).is_err());
+
+    // the same key in both namespaces and without namespace
+    let key = [3u8; 32];
+    trie.raw_update(&trie_db, key, vec![[0u8; 32]], 1).unwrap();
+    let mut root = trie.commit(&mut trie_db).unwrap();
+    for (i, hasher) in [hasher_a.clone(), hasher_b.clone()].into_iter().enumerate() {
+        let mut trie = ZkTrie::<Poseidon, _>::new_with_root(&trie_db, hasher, root).unwrap();
+        trie.raw_update(&trie_db, key, vec![[i as u8 + 1; 32]], 1)
+            .unwrap();
+        root = trie.commit(&mut trie_db).unwrap();
+    }
+
+    let trie = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+    assert_eq!(trie.leafs(&trie_db).count(), 3);
+    for (i, hasher) in [hasher_a, hasher_b].into_iter().enumerate() {
+        let trie = ZkTrie::<Poseidon, _>::new_with_root(&trie_db, hasher, root).unwrap();
+        assert_eq!(
+            trie.get::<_, [[u8; 32]; 1], _>(&trie_db, key).unwrap(),
+            Some([[i as u8 + 1; 32]])
+        );
+    }
+    assert_eq!(
+        trie.get::<_, [[u8; 32]; 1], _>(&trie_db, key).unwrap(),
+        Some([[0u8; 32]])
+    );
+}
+
+#[test]
+fn test_commit_with_arena() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..50u8).map(|i| [i; 32]).collect();
+    for (i, key) in keys.iter().enumerate() {
+        trie.raw_update(&trie_db, key, vec![[i as u8; 32]], 1)
+            .unwrap();
+    }
+    let (root, arena) = trie.commit_with_arena(&mut trie_db).unwrap();
+    assert_eq!(arena.len(), trie_db.inner().inner().len());
+
+    // nodes are stored as aligned slices of the arena
+    let base = arena.as_bytes().as_ptr() as usize;
+    let end = base + arena.as_bytes().len();
+    for ((node_hash, bytes), canonical) in arena.iter().zip(arena.canonical_nodes()) {
+        let stored = trie_db.inner().get(node_hash).unwrap().unwrap();
+        assert_eq!(stored.as_ptr(), bytes.as_ptr());
+        assert!((base..end).contains(&(stored.as_ptr() as usize)));
+        assert_eq!(stored.as_ptr() as usize % 16, 0);
+        let node = Node::<Poseidon>::try_from(canonical.as_slice()).unwrap();
+        assert_eq!(*node.get_or_calculate_node_hash().unwrap(), node_hash);
+    }
+    // children before their parents
+    assert_eq!(arena.iter().last().unwrap().0, root.hash());
+
+    let reopened = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(
+            reopened.get::<_, [[u8; 32]; 1], _>(&trie_db, key).unwrap(),
+            Some([[i as u8; 32]])
+        );
+    }
+
+    // nothing to commit
+    let (same_root, arena) = trie.commit_with_arena(&mut trie_db).unwrap();
+    assert_eq!(same_root, root);
+    assert!(arena.is_empty());
+
+    // canonical databases store the canonical bytes
+    trie_db.compact_to_canonical::<Poseidon>().unwrap();
+    trie.delete(&trie_db, keys[0]).unwrap();
+    let (root, arena) = trie.commit_with_arena(&mut trie_db).unwrap();
+    for (node_hash, canonical) in arena.iter().map(|(h, _)| h).zip(arena.canonical_nodes()) {
+        let stored = trie_db.inner().get(node_hash).unwrap().unwrap();
+        assert_eq!(stored.as_ref(), canonical.as_slice());
+    }
+    let reopened = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+    assert_eq!(reopened.leafs(&trie_db).count(), keys.len() - 1);
+}
+
+#[test]
+fn test_gc_incremental() {
+    let mut trie_db = NodeDb::new(HashMapDb::new(true));
+    let mut trie = ZkTrie::default();
+    for i in 0..20u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    for round in 1..4u8 {
+        for i in 0..5u8 {
+            trie.raw_update(&trie_db, [i * round; 32], vec![[round; 32]], 1)
+                .unwrap();
+        }
+        trie.commit(&mut trie_db).unwrap();
+    }
+
+    let mut retired: Vec<_> = trie.retired_nodes().collect();
+    retired.sort();
+    assert!(retired.len() > 3);
+    assert_eq!(trie.gc_incremental(&mut trie_db, 3).unwrap(), 3);
+    let mut remaining: Vec<_> = trie.retired_nodes().collect();
+    remaining.sort();
+    assert_eq!(remaining, retired[3..]);
+    for (_, node_hash) in retired[..3].iter() {
+        assert!(trie_db.get_node::<Poseidon>(node_hash).unwrap().is_none());
+    }
+
+    assert_eq!(
+        trie.gc_incremental(&mut trie_db, usize::MAX).unwrap(),
+        retired.len() - 3
+    );
+    assert_eq!(trie.retired_nodes().count(), 0);
+    assert_eq!(trie.gc_incremental(&mut trie_db, 10).unwrap(), 0);
+    for i in 0..20u8 {
+        assert!(trie
+            .get::<_, [[u8; 32]; 1], _>(&trie_db, [i; 32])
+            .unwrap()
+            .is_some());
+    }
+}
+
+#[test]
+fn test_verify_proof_absence() {
+    let hash = |k: &[u8]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+
+    // a single leaf at the root
+    trie.raw_update(&trie_db, [1u8; 32], vec![[1u8; 32]], 1)
+        .unwrap();
+    let root = trie.commit(&mut trie_db).unwrap().hash();
+    let proof = trie.prove(&trie_db, [2u8; 32]).unwrap();
+    let KeyProof::Absent {
+        conflicting_leaf: Some(leaf),
+    } = verify_proof::<Poseidon, _>(&root, &hash(&[2u8; 32]), &proof).unwrap()
+    else {
+        panic!("expected a conflicting leaf");
+    };
+    assert_eq!(leaf.as_leaf().unwrap().node_key(), hash(&[1u8; 32]));
+    let present = verify_proof::<Poseidon, _>(&root, &hash(&[1u8; 32]), &proof).unwrap();
+    assert!(present.is_present());
+
+    for i in 2..20u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap().hash();
+    let (mut empty, mut conflicting) = (0, 0);
+    for i in 0..200u16 {
+        let key = [i.to_le_bytes()[0], i.to_le_bytes()[1], 0xff];
+        let node_key = hash(&key);
+        let proof = trie.prove(&trie_db, key).unwrap();
+        match verify_proof::<Poseidon, _>(&root, &node_key, &proof).unwrap() {
+            KeyProof::Absent {
+                conflicting_leaf: None,
+            } => {
+                assert_eq!(
+                    proof[proof.len() - 2],
+                    Node::<Poseidon>::empty().canonical_value(true)
+                );
+                empty += 1;
+            }
+            KeyProof::Absent {
+                conflicting_leaf: Some(leaf),
+            } => {
+                // the other leaf shares the path down to its level
+                let other = leaf.as_leaf().unwrap().node_key();
+                assert_ne!(other, node_key);
+                for level in 0..proof.len() - 2 {
+                    assert_eq!(
+                        LsbFirst::path_bit::<Poseidon>(&other, level),
+                        LsbFirst::path_bit::<Poseidon>(&node_key, level)
+                    );
+                }
+                conflicting += 1;
+            }
+            KeyProof::Present(_) => panic!("key should be absent"),
+        }
+    }
+    assert!(empty > 0 && conflicting > 0);
+
+    // a leaf placed off its own path
+    let key_of = |bit: bool| {
+        (0..=u8::MAX)
+            .map(|i| hash(&[i; 32]))
+            .find(|k| LsbFirst::path_bit::<Poseidon>(k, 0) == bit)
+            .unwrap()
+    };
+    let (misplaced, absent) = (key_of(true), key_of(false));
+    let leaf = Node::<Poseidon>::new_leaf(misplaced, vec![[1u8; 32]], 1, None).unwrap();
+    let leaf_hash = *leaf.get_or_calculate_node_hash().unwrap();
+    let branch = Node::<Poseidon>::new_branch(NodeType::BranchLTRT, leaf_hash, ZkHash::ZERO);
+    let root = *branch.get_or_calculate_node_hash().unwrap();
+    let proof = [
+        branch.canonical_value(true),
+        leaf.canonical_value(true),
+        crate::trie::MAGIC_NODE_BYTES.to_vec(),
+    ];
+    assert!(matches!(
+        verify_proof::<Poseidon, _>(&root, &absent, &proof),
+        Err(ProofError::MisplacedLeaf(1))
+    ));
+}
+
+#[test]
+fn test_shared_proof_preorder() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..40u8).map(|i| [i; 32]).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let mut other = ZkTrie::default();
+    for k in keys.iter().take(5) {
+        other.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+    }
+    let other_root = other.commit(&mut trie_db).unwrap().hash();
+
+    let collect = |proved: &[[u8; 32]]| {
+        let mut nodes = SharedProofNodes::new();
+        let proofs: Vec<_> = proved
+            .iter()
+            .map(|k| trie.prove_shared(&trie_db, k, &mut nodes).unwrap())
+            .collect();
+        let other_proof = other.prove_shared(&trie_db, keys[0], &mut nodes).unwrap();
+        (nodes.into_preorder(), proofs, other_proof)
+    };
+    let proved = &keys[..20];
+    let (ordered, proofs, other_proof) = collect(proved);
+    let mut reversed: Vec<_> = proved.to_vec();
+    reversed.reverse();
+    let (reordered, _, _) = collect(&reversed);
+    // the order doesn't depend on the order of the keys
+    assert_eq!(ordered.nodes, reordered.nodes);
+
+    // the proofs are kept, from the root down, and the first proved trie comes first
+    let root_node = trie.prove(&trie_db, keys[0]).unwrap().swap_remove(0);
+    assert_eq!(ordered.nodes[0], root_node);
+    for (k, indices) in proved.iter().zip(proofs.iter()) {
+        assert_eq!(ordered.proof(indices), trie.prove(&trie_db, k).unwrap());
+        assert!(indices
+            .windows(2)
+            .all(|w| ordered.index_map[w[0]] < ordered.index_map[w[1]]));
+    }
+    let proof = ordered.proof(&other_proof);
+    assert_eq!(proof, other.prove(&trie_db, keys[0]).unwrap());
+    verify_proof::<Poseidon, _>(
+        &other_root,
+        &KeyHasher::<Poseidon>::hash(&NoCacheHasher, &keys[0]).unwrap(),
+        &proof,
+    )
+    .unwrap();
+    assert!(ordered.index_map[other_proof[0]] > ordered.index_map[proofs[0][0]]);
+
+    // preorder within the first trie: a node's path follows the previous one's
+    let mut paths = Vec::new();
+    for (k, indices) in proved.iter().zip(proofs.iter()) {
+        let node_key = KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+        for (level, index) in indices.iter().enumerate() {
+            let path = NodePath::from_bits(
+                (0..level).map(|l| LsbFirst::path_bit::<Poseidon>(&node_key, l)),
+            );
+            paths.push((ordered.index_map[*index], path));
+        }
+    }
+    paths.sort_by_key(|(position, _)| *position);
+    paths.dedup_by_key(|(position, _)| *position);
+    assert!(paths
+        .windows(2)
+        .all(|w| w[0].1.preorder_cmp(&w[1].1).is_lt()));
+}
+
+#[cfg(feature = "key-cache")]
+#[test]
+fn test_shared_key_cache() {
+    use crate::hash::key_hasher::SyncCachedKeyHasher;
+
+    let trie_db = NodeDb::default();
+    let hasher = SyncCachedKeyHasher::<Poseidon>::new(HashMapDb::default());
+    let keys = [[1u8; 20], [2u8; 20], [1u8; 20]];
+    assert_eq!(hasher.warm_up(keys).unwrap(), 2);
+    assert_eq!(hasher.warm_up(keys).unwrap(), 0);
+    for key in keys {
+        assert_eq!(
+            hasher.hash(&key).unwrap(),
+            <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &key).unwrap()
+        );
+    }
+
+    // clones share the cache
+    let mut tries = [
+        ZkTrie::<Poseidon, _>::new(hasher.clone()),
+        ZkTrie::<Poseidon, _>::new(hasher.clone()),
+    ];
+    for (i, trie) in tries.iter_mut().enumerate() {
+        trie.raw_update(&trie_db, [i as u8 + 3; 20], vec![[1u8; 32]], 1)
+            .unwrap();
+    }
+    assert_eq!(hasher.warm_up([[3u8; 20], [4u8; 20]]).unwrap(), 0);
+    hasher.flush().unwrap();
+    drop(tries);
+    assert_eq!(hasher.try_into_inner().unwrap().inner().len(), 4);
+}
+
+#[test]
+fn test_empty_leaf_values() {
+    use crate::trie::LeafValuesError;
+
+    assert_eq!(Poseidon::hash_bytes_array(&[], 0).unwrap(), ZkHash::ZERO);
+
+    // a malformed leaf without values still hashes instead of panicking
+    let leaf = Node::<Poseidon>::new_leaf(ZkHash::repeat_byte(1), vec![], 0, None).unwrap();
+    assert_eq!(
+        leaf.as_leaf()
+            .unwrap()
+            .get_or_calc_value_hash::<Poseidon>()
+            .unwrap(),
+        ZkHash::ZERO
+    );
+    let node_hash = *leaf.get_or_calculate_node_hash().unwrap();
+    let parsed = Node::<Poseidon>::try_from(leaf.canonical_value(false).as_slice()).unwrap();
+    assert_eq!(*parsed.get_or_calculate_node_hash().unwrap(), node_hash);
+
+    let trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    assert!(matches!(
+        trie.raw_update(&trie_db, [1u8; 32], vec![], 0),
+        Err(ZkTrieError::InvalidLeafValues(LeafValuesError::Empty))
+    ));
+}
+
+#[test]
+fn test_other_error() {
+    use crate::hash::poseidon::PoseidonError;
+    use std::error::Error;
+
+    type TrieError = ZkTrieError<PoseidonError, <HashMapDb as KVDatabase>::Error>;
+
+    fn assert_send_sync<T: Error + Send + Sync + 'static>(_: &T) {}
+
+    let io = std::io::Error::other("disk on fire");
+    let err: TrieError = ZkTrieError::other(io);
+    assert_send_sync(&err);
+    assert_eq!(err.to_string(), "disk on fire");
+    // errors can cross threads
+    let err = std::thread::spawn(move || err).join().unwrap();
+    assert!(matches!(err, ZkTrieError::Other(_)));
+
+    #[cfg(feature = "backtrace")]
+    {
+        use std::backtrace::{Backtrace, BacktraceStatus};
+
+        // captured if enabled by the environment
+        let enabled = Backtrace::capture().status() == BacktraceStatus::Captured;
+        let err: TrieError = ZkTrieError::other("disk on fire");
+        assert_eq!(err.backtrace().is_some(), enabled);
+        assert_eq!(err.to_string(), "disk on fire");
+        assert!(TrieError::NodeNotFound.backtrace().is_none());
+    }
+}
+
+#[test]
+fn test_recompute_root() {
+    use crate::trie::RecomputeRootError;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    assert_eq!(
+        <ZkTrie>::recompute_root(&[], 1).unwrap(),
+        <ZkTrie>::EMPTY_ROOT
+    );
+    for i in 0..500u32 {
+        let mut key = [0u8; 32];
+        key[..4].copy_from_slice(&i.to_be_bytes());
+        trie.raw_update(&trie_db, key, vec![[i as u8; 32]], 1)
+            .unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap();
+
+    let mut leaves: Vec<_> = trie
+        .leafs(&trie_db)
+        .map(|leaf| leaf.unwrap().into_owned())
+        .collect();
+    leaves.reverse();
+    for threads in [0, 1, 3, 8] {
+        assert_eq!(
+            <ZkTrie>::recompute_root(&leaves, threads).unwrap(),
+            root.hash()
+        );
+    }
+    let msb = <ZkTrie<Poseidon, NoCacheHasher, MsbFirst>>::recompute_root(&leaves, 4).unwrap();
+    assert_ne!(msb, root.hash());
+
+    // a missing leaf changes the root
+    assert_ne!(
+        <ZkTrie>::recompute_root(&leaves[1..], 4).unwrap(),
+        root.hash()
+    );
+    leaves.push(leaves[0].clone());
+    assert!(matches!(
+        <ZkTrie>::recompute_root(&leaves, 4),
+        Err(RecomputeRootError::DuplicateNodeKey(_))
+    ));
+    leaves.push(Node::empty());
+    assert!(matches!(
+        <ZkTrie>::recompute_root(&leaves, 4),
+        Err(RecomputeRootError::NotALeaf(501))
+    ));
+}
+
+#[test]
+fn test_merge_rw_sets() {
+    use crate::trie::{BatchWorker, RwSet};
+
+    let mut trie_db = NodeDb::default().into_shared();
+    let mut trie = ZkTrie::default();
+    for i in 0..8u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let base = trie.commit(&mut trie_db).unwrap();
+
+    // worker i reads key i, writes key i + 1
+    let sets: Vec<RwSet> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..4u8)
+            .map(|i| {
+                let db = trie_db.handle();
+                s.spawn(move || {
+                    let mut worker =
+                        BatchWorker::<Poseidon>::new(&db, NoCacheHasher, base).unwrap();
+                    let value: [[u8; 32]; 1] = worker.get(&db, [2 * i; 32]).unwrap().unwrap();
+                    worker
+                        .raw_update(&db, [2 * i + 1; 32], value.to_vec(), 1)
+                        .unwrap();
+                    // reading a written key is not recorded
+                    assert!(worker
+                        .get::<_, [[u8; 32]; 1], _>(&db, [2 * i + 1; 32])
+                        .unwrap()
+                        .is_some());
+                    if i == 3 {
+                        assert!(worker.delete(&db, [0u8; 32]).unwrap());
+                    }
+                    worker.finish()
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert!(sets.iter().all(|set| set.reads().len() == 1));
+
+    // worker 4 reads key 1, written by worker 0
+    let mut conflicting = BatchWorker::<Poseidon>::new(&trie_db, NoCacheHasher, base).unwrap();
+    conflicting
+        .get::<_, [[u8; 32]; 1], _>(&trie_db, [1u8; 32])
+        .unwrap();
+    conflicting
+        .raw_update(&trie_db, [9u8; 32], vec![[9u8; 32]], 1)
+        .unwrap();
+
+    let mut expected = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, base).unwrap();
+    let outcome = trie
+        .merge_rw_sets(&mut trie_db, sets.into_iter().chain([conflicting.finish()]))
+        .unwrap();
+    assert_eq!(outcome.applied, vec![0, 1, 2, 3]);
+    assert_eq!(outcome.conflicts.len(), 1);
+    assert_eq!(outcome.conflicts[0].0, 4);
+
+    for i in 0..4u8 {
+        expected
+            .raw_update(&trie_db, [2 * i + 1; 32], vec![[2 * i; 32]], 1)
+            .unwrap();
+    }
+    expected.delete(&trie_db, [0u8; 32]).unwrap();
+    assert_eq!(expected.commit(&mut trie_db).unwrap(), outcome.root);
+    assert!(!trie.contains_key(&trie_db, [9u8; 32]).unwrap());
+
+    // sets must be recorded against the current root
+    assert!(matches!(
+        trie.merge_rw_sets(&mut trie_db, [RwSet::new(base)]),
+        Err(ZkTrieError::RootMismatch { .. })
+    ));
+
+    // and the trie must be committed
+    trie.raw_update(&trie_db, [9u8; 32], vec![[9u8; 32]], 1)
+        .unwrap();
+    assert!(matches!(
+        trie.merge_rw_sets(&mut trie_db, []),
+        Err(ZkTrieError::UncommittedChanges)
+    ));
+}
+
+#[test]
+fn test_merge_rw_sets_rollback() {
+    use crate::trie::{BatchWorker, RwSet};
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    for i in 0..8u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let base = trie.commit(&mut trie_db).unwrap();
+    let node_key =
+        |k: [u8; 32]| <NoCacheHasher as KeyHasher<Poseidon>>::hash(&NoCacheHasher, &k).unwrap();
+
+    // deleting a missing key only reads it
+    let mut worker = BatchWorker::<Poseidon>::new(&trie_db, NoCacheHasher, base).unwrap();
+    assert!(!worker.delete(&trie_db, [9u8; 32]).unwrap());
+    assert!(worker.rw_set().is_read_only());
+    assert_eq!(worker.rw_set().reads().len(), 1);
+
+    // values are checked before anything is applied
+    let mut valid = RwSet::new(base);
+    valid.record_write(JournalOp::Update {
+        node_key: node_key([1u8; 32]),
+        value_preimages: vec![[9u8; 32]],
+        compression_flags: 1,
+    });
+    let mut invalid = RwSet::new(base);
+    invalid.record_write(JournalOp::Update {
+        node_key: node_key([2u8; 32]),
+        value_preimages: vec![],
+        compression_flags: 0,
+    });
+    assert!(matches!(
+        trie.merge_rw_sets(&mut trie_db, [valid.clone(), invalid]),
+        Err(ZkTrieError::InvalidLeafValues(LeafValuesError::Empty))
+    ));
+    assert!(!trie.is_dirty());
+
+    // a write failing on a missing node rolls back the writes applied before it
+    let (_, leaf) = trie
+        .traverse(&trie_db, &node_key([5u8; 32]))
+        .unwrap()
+        .pop()
+        .unwrap();
+    trie_db.set_gc_enabled(true);
+    trie_db.remove_node(leaf.node_hash().unwrap()).unwrap();
+    let mut missing = RwSet::new(base);
+    missing.record_write(JournalOp::Update {
+        node_key: node_key([5u8; 32]),
+        value_preimages: vec![[9u8; 32]],
+        compression_flags: 1,
+    });
+    assert!(matches!(
+        trie.merge_rw_sets(&mut trie_db, [valid.clone(), missing]),
+        Err(ZkTrieError::NodeNotFound)
+    ));
+    assert!(!trie.is_dirty());
+    assert_eq!(*trie.root().unwrap_ref(), base.hash());
+
+    let outcome = trie.merge_rw_sets(&mut trie_db, [valid]).unwrap();
+    assert_eq!(outcome.applied, vec![0]);
+    let value: Option<[[u8; 32]; 1]> = trie.get(&trie_db, [1u8; 32]).unwrap();
+    assert_eq!(value, Some([[9u8; 32]]));
+}
+
+#[test]
+fn test_resolve_lazy_hash() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::<Poseidon>::default();
+    for _ in 0..20 {
+        let k: [u8; 32] = random();
+        trie.raw_update(&trie_db, k, vec![k], 1).unwrap();
+    }
+
+    let root = trie.root().clone();
+    let LazyNodeHash::LazyBranch(lazy_root) = &root else {
+        panic!("root should be a dirty branch");
+    };
+    assert!(lazy_root.resolved().is_none());
+    assert!(trie.dirty_branch(lazy_root).is_some());
+
+    // children always come before their parents
+    let mut seen = HashSet::new();
+    for (lazy, node) in trie.dirty_branches() {
+        assert!(trie.dirty_branch(&lazy).is_some());
+        let branch = node.as_branch().unwrap();
+        for child in [branch.child_left(), branch.child_right()] {
+            if let LazyNodeHash::LazyBranch(child) = child {
+                assert!(seen.contains(&child.index()));
+            }
+        }
+        seen.insert(lazy.index());
+    }
+
+    let resolved = trie.resolve(&root).unwrap();
+    assert_eq!(lazy_root.resolved(), Some(&resolved));
+    assert_eq!(trie.commit(&mut trie_db).unwrap().hash(), resolved);
+    assert!(trie.dirty_branch(lazy_root).is_none());
+    assert_eq!(trie.resolve(trie.root()).unwrap(), resolved);
+
+    // a lazy hash taken before the commit, or from another trie, is rejected
+    assert!(matches!(
+        trie.resolve(&root),
+        Err(ZkTrieError::UnresolvedHashUsed)
+    ));
+    let mut other = ZkTrie::default();
+    for i in 0..2u8 {
+        other
+            .raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    assert!(matches!(
+        trie.resolve(other.root()),
+        Err(ZkTrieError::UnresolvedHashUsed)
+    ));
+}
+
+#[test]
+fn test_zk_map() {
+    use crate::trie::{decode_value_bytes, encode_value_bytes, BytesCodec, ZkMap, MAX_VALUE_BYTES};
+
+    for len in [0, 1, 32, 735, 736, 737, 767, 768, MAX_VALUE_BYTES] {
+        let bytes: Vec<u8> = (0..len).map(|_| random()).collect();
+        let (values, flags) = encode_value_bytes(&bytes).unwrap();
+        Node::<Poseidon>::check_leaf_values(&values, flags).unwrap();
+        assert_eq!(decode_value_bytes(&values), Some(bytes));
+    }
+    assert!(matches!(
+        encode_value_bytes(&vec![0; MAX_VALUE_BYTES + 1]),
+        Err(LeafValuesError::TooMany(_))
+    ));
+
+    let mut trie_db = NodeDb::default();
+    let mut map = ZkMap::<[u8; 32], Vec<u8>, _>::new(ZkTrie::default(), BytesCodec);
+    let entries: Vec<([u8; 32], Vec<u8>)> = (0..20)
+        .map(|i| (random(), (0..i * 100).map(|_| random()).collect()))
+        .collect();
+    for (k, v) in entries.iter() {
+        map.insert(&trie_db, k, v).unwrap();
+    }
+    map.commit(&mut trie_db).unwrap();
+    for (k, v) in entries.iter() {
+        assert_eq!(map.get(&trie_db, k).unwrap().as_ref(), Some(v));
+    }
+    assert!(map.remove(&trie_db, &entries[0].0).unwrap());
+    assert_eq!(map.get(&trie_db, &entries[0].0).unwrap(), None);
+
+    // the value is authenticated by the proof
+    let (k, v) = &entries[5];
+    let root = map.commit(&mut trie_db).unwrap().hash();
+    let proof = map.trie().prove(&trie_db, k).unwrap();
+    let node_key = KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let KeyProof::Present(leaf) = verify_proof::<Poseidon, _>(&root, &node_key, &proof).unwrap()
+    else {
+        panic!("expected the key to be present");
+    };
+    assert_eq!(
+        decode_value_bytes(leaf.as_leaf().unwrap().value_preimages()).as_ref(),
+        Some(v)
+    );
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn test_zk_map_cbor() {
+    use crate::trie::{CborCodec, ZkMap};
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Record {
+        name: String,
+        tags: Vec<String>,
+        score: u64,
+    }
+
+    let mut trie_db = NodeDb::default();
+    let mut map = ZkMap::<&str, Record, _>::new(ZkTrie::default(), CborCodec);
+    let record = Record {
+        name: "alice".to_string(),
+        tags: vec!["a".repeat(100), "b".repeat(1000)],
+        score: 42,
+    };
+    map.insert(&trie_db, &"alice", &record).unwrap();
+    map.commit(&mut trie_db).unwrap();
+    assert_eq!(map.get(&trie_db, &"alice").unwrap(), Some(record));
+    assert_eq!(map.get(&trie_db, &"bob").unwrap(), None);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_mmap_db_validation() {
+    use crate::db::{
+        kv::MmapDb,
+        snapshot::{snapshot_records, SnapshotError},
+    };
+    use alloy_primitives::bytes::Bytes;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    for i in 0..4u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let mut buffer = Vec::new();
+    trie.export_snapshot(&trie_db, &mut buffer).unwrap();
+    let aligned = |bytes: &[u8]| {
+        let mut aligned = rkyv::util::AlignedVec::<16>::new();
+        aligned.extend_from_slice(bytes);
+        Bytes::from_owner(aligned)
+    };
+
+    // shifted by one byte
+    let mut shifted = vec![0];
+    shifted.extend_from_slice(&buffer);
+    assert!(matches!(
+        MmapDb::from_bytes(aligned(&shifted).slice(1..)),
+        Err(SnapshotError::Misaligned)
+    ));
+
+    // nodes are validated on their first read, the other nodes stay readable
+    let records: Vec<_> = snapshot_records(&buffer)
+        .unwrap()
+        .map(|record| record.unwrap().0)
+        .collect();
+    let (first, second) = (records[0], records[1]);
+    // the first record, right after the 16 bytes header, claims another hash
+    let mut renamed = buffer.clone();
+    renamed[16] ^= 1;
+    let db = MmapDb::from_bytes(aligned(&renamed)).unwrap();
+    assert!(matches!(
+        db.get(first),
+        Err(SnapshotError::InvalidNode(hash)) if hash == first
+    ));
+    assert!(db.get(second).unwrap().is_some());
+    // garbage archived bytes
+    let mut garbage = buffer.clone();
+    garbage[64..80].fill(0xff);
+    let db = MmapDb::from_bytes(aligned(&garbage)).unwrap();
+    assert!(matches!(
+        db.get(first),
+        Err(SnapshotError::InvalidNode(hash)) if hash == first
+    ));
+    assert!(db.get(second).unwrap().is_some());
+    let db = unsafe { MmapDb::from_bytes_unchecked(aligned(&garbage)) }.unwrap();
+    assert!(db.get(first).unwrap().is_some());
+
+    // the index ends the snapshot
+    assert!(matches!(
+        MmapDb::from_bytes(aligned(&buffer[..buffer.len() - 1])),
+        Err(SnapshotError::InvalidIndex)
+    ));
+    let mut unindexed = buffer.clone();
+    unindexed[8..12].copy_from_slice(&1u32.to_le_bytes());
+    assert!(matches!(
+        MmapDb::from_bytes(aligned(&unindexed)),
+        Err(SnapshotError::UnsupportedVersion(1))
+    ));
+
+    let db = MmapDb::from_bytes(aligned(&buffer)).unwrap();
+    assert_eq!(db.len(), records.len());
+    assert!(!db.contains_key(ZkHash::repeat_byte(1).as_slice()).unwrap());
+    let mut keys = Vec::new();
+    assert!(db
+        .for_each_key(|k| keys.push(ZkHash::from_slice(k)))
+        .unwrap());
+    let mut sorted = records.clone();
+    sorted.sort();
+    assert_eq!(keys, sorted);
+}
+
+#[test]
+fn test_map_db_from_snapshot() {
+    use crate::db::kv::BTreeMapDb;
+    use alloy_primitives::bytes::Bytes;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap().hash();
+    let mut buffer = Vec::new();
+    let nodes = trie.export_snapshot(&trie_db, &mut buffer).unwrap();
+    let data = Bytes::from(buffer);
+    let range = data.as_ptr_range();
+
+    let hash_map_db = HashMapDb::from_snapshot(false, data.clone()).unwrap();
+    assert_eq!(hash_map_db.snapshot_nodes().len(), nodes);
+    // values are slices of the snapshot buffer
+    assert!(hash_map_db
+        .snapshot_nodes()
+        .values()
+        .all(|v| range.contains(&v.as_ptr())));
+    let btree_map_db = BTreeMapDb::from_snapshot(false, data.clone()).unwrap();
+    assert_eq!(btree_map_db.snapshot_nodes().len(), nodes);
+
+    let hash_map_db = NodeDb::new(hash_map_db);
+    let btree_map_db = NodeDb::new(btree_map_db);
+    let hash_map_trie = ZkTrie::<Poseidon>::new_with_root(
+        &hash_map_db,
+        NoCacheHasher,
+        hash_map_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    let btree_map_trie = ZkTrie::<Poseidon>::new_with_root(
+        &btree_map_db,
+        NoCacheHasher,
+        btree_map_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    for k in keys.iter() {
+        let value: Option<[[u8; 32]; 1]> = hash_map_trie.get(&hash_map_db, k).unwrap();
+        assert_eq!(value, Some([*k]));
+        let value: Option<[[u8; 32]; 1]> = btree_map_trie.get(&btree_map_db, k).unwrap();
+        assert_eq!(value, Some([*k]));
+    }
+
+    assert!(HashMapDb::from_snapshot(false, data.slice(..data.len() - 20)).is_err());
+}
+
+#[test]
+fn test_map_db_snapshot_nodes() {
+    use crate::db::{kv::BTreeMapDb, snapshot::SnapshotError};
+    use alloy_primitives::bytes::Bytes;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    for i in 0..4u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap();
+    let mut buffer = vec![0];
+    trie.export_snapshot(&trie_db, &mut buffer).unwrap();
+    let nodes = trie_db.inner().inner().len();
+
+    // a misaligned buffer is copied once
+    let data = Bytes::from(buffer.clone()).slice(1..);
+    let mut db = HashMapDb::from_snapshot(true, data.clone()).unwrap();
+    assert_eq!(db.snapshot_nodes().len(), nodes);
+    assert!(db.inner().is_empty());
+    let btree_db = BTreeMapDb::from_snapshot(true, data).unwrap();
+    assert_eq!(btree_db.snapshot_nodes().len(), nodes);
+
+    // writes and removals shadow the snapshot nodes
+    let node = db.get(root.hash().as_slice()).unwrap().unwrap();
+    assert_eq!(
+        db.put(root.hash().as_slice(), &node).unwrap(),
+        Some(node.clone())
+    );
+    assert_eq!(db.snapshot_nodes().len(), nodes - 1);
+    assert_eq!(db.get(root.hash().as_slice()).unwrap(), Some(node));
+    let (node_hash, _) = db.snapshot_nodes().iter().next().unwrap();
+    let node_hash = *node_hash;
+    db.remove(node_hash.as_slice()).unwrap();
+    assert!(!db.contains_key(node_hash.as_slice()).unwrap());
+    assert_eq!(db.into_inner().len(), nodes - 1);
+
+    // archived nodes are validated
+    let mut garbage = buffer[1..].to_vec();
+    garbage[64..80].fill(0xff);
+    assert!(matches!(
+        HashMapDb::from_snapshot(false, Bytes::from(garbage)),
+        Err(SnapshotError::InvalidNode(_))
+    ));
+}
+
+#[test]
+fn test_export_legacy() {
+    use crate::trie::LEGACY_PREIMAGE_PREFIX;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let mut out = HashMapDb::default();
+    assert!(matches!(
+        trie.export_legacy(&trie_db, &mut out, true),
+        Err(ZkTrieError::UncommittedChanges)
+    ));
+    let root = trie.commit(&mut trie_db).unwrap().hash();
+
+    let hash = |k: &[u8]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let preimages: HashMap<ZkHash, [u8; 32]> = keys.iter().map(|k| (hash(k), *k)).collect();
+    // leave one leaf without a preimage
+    let recovered = trie
+        .recover_key_preimages(&mut trie_db, |node_key| {
+            (*node_key != hash(&keys[0]))
+                .then(|| preimages.get(node_key).copied())
+                .flatten()
+        })
+        .unwrap();
+    assert_eq!(recovered, keys.len() - 1);
+
+    let export = trie.export_legacy(&trie_db, &mut out, true).unwrap();
+    assert_eq!(export.preimages, keys.len() - 1);
+    assert_eq!(out.inner().len(), export.nodes + export.preimages);
+
+    // nodes are stored canonically under their hash
+    let root_bytes = out.get(root.as_slice()).unwrap().unwrap();
+    let root_node = Node::<Poseidon>::try_from(root_bytes.as_ref()).unwrap();
+    assert_eq!(*root_node.get_or_calculate_node_hash().unwrap(), root);
+
+    for k in keys[1..].iter() {
+        let mut key = LEGACY_PREIMAGE_PREFIX.to_vec();
+        key.extend_from_slice(hash(k).as_slice());
+        assert_eq!(out.get(&key).unwrap().unwrap().as_ref(), k);
+    }
+
+    let mut nodes_only = HashMapDb::default();
+    let export = trie
+        .export_legacy(&trie_db, &mut nodes_only, false)
+        .unwrap();
+    assert_eq!(export.preimages, 0);
+    assert_eq!(nodes_only.inner().len(), export.nodes);
+}
+
+#[test]
+fn test_degenerate_proofs() {
+    let hash = |k: &[u8]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let key = [1u8; 32];
+
+    // empty trie
+    assert_eq!(trie.commit(&mut trie_db).unwrap(), CommittedRoot::EMPTY);
+    let proof = trie.prove(&trie_db, key).unwrap();
+    assert_eq!(proof, Proof::<Poseidon>::empty().encode());
+    let parsed = Proof::<Poseidon>::parse(&proof).unwrap();
+    assert_eq!(parsed.root().unwrap(), ZkHash::ZERO);
+    assert!(
+        !verify_proof::<Poseidon, _>(&ZkHash::ZERO, &hash(&key), &proof)
+            .unwrap()
+            .is_present()
+    );
+    assert!(verify_proof_stream::<Poseidon, _>(
+        &ZkHash::ZERO,
+        &hash(&key),
+        proof.concat().as_slice()
+    )
+    .unwrap()
+    .is_none());
+    assert!(trie
+        .get::<_, [[u8; 32]; 1], _>(&trie_db, key)
+        .unwrap()
+        .is_none());
+    assert!(!trie.delete(&trie_db, key).unwrap());
+    check_degenerate_apis(&mut trie, &trie_db, &ZkHash::ZERO);
+
+    // single leaf trie, the root is the leaf
+    trie.raw_update(&trie_db, key, vec![key], 1).unwrap();
+    let root = trie.commit(&mut trie_db).unwrap().hash();
+    let proof = trie.prove(&trie_db, key).unwrap();
+    assert_eq!(proof.len(), 2);
+    let parsed = Proof::<Poseidon>::parse(&proof).unwrap();
+    assert_eq!(parsed.root().unwrap(), root);
+    assert_eq!(parsed.encode(), proof);
+    assert!(verify_proof::<Poseidon, _>(&root, &hash(&key), &proof)
+        .unwrap()
+        .is_present());
+    check_degenerate_apis(&mut trie, &trie_db, &root);
+    let other = trie.prove(&trie_db, [2u8; 32]).unwrap();
+    assert!(
+        !verify_proof::<Poseidon, _>(&root, &hash(&[2u8; 32]), &other)
+            .unwrap()
+            .is_present()
+    );
+
+    // deleting the last leaf empties the trie
+    assert!(trie.delete(&trie_db, key).unwrap());
+    assert_eq!(trie.commit(&mut trie_db).unwrap(), CommittedRoot::EMPTY);
+    assert_eq!(*trie.root().unwrap_ref(), ZkHash::ZERO);
+    assert_eq!(
+        trie.prove(&trie_db, key).unwrap(),
+        Proof::<Poseidon>::empty().encode()
+    );
+
+    // an empty trie can be reopened at the zero root
+    let reopened =
+        ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, CommittedRoot::EMPTY).unwrap();
+    assert_eq!(
+        reopened.prove(&trie_db, key).unwrap(),
+        Proof::<Poseidon>::empty().encode()
+    );
+}
+
+/// Run the proof and traversal APIs on a committed trie with at most one leaf
+fn check_degenerate_apis(trie: &mut ZkTrie, trie_db: &NodeDb<HashMapDb>, root: &ZkHash) {
+    let leafs = usize::from(!root.is_zero());
+    let committed = trie_db.committed_root(*root).unwrap();
+    ZkTrie::<Poseidon>::open_verified(trie_db, NoCacheHasher, committed, OpenCheck::Full).unwrap();
+    let cached = ZkTrie::<Poseidon>::new_with_root(trie_db, NoCacheHasher, committed)
+        .unwrap()
+        .with_path_cache(trie_db, 4)
+        .unwrap();
+    assert_eq!(trie.count_leafs(trie_db).unwrap(), leafs);
+    assert_eq!(trie.leafs(trie_db).count(), leafs);
+
+    for key in [[1u8; 32], [2u8; 32]] {
+        let proof = trie.prove(trie_db, key).unwrap();
+        assert_eq!(cached.prove(trie_db, key).unwrap(), proof);
+        assert_eq!(
+            trie.proof_builder(trie_db).unwrap().prove(key).unwrap(),
+            proof
+        );
+        let mut stream = Vec::new();
+        trie.prove_into(trie_db, key, &mut stream).unwrap();
+        assert_eq!(stream, proof.concat());
+        let mut shared = SharedProofNodes::default();
+        trie.prove_shared(trie_db, key, &mut shared).unwrap();
+        assert_eq!(
+            trie.traverse(
+                trie_db,
+                &KeyHasher::<Poseidon>::hash(&NoCacheHasher, &key).unwrap()
+            )
+            .unwrap()
+            .len(),
+            1
+        );
+    }
+    trie.estimate_witness(trie_db, [[1u8; 32], [2u8; 32]])
+        .unwrap();
+
+    let range = trie
+        .prove_range(trie_db, &ZkHash::ZERO, &ZkHash::repeat_byte(0xff))
+        .unwrap();
+    assert_eq!(range.leaves.len(), leafs);
+    verify_range_proof(root, &ZkHash::ZERO, &range.leaves, &range.proof).unwrap();
+    assert_eq!(
+        ZkTrie::<Poseidon>::recompute_root(&range.leaves, 1).unwrap(),
+        *root
+    );
+
+    let mut buffer = Vec::new();
+    let manifest = trie
+        .export_snapshot_with_manifest(trie_db, &mut buffer)
+        .unwrap();
+    assert_eq!(manifest.nodes, leafs);
+    crate::db::snapshot::verify_backup(&manifest, buffer.as_slice()).unwrap();
+}
+
+#[test]
+fn test_proof_magic() {
+    const MAGIC: &[u8] = b"My custom proof";
+
+    let mut trie_db = NodeDb::new(HashMapDb::default());
+    let mut trie = ZkTrie::<Poseidon>::new(NoCacheHasher).with_proof_magic(MAGIC);
+    assert_eq!(trie.proof_magic(), MAGIC);
+    for i in 0..8u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap().hash();
+    let node_key = KeyHasher::<Poseidon>::hash(&NoCacheHasher, &[3u8; 32]).unwrap();
+
+    let proof = trie.prove(&trie_db, [3u8; 32]).unwrap();
+    assert_eq!(proof.last().unwrap().as_slice(), MAGIC);
+    assert_eq!(
+        trie.proof_builder(&trie_db)
+            .unwrap()
+            .prove([3u8; 32])
+            .unwrap(),
+        proof
+    );
+    let mut shared = SharedProofNodes::default();
+    let indices = trie.prove_shared(&trie_db, [3u8; 32], &mut shared).unwrap();
+    assert_eq!(shared.proof_with_magic(&indices, MAGIC), proof);
+
+    let parsed = Proof::<Poseidon>::parse_with_magic(&proof, MAGIC).unwrap();
+    assert!(parsed.verify(&root, &node_key).unwrap().is_some());
+    assert_eq!(parsed.encode_with_magic(MAGIC), proof);
+    assert!(matches!(
+        Proof::<Poseidon>::parse(&proof),
+        Err(ProofError::InvalidMagic)
+    ));
+
+    let stream = proof.concat();
+    let reader = ProofReader::new(stream.as_slice()).with_magic(MAGIC);
+    assert!(verify_proof_reader::<Poseidon, _>(&root, &node_key, reader)
+        .unwrap()
+        .is_some());
+    assert!(verify_proof_stream::<Poseidon, _>(&root, &node_key, stream.as_slice()).is_err());
+}
+
+#[test]
+#[should_panic(expected = "proof magic bytes")]
+fn test_proof_magic_node_type() {
+    let _ = ZkTrie::<Poseidon>::new(NoCacheHasher).with_proof_magic(&[NodeType::Leaf as u8]);
+}
+
+#[cfg(feature = "heal")]
+#[test]
+fn test_heal_missing_nodes() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mut source_db = NodeDb::new(HashMapDb::default());
+    let mut trie = ZkTrie::<Poseidon>::new(NoCacheHasher);
+    for i in 0..16u8 {
+        trie.raw_update(&source_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let root = trie.commit(&mut source_db).unwrap().hash();
+    let source_db = Arc::new(source_db);
+    let resolved = Arc::new(AtomicUsize::new(0));
+
+    let peer = source_db.clone();
+    let counter = resolved.clone();
+    let mut trie_db = NodeDb::new(HashMapDb::default()).with_resolver(
+        move |node_hash: &ZkHash| -> std::result::Result<_, crate::db::heal::ResolveError> {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(peer
+                .get_node::<Poseidon>(node_hash)?
+                .map(|node| node.view().canonical_value(true)))
+        },
+    );
+    assert!(trie_db.has_resolver());
+
+    // the root must be healed before the trie can be opened
+    assert!(trie_db.committed_root(root).is_err());
+    assert!(trie_db.get_node::<Poseidon>(&root).unwrap().is_some());
+    assert_eq!(trie_db.persist_healed().unwrap(), 1);
+    let healed = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    for i in 0..16u8 {
+        let value: [[u8; 32]; 1] = healed.get(&trie_db, [i; 32]).unwrap().unwrap();
+        assert_eq!(value, [[i; 32]]);
+    }
+    assert!(trie_db.healed_nodes() > 0);
+    let fetched = resolved.load(Ordering::Relaxed);
+    assert_eq!(fetched, 1 + trie_db.healed_nodes());
+
+    // healed nodes are served from the buffer, then from the backend once persisted
+    let proof = healed.prove(&trie_db, [3u8; 32]).unwrap();
+    assert_eq!(resolved.load(Ordering::Relaxed), fetched);
+    assert_eq!(trie_db.persist_healed().unwrap(), fetched - 1);
+    assert_eq!(trie_db.healed_nodes(), 0);
+    trie_db.clear_resolver();
+    assert_eq!(healed.prove(&trie_db, [3u8; 32]).unwrap(), proof);
+    let committed = trie_db.committed_root(root).unwrap();
+    ZkTrie::<Poseidon>::open_verified(&trie_db, NoCacheHasher, committed, OpenCheck::Full).unwrap();
+
+    // nodes not matching the requested hash are discarded
+    let peer = source_db.clone();
+    let lying_db = NodeDb::new(HashMapDb::default()).with_resolver(
+        move |_: &ZkHash| -> std::result::Result<_, crate::db::heal::ResolveError> {
+            Ok(peer
+                .get_node::<Poseidon>(&root)?
+                .map(|node| node.view().canonical_value(true)))
+        },
+    );
+    assert!(lying_db
+        .get_node::<Poseidon>(&ZkHash::repeat_byte(1))
+        .unwrap()
+        .is_none());
+    assert_eq!(lying_db.healed_nodes(), 0);
+
+    // the buffer is bounded, and flushed by the next commit
+    let peer = source_db.clone();
+    let mut trie_db = NodeDb::new(HashMapDb::default())
+        .with_resolver(
+            move |node_hash: &ZkHash| -> std::result::Result<_, crate::db::heal::ResolveError> {
+                Ok(peer
+                    .get_node::<Poseidon>(node_hash)?
+                    .map(|node| node.view().canonical_value(true)))
+            },
+        )
+        .with_healed_capacity(4);
+    trie_db.get_node::<Poseidon>(&root).unwrap().unwrap();
+    trie_db.persist_healed().unwrap();
+    let mut trie = ZkTrie::<Poseidon>::new_with_root(
+        &trie_db,
+        NoCacheHasher,
+        trie_db.committed_root(root).unwrap(),
+    )
+    .unwrap();
+    for i in 0..16u8 {
+        trie.get::<_, [[u8; 32]; 1], _>(&trie_db, [i; 32])
+            .unwrap()
+            .unwrap();
+        assert!(trie_db.healed_nodes() <= 4);
+    }
+    assert!(trie_db.healed_nodes() > 0);
+    trie.raw_update(&trie_db, [0u8; 32], vec![[1u8; 32]], 1)
+        .unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie_db.healed_nodes(), 0);
+}
+
+#[test]
+fn test_audit_mode() {
+    let mut trie_db = NodeDb::new(HashMapDb::default());
+    let mut trie = ZkTrie::<Poseidon>::new(NoCacheHasher).with_audit(AuditMode::Error);
+    assert_eq!(trie.audit(), AuditMode::Error);
+    for i in 0..8u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.leafs(&trie_db).count(), 8);
+
+    // store another leaf under the hash of the first one
+    let leafs: Vec<_> = trie.leafs(&trie_db).map(Result::unwrap).collect();
+    let victim = *leafs[0].node_hash().unwrap();
+    let node_key = leafs[0].as_leaf().unwrap().node_key();
+    let other = trie_db
+        .inner()
+        .get(leafs[1].node_hash().unwrap().as_slice())
+        .unwrap()
+        .unwrap();
+    trie_db.inner_mut().put(victim.as_slice(), &other).unwrap();
+
+    assert!(matches!(
+        trie.get_node_by_key(&trie_db, &node_key),
+        Err(ZkTrieError::CorruptedNode(node_hash)) if node_hash == victim
+    ));
+    for mode in [AuditMode::Off, AuditMode::Log] {
+        let trie = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root)
+            .unwrap()
+            .with_audit(mode);
+        // the key of the stored leaf differs, so the key is reported absent
+        let node = trie.get_node_by_key(&trie_db, &node_key).unwrap();
+        assert_eq!(node.node_type(), NodeType::Empty);
+    }
+}
+
+#[test]
+fn test_depth_histogram() {
+    use crate::db::histogram::DepthHistogram;
+
+    let mut trie_db =
+        NodeDb::new(HashMapDb::default()).with_depth_histogram(DepthHistogram::default());
+    let mut trie = ZkTrie::<Poseidon>::new(NoCacheHasher);
+    let keys: Vec<[u8; 32]> = (0..64).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let histogram = trie_db.depth_histogram().unwrap().clone();
+    assert_eq!(histogram, trie.compute_depth_histogram(&trie_db).unwrap());
+    assert_eq!(histogram.total_leafs(), 64);
+    // branches with an empty child are kept where keys share a prefix
+    assert!(histogram.total_branches() >= 63);
+    assert_eq!(histogram.branches_at(0), 1);
+    assert_eq!(
+        DepthHistogram::from_bytes(&histogram.to_bytes()).unwrap(),
+        histogram
+    );
+
+    // deletions collapse leafs upwards, insertions push them down
+    for (i, k) in keys.iter().enumerate() {
+        match i % 3 {
+            0 => assert!(trie.delete(&trie_db, k).unwrap()),
+            1 => trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap(),
+            _ => {}
+        }
+    }
+    for _ in 0..16 {
+        let k: [u8; 32] = random();
+        trie.raw_update(&trie_db, k, vec![k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let histogram = trie_db.depth_histogram().unwrap().clone();
+    assert_eq!(histogram, trie.compute_depth_histogram(&trie_db).unwrap());
+    assert_eq!(histogram.total_leafs(), 64 - 22 + 16);
+
+    // the histogram is persisted by every commit
+    trie_db.set_depth_histogram(None);
+    assert!(trie_db.load_depth_histogram().unwrap());
+    assert_eq!(trie_db.depth_histogram(), Some(&histogram));
+
+    for k in trie.leafs(&trie_db).map(Result::unwrap).collect::<Vec<_>>() {
+        let node_key = k.as_leaf().unwrap().node_key();
+        trie.delete_by_node_key(&trie_db, node_key).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    assert!(trie_db.depth_histogram().unwrap().is_empty());
+}
+
+#[test]
+fn test_commit_report() {
+    let mut trie_db = NodeDb::new(HashMapDb::default());
+    let mut trie = ZkTrie::<Poseidon>::new(NoCacheHasher);
+    let keys: Vec<[u8; 32]> = (0..32).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let (root, report) = trie.commit_with_report(&mut trie_db).unwrap();
+    let branches = trie
+        .compute_depth_histogram(&trie_db)
+        .unwrap()
+        .total_branches() as usize;
+    assert_eq!(report.new_nodes, 32 + branches);
+    assert_eq!(report.hash_invocations, branches);
+    assert_eq!(report.replaced_nodes, 0);
+    assert_eq!(report.freed_candidates, 0);
+    assert_eq!(
+        report.bytes_written,
+        trie.iter(&trie_db)
+            .map(|node| node.unwrap())
+            .filter(|node| node.node_type() != NodeType::Empty)
+            .map(|node| trie_db
+                .inner()
+                .get(node.node_hash().unwrap().as_slice())
+                .unwrap()
+                .unwrap()
+                .len())
+            .sum::<usize>()
+    );
+
+    // a value update replaces the path of the leaf
+    trie.raw_update(&trie_db, keys[0], vec![[1u8; 32]], 1)
+        .unwrap();
+    let (_, report) = trie.commit_with_report(&mut trie_db).unwrap();
+    let depth = trie
+        .traverse(
+            &trie_db,
+            &KeyHasher::<Poseidon>::hash(&NoCacheHasher, &keys[0]).unwrap(),
+        )
+        .unwrap()
+        .len();
+    assert_eq!(report.new_nodes, depth);
+    assert_eq!(report.replaced_nodes, depth);
+    assert_eq!(report.freed_candidates, depth);
+    assert_eq!(report.hash_invocations, depth - 1);
+    assert_eq!(trie.retired_nodes().count(), depth);
+
+    // writing back the old value revives the retired nodes
+    trie.raw_update(&trie_db, keys[0], vec![keys[0]], 1)
+        .unwrap();
+    let (reverted, report) = trie.commit_with_report(&mut trie_db).unwrap();
+    assert_eq!(reverted, root);
+    assert_eq!(report.freed_candidates, depth);
+
+    let (_, report) = trie.commit_with_report(&mut trie_db).unwrap();
+    assert_eq!(report, CommitReport::default());
+}
+
+#[test]
+fn test_commit_coordinator() {
+    let mut trie_db = NodeDb::new(HashMapDb::default());
+    assert!(trie_db.inner().is_extend_atomic());
+    let mut tries: Vec<ZkTrie> = (0..3).map(|_| ZkTrie::new(NoCacheHasher)).collect();
+    for (i, trie) in tries.iter_mut().enumerate() {
+        for j in 0..8u8 {
+            trie.raw_update(&trie_db, [i as u8 * 16 + j; 32], vec![[j; 32]], 1)
+                .unwrap();
+        }
+    }
+
+    // a trie changed after staging fails the whole commit without writing anything
+    let mut coordinator = CommitCoordinator::new();
+    for (i, trie) in tries.iter().enumerate() {
+        assert_eq!(coordinator.stage(trie).unwrap(), i);
+    }
+    let staged_roots: Vec<_> = coordinator.staged().iter().map(|set| set.root()).collect();
+    tries[2]
+        .raw_update(&trie_db, [0xffu8; 32], vec![[1u8; 32]], 1)
+        .unwrap();
+    let changed_root = tries[2].resolve_hash(tries[2].root()).unwrap();
+    assert!(matches!(
+        coordinator.commit(&mut trie_db, &mut tries.iter_mut().collect::<Vec<_>>()),
+        Err(ZkTrieError::RootMismatch { expected, actual })
+            if expected == staged_roots[2] && actual == changed_root
+    ));
+    for root in staged_roots {
+        assert!(trie_db.get_node::<Poseidon>(&root).unwrap().is_none());
+    }
+    assert!(tries.iter().all(|trie| trie.is_dirty()));
+
+    let mut coordinator = CommitCoordinator::new();
+    for trie in tries.iter() {
+        coordinator.stage(trie).unwrap();
+    }
+    let expected: Vec<_> = coordinator.staged().iter().map(|set| set.root()).collect();
+    let roots = coordinator
+        .commit(&mut trie_db, &mut tries.iter_mut().collect::<Vec<_>>())
+        .unwrap();
+    assert_eq!(
+        roots.iter().map(|root| root.hash()).collect::<Vec<_>>(),
+        expected
+    );
+    for (trie, root) in tries.iter().zip(roots) {
+        assert!(!trie.is_dirty());
+        assert_eq!(trie.root_version(), 1);
+        ZkTrie::<Poseidon>::open_verified(&trie_db, NoCacheHasher, root, OpenCheck::Full).unwrap();
+    }
+
+    // an empty coordinator has nothing to do
+    assert!(CommitCoordinator::<Poseidon>::new()
+        .commit::<_, NoCacheHasher, LsbFirst>(&mut trie_db, &mut [])
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_gc_policy() {
+    use crate::db::{GcPolicy, GcSkipped};
+
+    let mut trie_db = NodeDb::new(HashMapDb::new(false));
+    assert_eq!(trie_db.gc_policy(), GcPolicy::Warn);
+    let mut trie = ZkTrie::default();
+    for i in 0..4u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    trie.raw_update(&trie_db, [0u8; 32], vec![[9u8; 32]], 1)
+        .unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    let retired = trie.retired_nodes().count();
+    assert!(retired > 0);
+
+    // the default policy and ignore skip without error
+    trie.gc(&mut trie_db).unwrap();
+    let mut trie_db = trie_db.with_gc_policy(GcPolicy::Ignore);
+    assert_eq!(trie.gc_incremental(&mut trie_db, 10).unwrap(), 0);
+    assert_eq!(trie.retired_nodes().count(), retired);
+
+    let mut trie_db = trie_db.with_gc_policy(GcPolicy::Error);
+    assert!(matches!(
+        trie.gc(&mut trie_db),
+        Err(ZkTrieError::GcSkipped(GcSkipped::Disabled))
+    ));
+    assert!(matches!(
+        trie.gc_incremental(&mut trie_db, 10),
+        Err(ZkTrieError::GcSkipped(GcSkipped::Disabled))
+    ));
+    assert_eq!(trie.retired_nodes().count(), retired);
+
+    trie.raw_update(&trie_db, [1u8; 32], vec![[9u8; 32]], 1)
+        .unwrap();
+    assert!(matches!(
+        trie.full_gc(&mut trie_db, HashMapDb::default()),
+        Err(ZkTrieError::GcSkipped(GcSkipped::Dirty))
+    ));
+
+    trie.commit(&mut trie_db).unwrap();
+    trie_db.set_gc_enabled(true);
+    trie.gc(&mut trie_db).unwrap();
+    assert_eq!(trie.retired_nodes().count(), 0);
+}
+
+#[test]
+fn test_iter_preimages() {
+    let hash = |k: &[u8]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..10).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.iter_preimages(&trie_db, true).count(), 0);
+
+    let preimages: HashMap<ZkHash, [u8; 32]> = keys.iter().map(|k| (hash(k), *k)).collect();
+    trie.recover_key_preimages(&mut trie_db, |node_key| {
+        (*node_key != hash(&keys[0]))
+            .then(|| preimages.get(node_key).copied())
+            .flatten()
+    })
+    .unwrap();
+
+    let all: Vec<_> = trie
+        .iter_preimages(&trie_db, false)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(all.len(), keys.len());
+    let node_keys: Vec<_> = trie
+        .leafs(&trie_db)
+        .map(|leaf| leaf.unwrap().as_leaf().unwrap().node_key())
+        .collect();
+    assert_eq!(
+        all.iter()
+            .map(|(_, node_key, _)| *node_key)
+            .collect::<Vec<_>>(),
+        node_keys
+    );
+    for (preimage, node_key, values) in all.iter() {
+        match preimage {
+            Some(preimage) => {
+                assert_eq!(hash(preimage), *node_key);
+                assert_eq!(values, &[*preimage]);
+            }
+            None => assert_eq!(*node_key, hash(&keys[0])),
+        }
+    }
+
+    let known: Vec<_> = trie
+        .iter_preimages(&trie_db, true)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(known.len(), keys.len() - 1);
+    assert!(known.iter().all(|(preimage, _, _)| preimage.is_some()));
+}
+
+#[test]
+fn test_canonical_cache() {
+    let mut plain_db = NodeDb::default().into_shared();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&plain_db, k, vec![*k], 1).unwrap();
+    }
+    let root = trie.commit(&mut plain_db).unwrap();
+    let trie_db = plain_db.handle().with_canonical_cache(64);
+    assert_eq!(plain_db.canonical_cache_len(), None);
+    assert_eq!(trie_db.canonical_cache_len(), Some(0));
+
+    let trie = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+    for k in keys.iter() {
+        let expected = trie.prove(&plain_db, k).unwrap();
+        assert_eq!(trie.prove(&trie_db, k).unwrap(), expected);
+        // served from the cache
+        assert_eq!(trie.prove(&trie_db, k).unwrap(), expected);
+        assert!(trie_db.canonical_cache_len().unwrap() <= 64);
+    }
+    // the oldest nodes are evicted
+    let small_db = plain_db.handle().with_canonical_cache(2);
+    assert_eq!(
+        trie.prove(&small_db, keys[0]).unwrap(),
+        trie.prove(&plain_db, keys[0]).unwrap()
+    );
+    assert_eq!(small_db.canonical_cache_len(), Some(2));
+
+    // handles share the cache
+    let handle = trie_db.handle();
+    handle.clear_canonical_cache();
+    assert_eq!(trie_db.canonical_cache_len(), Some(0));
+    let proof = trie.prove(&handle, keys[0]).unwrap();
+    // the magic bytes are not a node
+    assert_eq!(trie_db.canonical_cache_len(), Some(proof.len() - 1));
+
+    // key preimages are cached apart
+    let trie = trie.with_key_preimages_in_proofs(true);
+    trie.prove(&trie_db, keys[0]).unwrap();
+    assert_eq!(trie_db.canonical_cache_len(), Some(2 * (proof.len() - 1)));
+}
+
+#[test]
+fn test_put_archived_node() {
+    use crate::db::ArchivedNodeError;
+
+    let mut source_db = NodeDb::default();
+    let mut trie = ZkTrie::<Poseidon>::new(NoCacheHasher);
+    for i in 0..16u8 {
+        trie.raw_update(&source_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let root = trie.commit(&mut source_db).unwrap();
+
+    let mut trie_db = NodeDb::default();
+    for node in trie.iter(&source_db) {
+        if let INode::Archived(viewer) = node.unwrap() {
+            trie_db
+                .put_archived_node::<Poseidon>(viewer.node_hash, &viewer.data)
+                .unwrap();
+        }
+    }
+    let copied = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+    for i in 0..16u8 {
+        let value: [[u8; 32]; 1] = copied.get(&trie_db, [i; 32]).unwrap().unwrap();
+        assert_eq!(value, [[i; 32]]);
+    }
+
+    let leaf =
+        Node::<Poseidon>::new_leaf(ZkHash::repeat_byte(1), vec![[1u8; 32]], 1, None).unwrap();
+    let node_hash = *leaf.get_or_calculate_node_hash().unwrap();
+    let bytes = leaf.clone().archived().to_vec();
+    let mut trie_db = NodeDb::default();
+    assert!(matches!(
+        trie_db.put_archived_node::<Poseidon>(ZkHash::repeat_byte(2), bytes.clone()),
+        Err(ArchivedNodeError::HashMismatch { actual, .. }) if actual == node_hash
+    ));
+    assert!(matches!(
+        trie_db.put_archived_node::<Poseidon>(node_hash, vec![0xff; 7]),
+        Err(ArchivedNodeError::Invalid(_))
+    ));
+    assert!(matches!(
+        trie_db.put_archived_node::<Poseidon>(node_hash, &bytes[..bytes.len() - 1]),
+        Err(ArchivedNodeError::Invalid(_))
+    ));
+
+    // a lying cached hash is rejected even under the right node hash
+    let forged =
+        Node::<Poseidon>::new_leaf(ZkHash::repeat_byte(1), vec![[1u8; 32]], 1, None).unwrap();
+    unsafe { forged.set_node_hash(ZkHash::repeat_byte(3)) };
+    assert!(matches!(
+        trie_db.put_archived_node::<Poseidon>(node_hash, forged.archived()),
+        Err(ArchivedNodeError::HashMismatch { expected, .. }) if expected == ZkHash::repeat_byte(3)
+    ));
+    assert!(trie_db.get_node::<Poseidon>(&node_hash).unwrap().is_none());
+
+    trie_db
+        .put_archived_node::<Poseidon>(node_hash, bytes)
+        .unwrap();
+    assert!(trie_db.get_node::<Poseidon>(&node_hash).unwrap().is_some());
+}
+
+#[test]
+fn test_put_misaligned_archived_node() {
+    let leaf =
+        Node::<Poseidon>::new_leaf(ZkHash::repeat_byte(1), vec![[1u8; 32]], 1, None).unwrap();
+    let node_hash = *leaf.get_or_calculate_node_hash().unwrap();
+    let archived = leaf.archived();
+    // shifted by one byte, never aligned for the archived node
+    let mut buffer = rkyv::util::AlignedVec::<16>::new();
+    buffer.push(0);
+    buffer.extend_from_slice(&archived);
+    let misaligned = &buffer[1..];
+    assert_ne!(misaligned.as_ptr() as usize % 2, 0);
+
+    let mut trie_db = NodeDb::default();
+    trie_db
+        .put_archived_node::<Poseidon>(node_hash, misaligned)
+        .unwrap();
+    let node = trie_db.get_node::<Poseidon>(&node_hash).unwrap().unwrap();
+    assert_eq!(node.view().cached_node_hash(), Some(node_hash));
+
+    let mut trie_db = NodeDb::default();
+    unsafe { trie_db.put_archived_node_unchecked(node_hash, misaligned) }.unwrap();
+    let node = trie_db.get_node::<Poseidon>(&node_hash).unwrap().unwrap();
+    assert_eq!(
+        node.view().as_leaf().unwrap().value_preimages(),
+        &[[1u8; 32]]
+    );
+}
+
+#[test]
+fn test_checkpoint_roots() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let mut reference = ZkTrie::default();
+    let mut expected = Vec::new();
+    for tx in 0..6u8 {
+        for i in 0..4u8 {
+            let key = [tx.wrapping_mul(3).wrapping_add(i) % 10; 32];
+            trie.raw_update(&trie_db, key, vec![[tx; 32]], 1).unwrap();
+            reference
+                .raw_update(&trie_db, key, vec![[tx; 32]], 1)
+                .unwrap();
+        }
+        if tx == 4 {
+            trie.delete(&trie_db, [0u8; 32]).unwrap();
+            reference.delete(&trie_db, [0u8; 32]).unwrap();
+        }
+        assert_eq!(trie.checkpoint_root(), tx as usize);
+        expected.push(reference.commit(&mut trie_db).unwrap().hash());
+    }
+
+    // superseded roots survive shrinking and committing
+    trie.shrink_dirty();
+    let root = trie.commit(&mut trie_db).unwrap();
+    assert_eq!(root.hash(), expected[5]);
+    assert_eq!(trie.checkpoint_roots().unwrap(), expected);
+
+    trie.raw_update(&trie_db, [42u8; 32], vec![[42u8; 32]], 1)
+        .unwrap();
+    trie.checkpoint_root();
+    // resolved without committing
+    let roots = trie.checkpoint_roots().unwrap();
+    assert_eq!(roots[..6], expected);
+    let root = trie.commit(&mut trie_db).unwrap();
+    assert_eq!(roots[6], root.hash());
+    assert_eq!(trie.checkpoint_roots().unwrap(), roots);
+
+    trie.clear_checkpoints();
+    assert!(trie.checkpoint_roots().unwrap().is_empty());
+}
+
+#[test]
+fn test_stream() {
+    use std::ops::ControlFlow;
+
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    for i in 0..40u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+
+    let expected: Vec<_> = trie
+        .iter(&trie_db)
+        .with_paths()
+        .map(|result| {
+            let (path, node) = result.unwrap();
+            (path, *node.get_or_calculate_node_hash().unwrap())
+        })
+        .collect();
+
+    let mut stream = trie.stream(&trie_db, TraversalOrder::DepthFirst);
+    let mut depth_first = Vec::new();
+    while let Some(next) = stream.next_node() {
+        let (path, node) = next.unwrap();
+        depth_first.push((path, *node.get_or_calculate_node_hash().unwrap()));
+        // right siblings of the path and the children of the node
+        assert!(stream.pending() <= path.depth() + 2);
+    }
+    assert_eq!(depth_first, expected);
+
+    let mut stream = trie.stream(&trie_db, TraversalOrder::BreadthFirst);
+    let mut breadth_first = Vec::new();
+    while let Some(next) = stream.next_node() {
+        let (path, node) = next.unwrap();
+        breadth_first.push((path, *node.get_or_calculate_node_hash().unwrap()));
+    }
+    assert!(breadth_first
+        .windows(2)
+        .all(|pair| pair[0].0.depth() <= pair[1].0.depth()));
+    assert_eq!(breadth_first.len(), expected.len());
+    assert_eq!(
+        breadth_first.into_iter().collect::<HashSet<_>>(),
+        expected.iter().copied().collect::<HashSet<_>>()
+    );
+
+    let leafs: Vec<_> = trie
+        .leafs(&trie_db)
+        .map(|leaf| leaf.unwrap().as_leaf().unwrap().node_key())
+        .collect();
+    let mut visited = Vec::new();
+    let count = trie
+        .for_each_leaf(&trie_db, |_, leaf| {
+            visited.push(leaf.node_key());
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+    assert_eq!(count, 40);
+    assert_eq!(visited, leafs);
+
+    let count = trie
+        .for_each_leaf(&trie_db, |_, _| ControlFlow::Break(()))
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[test]
+fn test_prehashed_key_hasher() {
+    use crate::hash::key_hasher::{InvalidKeyLength, PrehashedKeyHasher};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Counts the reported key preimages
+    struct Preimages(Arc<AtomicUsize>);
+
+    impl SecondaryCommitment for Preimages {
+        fn update(&mut self, key: Option<&[u8]>, _: &ZkHash, _: &[[u8; 32]], _: u32) {
+            self.0.fetch_add(key.is_some() as usize, Ordering::Relaxed);
+        }
+
+        fn delete(&mut self, key: Option<&[u8]>, _: &ZkHash) {
+            self.0.fetch_add(key.is_some() as usize, Ordering::Relaxed);
+        }
+
+        fn commit(&mut self) -> std::result::Result<ZkHash, Box<dyn Error + Send + Sync>> {
+            Ok(ZkHash::ZERO)
+        }
+    }
+
+    let hash = |k: &[u8]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::<Poseidon>::new(NoCacheHasher);
+    let mut prehashed = ZkTrie::<Poseidon, PrehashedKeyHasher>::new(PrehashedKeyHasher);
+    let preimages = Arc::new(AtomicUsize::new(0));
+    prehashed.set_secondary_commitment(Preimages(preimages.clone()));
+    for i in 0..20u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+        prehashed
+            .raw_update(&trie_db, hash(&[i; 32]), vec![[i; 32]], 1)
+            .unwrap();
+    }
+    assert_eq!(
+        prehashed.commit(&mut trie_db).unwrap(),
+        trie.commit(&mut trie_db).unwrap()
+    );
+    let value: [[u8; 32]; 1] = prehashed.get(&trie_db, hash(&[3u8; 32])).unwrap().unwrap();
+    assert_eq!(value, [[3u8; 32]]);
+    // node keys are not reported as preimages
+    assert!(prehashed.delete(&trie_db, hash(&[3u8; 32])).unwrap());
+    assert_eq!(preimages.load(Ordering::Relaxed), 0);
+
+    match prehashed.raw_update(&trie_db, [1u8; 31], vec![[1u8; 32]], 1) {
+        Err(ZkTrieError::KeyHasher(KeyHasherError::Other(e))) => {
+            assert_eq!(e.downcast_ref::<InvalidKeyLength>().unwrap().0, 31);
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+    // not a field element
+    assert!(matches!(
+        prehashed.get::<_, [[u8; 32]; 1], _>(&trie_db, [0xffu8; 32]),
+        Err(ZkTrieError::KeyHasher(KeyHasherError::Hash(_)))
+    ));
+}
+
+#[test]
+fn test_apply_update_from_proof() {
+    let hash = |k: &[u8; 32]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let mut keys: Vec<[u8; 32]> = (0..30).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let mut root = trie.commit(&mut trie_db).unwrap().hash();
+
+    for i in 0..40 {
+        let (key, new_value) = match i % 4 {
+            0 => (keys[i % keys.len()], Some(random::<[u8; 32]>())),
+            1 => {
+                keys.push(random());
+                (*keys.last().unwrap(), Some(random()))
+            }
+            2 => (keys.swap_remove(i % keys.len()), None),
+            _ => (random(), None),
+        };
+        let proof = trie.prove(&trie_db, key).unwrap();
+        let new_root = apply_update_from_proof::<Poseidon, _>(
+            &root,
+            &proof,
+            &hash(&key),
+            new_value.map(|v| (vec![v], 1)),
+        )
+        .unwrap();
+        match new_value {
+            Some(v) => trie.raw_update(&trie_db, key, vec![v], 1).unwrap(),
+            None => assert_eq!(trie.delete(&trie_db, key).unwrap(), i % 4 == 2),
+        }
+        root = trie.commit(&mut trie_db).unwrap().hash();
+        assert_eq!(new_root, root, "op {i}");
+    }
+
+    // deleting down to the empty trie prunes every branch
+    while let Some(key) = keys.pop() {
+        let proof = Proof::<Poseidon>::parse(&trie.prove(&trie_db, key).unwrap()).unwrap();
+        let new_root = proof.apply_update(&root, &hash(&key), None).unwrap();
+        trie.delete(&trie_db, key).unwrap();
+        root = trie.commit(&mut trie_db).unwrap().hash();
+        assert_eq!(new_root, root);
+    }
+    assert!(root.is_zero());
+
+    let key = [1u8; 32];
+    let proof = trie.prove(&trie_db, key).unwrap();
+    let new_root = Proof::<Poseidon>::parse(&proof)
+        .unwrap()
+        .apply_update(&root, &hash(&key), Some((vec![key], 1)))
+        .unwrap();
+    trie.raw_update(&trie_db, key, vec![key], 1).unwrap();
+    assert_eq!(new_root, trie.commit(&mut trie_db).unwrap().hash());
+    assert!(matches!(
+        apply_update_from_proof::<Poseidon, _>(&new_root, &proof, &hash(&key), None),
+        Err(ProofError::HashMismatch { .. })
+    ));
+    assert!(matches!(
+        Proof::<Poseidon>::parse(&proof).unwrap().apply_update(
+            &root,
+            &hash(&key),
+            Some((vec![], 0))
+        ),
+        Err(ProofError::InvalidLeafValues(_))
+    ));
+}
+
+#[test]
+fn test_pin_root() {
+    let mut trie_db = NodeDb::new(HashMapDb::new(true)).into_shared();
+    let mut trie = ZkTrie::default();
+    for i in 0..20u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let old_root = trie.commit(&mut trie_db).unwrap().hash();
+    let prover_db = trie_db.handle();
+    prover_db.pin(old_root).unwrap();
+    prover_db.pin(old_root).unwrap();
+    assert_eq!(trie_db.pinned_roots(), vec![old_root]);
+
+    for i in 0..10u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i + 1; 32]], 1)
+            .unwrap();
+    }
+    let new_root = trie.commit(&mut trie_db).unwrap().hash();
+    let retired = trie.retired_nodes().count();
+    assert!(retired > 0);
+
+    // collections on another handle leave the pinned root intact
+    trie.gc(&mut trie_db).unwrap();
+    assert_eq!(trie.gc_incremental(&mut trie_db, 10).unwrap(), 0);
+    assert_eq!(trie.retired_nodes().count(), retired);
+    trie_db
+        .full_gc::<Poseidon, _>(&[new_root], HashMapDb::default())
+        .unwrap();
+    let old_trie = ZkTrie::<Poseidon>::new_with_root(
+        &prover_db,
+        NoCacheHasher,
+        prover_db.committed_root(old_root).unwrap(),
+    )
+    .unwrap();
+    for i in 0..20u8 {
+        old_trie.prove(&prover_db, [i; 32]).unwrap();
+    }
+
+    // nodes retired since are collected, the ones of the pinned root are kept
+    for i in 0..10u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i + 2; 32]], 1)
+            .unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    let newly_retired = trie.retired_nodes().count() - retired;
+    assert!(newly_retired > 0);
+    assert_eq!(
+        trie.gc_incremental(&mut trie_db, newly_retired - 1)
+            .unwrap(),
+        newly_retired - 1
+    );
+    trie.gc(&mut trie_db).unwrap();
+    assert_eq!(trie.retired_nodes().count(), retired);
+    for i in 0..20u8 {
+        old_trie.prove(&prover_db, [i; 32]).unwrap();
+    }
+
+    assert!(prover_db.unpin(old_root));
+    assert!(prover_db.unpin(old_root));
+    assert!(!prover_db.unpin(old_root));
+    assert!(trie_db.pinned_roots().is_empty());
+    trie.gc(&mut trie_db).unwrap();
+    assert_eq!(trie.retired_nodes().count(), 0);
+    assert!(matches!(
+        prover_db.pin(old_root),
+        Err(crate::db::NodeDbError::NodeNotFound(root)) if root == old_root
+    ));
+    assert!(trie_db.pinned_roots().is_empty());
+    prover_db.pin(ZkHash::ZERO).unwrap();
+}
+
+#[test]
+fn test_pin_walk_cached() {
+    let mut trie_db = NodeDb::new(HashMapDb::new(true));
+    let mut trie = ZkTrie::default();
+    for i in 0..20u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i; 32]], 1)
+            .unwrap();
+    }
+    let old_root = trie.commit(&mut trie_db).unwrap().hash();
+    trie_db.pin(old_root).unwrap();
+    for i in 0..10u8 {
+        trie.raw_update(&trie_db, [i; 32], vec![[i + 1; 32]], 1)
+            .unwrap();
+    }
+    trie.commit(&mut trie_db).unwrap();
+    trie.gc(&mut trie_db).unwrap();
+    let (_, pinned_node) = trie.retired_nodes().next().unwrap();
+
+    // the pinned root is walked once, later collections don't read its nodes
+    trie_db.remove_node(&pinned_node).unwrap();
+    trie.gc(&mut trie_db).unwrap();
+    assert_eq!(trie.gc_incremental(&mut trie_db, 10).unwrap(), 0);
+
+    // unpinning drops the walk, pinning again walks the root again
+    assert!(trie_db.unpin(old_root));
+    trie_db.pin(old_root).unwrap();
+    assert!(matches!(
+        trie.gc(&mut trie_db),
+        Err(ZkTrieError::NodeNotFound)
+    ));
+}
+
+#[test]
+fn test_verify_legacy_proof() {
+    let hash = |k: &[u8; 32]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..20).map(|_| random()).collect();
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![*k], 1).unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap().hash();
+    let absent = (0..)
+        .map(|_| random::<[u8; 32]>())
+        .find(|k| {
+            let proof = trie.prove(&trie_db, k).unwrap();
+            proof[proof.len() - 2] == [NodeType::Empty as u8]
+        })
+        .unwrap();
+
+    for (key, legacy_type) in [(keys[0], 1u8), (absent, 2u8)] {
+        let mut proof = trie.prove(&trie_db, key).unwrap();
+        let expected = verify_proof::<Poseidon, _>(&root, &hash(&key), &proof).unwrap();
+        let terminal = proof.len() - 2;
+        proof[terminal][0] = legacy_type;
+        assert!(matches!(
+            verify_proof::<Poseidon, _>(&root, &hash(&key), &proof),
+            Err(ProofError::InvalidNodeBytes(
+                ParseNodeError::InvalidNodeType(t)
+            )) if t == legacy_type
+        ));
+        let legacy = verify_legacy_proof::<Poseidon, _>(&root, &hash(&key), &proof).unwrap();
+        assert_eq!(legacy.is_present(), expected.is_present());
+
+        let stream = proof.concat();
+        let reader = ProofReader::new(stream.as_slice()).with_legacy_node_types(true);
+        let streamed = verify_proof_reader::<Poseidon, _>(&root, &hash(&key), reader).unwrap();
+        assert_eq!(streamed.is_some(), expected.is_present());
+    }
+
+    // a legacy parent node is read as the branch type its parent or the root references
+    let mut proof = trie.prove(&trie_db, keys[0]).unwrap();
+    proof[0][0] = 0;
+    let parsed = Proof::<Poseidon>::parse_legacy(&proof, &root).unwrap();
+    assert_eq!(parsed.root().unwrap(), root);
+    assert!(matches!(
+        Proof::<Poseidon>::parse_legacy(&proof, &ZkHash::repeat_byte(1)),
+        Err(ProofError::LegacyParentNode(0))
+    ));
+    let stream = proof.concat();
+    let mut reader = ProofReader::new(stream.as_slice()).with_legacy_node_types(true);
+    assert!(matches!(
+        reader.next_node::<Poseidon>(),
+        Err(ProofError::LegacyParentNode(0))
+    ));
+}
+
+#[test]
+fn test_legacy_parent_proof() {
+    // proofs of keys `[1; 32]` and `[9; 32]` in a trie of the keys `[i; 32]` with values
+    // `[[i; 32]]` for `i` in `1..=4`, with the legacy parent (0) and leaf (1) node types
+    const ROOT: &str = "18440223c154a5bf263cc0a6d5c4544e4de47895c765f058826f4306287fd6b6";
+    const PRESENT: [&str; 6] = [
+        "0008f01c7463c36d1aa58ed25ed141a0dcd7db40e694dbcc729089c5f08ffd90a4086b32e6329319da1aa1c6f9cd6b841a07f391861d969e79c4843f713a78ea31",
+        "00000000000000000000000000000000000000000000000000000000000000000012bcd12e4fe99d928408c8a4b9195cea464de23a0b8f7b55e6ad76d2822f5b41",
+        "0002dcdeface038e7c4eb3650ef17b304527050c74948bc329991c1748f90a6f100000000000000000000000000000000000000000000000000000000000000000",
+        "000854f62a852dc1b0e0b18a644b9972ff09d2182f82995bce8a1851401bbc57b30000000000000000000000000000000000000000000000000000000000000000",
+        "0029522527e0b158d32d9f1d1ebf470269372dcba236e46465eb4628f43ddd8cdc0b3b59b703589a9e1606123a75c101677a8ccec734128cb440b7390851f570ef",
+        "0104e80b8f64c91b2f037a15e2e2b94f1da143d6694cec10b5e816df377f3e4eb201010000010101010101010101010101010101010101010101010101010101010101010100",
+    ];
+    const ABSENT: [&str; 3] = [
+        "0008f01c7463c36d1aa58ed25ed141a0dcd7db40e694dbcc729089c5f08ffd90a4086b32e6329319da1aa1c6f9cd6b841a07f391861d969e79c4843f713a78ea31",
+        "002f4aa830e305e220c39be1bb7dbf62e5c2c6a0eefe420c8b1f3e3f5fb9c2a9e309ec1b4553eb262f42d8feee0599b1dbf742821fbd48a5880c4ea257843e677b",
+        "0112c2c07432689772bc61a26d6ec22ba953629cb8faa76b6056de099299abcba501010000040404040404040404040404040404040404040404040404040404040404040400",
+    ];
+    let root = ZkHash::from_slice(&hex::decode(ROOT).unwrap());
+    let decode = |nodes: &[&str]| {
+        let mut proof: Vec<Vec<u8>> = nodes.iter().map(|n| hex::decode(n).unwrap()).collect();
+        proof.push(crate::trie::MAGIC_NODE_BYTES.to_vec());
+        proof
+    };
+    let hash = |k: [u8; 32]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, &k).unwrap();
+
+    let present = decode(&PRESENT);
+    let leaf = verify_legacy_proof::<Poseidon, _>(&root, &hash([1; 32]), &present)
+        .unwrap()
+        .into_present()
+        .unwrap();
+    assert_eq!(leaf.as_leaf().unwrap().value_preimages(), &[[1; 32]]);
+    let proof = Proof::<Poseidon>::parse_legacy(&present, &root).unwrap();
+    let types: Vec<_> = proof.nodes().iter().map(|n| n.node_type()).collect();
+    assert_eq!(
+        types,
+        [
+            NodeType::BranchLBRB,
+            NodeType::BranchLTRB,
+            NodeType::BranchLBRT,
+            NodeType::BranchLBRT,
+            NodeType::BranchLTRT,
+            NodeType::Leaf,
+        ]
+    );
+    // re-encoded with the current node types, it's a regular proof
+    assert!(
+        verify_proof::<Poseidon, _>(&root, &hash([1; 32]), &proof.encode())
+            .unwrap()
+            .is_present()
+    );
+
+    let absent = decode(&ABSENT);
+    let key_proof = verify_legacy_proof::<Poseidon, _>(&root, &hash([9; 32]), &absent).unwrap();
+    assert!(matches!(
+        key_proof,
+        KeyProof::Absent { conflicting_leaf: Some(leaf) }
+            if leaf.as_leaf().unwrap().node_key() == hash([4; 32])
+    ));
+
+    for (key, proof, present) in [([1; 32], &present, true), ([9; 32], &absent, false)] {
+        let stream = proof.concat();
+        let reader = ProofReader::new(stream.as_slice()).with_legacy_node_types(true);
+        let streamed = verify_proof_reader::<Poseidon, _>(&root, &hash(key), reader).unwrap();
+        assert_eq!(streamed.is_some(), present);
+    }
+}
+
+#[test]
+fn test_hash_many() {
+    use crate::hash::key_hasher::{NamespacedKeyHasher, ParallelKeyHasher};
+
+    let keys: Vec<[u8; 20]> = (0..300).map(|_| random()).collect();
+    let keys: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+    let expected: Vec<ZkHash> = keys
+        .iter()
+        .map(|k| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap())
+        .collect();
+    assert_eq!(
+        KeyHasher::<Poseidon>::hash_many(&NoCacheHasher, &keys).unwrap(),
+        expected
+    );
+    for threads in [0, 1, 4] {
+        let hasher = ParallelKeyHasher::new(threads);
+        assert_eq!(
+            KeyHasher::<Poseidon>::hash_many(&hasher, &keys).unwrap(),
+            expected
+        );
+        assert_eq!(
+            KeyHasher::<Poseidon>::hash_many(&hasher, &keys[..3]).unwrap(),
+            expected[..3]
+        );
+    }
+    let mut invalid = keys.clone();
+    invalid[200] = &[0u8; 33];
+    assert!(matches!(
+        KeyHasher::<Poseidon>::hash_many(&ParallelKeyHasher::new(4), &invalid),
+        Err(KeyHasherError::Hash(_))
+    ));
+
+    let namespaced =
+        NamespacedKeyHasher::new::<Poseidon>(ParallelKeyHasher::new(4), b"ns").unwrap();
+    let hashes = KeyHasher::<Poseidon>::hash_many(&namespaced, &keys).unwrap();
+    for (key, hash) in keys.iter().zip(hashes) {
+        assert_eq!(KeyHasher::<Poseidon>::hash(&namespaced, key).unwrap(), hash);
+    }
+
+    #[cfg(feature = "key-cache")]
+    {
+        use crate::hash::key_hasher::SyncCachedKeyHasher;
+
+        let hasher = SyncCachedKeyHasher::<Poseidon>::new(HashMapDb::default());
+        hasher.warm_up(&keys[..10]).unwrap();
+        let mut repeated = keys.clone();
+        repeated.push(keys[20]);
+        let hashes = KeyHasher::<Poseidon>::hash_many(&hasher, &repeated).unwrap();
+        assert_eq!(hashes[..keys.len()], expected);
+        assert_eq!(hashes[keys.len()], expected[20]);
+        assert_eq!(hasher.warm_up(&keys).unwrap(), 0);
+    }
+
+    // batched paths hash like single keys
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::<Poseidon, _>::new(ParallelKeyHasher::new(4));
+    for k in keys.iter() {
+        trie.raw_update(&trie_db, k, vec![[1u8; 32]], 1).unwrap();
+    }
+    let root = trie.commit(&mut trie_db).unwrap();
+    let mut shared = SharedProofNodes::default();
+    for k in keys.iter() {
+        trie.prove_shared(&trie_db, k, &mut shared).unwrap();
+    }
+    let estimate = trie.estimate_witness(&trie_db, &keys).unwrap();
+    assert_eq!(estimate.nodes, shared.len());
+    let mut builder = witness::BatchWitnessBuilder::new(root);
+    builder.prove_block(&trie, &trie_db, &keys).unwrap();
+    assert_eq!(builder.nodes(), shared.len());
+}
+
+#[test]
+fn test_leaf_versions() {
+    let mut trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let keys: Vec<[u8; 32]> = (0..4).map(|_| random()).collect();
+    trie.raw_update(&trie_db, keys[0], vec![[1u8; 32]], 1)
+        .unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.last_modified(&trie_db, keys[0]).unwrap(), None);
+    assert_eq!(trie.changed_since(&trie_db, 0).unwrap(), None);
+
+    let mut trie = trie.with_leaf_versions();
+    assert!(trie.has_leaf_versions());
+    // changes before enabling are not recorded
+    assert_eq!(trie.last_modified(&trie_db, keys[0]).unwrap(), None);
+
+    for k in keys.iter().skip(1) {
+        trie.raw_update(&trie_db, k, vec![[2u8; 32]], 1).unwrap();
+    }
+    // not recorded until committed
+    assert_eq!(trie.last_modified(&trie_db, keys[1]).unwrap(), None);
+    trie.commit(&mut trie_db).unwrap();
+    let v1 = trie.root_version();
+    for k in keys.iter().skip(1) {
+        assert_eq!(trie.last_modified(&trie_db, k).unwrap(), Some(v1));
+    }
+
+    // unchanged values don't bump the version
+    trie.raw_update(&trie_db, keys[1], vec![[2u8; 32]], 1)
+        .unwrap();
+    trie.raw_update(&trie_db, keys[2], vec![[3u8; 32]], 1)
+        .unwrap();
+    trie.delete(&trie_db, keys[3]).unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    let v2 = trie.root_version();
+    assert!(v2 > v1);
+    assert_eq!(trie.last_modified(&trie_db, keys[1]).unwrap(), Some(v1));
+    assert_eq!(trie.last_modified(&trie_db, keys[2]).unwrap(), Some(v2));
+    assert_eq!(trie.last_modified(&trie_db, keys[3]).unwrap(), Some(v2));
+
+    let node_key = |k: &[u8; 32]| KeyHasher::<Poseidon>::hash(&NoCacheHasher, k).unwrap();
+    let mut expected = vec![(node_key(&keys[2]), v2), (node_key(&keys[3]), v2)];
+    expected.sort();
+    assert_eq!(trie.changed_since(&trie_db, v1).unwrap(), Some(expected));
+    assert_eq!(
+        trie.changed_since(&trie_db, v1 - 1).unwrap().unwrap().len(),
+        3
+    );
+    assert_eq!(trie.changed_since(&trie_db, v2).unwrap(), Some(vec![]));
+    assert_eq!(
+        trie.last_modified_by_node_key(&trie_db, &node_key(&keys[2]))
+            .unwrap(),
+        Some(v2)
+    );
+
+    // the versions are reloaded with the root
+    let root = trie.commit(&mut trie_db).unwrap();
+    let mut trie = ZkTrie::<Poseidon>::new_with_root(&trie_db, NoCacheHasher, root).unwrap();
+    assert!(trie.has_leaf_versions());
+    assert_eq!(trie.root_version(), v2);
+    assert_eq!(trie.last_modified(&trie_db, keys[2]).unwrap(), Some(v2));
+    trie.raw_update(&trie_db, keys[0], vec![[4u8; 32]], 1)
+        .unwrap();
+    trie.commit(&mut trie_db).unwrap();
+    assert_eq!(trie.root_version(), v2 + 1);
+    assert_eq!(
+        trie.changed_since(&trie_db, v2).unwrap(),
+        Some(vec![(node_key(&keys[0]), v2 + 1)])
+    );
+}
+
+#[test]
+fn test_get_fallback() {
+    use crate::trie::Fallback;
+    type Value = Fallback<[[u8; 32]; 2], [[u8; 32]; 1]>;
+
+    let trie_db = NodeDb::default();
+    let mut trie = ZkTrie::default();
+    let (old, new): ([u8; 32], [u8; 32]) = (random(), random());
+    trie.raw_update(&trie_db, old, vec![[1u8; 32]], 1).unwrap();
+    trie.raw_update(&trie_db, new, vec![[2u8; 32], [3u8; 32]], 3)
+        .unwrap();
+
+    let value = trie.get::<_, Value, _>(&trie_db, new).unwrap().unwrap();
+    assert!(value.is_primary());
+    assert_eq!(value.primary(), Some([[2u8; 32], [3u8; 32]]));
+    let value = trie.get::<_, Value, _>(&trie_db, old).unwrap().unwrap();
+    assert_eq!(value, Fallback::Secondary([[1u8; 32]]));
+    assert_eq!(value.primary(), None);
+    assert_eq!(value.secondary(), Some([[1u8; 32]]));
+
+    // nested fallbacks are tried in order
+    let value = trie
+        .get::<_, Fallback<[[u8; 32]; 3], Value>, _>(&trie_db, old)
+        .unwrap()
+        .unwrap();
+    assert_eq!(value, Fallback::Secondary(Fallback::Secondary([[1u8; 32]])));
+    // no decoder matches
+    assert!(matches!(
+        trie.get::<_, Fallback<[[u8; 32]; 3], [[u8; 32]; 4]>, _>(&trie_db, old),
+        Err(ZkTrieError::UnexpectValue)
+    ));
+}
@@ -0,0 +1,159 @@
+//! Witnesses of a rollup batch spanning several blocks, see [`BatchWitnessBuilder`].
+//!
+//! Every block is executed against the post root of the previous one,
+//! the nodes touched by all blocks are deduplicated into one [`BatchWitness`]
+//! keyed by the root the batch starts from.
+use super::{imp::Result, CommittedRoot, PathOrder, SharedProofNodes, ZkTrie, ZkTrieError};
+use crate::{
+    db::{kv::KVDatabase, NodeDb},
+    hash::{key_hasher::KeyHasher, HashScheme, ZkHash},
+};
+
+/// The nodes touched by the blocks of a batch, see [`BatchWitnessBuilder`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatchWitness {
+    /// The root the batch starts from
+    pub start_root: ZkHash,
+    /// The post root of every block, in order
+    pub block_roots: Vec<ZkHash>,
+    /// The unique canonical node bytes, in the order they're first touched
+    pub nodes: Vec<Vec<u8>>,
+}
+
+impl BatchWitness {
+    /// The root after the last block, the start root if the batch is empty.
+    pub fn end_root(&self) -> ZkHash {
+        self.block_roots.last().copied().unwrap_or(self.start_root)
+    }
+}
+
+/// Accumulates the nodes touched by the blocks of a batch.
+///
+/// For every block, prove the touched keys against the pre-state with
+/// [`prove_block`](BatchWitnessBuilder::prove_block), before the block is applied,
+/// then apply and commit it and pass its post root to
+/// [`end_block`](BatchWitnessBuilder::end_block).
+#[derive(Clone, Debug)]
+pub struct BatchWitnessBuilder {
+    start_root: ZkHash,
+    block_roots: Vec<ZkHash>,
+    nodes: SharedProofNodes,
+}
+
+impl BatchWitnessBuilder {
+    /// Start a batch from `start_root`.
+    pub fn new(start_root: CommittedRoot) -> Self {
+        Self {
+            start_root: start_root.hash(),
+            block_roots: Vec::new(),
+            nodes: SharedProofNodes::new(),
+        }
+    }
+
+    /// Get the root the batch starts from
+    #[inline]
+    pub fn start_root(&self) -> ZkHash {
+        self.start_root
+    }
+
+    /// Get the root the next block is executed against
+    #[inline]
+    pub fn current_root(&self) -> ZkHash {
+        self.block_roots.last().copied().unwrap_or(self.start_root)
+    }
+
+    /// Number of ended blocks
+    #[inline]
+    pub fn blocks(&self) -> usize {
+        self.block_roots.len()
+    }
+
+    /// Number of unique nodes so far
+    #[inline]
+    pub fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Add the proof nodes of `keys` in the pre-state of the current block.
+    ///
+    /// `trie` must be committed at [`current_root`](BatchWitnessBuilder::current_root),
+    /// otherwise [`ZkTrieError::RootMismatch`] is returned.
+    pub fn prove_block<H, Db, K, P, KEY>(
+        &mut self,
+        trie: &ZkTrie<H, K, P>,
+        db: &NodeDb<Db>,
+        keys: impl IntoIterator<Item = KEY>,
+    ) -> Result<(), H, Db>
+    where
+        H: HashScheme,
+        Db: KVDatabase,
+        K: KeyHasher<H>,
+        P: PathOrder,
+        KEY: AsRef<[u8]>,
+    {
+        if trie.is_dirty() {
+            warn!("dirty nodes found, commit before proving a block");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let root = *trie.root().unwrap_ref();
+        if root != self.current_root() {
+            return Err(ZkTrieError::RootMismatch {
+                expected: self.current_root(),
+                actual: root,
+            });
+        }
+        self.prove_nested(trie, db, keys)
+    }
+
+    /// Add the proof nodes of `keys` in a trie referenced by the pre-state of the current block,
+    /// e.g. the storage trie of an account.
+    ///
+    /// The root of `trie` is not checked, it must be committed.
+    /// The keys are hashed in a batch, see [`KeyHasher::hash_many`].
+    pub fn prove_nested<H, Db, K, P, KEY>(
+        &mut self,
+        trie: &ZkTrie<H, K, P>,
+        db: &NodeDb<Db>,
+        keys: impl IntoIterator<Item = KEY>,
+    ) -> Result<(), H, Db>
+    where
+        H: HashScheme,
+        Db: KVDatabase,
+        K: KeyHasher<H>,
+        P: PathOrder,
+        KEY: AsRef<[u8]>,
+    {
+        if trie.is_dirty() {
+            warn!("dirty nodes found, commit before proving a block");
+            return Err(ZkTrieError::UncommittedChanges);
+        }
+        let keys: Vec<KEY> = keys.into_iter().collect();
+        let keys: Vec<&[u8]> = keys.iter().map(AsRef::as_ref).collect();
+        for node_key in trie.key_hasher().hash_many(&keys)? {
+            trie.prove_shared_node_key(db, node_key, &mut self.nodes)?;
+        }
+        Ok(())
+    }
+
+    /// End the current block, the next block is executed against `post_root`.
+    pub fn end_block(&mut self, post_root: CommittedRoot) {
+        trace!(
+            block = self.block_roots.len(),
+            nodes = self.nodes.len(),
+            "ended block"
+        );
+        self.block_roots.push(post_root.hash());
+    }
+
+    /// Take the witness of the batch.
+    ///
+    /// Nodes proved for a block that hasn't ended are included too.
+    pub fn build(self) -> BatchWitness {
+        BatchWitness {
+            start_root: self.start_root,
+            block_roots: self.block_roots,
+            nodes: self.nodes.into_nodes(),
+        }
+    }
+}